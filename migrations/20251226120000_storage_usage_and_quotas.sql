@@ -0,0 +1,15 @@
+-- Per-user, per-category storage accounting and optional per-user quota override
+CREATE TABLE IF NOT EXISTS storage_usage (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    category TEXT NOT NULL,
+    bytes BIGINT NOT NULL DEFAULT 0,
+    files INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, category)
+);
+
+CREATE INDEX IF NOT EXISTS idx_storage_usage_bytes ON storage_usage(bytes DESC);
+
+-- NULL = use the configured default quota
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS storage_quota_bytes BIGINT;
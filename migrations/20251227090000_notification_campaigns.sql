@@ -0,0 +1,26 @@
+-- Bulk/segmented notices: one campaign row, one notification row per recipient.
+CREATE TABLE IF NOT EXISTS notification_campaigns (
+    id SERIAL PRIMARY KEY,
+    sender_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    -- stored once for all recipients; their notification rows leave attachment_path NULL
+    attachment_path TEXT,
+    audience JSONB NOT NULL DEFAULT '{}'::jsonb,
+    status TEXT NOT NULL DEFAULT 'pending', -- pending | sending | completed | failed
+    total_recipients INTEGER NOT NULL DEFAULT 0,
+    delivered INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_notification_campaigns_sender ON notification_campaigns(sender_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_notification_campaigns_status ON notification_campaigns(status);
+
+ALTER TABLE notifications
+    ADD COLUMN IF NOT EXISTS campaign_id INTEGER REFERENCES notification_campaigns(id) ON DELETE CASCADE;
+
+-- one notice per recipient per campaign, so an interrupted fan-out can resume safely
+CREATE UNIQUE INDEX IF NOT EXISTS idx_notifications_campaign_user ON notifications(campaign_id, user_id) WHERE campaign_id IS NOT NULL;
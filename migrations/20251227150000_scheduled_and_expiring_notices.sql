@@ -0,0 +1,12 @@
+-- Scheduled notices are campaigns in status 'scheduled' until send_at;
+-- the scheduler then moves them to 'pending' for delivery.
+ALTER TABLE notification_campaigns
+    ADD COLUMN IF NOT EXISTS send_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS cancelled_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_notification_campaigns_due ON notification_campaigns(send_at) WHERE status = 'scheduled';
+
+-- Expired notices are hidden from users but kept for the sender's records
+ALTER TABLE notifications
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
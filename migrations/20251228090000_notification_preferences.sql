@@ -0,0 +1,28 @@
+-- Per-user, per-category delivery preferences and email digests.
+ALTER TABLE notifications
+    ADD COLUMN IF NOT EXISTS category TEXT NOT NULL DEFAULT 'announcement',
+    -- set once the notice went out by immediate email / in a digest
+    ADD COLUMN IF NOT EXISTS emailed_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS digested_at TIMESTAMPTZ;
+
+ALTER TABLE notification_campaigns
+    ADD COLUMN IF NOT EXISTS category TEXT NOT NULL DEFAULT 'announcement';
+
+-- mode: in_app | email (in-app + immediate email) | digest (in-app + daily email) | muted
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    category TEXT NOT NULL,
+    mode TEXT NOT NULL CHECK (mode IN ('in_app', 'email', 'digest', 'muted')),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, category)
+);
+
+CREATE TABLE IF NOT EXISTS notification_digests (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    notification_count INTEGER NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_notification_digests_user ON notification_digests(user_id, sent_at DESC);
+CREATE INDEX IF NOT EXISTS idx_notifications_digest_pending ON notifications(user_id) WHERE NOT read AND digested_at IS NULL AND emailed_at IS NULL;
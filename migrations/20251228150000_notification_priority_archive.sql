@@ -0,0 +1,24 @@
+-- Priority, per-user archive/delete, and indexes for cursor pagination.
+ALTER TABLE notifications
+    ADD COLUMN IF NOT EXISTS priority TEXT NOT NULL DEFAULT 'normal',
+    ADD COLUMN IF NOT EXISTS archived_at TIMESTAMPTZ,
+    -- deleted by the recipient; kept so campaign stats stay intact
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+ALTER TABLE notification_campaigns
+    ADD COLUMN IF NOT EXISTS priority TEXT NOT NULL DEFAULT 'normal';
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'notifications_category_check') THEN
+        ALTER TABLE notifications ADD CONSTRAINT notifications_category_check
+            CHECK (category IN ('system', 'booking', 'payment', 'kyc', 'support', 'announcement'));
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'notifications_priority_check') THEN
+        ALTER TABLE notifications ADD CONSTRAINT notifications_priority_check
+            CHECK (priority IN ('low', 'normal', 'high', 'urgent'));
+    END IF;
+END $$;
+
+CREATE INDEX IF NOT EXISTS idx_notifications_user_id_desc ON notifications(user_id, id DESC) WHERE deleted_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_notifications_user_unread ON notifications(user_id) WHERE NOT read AND deleted_at IS NULL AND archived_at IS NULL;
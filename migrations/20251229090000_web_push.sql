@@ -0,0 +1,20 @@
+-- Web Push subscriptions (one per browser/device) and the per-category push switch.
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    endpoint TEXT NOT NULL UNIQUE,
+    -- client keys from PushSubscription.getKey(), base64url
+    p256dh TEXT NOT NULL,
+    auth TEXT NOT NULL,
+    user_agent TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_success_at TIMESTAMPTZ,
+    -- consecutive failed deliveries other than 404/410 (those delete the row)
+    failure_count INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_push_subscriptions_user ON push_subscriptions(user_id);
+
+-- NULL = category default (see notify_prefs::default_push)
+ALTER TABLE notification_preferences
+    ADD COLUMN IF NOT EXISTS push BOOLEAN;
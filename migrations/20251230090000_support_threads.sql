@@ -0,0 +1,42 @@
+-- Support tickets become threads: messages from the user and staff, an
+-- assignee, and a fixed status workflow new -> open -> pending_user -> resolved -> closed.
+ALTER TABLE support_requests
+    ADD COLUMN IF NOT EXISTS subject TEXT,
+    ADD COLUMN IF NOT EXISTS assignee_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS last_message_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS resolved_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS closed_at TIMESTAMPTZ;
+
+-- the old two-state flag: done meant handled
+UPDATE support_requests SET status = 'closed', closed_at = COALESCE(closed_at, updated_at) WHERE status = 'done';
+UPDATE support_requests SET status = 'new' WHERE status NOT IN ('new', 'open', 'pending_user', 'resolved', 'closed');
+UPDATE support_requests SET last_message_at = created_at WHERE last_message_at IS NULL;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'support_requests_status_check') THEN
+        ALTER TABLE support_requests ADD CONSTRAINT support_requests_status_check
+            CHECK (status IN ('new', 'open', 'pending_user', 'resolved', 'closed'));
+    END IF;
+END $$;
+
+CREATE TABLE IF NOT EXISTS support_messages (
+    id SERIAL PRIMARY KEY,
+    request_id INTEGER NOT NULL REFERENCES support_requests(id) ON DELETE CASCADE,
+    author_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    -- user: the ticket owner; staff: an admin or agent
+    author_kind TEXT NOT NULL CHECK (author_kind IN ('user', 'staff')),
+    body TEXT NOT NULL,
+    attachment_path TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_support_messages_request ON support_messages(request_id, id);
+CREATE INDEX IF NOT EXISTS idx_support_requests_user ON support_requests(user_id, last_message_at DESC);
+CREATE INDEX IF NOT EXISTS idx_support_requests_assignee ON support_requests(assignee_id) WHERE assignee_id IS NOT NULL;
+
+-- the opening message of existing tickets
+INSERT INTO support_messages (request_id, author_id, author_kind, body, attachment_path, created_at)
+SELECT sr.id, sr.user_id, 'user', sr.body, sr.attachment_path, sr.created_at
+FROM support_requests sr
+WHERE NOT EXISTS (SELECT 1 FROM support_messages m WHERE m.request_id = sr.id);
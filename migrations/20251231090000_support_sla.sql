@@ -0,0 +1,50 @@
+-- Support priorities, SLA timers and canned replies.
+ALTER TABLE support_requests
+    ADD COLUMN IF NOT EXISTS priority TEXT NOT NULL DEFAULT 'normal',
+    ADD COLUMN IF NOT EXISTS first_response_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS first_response_due_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS resolution_due_at TIMESTAMPTZ,
+    -- the resolution clock stops while the ticket waits for the user
+    ADD COLUMN IF NOT EXISTS sla_paused_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS sla_paused_seconds BIGINT NOT NULL DEFAULT 0,
+    -- set once when the breach is detected and escalated
+    ADD COLUMN IF NOT EXISTS first_response_breached_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS resolution_breached_at TIMESTAMPTZ;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'support_requests_priority_check') THEN
+        ALTER TABLE support_requests ADD CONSTRAINT support_requests_priority_check
+            CHECK (priority IN ('low', 'normal', 'high', 'urgent'));
+    END IF;
+END $$;
+
+-- existing tickets: first staff answer, and due times from the default policy
+UPDATE support_requests sr SET first_response_at = (
+    SELECT MIN(m.created_at) FROM support_messages m WHERE m.request_id = sr.id AND m.author_kind = 'staff'
+) WHERE first_response_at IS NULL;
+UPDATE support_requests SET
+    first_response_due_at = created_at + interval '24 hours',
+    resolution_due_at = created_at + interval '72 hours'
+WHERE first_response_due_at IS NULL;
+UPDATE support_requests SET sla_paused_at = COALESCE(last_message_at, updated_at) WHERE status = 'pending_user' AND sla_paused_at IS NULL;
+-- do not escalate history
+UPDATE support_requests SET first_response_breached_at = first_response_due_at
+WHERE first_response_due_at < now() AND first_response_at IS NULL AND first_response_breached_at IS NULL;
+UPDATE support_requests SET resolution_breached_at = resolution_due_at
+WHERE resolution_due_at < now() AND status NOT IN ('resolved', 'closed') AND resolution_breached_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_support_requests_first_response_due ON support_requests(first_response_due_at)
+    WHERE first_response_at IS NULL AND first_response_breached_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_support_requests_resolution_due ON support_requests(resolution_due_at)
+    WHERE resolution_breached_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS support_canned_replies (
+    id SERIAL PRIMARY KEY,
+    title TEXT NOT NULL,
+    -- may contain {{user_name}}, {{first_name}}, {{kyc_status}}, {{ticket_id}}, {{subject}}, {{agent_name}}
+    body TEXT NOT NULL,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
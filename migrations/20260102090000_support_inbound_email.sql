@@ -0,0 +1,18 @@
+-- Inbound support mail, one row per message received (maildir, SMTP, CLI or
+-- the admin endpoint). dedupe_key is the Message-ID, or a hash of the raw
+-- message, so re-delivered mail is filed only once.
+CREATE TABLE IF NOT EXISTS support_inbound_emails (
+    id SERIAL PRIMARY KEY,
+    dedupe_key TEXT NOT NULL UNIQUE,
+    message_id TEXT,
+    source TEXT NOT NULL,
+    from_address TEXT,
+    subject TEXT,
+    -- processing, created, replied or rejected
+    outcome TEXT NOT NULL,
+    ticket_id INTEGER REFERENCES support_requests(id) ON DELETE SET NULL,
+    detail TEXT,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_support_inbound_emails_received ON support_inbound_emails(received_at DESC);
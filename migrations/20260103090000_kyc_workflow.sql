@@ -0,0 +1,58 @@
+-- Strict KYC review workflow (services/kyc.rs): reasons, partial resubmission and history.
+ALTER TABLE teacher_verifications
+    ADD COLUMN IF NOT EXISTS rejection_reasons TEXT[] NOT NULL DEFAULT '{}',
+    -- items asked for again by a needs_more_info decision, and those resent since
+    ADD COLUMN IF NOT EXISTS requested_items TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS resent_items TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS submitted_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS review_started_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS decided_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS submission_count INTEGER NOT NULL DEFAULT 0;
+
+-- free-form statuses from the old decision endpoint ("pending" and anything else) wait for review
+UPDATE teacher_verifications SET status = 'submitted'
+WHERE status NOT IN ('draft', 'submitted', 'under_review', 'approved', 'rejected', 'needs_more_info');
+UPDATE teacher_verifications SET submitted_at = created_at, submission_count = 1 WHERE submitted_at IS NULL AND status <> 'draft';
+UPDATE teacher_verifications SET decided_at = COALESCE(updated_at, created_at)
+WHERE decided_at IS NULL AND status IN ('approved', 'rejected');
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'teacher_verifications_status_check') THEN
+        ALTER TABLE teacher_verifications ADD CONSTRAINT teacher_verifications_status_check
+            CHECK (status IN ('draft', 'submitted', 'under_review', 'approved', 'rejected', 'needs_more_info'));
+    END IF;
+END $$;
+
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_user ON teacher_verifications(user_id, id DESC);
+
+CREATE TABLE IF NOT EXISTS kyc_status_history (
+    id SERIAL PRIMARY KEY,
+    verification_id INTEGER NOT NULL REFERENCES teacher_verifications(id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    -- NULL for system changes
+    actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    reasons TEXT[] NOT NULL DEFAULT '{}',
+    -- requested items for needs_more_info, resent items for a resubmission
+    items TEXT[] NOT NULL DEFAULT '{}',
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_kyc_status_history_verification ON kyc_status_history(verification_id, id);
+
+-- existing cases start their history in their current state
+INSERT INTO kyc_status_history (verification_id, from_status, to_status, actor_id, note, created_at)
+SELECT tv.id, NULL, tv.status, u.id, NULLIF(tv.admin_note, ''), COALESCE(tv.updated_at, tv.created_at, now())
+FROM teacher_verifications tv
+LEFT JOIN users u ON u.id = tv.reviewed_by
+WHERE NOT EXISTS (SELECT 1 FROM kyc_status_history h WHERE h.verification_id = tv.id);
+
+-- kyc_verified follows the latest case from now on
+UPDATE users u SET
+    kyc_verified = (l.status = 'approved'),
+    kyc_verified_at = CASE WHEN l.status = 'approved' THEN COALESCE(u.kyc_verified_at, l.decided_at) END
+FROM (SELECT DISTINCT ON (user_id) user_id, status, decided_at FROM teacher_verifications ORDER BY user_id, id DESC) l
+WHERE l.user_id = u.id;
+UPDATE users SET kyc_verified = FALSE, kyc_verified_at = NULL
+WHERE kyc_verified AND NOT EXISTS (SELECT 1 FROM teacher_verifications tv WHERE tv.user_id = users.id);
@@ -0,0 +1,15 @@
+-- KYC review queue: claims with a timeout, risk flags and four-eyes approval.
+ALTER TABLE teacher_verifications
+    ADD COLUMN IF NOT EXISTS claimed_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS claim_expires_at TIMESTAMPTZ,
+    -- set at submission; any flag makes the case high risk
+    ADD COLUMN IF NOT EXISTS risk_flags TEXT[] NOT NULL DEFAULT '{}',
+    -- first of two approvals on a high-risk case
+    ADD COLUMN IF NOT EXISTS first_approved_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS first_approved_at TIMESTAMPTZ;
+
+-- cases already under review have no claim; put them back in the queue
+UPDATE teacher_verifications SET status = 'submitted' WHERE status = 'under_review' AND claimed_by IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_queue ON teacher_verifications(submitted_at) WHERE status = 'submitted';
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_claims ON teacher_verifications(claim_expires_at) WHERE status = 'under_review';
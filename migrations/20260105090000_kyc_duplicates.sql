@@ -0,0 +1,30 @@
+-- Duplicate identity detection across KYC cases (services/kyc_duplicates.rs).
+ALTER TABLE teacher_verifications
+    -- 64-bit difference hashes of the ID images, NULL when not hashed (yet)
+    ADD COLUMN IF NOT EXISTS front_phash BIGINT,
+    ADD COLUMN IF NOT EXISTS back_phash BIGINT,
+    -- normalized full_name and address used for matching
+    ADD COLUMN IF NOT EXISTS name_key TEXT,
+    ADD COLUMN IF NOT EXISTS address_key TEXT,
+    ADD COLUMN IF NOT EXISTS fingerprinted_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_name_key ON teacher_verifications(name_key, dob);
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_address_key ON teacher_verifications(address_key);
+
+-- number of differing bits between two hashes, NULL if either is missing
+CREATE OR REPLACE FUNCTION phash_distance(a BIGINT, b BIGINT) RETURNS INTEGER AS $$
+    SELECT length(replace(((a # b)::bit(64))::text, '0', ''))
+$$ LANGUAGE sql IMMUTABLE;
+
+-- likely duplicates, stored in both directions
+CREATE TABLE IF NOT EXISTS kyc_duplicate_matches (
+    verification_id INTEGER NOT NULL REFERENCES teacher_verifications(id) ON DELETE CASCADE,
+    match_id INTEGER NOT NULL REFERENCES teacher_verifications(id) ON DELETE CASCADE,
+    -- same_document, same_name_dob, same_address
+    signals TEXT[] NOT NULL,
+    -- closest distance between the two cases' ID images
+    distance INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (verification_id, match_id)
+);
+CREATE INDEX IF NOT EXISTS idx_kyc_duplicate_matches_match ON kyc_duplicate_matches(match_id);
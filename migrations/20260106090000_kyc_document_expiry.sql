@@ -0,0 +1,15 @@
+-- ID document details and expiry-driven re-verification (services/kyc_expiry.rs).
+ALTER TABLE teacher_verifications
+    ADD COLUMN IF NOT EXISTS doc_type TEXT,
+    -- HMAC-SHA256 of the normalized document number; the number itself is not kept
+    ADD COLUMN IF NOT EXISTS doc_number_hash TEXT,
+    ADD COLUMN IF NOT EXISTS doc_expires_on DATE,
+    -- smallest KYC_EXPIRY_WARN_DAYS threshold the teacher was warned at
+    ADD COLUMN IF NOT EXISTS expiry_warned_days INTEGER;
+
+ALTER TABLE teacher_verifications DROP CONSTRAINT IF EXISTS teacher_verifications_status_check;
+ALTER TABLE teacher_verifications ADD CONSTRAINT teacher_verifications_status_check
+    CHECK (status IN ('draft', 'submitted', 'under_review', 'approved', 'rejected', 'needs_more_info', 'reverification_required'));
+
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_expiry ON teacher_verifications(doc_expires_on) WHERE status = 'approved';
+CREATE INDEX IF NOT EXISTS idx_teacher_verifications_doc_number ON teacher_verifications(doc_number_hash);
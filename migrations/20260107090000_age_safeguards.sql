@@ -0,0 +1,22 @@
+-- Age-aware rules (services/age.rs).
+ALTER TABLE users
+    -- under ADULT_AGE by birthday; kept current by a daily job
+    ADD COLUMN IF NOT EXISTS is_minor BOOLEAN NOT NULL DEFAULT FALSE,
+    -- birthday taken from an approved KYC case, no longer editable by the user
+    ADD COLUMN IF NOT EXISTS birthday_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    -- 'public' or 'private'; minors are always treated as private
+    ADD COLUMN IF NOT EXISTS profile_visibility TEXT NOT NULL DEFAULT 'public';
+
+UPDATE users SET is_minor = TRUE WHERE birthday IS NOT NULL AND birthday > CURRENT_DATE - interval '18 years';
+
+-- guardians responsible for a minor account
+CREATE TABLE IF NOT EXISTS guardian_links (
+    id SERIAL PRIMARY KEY,
+    minor_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    guardian_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (minor_id, guardian_id),
+    CHECK (minor_id <> guardian_id)
+);
+CREATE INDEX IF NOT EXISTS idx_guardian_links_guardian ON guardian_links(guardian_id);
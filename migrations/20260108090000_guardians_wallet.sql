@@ -0,0 +1,60 @@
+-- Coin wallets (services/wallet_service.rs) and guardian accounts
+-- (services/guardian.rs).
+CREATE TABLE IF NOT EXISTS wallets (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    balance BIGINT NOT NULL DEFAULT 0 CHECK (balance >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- every balance change, signed: credits positive, debits negative
+CREATE TABLE IF NOT EXISTS wallet_ledger (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    amount BIGINT NOT NULL CHECK (amount <> 0),
+    balance_after BIGINT NOT NULL,
+    -- adjustment, top_up_sent, top_up_received, ...
+    kind TEXT NOT NULL,
+    -- what the movement belongs to, e.g. 'user:12'
+    reference TEXT,
+    actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_wallet_ledger_user ON wallet_ledger(user_id, id DESC);
+
+-- bookings costing more than this need a guardian's approval; NULL uses
+-- GUARDIAN_SPENDING_LIMIT
+ALTER TABLE users ADD COLUMN IF NOT EXISTS guardian_spending_limit BIGINT CHECK (guardian_spending_limit >= 0);
+
+-- an invitation to link a guardian and a student, accepted by the other side
+CREATE TABLE IF NOT EXISTS guardian_invites (
+    id SERIAL PRIMARY KEY,
+    inviter_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- 'guardian' when a guardian invites a student, 'student' the other way round
+    inviter_side TEXT NOT NULL CHECK (inviter_side IN ('guardian', 'student')),
+    email TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined', 'revoked')),
+    accepted_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    decided_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS idx_guardian_invites_email ON guardian_invites(lower(email)) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_guardian_invites_inviter ON guardian_invites(inviter_id);
+
+-- spending by a linked student that waits for a guardian
+CREATE TABLE IF NOT EXISTS spending_approvals (
+    id SERIAL PRIMARY KEY,
+    minor_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    amount BIGINT NOT NULL CHECK (amount > 0),
+    -- what is being paid for, e.g. kind 'booking' and its id
+    kind TEXT NOT NULL,
+    reference_id INTEGER,
+    description TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'declined', 'cancelled')),
+    decided_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    decided_at TIMESTAMPTZ,
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_spending_approvals_minor ON spending_approvals(minor_id, status);
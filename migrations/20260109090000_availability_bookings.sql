@@ -0,0 +1,81 @@
+-- Teacher availability (services/availability.rs) and bookings
+-- (services/bookings.rs).
+
+-- IANA timezone the user sees times in, e.g. 'Europe/Berlin'
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone TEXT;
+
+-- per-teacher booking settings
+CREATE TABLE IF NOT EXISTS teacher_schedules (
+    teacher_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- IANA timezone the weekly hours and exceptions are written in
+    timezone TEXT NOT NULL DEFAULT 'UTC',
+    slot_minutes INTEGER NOT NULL DEFAULT 60 CHECK (slot_minutes BETWEEN 15 AND 480),
+    -- free time kept before and after every session
+    buffer_before_minutes INTEGER NOT NULL DEFAULT 0 CHECK (buffer_before_minutes BETWEEN 0 AND 240),
+    buffer_after_minutes INTEGER NOT NULL DEFAULT 0 CHECK (buffer_after_minutes BETWEEN 0 AND 240),
+    -- how soon and how far ahead sessions can be booked
+    min_notice_minutes INTEGER NOT NULL DEFAULT 720 CHECK (min_notice_minutes >= 0),
+    horizon_days INTEGER NOT NULL DEFAULT 60 CHECK (horizon_days BETWEEN 1 AND 365),
+    price_coins BIGINT NOT NULL DEFAULT 0 CHECK (price_coins >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- weekly recurring hours in the teacher's local time; an end of 00:00 means midnight
+CREATE TABLE IF NOT EXISTS availability_rules (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- ISO weekday, 1 = Monday .. 7 = Sunday
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    CHECK (end_time > start_time OR end_time = '00:00')
+);
+CREATE INDEX IF NOT EXISTS idx_availability_rules_teacher ON availability_rules(teacher_id, weekday);
+
+-- changes for one local date: 'available' hours replace that day's weekly
+-- hours, 'unavailable' removes the given hours (the whole day without times)
+CREATE TABLE IF NOT EXISTS availability_exceptions (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    on_date DATE NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('available', 'unavailable')),
+    start_time TIME,
+    end_time TIME,
+    note TEXT,
+    CHECK ((start_time IS NULL AND end_time IS NULL AND kind = 'unavailable')
+        OR (start_time IS NOT NULL AND end_time IS NOT NULL AND (end_time > start_time OR end_time = '00:00')))
+);
+CREATE INDEX IF NOT EXISTS idx_availability_exceptions_teacher ON availability_exceptions(teacher_id, on_date);
+
+-- absolute periods without any availability (holidays, trips)
+CREATE TABLE IF NOT EXISTS availability_blackouts (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    reason TEXT,
+    CHECK (ends_at > starts_at)
+);
+CREATE INDEX IF NOT EXISTS idx_availability_blackouts_teacher ON availability_blackouts(teacher_id, ends_at);
+
+CREATE TABLE IF NOT EXISTS bookings (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    student_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    -- awaiting_approval: held for a guardian's decision, nothing charged yet
+    status TEXT NOT NULL DEFAULT 'confirmed'
+        CHECK (status IN ('awaiting_approval', 'confirmed', 'completed', 'cancelled', 'declined')),
+    price_coins BIGINT NOT NULL DEFAULT 0,
+    -- coins taken from the student and not yet paid out or refunded
+    escrow_coins BIGINT NOT NULL DEFAULT 0 CHECK (escrow_coins >= 0),
+    approval_id INTEGER REFERENCES spending_approvals(id) ON DELETE SET NULL,
+    cancelled_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    cancel_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (ends_at > starts_at)
+);
+CREATE INDEX IF NOT EXISTS idx_bookings_teacher_time ON bookings(teacher_id, starts_at) WHERE status IN ('awaiting_approval', 'confirmed');
+CREATE INDEX IF NOT EXISTS idx_bookings_student ON bookings(student_id, starts_at DESC);
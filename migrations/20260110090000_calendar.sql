@@ -0,0 +1,34 @@
+-- iCalendar feeds, invites and imported busy times (services/calendar.rs).
+
+-- secret part of the user's subscription feed URL; NULL until first requested
+ALTER TABLE users ADD COLUMN IF NOT EXISTS calendar_token TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_calendar_token ON users(calendar_token) WHERE calendar_token IS NOT NULL;
+
+-- iCalendar SEQUENCE of the booking's event, raised on every reschedule and cancellation
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS ical_sequence INTEGER NOT NULL DEFAULT 0;
+
+-- calendar object (METHOD REQUEST/CANCEL) attached when the notice is emailed
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS ics TEXT;
+
+-- one uploaded .ics file; uploading a file with the same name replaces it
+CREATE TABLE IF NOT EXISTS calendar_imports (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    busy_count INTEGER NOT NULL DEFAULT 0,
+    skipped_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (teacher_id, name)
+);
+
+-- busy periods from imported calendars; block open slots like blackouts
+CREATE TABLE IF NOT EXISTS calendar_busy (
+    id BIGSERIAL PRIMARY KEY,
+    import_id INTEGER NOT NULL REFERENCES calendar_imports(id) ON DELETE CASCADE,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    summary TEXT,
+    CHECK (ends_at > starts_at)
+);
+CREATE INDEX IF NOT EXISTS idx_calendar_busy_teacher ON calendar_busy(teacher_id, ends_at);
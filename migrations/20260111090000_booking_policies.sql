@@ -0,0 +1,58 @@
+-- Cancellation and reschedule policies (services/policy.rs).
+
+-- platform-defined templates; teachers pick one, admins edit them
+CREATE TABLE IF NOT EXISTS booking_policies (
+    key TEXT PRIMARY KEY CHECK (key ~ '^[a-z0-9_]{1,40}$'),
+    name TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    -- student cancels at least this many hours ahead: full refund
+    full_refund_hours INTEGER NOT NULL CHECK (full_refund_hours >= 0),
+    -- at least this many hours ahead: partial_refund_percent back; later: nothing
+    partial_refund_hours INTEGER NOT NULL CHECK (partial_refund_hours >= 0),
+    partial_refund_percent INTEGER NOT NULL CHECK (partial_refund_percent BETWEEN 0 AND 100),
+    -- teacher cancels with less notice: pays teacher_penalty_percent of the price to the student
+    teacher_notice_hours INTEGER NOT NULL CHECK (teacher_notice_hours >= 0),
+    teacher_penalty_percent INTEGER NOT NULL CHECK (teacher_penalty_percent BETWEEN 0 AND 100),
+    -- reschedules a student may make per booking, and how late
+    max_reschedules INTEGER NOT NULL CHECK (max_reschedules >= 0),
+    reschedule_notice_hours INTEGER NOT NULL CHECK (reschedule_notice_hours >= 0),
+    -- retired templates stay on existing bookings but cannot be picked
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (partial_refund_hours <= full_refund_hours)
+);
+
+INSERT INTO booking_policies (key, name, description, full_refund_hours, partial_refund_hours, partial_refund_percent,
+                              teacher_notice_hours, teacher_penalty_percent, max_reschedules, reschedule_notice_hours)
+VALUES
+    ('flexible', 'Flexible', 'Full refund up to 24 hours before, half up to 2 hours before.', 24, 2, 50, 12, 10, 3, 4),
+    ('moderate', 'Moderate', 'Full refund up to 48 hours before, half up to 24 hours before.', 48, 24, 50, 24, 20, 2, 24),
+    ('strict', 'Strict', 'Full refund up to 7 days before, half up to 48 hours before.', 168, 48, 50, 48, 30, 1, 48)
+ON CONFLICT (key) DO NOTHING;
+
+ALTER TABLE teacher_schedules ADD COLUMN IF NOT EXISTS policy_key TEXT NOT NULL DEFAULT 'flexible'
+    REFERENCES booking_policies(key) ON UPDATE CASCADE;
+
+-- the policy as it was when the booking was made; later template edits don't change it
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS policy JSONB;
+-- reschedules made by the student side, limited by max_reschedules
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS reschedule_count INTEGER NOT NULL DEFAULT 0;
+
+-- every evaluated cancel or reschedule request, allowed or not
+CREATE TABLE IF NOT EXISTS booking_policy_decisions (
+    id SERIAL PRIMARY KEY,
+    booking_id INTEGER NOT NULL REFERENCES bookings(id) ON DELETE CASCADE,
+    action TEXT NOT NULL CHECK (action IN ('cancel', 'reschedule')),
+    actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    -- student covers the student's guardians
+    side TEXT NOT NULL CHECK (side IN ('student', 'teacher')),
+    policy_key TEXT,
+    minutes_before INTEGER NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    refund_coins BIGINT NOT NULL DEFAULT 0,
+    teacher_fee_coins BIGINT NOT NULL DEFAULT 0,
+    penalty_coins BIGINT NOT NULL DEFAULT 0,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_booking_policy_decisions_booking ON booking_policy_decisions(booking_id, created_at);
@@ -0,0 +1,60 @@
+-- Group sessions with capacity and waitlists (services/groups.rs).
+
+CREATE TABLE IF NOT EXISTS group_sessions (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    min_seats INTEGER NOT NULL CHECK (min_seats >= 1),
+    max_seats INTEGER NOT NULL,
+    seat_price_coins BIGINT NOT NULL DEFAULT 0 CHECK (seat_price_coins >= 0),
+    -- fewer than min_seats booked by then: cancelled with full refunds
+    cutoff_at TIMESTAMPTZ NOT NULL,
+    -- how long a waitlisted student has to claim a freed seat
+    claim_minutes INTEGER NOT NULL CHECK (claim_minutes > 0),
+    -- open: before the cutoff; confirmed: the minimum was met
+    status TEXT NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'confirmed', 'cancelled', 'completed')),
+    cancel_reason TEXT,
+    -- the teacher's cancellation policy when the session was created (see policy.rs)
+    policy JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (max_seats >= min_seats),
+    CHECK (ends_at > starts_at),
+    CHECK (cutoff_at <= starts_at)
+);
+CREATE INDEX IF NOT EXISTS idx_group_sessions_teacher_time ON group_sessions(teacher_id, starts_at) WHERE status IN ('open', 'confirmed');
+CREATE INDEX IF NOT EXISTS idx_group_sessions_upcoming ON group_sessions(starts_at) WHERE status IN ('open', 'confirmed');
+
+CREATE TABLE IF NOT EXISTS group_seats (
+    id SERIAL PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES group_sessions(id) ON DELETE CASCADE,
+    student_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- awaiting_approval: held for a guardian's decision, nothing charged yet
+    status TEXT NOT NULL CHECK (status IN ('awaiting_approval', 'booked', 'cancelled', 'declined')),
+    price_coins BIGINT NOT NULL DEFAULT 0,
+    escrow_coins BIGINT NOT NULL DEFAULT 0 CHECK (escrow_coins >= 0),
+    approval_id INTEGER REFERENCES spending_approvals(id) ON DELETE SET NULL,
+    cancel_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_group_seats_one_per_student ON group_seats(session_id, student_id)
+    WHERE status IN ('awaiting_approval', 'booked');
+CREATE INDEX IF NOT EXISTS idx_group_seats_student ON group_seats(student_id, created_at DESC);
+
+CREATE TABLE IF NOT EXISTS group_waitlist (
+    id SERIAL PRIMARY KEY,
+    session_id INTEGER NOT NULL REFERENCES group_sessions(id) ON DELETE CASCADE,
+    student_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- offered: a freed seat is kept for this student until offer_expires_at
+    status TEXT NOT NULL DEFAULT 'waiting' CHECK (status IN ('waiting', 'offered', 'claimed', 'expired', 'left')),
+    offer_expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_group_waitlist_one_per_student ON group_waitlist(session_id, student_id)
+    WHERE status IN ('waiting', 'offered');
+CREATE INDEX IF NOT EXISTS idx_group_waitlist_queue ON group_waitlist(session_id, id) WHERE status IN ('waiting', 'offered');
@@ -0,0 +1,71 @@
+-- Prepaid session packages (services/packages.rs) and recurring bookings
+-- (services/series.rs).
+
+-- what a teacher sells, e.g. 10 sessions at 15% off, valid 120 days
+CREATE TABLE IF NOT EXISTS package_offers (
+    id SERIAL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    sessions INTEGER NOT NULL CHECK (sessions BETWEEN 2 AND 100),
+    discount_percent INTEGER NOT NULL CHECK (discount_percent BETWEEN 0 AND 90),
+    -- days from purchase until unused sessions expire
+    valid_days INTEGER NOT NULL CHECK (valid_days BETWEEN 7 AND 730),
+    -- share of the value of unused sessions refunded at expiry; the rest goes to the teacher
+    unused_refund_percent INTEGER NOT NULL CHECK (unused_refund_percent BETWEEN 0 AND 100),
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_package_offers_teacher ON package_offers(teacher_id) WHERE active;
+
+-- a bought package; the coins for sessions_left sessions are held on it
+CREATE TABLE IF NOT EXISTS packages (
+    id SERIAL PRIMARY KEY,
+    offer_id INTEGER REFERENCES package_offers(id) ON DELETE SET NULL,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    student_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    sessions_total INTEGER NOT NULL,
+    -- sessions not yet booked; each booking moves session_price_coins into its escrow
+    sessions_left INTEGER NOT NULL CHECK (sessions_left >= 0),
+    session_price_coins BIGINT NOT NULL CHECK (session_price_coins > 0),
+    valid_days INTEGER NOT NULL,
+    unused_refund_percent INTEGER NOT NULL,
+    -- awaiting_approval: held for a guardian's decision, nothing charged yet
+    status TEXT NOT NULL CHECK (status IN ('awaiting_approval', 'active', 'expired', 'declined')),
+    approval_id INTEGER REFERENCES spending_approvals(id) ON DELETE SET NULL,
+    -- set when the package is paid for
+    expires_at TIMESTAMPTZ,
+    expiry_warned_at TIMESTAMPTZ,
+    sessions_expired INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_packages_student ON packages(student_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_packages_teacher ON packages(teacher_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_packages_expiry ON packages(expires_at) WHERE status = 'active';
+
+-- the same weekly time every interval_weeks, booked as it comes within the teacher's horizon
+CREATE TABLE IF NOT EXISTS booking_series (
+    id SERIAL PRIMARY KEY,
+    student_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    teacher_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- occurrences are paid from this package, else one by one from the wallet
+    package_id INTEGER REFERENCES packages(id) ON DELETE SET NULL,
+    first_starts_at TIMESTAMPTZ NOT NULL,
+    interval_weeks INTEGER NOT NULL CHECK (interval_weeks BETWEEN 1 AND 4),
+    occurrences INTEGER NOT NULL CHECK (occurrences BETWEEN 1 AND 104),
+    -- occurrences before this index have been booked or passed over
+    next_index INTEGER NOT NULL DEFAULT 0,
+    -- occurrences the student skipped before they were booked
+    skipped INTEGER[] NOT NULL DEFAULT '{}',
+    status TEXT NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'finished', 'cancelled')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_booking_series_student ON booking_series(student_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_booking_series_active ON booking_series(id) WHERE status = 'active';
+
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS package_id INTEGER REFERENCES packages(id) ON DELETE SET NULL;
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS series_id INTEGER REFERENCES booking_series(id) ON DELETE SET NULL;
+ALTER TABLE bookings ADD COLUMN IF NOT EXISTS series_index INTEGER;
+CREATE INDEX IF NOT EXISTS idx_bookings_series ON bookings(series_id, series_index) WHERE series_id IS NOT NULL;
@@ -0,0 +1,6 @@
+-- A series is 'pending' until its first booking succeeds (services/series.rs),
+-- so one whose first booking failed is never extended.
+
+ALTER TABLE booking_series DROP CONSTRAINT IF EXISTS booking_series_status_check;
+ALTER TABLE booking_series ADD CONSTRAINT booking_series_status_check
+    CHECK (status IN ('pending', 'active', 'finished', 'cancelled'));
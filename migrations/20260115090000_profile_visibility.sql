@@ -0,0 +1,16 @@
+-- How a user is named to someone else (services/age.rs). Private profiles,
+-- and minors whatever they chose, show only their first name and the initial
+-- of their last one; they themselves, their guardians and staff see the full
+-- name. `viewer` may be NULL for anonymous visitors.
+CREATE OR REPLACE FUNCTION shown_name(u users, viewer INTEGER) RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN NOT (u.is_minor OR u.profile_visibility = 'private')
+            OR u.id = viewer
+            OR EXISTS (SELECT 1 FROM guardian_links g WHERE g.minor_id = u.id AND g.guardian_id = viewer)
+            OR EXISTS (SELECT 1 FROM users v WHERE v.id = viewer AND v.role IN ('admin', 'agent'))
+        THEN u.full_name
+        WHEN btrim(u.full_name) LIKE '% %'
+        THEN split_part(btrim(u.full_name), ' ', 1) || ' ' || left(substring(btrim(u.full_name) FROM '([^ ]+)$'), 1) || '.'
+        ELSE btrim(u.full_name)
+    END
+$$ LANGUAGE sql STABLE;
@@ -1,14 +1,8 @@
 use actix_files::NamedFile;
 use actix_session::Session;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse, Responder};
 use actix_multipart::Multipart;
 use futures_util::StreamExt;
-use tokio::fs::File as TokioFile;
-use tokio::io::AsyncWriteExt;
-use uuid::Uuid;
-use std::fs;
-use std::path::PathBuf;
-use sanitize_filename::sanitize;
 use chrono::{NaiveDate, NaiveDateTime, DateTime, Utc};
 use serde::Deserialize;
 use serde_json::json;
@@ -18,10 +12,11 @@ use rand_core::OsRng;
 use argon2::Argon2;
 use password_hash::{SaltString, PasswordHasher};
 
+use crate::services::{age, campaigns, kyc, kyc_queue, notify_prefs, policy, quota, resumable, signed_url, storage, wallet_service};
 use crate::POOL_DATA;
-use crate::routes::notifications::{broadcast_notification, NotificationEvent};
+use crate::routes::notifications::NotificationEvent;
 
-fn ensure_admin(session: &Session) -> Result<i32, HttpResponse> {
+pub(crate) fn ensure_admin(session: &Session) -> Result<i32, HttpResponse> {
     let user_id = session
         .get::<i32>("user_id")
         .unwrap_or(None)
@@ -40,6 +35,18 @@ fn ensure_admin(session: &Session) -> Result<i32, HttpResponse> {
     Ok(user_id)
 }
 
+/// Admins and support agents (role "agent"), for the support desk.
+pub(crate) fn ensure_staff(session: &Session) -> Result<i32, HttpResponse> {
+    let role = session.get::<String>("role").unwrap_or(None).unwrap_or_default();
+    if role == "agent" {
+        return session
+            .get::<i32>("user_id")
+            .unwrap_or(None)
+            .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})));
+    }
+    ensure_admin(session)
+}
+
 #[get("/admin")]
 async fn admin_portal(session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
     if let Err(resp) = ensure_admin(&session) {
@@ -64,11 +71,19 @@ async fn list_kyc(query: web::Query<KycQuery>, session: Session) -> impl Respond
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
 
-    let mut sql = "SELECT tv.id, tv.status, tv.created_at, tv.full_name, tv.dob, tv.gender, tv.address, tv.admin_note, u.email, u.full_name AS user_name, tv.front_id_path, tv.back_id_path FROM teacher_verifications tv JOIN users u ON tv.user_id = u.id WHERE 1=1".to_string();
+    let mut sql = "SELECT tv.id, tv.status, tv.created_at, tv.submitted_at, tv.decided_at, tv.submission_count, tv.rejection_reasons, tv.requested_items, tv.resent_items, tv.claimed_by, cu.full_name AS claimed_by_name, tv.claim_expires_at, tv.risk_flags, tv.first_approved_by, fu.full_name AS first_approved_by_name, tv.full_name, tv.dob, tv.gender, tv.address, tv.doc_type, tv.doc_number_hash IS NOT NULL AS has_doc_number, tv.doc_expires_on, tv.admin_note, u.email, u.full_name AS user_name, tv.front_id_path, tv.back_id_path,
+        (SELECT COALESCE(json_agg(json_build_object('id', o.id, 'status', o.status, 'user_id', o.user_id, 'user_email', ou.email, 'signals', m.signals, 'distance', m.distance) ORDER BY o.id), '[]'::json)
+         FROM kyc_duplicate_matches m JOIN teacher_verifications o ON o.id = m.match_id JOIN users ou ON ou.id = o.user_id
+         WHERE m.verification_id = tv.id) AS duplicates
+        FROM teacher_verifications tv JOIN users u ON tv.user_id = u.id LEFT JOIN users cu ON cu.id = tv.claimed_by LEFT JOIN users fu ON fu.id = tv.first_approved_by WHERE 1=1".to_string();
     let mut binds: Vec<String> = Vec::new();
-    if let Some(s) = &query.status {
-        sql.push_str(" AND tv.status = $1");
-        binds.push(s.to_lowercase());
+    match query.status.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        Some(s) => {
+            sql.push_str(" AND tv.status = $1");
+            binds.push(s.to_lowercase());
+        }
+        // drafts are the teacher's own until submitted
+        None => sql.push_str(" AND tv.status <> 'draft'"),
     }
     let mut search_clause = String::new();
     if let Some(search) = &query.search {
@@ -76,7 +91,7 @@ async fn list_kyc(query: web::Query<KycQuery>, session: Session) -> impl Respond
         else { sql.push_str(" AND (u.email ILIKE $2 OR u.full_name ILIKE $2)"); }
         search_clause = format!("%{}%", search);
     }
-    sql.push_str(" ORDER BY tv.created_at DESC");
+    sql.push_str(" ORDER BY COALESCE(tv.submitted_at, tv.created_at::TIMESTAMPTZ) DESC");
 
     let rows = match (binds.len(), search_clause.is_empty()) {
         (0, true) => sqlx::query(&sql).fetch_all(pool_data.get_ref()).await,
@@ -96,19 +111,46 @@ async fn list_kyc(query: web::Query<KycQuery>, session: Session) -> impl Respond
                         .map(|d| d.to_rfc3339())
                         .or_else(|_| r.try_get::<NaiveDateTime, _>("created_at").map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc).to_rfc3339()))
                         .unwrap_or_default();
+                    let submitted_at = r
+                        .try_get::<Option<DateTime<Utc>>, _>("submitted_at")
+                        .ok()
+                        .flatten()
+                        .map(|d| d.to_rfc3339())
+                        .unwrap_or(submitted_at);
+                    let id = r.get::<i32,_>("id");
+                    let front_path: Option<String> = r.try_get("front_id_path").ok();
+                    let back_path: Option<String> = r.try_get("back_id_path").ok();
+                    let reasons: Vec<String> = r.try_get("rejection_reasons").unwrap_or_default();
                     json!({
-                        "id": r.get::<i32,_>("id"),
+                        "id": id,
                         "status": r.get::<String,_>("status"),
                         "submitted_at": submitted_at,
+                        "decided_at": r.try_get::<Option<DateTime<Utc>>,_>("decided_at").ok().flatten().map(|d| d.to_rfc3339()),
+                        "submission_count": r.try_get::<i32,_>("submission_count").unwrap_or(0),
+                        "reasons": kyc::reasons_json(&reasons),
+                        "requested_items": r.try_get::<Vec<String>,_>("requested_items").unwrap_or_default(),
+                        "resent_items": r.try_get::<Vec<String>,_>("resent_items").unwrap_or_default(),
+                        "claimed_by": r.try_get::<Option<i32>,_>("claimed_by").ok().flatten(),
+                        "claimed_by_name": r.try_get::<Option<String>,_>("claimed_by_name").ok().flatten(),
+                        "claim_expires_at": r.try_get::<Option<DateTime<Utc>>,_>("claim_expires_at").ok().flatten().map(|d| d.to_rfc3339()),
+                        "risk_flags": r.try_get::<Vec<String>,_>("risk_flags").unwrap_or_default(),
+                        "first_approved_by": r.try_get::<Option<i32>,_>("first_approved_by").ok().flatten(),
+                        "first_approved_by_name": r.try_get::<Option<String>,_>("first_approved_by_name").ok().flatten(),
+                        "duplicates": r.try_get::<serde_json::Value,_>("duplicates").unwrap_or_else(|_| json!([])),
                         "full_name": r.try_get::<String,_>("full_name").unwrap_or_default(),
                         "dob": r.try_get::<NaiveDate,_>("dob").ok().map(|d| d.to_string()),
                         "gender": r.try_get::<String,_>("gender").unwrap_or_default(),
                         "address": r.try_get::<String,_>("address").unwrap_or_default(),
+                        "doc_type": r.try_get::<Option<String>,_>("doc_type").ok().flatten(),
+                        "has_doc_number": r.try_get::<bool,_>("has_doc_number").unwrap_or(false),
+                        "doc_expires_on": r.try_get::<Option<NaiveDate>,_>("doc_expires_on").ok().flatten().map(|d| d.to_string()),
                         "user_email": r.try_get::<String,_>("email").unwrap_or_default(),
                         "user_name": r.try_get::<String,_>("user_name").unwrap_or_default(),
                         "admin_note": r.try_get::<String,_>("admin_note").unwrap_or_default(),
-                        "front_url": format!("/api/admin/kyc_requests/{}/file/front", r.get::<i32,_>("id")),
-                        "back_url": format!("/api/admin/kyc_requests/{}/file/back", r.get::<i32,_>("id")),
+                        "front_url": format!("/api/admin/kyc_requests/{}/file/front", id),
+                        "back_url": format!("/api/admin/kyc_requests/{}/file/back", id),
+                        "front_signed_url": front_path.as_ref().map(|p| signed_url::sign_default("kyc-front", id, p)),
+                        "back_signed_url": back_path.as_ref().map(|p| signed_url::sign_default("kyc-back", id, p)),
                     })
                 })
                 .collect();
@@ -121,9 +163,38 @@ async fn list_kyc(query: web::Query<KycQuery>, session: Session) -> impl Respond
 #[derive(Deserialize)]
 struct DecisionPayload {
     status: String,
+    #[serde(default)]
+    reasons: Vec<String>,
+    /// Items to resend for needs_more_info; defaults come from the reasons
+    #[serde(default)]
+    items: Vec<String>,
     note: Option<String>,
 }
 
+fn refused(r: kyc_queue::Refused) -> (StatusCode, String) {
+    match r {
+        kyc_queue::Refused::NotFound => (StatusCode::NOT_FOUND, "verification not found".to_string()),
+        kyc_queue::Refused::Conflict(msg) => (StatusCode::CONFLICT, msg),
+    }
+}
+
+/// Apply one reviewer action to case `id`: claim it (status "under_review"),
+/// or decide it. Errors carry the HTTP status to answer with.
+async fn apply_decision(pool: &sqlx::PgPool, id: i32, reviewer_id: i32, payload: &DecisionPayload) -> Result<serde_json::Value, (StatusCode, String)> {
+    let status = payload.status.trim().to_lowercase();
+    let db_err = |e: sqlx::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("db: {}", e));
+    if status == "under_review" {
+        kyc_queue::claim(pool, id, reviewer_id).await.map_err(db_err)?.map_err(refused)?;
+        return Ok(json!({"status": status}));
+    }
+    let note = payload.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    let requested = kyc::validate_decision(&status, &payload.reasons, &payload.items, note).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    match kyc_queue::decide(pool, id, reviewer_id, &status, &payload.reasons, &requested, note).await.map_err(db_err)?.map_err(refused)? {
+        kyc_queue::Decided::Applied => Ok(json!({"status": status, "kyc_verified": status == "approved"})),
+        kyc_queue::Decided::FirstApproval => Ok(json!({"status": "submitted", "awaiting_second_approval": true, "kyc_verified": false})),
+    }
+}
+
 #[post("/api/admin/kyc_requests/{id}/decision")]
 async fn decide_kyc(path: web::Path<i32>, payload: web::Json<DecisionPayload>, session: Session) -> impl Responder {
     let admin_id = match ensure_admin(&session) {
@@ -131,57 +202,25 @@ async fn decide_kyc(path: web::Path<i32>, payload: web::Json<DecisionPayload>, s
         Err(resp) => return resp,
     };
 
-    let status = payload.status.trim().to_lowercase();
-    if status != "approved" && status != "rejected" && status != "pending" {
-        return HttpResponse::BadRequest().json(json!({"error": "invalid status"}));
-    }
-
     let pool_data = match POOL_DATA.get() {
         Some(p) => p,
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
 
-    let user_row = sqlx::query("SELECT user_id FROM teacher_verifications WHERE id = $1")
-        .bind(*path)
-        .fetch_optional(pool_data.get_ref())
-        .await;
-
-    let user_id: i32 = match user_row {
-        Ok(Some(r)) => r.get::<i32, _>("user_id"),
-        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "verification not found"})),
-        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
-    };
-
-    let res = sqlx::query("UPDATE teacher_verifications SET status = $1, admin_note = $2, reviewed_by = $3, updated_at = now() WHERE id = $4")
-        .bind(&status)
-        .bind(payload.note.as_deref().unwrap_or(""))
-        .bind(admin_id)
-        .bind(*path)
-        .execute(pool_data.get_ref())
-        .await;
-
-    if let Err(e) = res {
-        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
-    }
-
-    let approved = status == "approved";
-    if let Err(e) = sqlx::query("UPDATE users SET kyc_verified = $1, kyc_verified_at = CASE WHEN $1 THEN now() ELSE NULL END WHERE id = $2")
-        .bind(approved)
-        .bind(user_id)
-        .execute(pool_data.get_ref())
-        .await
-    {
-        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+    match apply_decision(pool_data.get_ref(), *path, admin_id, &payload).await {
+        Ok(mut result) => {
+            result["ok"] = json!(true);
+            HttpResponse::Ok().json(result)
+        }
+        Err((code, error)) => HttpResponse::build(code).json(json!({"error": error})),
     }
-
-    HttpResponse::Ok().json(json!({"ok": true, "status": status, "kyc_verified": approved}))
 }
 
 #[derive(Deserialize)]
 struct BulkDecisionPayload {
     ids: Vec<i32>,
-    status: String,
-    note: Option<String>,
+    #[serde(flatten)]
+    decision: DecisionPayload,
 }
 
 #[post("/api/admin/kyc_requests/bulk_decision")]
@@ -192,9 +231,12 @@ async fn bulk_decide(payload: web::Json<BulkDecisionPayload>, session: Session)
     };
 
     if payload.ids.is_empty() { return HttpResponse::BadRequest().json(json!({"error": "no ids provided"})); }
-    let status = payload.status.trim().to_lowercase();
-    if status != "approved" && status != "rejected" && status != "pending" {
-        return HttpResponse::BadRequest().json(json!({"error": "invalid status"}));
+    let status = payload.decision.status.trim().to_lowercase();
+    if status != "under_review" {
+        let note = payload.decision.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+        if let Err(e) = kyc::validate_decision(&status, &payload.decision.reasons, &payload.decision.items, note) {
+            return HttpResponse::BadRequest().json(json!({"error": e.to_string()}));
+        }
     }
 
     let pool_data = match POOL_DATA.get() {
@@ -202,29 +244,144 @@ async fn bulk_decide(payload: web::Json<BulkDecisionPayload>, session: Session)
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
 
-    let res = sqlx::query("UPDATE teacher_verifications SET status = $1, admin_note = $2, reviewed_by = $3, updated_at = now() WHERE id = ANY($4)")
-        .bind(&status)
-        .bind(payload.note.as_deref().unwrap_or(""))
-        .bind(admin_id)
-        .bind(&payload.ids)
-        .execute(pool_data.get_ref())
-        .await;
+    // each case moves on its own; ones claimed by someone else or already
+    // decided are reported back instead of being overwritten
+    let mut updated = 0;
+    let mut awaiting_second_approval = Vec::new();
+    let mut skipped = Vec::new();
+    for id in &payload.ids {
+        match apply_decision(pool_data.get_ref(), *id, admin_id, &payload.decision).await {
+            Ok(result) => {
+                updated += 1;
+                if result.get("awaiting_second_approval").is_some() {
+                    awaiting_second_approval.push(id);
+                }
+            }
+            Err((_, error)) => skipped.push(json!({"id": id, "error": error})),
+        }
+    }
 
-    if let Err(e) = res {
-        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+    HttpResponse::Ok().json(json!({
+        "ok": skipped.is_empty(),
+        "status": status,
+        "count": updated,
+        "awaiting_second_approval": awaiting_second_approval,
+        "skipped": skipped,
+    }))
+}
+
+#[get("/api/admin/kyc_requests/{id}/history")]
+async fn kyc_history(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc::history(pool_data.get_ref(), *path).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
+}
 
-    let approved = status == "approved";
-    if let Err(e) = sqlx::query("UPDATE users SET kyc_verified = $1, kyc_verified_at = CASE WHEN $1 THEN now() ELSE NULL END WHERE id IN (SELECT user_id FROM teacher_verifications WHERE id = ANY($2))")
-        .bind(approved)
-        .bind(&payload.ids)
-        .execute(pool_data.get_ref())
-        .await
-    {
-        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+#[get("/api/admin/kyc/reasons")]
+async fn kyc_reasons(session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let reasons: Vec<serde_json::Value> = kyc::REASONS
+        .iter()
+        .map(|r| json!({"code": r.code, "label": r.label, "items": r.items}))
+        .collect();
+    HttpResponse::Ok().json(json!({"reasons": reasons, "items": kyc::ITEMS, "statuses": kyc::STATUSES}))
+}
+
+#[get("/api/admin/kyc/queue")]
+async fn kyc_queue_summary(session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc_queue::summary(pool_data.get_ref(), admin_id).await {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/kyc/queue/next")]
+async fn kyc_claim_next(session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc_queue::next(pool_data.get_ref(), admin_id).await {
+        Ok(id) => HttpResponse::Ok().json(json!({"id": id, "claim_minutes": kyc_queue::claim_minutes()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/kyc_requests/{id}/claim")]
+async fn kyc_claim(path: web::Path<i32>, session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc_queue::claim(pool_data.get_ref(), *path, admin_id).await {
+        Ok(Ok(())) => HttpResponse::Ok().json(json!({"ok": true, "claim_minutes": kyc_queue::claim_minutes()})),
+        Ok(Err(r)) => {
+            let (code, error) = refused(r);
+            HttpResponse::build(code).json(json!({"error": error}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/kyc_requests/{id}/release")]
+async fn kyc_release(path: web::Path<i32>, session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc_queue::release(pool_data.get_ref(), *path, admin_id).await {
+        Ok(Ok(())) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(Err(r)) => {
+            let (code, error) = refused(r);
+            HttpResponse::build(code).json(json!({"error": error}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
+}
 
-    HttpResponse::Ok().json(json!({"ok": true, "status": status, "count": payload.ids.len(), "kyc_verified": approved}))
+#[derive(Deserialize)]
+struct RiskPayload {
+    high_risk: bool,
+}
+
+#[post("/api/admin/kyc_requests/{id}/risk")]
+async fn kyc_set_risk(path: web::Path<i32>, payload: web::Json<RiskPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    match kyc_queue::set_manual_flag(pool_data.get_ref(), *path, payload.high_risk).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(false) => HttpResponse::Conflict().json(json!({"error": "only waiting or claimed verifications can be flagged"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
 }
 
 #[get("/api/admin/kyc_requests/{id}/file/{side}")]
@@ -263,7 +420,7 @@ async fn export_kyc(session: Session) -> impl Responder {
         Some(p) => p,
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
-    let rows = sqlx::query("SELECT tv.id, tv.status, tv.created_at, tv.full_name, tv.dob, tv.gender, tv.address, u.email, u.full_name AS user_name FROM teacher_verifications tv JOIN users u ON tv.user_id = u.id ORDER BY tv.created_at DESC")
+    let rows = sqlx::query("SELECT tv.id, tv.status, tv.created_at, tv.full_name, tv.dob, tv.gender, tv.address, u.email, u.full_name AS user_name FROM teacher_verifications tv JOIN users u ON tv.user_id = u.id WHERE tv.status <> 'draft' ORDER BY tv.created_at DESC")
         .fetch_all(pool_data.get_ref())
         .await;
     match rows {
@@ -299,7 +456,7 @@ async fn list_users(session: Session) -> impl Responder {
         Some(p) => p,
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
-    let rows = sqlx::query("SELECT id, full_name, email, role, active, verified, kyc_verified, kyc_verified_at, created_at FROM users ORDER BY created_at DESC")
+    let rows = sqlx::query("SELECT u.id, u.full_name, u.email, u.role, u.active, u.verified, u.kyc_verified, u.kyc_verified_at, u.birthday, u.birthday_verified, u.is_minor, (SELECT COUNT(*) FROM guardian_links g WHERE g.minor_id = u.id) AS guardians, COALESCE((SELECT balance FROM wallets w WHERE w.user_id = u.id), 0) AS balance, u.created_at, u.storage_quota_bytes, COALESCE(su.bytes, 0)::BIGINT AS storage_used_bytes FROM users u LEFT JOIN (SELECT user_id, SUM(bytes) AS bytes FROM storage_usage GROUP BY user_id) su ON su.user_id = u.id ORDER BY u.created_at DESC")
         .fetch_all(pool_data.get_ref())
         .await;
     match rows {
@@ -314,6 +471,13 @@ async fn list_users(session: Session) -> impl Responder {
                     "verified": r.try_get::<bool,_>("verified").unwrap_or(false),
                     "kyc_verified": r.try_get::<bool,_>("kyc_verified").unwrap_or(false),
                     "kyc_verified_at": r.try_get::<NaiveDateTime,_>("kyc_verified_at").ok().map(|d| d.to_string()),
+                    "birthday": r.try_get::<Option<NaiveDate>,_>("birthday").ok().flatten().map(|d| d.to_string()),
+                    "birthday_verified": r.try_get::<bool,_>("birthday_verified").unwrap_or(false),
+                    "is_minor": r.try_get::<bool,_>("is_minor").unwrap_or(false),
+                    "guardians": r.try_get::<i64,_>("guardians").unwrap_or(0),
+                    "balance": r.try_get::<i64,_>("balance").unwrap_or(0),
+                    "storage_used_bytes": r.try_get::<i64,_>("storage_used_bytes").unwrap_or(0),
+                    "storage_quota_bytes": r.try_get::<Option<i64>,_>("storage_quota_bytes").ok().flatten().unwrap_or_else(quota::default_quota_bytes),
                     "created_at": r
                         .try_get::<DateTime<Utc>, _>("created_at")
                         .map(|d| d.to_rfc3339())
@@ -334,10 +498,19 @@ struct RolePayload { role: String }
 async fn update_role(path: web::Path<i32>, payload: web::Json<RolePayload>, session: Session) -> impl Responder {
     if let Err(resp) = ensure_admin(&session) { return resp; }
     let role = payload.role.trim().to_lowercase();
-    if role != "admin" && role != "teacher" && role != "student" {
+    if role != "admin" && role != "agent" && role != "teacher" && role != "student" && role != "guardian" {
         return HttpResponse::BadRequest().json(json!({"error": "invalid role"}));
     }
     let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    if role == "teacher" {
+        match age::standing(pool_data.get_ref(), *path).await {
+            Ok(Some(s)) if !s.may_teach() => {
+                return HttpResponse::Conflict().json(json!({"error": format!("teachers must be at least {} years old", age::adult_age())}));
+            }
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+            _ => {}
+        }
+    }
     let res = sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
         .bind(&role)
         .bind(*path)
@@ -349,6 +522,132 @@ async fn update_role(path: web::Path<i32>, payload: web::Json<RolePayload>, sess
     }
 }
 
+#[get("/api/admin/users/{id}/guardians")]
+async fn list_guardians(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match age::guardians(pool_data.get_ref(), *path).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct GuardianPayload { email: String }
+
+// Link an existing account as guardian of user {id}.
+#[post("/api/admin/users/{id}/guardians")]
+async fn add_guardian(path: web::Path<i32>, payload: web::Json<GuardianPayload>, session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let guardian = sqlx::query("SELECT id FROM users WHERE lower(email) = lower($1)")
+        .bind(payload.email.trim())
+        .fetch_optional(pool_data.get_ref())
+        .await;
+    let guardian_id: i32 = match guardian {
+        Ok(Some(r)) => r.get("id"),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "no account with that email"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match age::link_guardian(pool_data.get_ref(), *path, guardian_id, Some(admin_id)).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"ok": true, "guardian_id": guardian_id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/users/{id}/guardians/{guardian_id}/delete")]
+async fn remove_guardian(path: web::Path<(i32, i32)>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let (minor_id, guardian_id) = path.into_inner();
+    match age::unlink_guardian(pool_data.get_ref(), minor_id, guardian_id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "not linked"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/admin/users/{id}/wallet")]
+async fn get_wallet(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let balance = match wallet_service::balance(pool, *path).await {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match wallet_service::history(pool, *path, 100).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"balance": balance, "items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct WalletAdjustPayload { amount: i64, note: String }
+
+// Credit (positive) or debit (negative) a wallet by hand, with a reason.
+#[post("/api/admin/users/{id}/wallet")]
+async fn adjust_wallet(path: web::Path<i32>, payload: web::Json<WalletAdjustPayload>, session: Session) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let note = payload.note.trim();
+    if note.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "a note is required"}));
+    }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let mut tx = match pool_data.get_ref().begin().await {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let entry = wallet_service::Entry { kind: "adjustment", actor_id: Some(admin_id), note: Some(note), ..Default::default() };
+    let balance = match wallet_service::post(&mut tx, *path, payload.amount, &entry).await {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    };
+    if let Err(e) = tx.commit().await {
+        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+    }
+    HttpResponse::Ok().json(json!({"ok": true, "balance": balance}))
+}
+
+// Cancellation policy templates, including retired ones.
+#[get("/api/admin/policies")]
+async fn list_policies(session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match policy::templates(pool_data.get_ref(), true).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items.iter().map(|p| p.to_json()).collect::<Vec<_>>()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// Create or update a template by key; existing bookings keep the terms they were made under.
+#[post("/api/admin/policies")]
+async fn save_policy(payload: web::Json<policy::Policy>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match policy::save(pool_data.get_ref(), &payload).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Why a booking's cancel and reschedule requests came out as they did.
+#[get("/api/admin/bookings/{id}/decisions")]
+async fn booking_decisions(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match policy::decisions(pool_data.get_ref(), *path).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
 #[derive(Deserialize)]
 struct ActivePayload { active: bool }
 
@@ -388,6 +687,84 @@ async fn reset_password(path: web::Path<i32>, session: Session) -> impl Responde
     }
 }
 
+#[derive(Deserialize)]
+struct QuotaPayload { quota_bytes: Option<i64> }
+
+// Set a per-user quota override; null resets to the configured default
+#[post("/api/admin/users/{id}/quota")]
+async fn update_quota(path: web::Path<i32>, payload: web::Json<QuotaPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    if payload.quota_bytes.map(|q| q < 0).unwrap_or(false) {
+        return HttpResponse::BadRequest().json(json!({"error": "quota_bytes must be >= 0"}));
+    }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let res = sqlx::query("UPDATE users SET storage_quota_bytes = $1 WHERE id = $2")
+        .bind(payload.quota_bytes)
+        .bind(*path)
+        .execute(pool_data.get_ref())
+        .await;
+    match res {
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true, "quota_bytes": payload.quota_bytes.unwrap_or_else(quota::default_quota_bytes)})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct StorageReportQuery { limit: Option<i64> }
+
+// Biggest storage consumers with a per-category breakdown
+#[get("/api/admin/storage/report")]
+async fn storage_report(query: web::Query<StorageReportQuery>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let limit = query.limit.unwrap_or(20).clamp(1, 200);
+
+    let rows = sqlx::query("SELECT u.id, u.full_name, u.email, u.role, u.storage_quota_bytes,
+            SUM(su.bytes)::BIGINT AS total_bytes, SUM(su.files)::BIGINT AS total_files,
+            json_object_agg(su.category, su.bytes) AS by_category
+        FROM storage_usage su JOIN users u ON u.id = su.user_id
+        GROUP BY u.id
+        HAVING SUM(su.bytes) > 0
+        ORDER BY total_bytes DESC
+        LIMIT $1")
+        .bind(limit)
+        .fetch_all(pool_data.get_ref())
+        .await;
+
+    match rows {
+        Ok(list) => {
+            let mapped: Vec<serde_json::Value> = list.into_iter().map(|r| {
+                let quota_bytes = r.try_get::<Option<i64>,_>("storage_quota_bytes").ok().flatten().unwrap_or_else(quota::default_quota_bytes);
+                let total: i64 = r.try_get("total_bytes").unwrap_or(0);
+                json!({
+                    "id": r.get::<i32,_>("id"),
+                    "full_name": r.get::<String,_>("full_name"),
+                    "email": r.get::<String,_>("email"),
+                    "role": r.try_get::<String,_>("role").unwrap_or_else(|_| "student".to_string()),
+                    "used_bytes": total,
+                    "files": r.try_get::<i64,_>("total_files").unwrap_or(0),
+                    "quota_bytes": quota_bytes,
+                    "percent_used": if quota_bytes > 0 { (total as f64 / quota_bytes as f64 * 1000.0).round() / 10.0 } else { 100.0 },
+                    "by_category": r.try_get::<serde_json::Value,_>("by_category").unwrap_or(serde_json::Value::Null),
+                })
+            }).collect();
+            HttpResponse::Ok().json(json!({"items": mapped}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// Rebuild usage counters from the files on disk
+#[post("/api/admin/storage/recalculate")]
+async fn storage_recalculate(session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match quota::recalculate(pool_data.get_ref()).await {
+        Ok(n) => HttpResponse::Ok().json(json!({"ok": true, "rows": n})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
 #[derive(Deserialize)]
 struct SearchUsersQuery { q: Option<String> }
 
@@ -442,189 +819,180 @@ async fn search_users(query: web::Query<SearchUsersQuery>, session: Session) ->
     }
 }
 
-#[derive(Deserialize)]
-struct SupportQuery { status: Option<String> }
-
-#[get("/api/admin/support_requests")]
-async fn list_support_requests(query: web::Query<SupportQuery>, session: Session) -> impl Responder {
-    if let Err(resp) = ensure_admin(&session) { return resp; }
-    let pool_data = match POOL_DATA.get() {
-        Some(p) => p,
-        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
-    };
+/// Text fields and attachment of a notice/campaign multipart form.
+struct NoticeForm {
+    fields: std::collections::HashMap<String, String>,
+    attachment_path: Option<String>,
+    attachment_bytes: i64,
+}
 
-    let status = query.status.clone().unwrap_or_else(|| "new".to_string());
-    let rows = sqlx::query("SELECT sr.id, sr.role, sr.kyc_status, sr.body, sr.attachment_path, sr.status, sr.created_at, u.full_name, u.email FROM support_requests sr JOIN users u ON sr.user_id = u.id WHERE sr.status = $1 ORDER BY sr.created_at DESC")
-        .bind(&status)
-        .fetch_all(pool_data.get_ref())
-        .await;
+impl NoticeForm {
+    fn text(&self, name: &str) -> Option<String> {
+        self.fields.get(name).map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+    }
 
-    match rows {
-        Ok(list) => {
-            let mapped: Vec<serde_json::Value> = list.into_iter().map(|r| {
-                let created_at = r
-                    .try_get::<DateTime<Utc>, _>("created_at")
-                    .map(|d| d.to_rfc3339())
-                    .or_else(|_| r.try_get::<NaiveDateTime, _>("created_at").map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc).to_rfc3339()))
-                    .unwrap_or_default();
-                json!({
-                    "id": r.get::<i32,_>("id"),
-                    "role": r.get::<String,_>("role"),
-                    "kyc_status": r.get::<String,_>("kyc_status"),
-                    "body": r.get::<String,_>("body"),
-                    "status": r.get::<String,_>("status"),
-                    "created_at": created_at,
-                    "user_name": r.get::<String,_>("full_name"),
-                    "user_email": r.get::<String,_>("email"),
-                    "attachment_url": r.try_get::<String,_>("attachment_path").ok().map(|_| format!("/api/admin/support_requests/{}/attachment", r.get::<i32,_>("id"))),
-                })
-            }).collect();
-            HttpResponse::Ok().json(json!({"items": mapped}))
-        }
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    /// Optional RFC 3339 timestamp field.
+    fn time(&self, name: &str) -> Result<Option<DateTime<Utc>>, HttpResponse> {
+        parse_time(name, self.text(name).as_deref())
     }
 }
 
-#[post("/api/admin/support_requests/{id}/status")]
-async fn update_support_status(path: web::Path<i32>, payload: web::Json<serde_json::Value>, session: Session) -> impl Responder {
-    if let Err(resp) = ensure_admin(&session) { return resp; }
-    let status = payload.get("status").and_then(|v| v.as_str()).unwrap_or("").to_lowercase();
-    if status != "new" && status != "done" { return HttpResponse::BadRequest().json(json!({"error":"invalid status"})); }
-
-    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
-    let res = sqlx::query("UPDATE support_requests SET status = $1, updated_at = now() WHERE id = $2")
-        .bind(&status)
-        .bind(*path)
-        .execute(pool_data.get_ref())
-        .await;
-    match res {
-        Ok(_) => HttpResponse::Ok().json(json!({"ok": true, "status": status})),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+fn parse_time(name: &str, value: Option<&str>) -> Result<Option<DateTime<Utc>>, HttpResponse> {
+    match value.map(str::trim).filter(|s| !s.is_empty()) {
+        None => Ok(None),
+        Some(s) => DateTime::parse_from_rfc3339(s)
+            .map(|d| Some(d.with_timezone(&Utc)))
+            .map_err(|_| HttpResponse::BadRequest().json(json!({"error": format!("{} must be an RFC 3339 timestamp", name)}))),
     }
 }
 
-#[get("/api/admin/support_requests/{id}/attachment")]
-async fn support_attachment(path: web::Path<i32>, session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
-    if ensure_admin(&session).is_err() {
-        return Err(actix_web::error::ErrorUnauthorized("unauthorized"));
+/// Validate `send_at`/`expires_at`. A `send_at` that is not in the future means "now".
+fn check_schedule(send_at: Option<DateTime<Utc>>, expires_at: Option<DateTime<Utc>>) -> Result<Option<DateTime<Utc>>, HttpResponse> {
+    let now = Utc::now();
+    let send_at = send_at.filter(|t| *t > now);
+    if let Some(exp) = expires_at {
+        if exp <= send_at.unwrap_or(now) {
+            return Err(HttpResponse::BadRequest().json(json!({"error": "expires_at must be after send_at"})));
+        }
     }
+    Ok(send_at)
+}
 
-    let pool_data = POOL_DATA.get().ok_or_else(|| actix_web::error::ErrorInternalServerError("no db"))?;
-    let row = sqlx::query("SELECT attachment_path FROM support_requests WHERE id = $1")
-        .bind(*path)
-        .fetch_optional(pool_data.get_ref())
-        .await
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+/// Validated content and delivery options shared by single notices and campaigns.
+struct NoticeContent {
+    title: String,
+    body: String,
+    category: String,
+    priority: String,
+    send_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+}
 
-    if let Some(r) = row {
-        let path_str: Option<String> = r.try_get("attachment_path").ok();
-        if let Some(p) = path_str {
-            let fs_path = PathBuf::from(&p);
-            if fs_path.exists() {
-                let file = NamedFile::open_async(fs_path).await?;
-                return Ok(file.into_response(&req));
-            }
+impl NoticeForm {
+    fn content(&self) -> Result<NoticeContent, HttpResponse> {
+        let title = self.text("title").ok_or_else(|| HttpResponse::BadRequest().json(json!({"error": "title required"})))?;
+        let body = self.text("body").ok_or_else(|| HttpResponse::BadRequest().json(json!({"error": "body required"})))?;
+        let category = self.text("category").unwrap_or_else(|| "announcement".to_string());
+        if !notify_prefs::CATEGORIES.contains(&category.as_str()) {
+            return Err(HttpResponse::BadRequest().json(json!({"error": format!("unknown category: {}", category)})));
+        }
+        let priority = self.text("priority").unwrap_or_else(|| "normal".to_string());
+        if !notify_prefs::PRIORITIES.contains(&priority.as_str()) {
+            return Err(HttpResponse::BadRequest().json(json!({"error": format!("unknown priority: {}", priority)})));
         }
+        let expires_at = self.time("expires_at")?;
+        let send_at = check_schedule(self.time("send_at")?, expires_at)?;
+        Ok(NoticeContent { title, body, category, priority, send_at, expires_at })
     }
-    Err(actix_web::error::ErrorNotFound("file not found"))
 }
 
-#[post("/api/admin/notifications")]
-async fn admin_send_notification(session: Session, mut payload: Multipart) -> impl Responder {
-    let admin_id = match ensure_admin(&session) {
-        Ok(id) => id,
-        Err(resp) => return resp,
-    };
+/// Read a notice form, storing `attachment` (or claiming a finished resumable
+/// `upload_id`) against the admin's notification quota.
+async fn read_notice_form(session: &Session, pool: &sqlx::PgPool, admin_id: i32, payload: &mut Multipart) -> Result<NoticeForm, HttpResponse> {
+    let mut form = NoticeForm { fields: Default::default(), attachment_path: None, attachment_bytes: 0 };
 
-    let pool_data = match POOL_DATA.get() {
-        Some(p) => p,
-        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    let quota_left = match quota::remaining(pool, admin_id, storage::NOTIFICATION.name).await {
+        Ok(n) => n,
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))),
     };
 
-    let mut target_user: Option<i32> = None;
-    let mut title: Option<String> = None;
-    let mut body: Option<String> = None;
-    let mut attachment_path: Option<String> = None;
-
-    // Ensure uploads dir exists
-    let upload_dir = std::path::Path::new("uploads/notifications");
-    if !upload_dir.exists() {
-        let _ = fs::create_dir_all(upload_dir);
-    }
-
     while let Some(field_res) = payload.next().await {
         let mut field = match field_res {
             Ok(f) => f,
-            Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("multipart error: {}", e)})),
+            Err(e) => return Err(HttpResponse::BadRequest().json(json!({"error": format!("multipart error: {}", e)}))),
         };
         let name = field.name().to_string();
         if name == "attachment" {
-            let original = field
-                .content_disposition()
-                .get_filename()
-                .map(|v| sanitize(v));
-            let safe_name = original.unwrap_or_else(|| format!("file-{}.bin", Uuid::new_v4()));
-            let full_path = upload_dir.join(format!("{}-{}", Uuid::new_v4(), safe_name));
-            let mut f = match TokioFile::create(&full_path).await {
-                Ok(file) => file,
-                Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("file create: {}", e)})),
-            };
-            while let Some(chunk) = field.next().await {
-                let data = match chunk {
-                    Ok(d) => d,
-                    Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("upload chunk: {}", e)})),
-                };
-                if let Err(e) = f.write_all(&data).await {
-                    return HttpResponse::InternalServerError().json(json!({"error": format!("write failed: {}", e)}));
+            match storage::save_field(&mut field, &storage::NOTIFICATION, quota_left).await {
+                Ok(stored) => {
+                    form.attachment_bytes = stored.bytes as i64;
+                    form.attachment_path = Some(stored.path);
                 }
+                Err(e) => return Err(e.to_response()),
             }
-            attachment_path = Some(full_path.to_string_lossy().to_string());
         } else {
             let mut bytes = Vec::new();
             while let Some(chunk) = field.next().await {
                 let data = match chunk {
                     Ok(d) => d,
-                    Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("field read: {}", e)})),
+                    Err(e) => return Err(HttpResponse::BadRequest().json(json!({"error": format!("field read: {}", e)}))),
                 };
                 bytes.extend_from_slice(&data);
             }
-            let text = String::from_utf8(bytes).unwrap_or_default();
-            match name.as_str() {
-                "user_id" => {
-                    target_user = text.trim().parse::<i32>().ok();
+            form.fields.insert(name, String::from_utf8(bytes).unwrap_or_default());
+        }
+    }
+
+    // Attachment sent earlier through the resumable upload endpoints
+    if form.attachment_path.is_none() {
+        if let Some(id) = form.text("upload_id") {
+            let uploader = session.get::<i32>("user_id").unwrap_or(None).unwrap_or(admin_id);
+            match resumable::take_completed(pool, &id, uploader, storage::NOTIFICATION.name).await {
+                Ok(Some(p)) => {
+                    form.attachment_bytes = quota::file_size(&p);
+                    form.attachment_path = Some(p);
                 }
-                "title" => title = Some(text.trim().to_string()),
-                "body" => body = Some(text.trim().to_string()),
-                _ => {}
+                Ok(None) => return Err(HttpResponse::BadRequest().json(json!({"error": "upload_id is not a completed upload"}))),
+                Err(e) => return Err(HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))),
             }
         }
     }
 
-    let user_id = match target_user {
+    Ok(form)
+}
+
+#[post("/api/admin/notifications")]
+async fn admin_send_notification(session: Session, mut payload: Multipart) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let form = match read_notice_form(&session, pool_data.get_ref(), admin_id, &mut payload).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let attachment_path = form.attachment_path.clone();
+    let attachment_bytes = form.attachment_bytes;
+
+    let user_id = match form.text("user_id").and_then(|s| s.parse::<i32>().ok()) {
         Some(id) => id,
         None => return HttpResponse::BadRequest().json(json!({"error": "user_id required"})),
     };
-    let title = match title {
-        Some(t) if !t.is_empty() => t,
-        _ => return HttpResponse::BadRequest().json(json!({"error": "title required"})),
-    };
-    let body_val = match body {
-        Some(b) if !b.is_empty() => b,
-        _ => return HttpResponse::BadRequest().json(json!({"error": "body required"})),
+    let content = match form.content() {
+        Ok(c) => c,
+        Err(resp) => return resp,
     };
 
-    let row = sqlx::query("INSERT INTO notifications (user_id, sender_id, title, body, attachment_path) VALUES ($1, $2, $3, $4, $5) RETURNING id, created_at")
+    // A future notice is held as a one-recipient scheduled campaign
+    if content.send_at.is_some() {
+        let audience = campaigns::Audience { user_ids: vec![user_id], ..Default::default() };
+        return create_campaign(pool_data.get_ref(), admin_id, &form, &content, &audience).await;
+    }
+
+    let row = sqlx::query("INSERT INTO notifications (user_id, sender_id, title, body, attachment_path, expires_at, category, priority) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id, created_at")
         .bind(user_id)
         .bind(admin_id)
-        .bind(&title)
-        .bind(&body_val)
+        .bind(&content.title)
+        .bind(&content.body)
         .bind(&attachment_path)
+        .bind(content.expires_at)
+        .bind(&content.category)
+        .bind(&content.priority)
         .fetch_one(pool_data.get_ref())
         .await;
 
     match row {
         Ok(r) => {
             let notif_id: i32 = r.get("id");
+            if attachment_path.is_some() {
+                let _ = quota::record(pool_data.get_ref(), admin_id, storage::NOTIFICATION.name, attachment_bytes, 1).await;
+            }
             let created_at = r
                 .try_get::<DateTime<Utc>, _>("created_at")
                 .unwrap_or_else(|_| {
@@ -632,34 +1000,144 @@ async fn admin_send_notification(session: Session, mut payload: Multipart) -> im
                     DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc)
                 });
             let attachment_url = attachment_path.as_ref().map(|_| format!("/api/notifications/{}/attachment", notif_id));
-            broadcast_notification(NotificationEvent {
+            let attachment_signed_url = attachment_path.as_ref().map(|p| signed_url::sign_default("notification", notif_id, p));
+            let event = NotificationEvent {
                 id: notif_id,
                 user_id,
-                title: title.clone(),
-                body: body_val.clone(),
+                title: content.title,
+                body: content.body,
                 attachment_url,
+                attachment_signed_url,
+                category: content.category,
+                priority: content.priority,
                 created_at: created_at.to_rfc3339(),
+                expires_at: content.expires_at.map(|d| d.to_rfc3339()),
                 read: false,
-            });
+            };
+            if let Err(e) = notify_prefs::dispatch(pool_data.get_ref(), vec![event]).await {
+                eprintln!("notice {} dispatch failed: {}", notif_id, e);
+            }
             HttpResponse::Ok().json(json!({"ok": true, "id": notif_id}))
         }
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
 }
 
+#[post("/api/admin/notifications/campaigns")]
+async fn admin_create_campaign(session: Session, mut payload: Multipart) -> impl Responder {
+    let admin_id = match ensure_admin(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let form = match read_notice_form(&session, pool_data.get_ref(), admin_id, &mut payload).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let audience: campaigns::Audience = match form.text("audience").map(|a| serde_json::from_str(&a)) {
+        Some(Ok(a)) => a,
+        Some(Err(e)) => return HttpResponse::BadRequest().json(json!({"error": format!("invalid audience: {}", e)})),
+        None => return HttpResponse::BadRequest().json(json!({"error": "audience required"})),
+    };
+    if audience.is_empty() && !audience.all {
+        return HttpResponse::BadRequest().json(json!({"error": "audience has no criteria (set all=true to notify everyone)"}));
+    }
+    let content = match form.content() {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
+    create_campaign(pool_data.get_ref(), admin_id, &form, &content, &audience).await
+}
+
+/// Insert a campaign and start delivery now, or leave it for the scheduler when `send_at` is set.
+async fn create_campaign(pool: &sqlx::PgPool, admin_id: i32, form: &NoticeForm, content: &NoticeContent, audience: &campaigns::Audience) -> HttpResponse {
+    let send_at = content.send_at;
+    let status = if send_at.is_some() { "scheduled" } else { "pending" };
+    let row = sqlx::query("INSERT INTO notification_campaigns (sender_id, title, body, attachment_path, audience, status, send_at, expires_at, category, priority) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id")
+        .bind(admin_id)
+        .bind(&content.title)
+        .bind(&content.body)
+        .bind(&form.attachment_path)
+        .bind(sqlx::types::Json(audience))
+        .bind(status)
+        .bind(send_at)
+        .bind(content.expires_at)
+        .bind(&content.category)
+        .bind(&content.priority)
+        .fetch_one(pool)
+        .await;
+
+    match row {
+        Ok(r) => {
+            let campaign_id: i32 = r.get("id");
+            if form.attachment_path.is_some() {
+                let _ = quota::record(pool, admin_id, storage::NOTIFICATION.name, form.attachment_bytes, 1).await;
+            }
+            if send_at.is_none() {
+                campaigns::spawn_delivery(pool.clone(), campaign_id);
+            }
+            HttpResponse::Accepted().json(json!({"ok": true, "campaign_id": campaign_id, "status": status, "send_at": send_at.map(|d| d.to_rfc3339())}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+fn campaign_json(r: &sqlx::postgres::PgRow) -> serde_json::Value {
+    let id: i32 = r.get("id");
+    let total: i32 = r.get("total_recipients");
+    let delivered: i32 = r.get("delivered");
+    let read_count: i64 = r.get("read_count");
+    let attachment_path: Option<String> = r.try_get("attachment_path").ok();
+    json!({
+        "kind": "campaign",
+        "campaign_id": id,
+        "title": r.get::<String,_>("title"),
+        "body": r.get::<String,_>("body"),
+        "has_attachment": attachment_path.is_some(),
+        "audience": r.get::<serde_json::Value,_>("audience"),
+        "category": r.get::<String,_>("category"),
+        "priority": r.get::<String,_>("priority"),
+        "status": r.get::<String,_>("status"),
+        "error": r.try_get::<Option<String>,_>("error").ok().flatten(),
+        "total_recipients": total,
+        "delivered": delivered,
+        "progress": if total > 0 { delivered as f64 / total as f64 } else if r.get::<String,_>("status") == "completed" { 1.0 } else { 0.0 },
+        "read_count": read_count,
+        "read_rate": if delivered > 0 { read_count as f64 / delivered as f64 } else { 0.0 },
+        "created_at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+        "send_at": r.try_get::<Option<DateTime<Utc>>,_>("send_at").ok().flatten().map(|d| d.to_rfc3339()),
+        "expires_at": r.try_get::<Option<DateTime<Utc>>,_>("expires_at").ok().flatten().map(|d| d.to_rfc3339()),
+        "completed_at": r.try_get::<Option<DateTime<Utc>>,_>("completed_at").ok().flatten().map(|d| d.to_rfc3339()),
+    })
+}
+
+const CAMPAIGN_SELECT: &str = "SELECT c.id, c.title, c.body, c.attachment_path, c.audience, c.category, c.priority, c.status, c.error, c.total_recipients, c.delivered, c.created_at, c.send_at, c.expires_at, c.completed_at,
+        (SELECT COUNT(*) FROM notifications n WHERE n.campaign_id = c.id AND n.read) AS read_count
+     FROM notification_campaigns c";
+
 #[get("/api/admin/notifications")]
 async fn admin_list_notifications(session: Session) -> impl Responder {
     let admin_id = match ensure_admin(&session) { Ok(id)=>id, Err(resp)=>return resp };
     let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
 
-    let rows = sqlx::query("SELECT n.id, n.title, n.body, n.attachment_path, n.created_at, u.full_name, u.email, u.role FROM notifications n JOIN users u ON u.id = n.user_id WHERE n.sender_id = $1 ORDER BY n.created_at DESC LIMIT 100")
+    // Campaign recipients are summarized by their campaign entry instead of listed one by one
+    let rows = sqlx::query("SELECT n.id, n.title, n.body, n.attachment_path, n.category, n.priority, n.created_at, n.expires_at, u.full_name, u.email, u.role FROM notifications n JOIN users u ON u.id = n.user_id WHERE n.sender_id = $1 AND n.campaign_id IS NULL ORDER BY n.created_at DESC LIMIT 100")
+        .bind(admin_id)
+        .fetch_all(pool_data.get_ref())
+        .await;
+    let campaign_rows = sqlx::query(&format!("{} WHERE c.sender_id = $1 ORDER BY c.created_at DESC LIMIT 100", CAMPAIGN_SELECT))
         .bind(admin_id)
         .fetch_all(pool_data.get_ref())
         .await;
 
-    match rows {
-        Ok(list) => {
-            let mapped: Vec<serde_json::Value> = list.into_iter().map(|r| {
+    match (rows, campaign_rows) {
+        (Ok(list), Ok(campaign_list)) => {
+            let mut mapped: Vec<(DateTime<Utc>, serde_json::Value)> = list.into_iter().map(|r| {
                 let id: i32 = r.get("id");
                 let attachment_path: Option<String> = r.try_get("attachment_path").ok();
                 let created_at = r
@@ -668,28 +1146,252 @@ async fn admin_list_notifications(session: Session) -> impl Responder {
                         let naive: NaiveDateTime = r.get("created_at");
                         DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc)
                     });
-                json!({
+                let expires_at: Option<DateTime<Utc>> = r.try_get("expires_at").ok().flatten();
+                (created_at, json!({
+                    "kind": "notice",
+                    "expires_at": expires_at.map(|d| d.to_rfc3339()),
+                    "expired": expires_at.map(|d| d <= Utc::now()).unwrap_or(false),
                     "id": id,
                     "title": r.get::<String,_>("title"),
                     "body": r.get::<String,_>("body"),
-                    "attachment_url": attachment_path.map(|_| format!("/api/notifications/{}/attachment", id)),
+                    "category": r.get::<String,_>("category"),
+                    "priority": r.get::<String,_>("priority"),
+                    "attachment_url": attachment_path.as_ref().map(|_| format!("/api/notifications/{}/attachment", id)),
+                    "attachment_signed_url": attachment_path.as_ref().map(|p| signed_url::sign_default("notification", id, p)),
                     "created_at": created_at.to_rfc3339(),
                     "user_name": r.get::<String,_>("full_name"),
                     "user_email": r.get::<String,_>("email"),
                     "user_role": r.try_get::<String,_>("role").unwrap_or_else(|_| "student".to_string()),
-                })
+                }))
             }).collect();
-            HttpResponse::Ok().json(json!({"items": mapped}))
+            mapped.extend(campaign_list.iter().map(|r| (r.get::<DateTime<Utc>,_>("created_at"), campaign_json(r))));
+            mapped.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+            mapped.truncate(100);
+            HttpResponse::Ok().json(json!({"items": mapped.into_iter().map(|(_, v)| v).collect::<Vec<_>>()}))
+        }
+        (Err(e), _) | (_, Err(e)) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct CampaignQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    /// Only recipients that have (true) or haven't (false) read the notice
+    read: Option<bool>,
+}
+
+#[get("/api/admin/notifications/campaigns/{id}")]
+async fn admin_get_campaign(path: web::Path<i32>, query: web::Query<CampaignQuery>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let campaign_id = *path;
+
+    let campaign = match sqlx::query(&format!("{} WHERE c.id = $1", CAMPAIGN_SELECT))
+        .bind(campaign_id)
+        .fetch_optional(pool_data.get_ref())
+        .await
+    {
+        Ok(Some(r)) => campaign_json(&r),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error":"not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let rows = sqlx::query(
+        "SELECT n.id, n.user_id, n.read, n.created_at, u.full_name, u.email, u.role
+         FROM notifications n JOIN users u ON u.id = n.user_id
+         WHERE n.campaign_id = $1 AND ($2::bool IS NULL OR n.read = $2)
+         ORDER BY n.id LIMIT $3 OFFSET $4",
+    )
+    .bind(campaign_id)
+    .bind(query.read)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool_data.get_ref())
+    .await;
+
+    match rows {
+        Ok(list) => {
+            let recipients: Vec<serde_json::Value> = list.into_iter().map(|r| json!({
+                "notification_id": r.get::<i32,_>("id"),
+                "user_id": r.get::<i32,_>("user_id"),
+                "user_name": r.get::<String,_>("full_name"),
+                "user_email": r.get::<String,_>("email"),
+                "user_role": r.try_get::<String,_>("role").unwrap_or_else(|_| "student".to_string()),
+                "read": r.get::<bool,_>("read"),
+                "delivered_at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+            })).collect();
+            HttpResponse::Ok().json(json!({"campaign": campaign, "recipients": recipients, "limit": limit, "offset": offset}))
         }
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
 }
 
+#[derive(Deserialize)]
+struct UpdateCampaignPayload {
+    title: Option<String>,
+    body: Option<String>,
+    send_at: Option<String>,
+    expires_at: Option<String>,
+    clear_expiry: Option<bool>,
+    audience: Option<campaigns::Audience>,
+}
+
+/// Edit a scheduled campaign. Once delivery has started only the expiry can change.
+#[post("/api/admin/notifications/campaigns/{id}/update")]
+async fn admin_update_campaign(path: web::Path<i32>, payload: web::Json<UpdateCampaignPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let campaign_id = *path;
+
+    let row = match sqlx::query("SELECT status, send_at, expires_at FROM notification_campaigns WHERE id = $1")
+        .bind(campaign_id)
+        .fetch_optional(pool_data.get_ref())
+        .await
+    {
+        Ok(Some(r)) => r,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error":"not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let status: String = row.get("status");
+    let (send_at, expires_at) = match (parse_time("send_at", payload.send_at.as_deref()), parse_time("expires_at", payload.expires_at.as_deref())) {
+        (Ok(s), Ok(e)) => (s, e),
+        (Err(resp), _) | (_, Err(resp)) => return resp,
+    };
+    let expires_at = if payload.clear_expiry.unwrap_or(false) {
+        None
+    } else {
+        expires_at.or(row.try_get("expires_at").ok().flatten())
+    };
+
+    if status != "scheduled" {
+        let only_expiry = payload.title.is_none() && payload.body.is_none() && payload.send_at.is_none() && payload.audience.is_none();
+        if !only_expiry || status == "cancelled" {
+            return HttpResponse::Conflict().json(json!({"error": format!("campaign is {}; only the expiry of a sent campaign can change", status)}));
+        }
+        let res = sqlx::query("UPDATE notification_campaigns SET expires_at = $1 WHERE id = $2")
+            .bind(expires_at)
+            .bind(campaign_id)
+            .execute(pool_data.get_ref())
+            .await
+            .and(
+                sqlx::query("UPDATE notifications SET expires_at = $1 WHERE campaign_id = $2")
+                    .bind(expires_at)
+                    .bind(campaign_id)
+                    .execute(pool_data.get_ref())
+                    .await,
+            );
+        return match res {
+            Ok(_) => HttpResponse::Ok().json(json!({"ok": true, "campaign_id": campaign_id})),
+            Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+        };
+    }
+
+    let send_at = match send_at {
+        Some(t) if t <= Utc::now() => return HttpResponse::BadRequest().json(json!({"error": "send_at must be in the future"})),
+        Some(t) => t,
+        None => row.get::<DateTime<Utc>, _>("send_at"),
+    };
+    if matches!(expires_at, Some(exp) if exp <= send_at) {
+        return HttpResponse::BadRequest().json(json!({"error": "expires_at must be after send_at"}));
+    }
+    if let Some(a) = &payload.audience {
+        if a.is_empty() && !a.all {
+            return HttpResponse::BadRequest().json(json!({"error": "audience has no criteria (set all=true to notify everyone)"}));
+        }
+    }
+
+    // Guarded on status so an edit racing the scheduler can't change a notice mid-delivery
+    let res = sqlx::query(
+        "UPDATE notification_campaigns
+         SET title = COALESCE(NULLIF($1, ''), title), body = COALESCE(NULLIF($2, ''), body),
+             send_at = $3, expires_at = $4, audience = COALESCE($5, audience)
+         WHERE id = $6 AND status = 'scheduled'",
+    )
+    .bind(payload.title.as_deref().map(str::trim))
+    .bind(payload.body.as_deref().map(str::trim))
+    .bind(send_at)
+    .bind(expires_at)
+    .bind(payload.audience.as_ref().map(sqlx::types::Json))
+    .bind(campaign_id)
+    .execute(pool_data.get_ref())
+    .await;
+
+    match res {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::Conflict().json(json!({"error": "campaign is no longer scheduled"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true, "campaign_id": campaign_id, "send_at": send_at.to_rfc3339()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+/// Cancel a scheduled campaign before it is sent. The record is kept; its attachment is released.
+#[post("/api/admin/notifications/campaigns/{id}/cancel")]
+async fn admin_cancel_campaign(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let campaign_id = *path;
+
+    let row = sqlx::query(
+        "UPDATE notification_campaigns c SET status = 'cancelled', cancelled_at = now(), attachment_path = NULL
+         FROM (SELECT id, attachment_path FROM notification_campaigns WHERE id = $1 FOR UPDATE) old
+         WHERE c.id = old.id AND c.status = 'scheduled'
+         RETURNING c.sender_id, old.attachment_path",
+    )
+    .bind(campaign_id)
+    .fetch_optional(pool_data.get_ref())
+    .await;
+    let (sender, attachment): (Option<i32>, Option<String>) = match row {
+        Ok(Some(r)) => (r.try_get("sender_id").ok().flatten(), r.try_get("attachment_path").ok()),
+        Ok(None) => return HttpResponse::Conflict().json(json!({"error":"campaign not found or not scheduled"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+
+    if let Some(path) = attachment {
+        if let Some(sender) = sender {
+            let _ = quota::record(pool_data.get_ref(), sender, storage::NOTIFICATION.name, -quota::file_size(&path), -1).await;
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    HttpResponse::Ok().json(json!({"ok": true, "campaign_id": campaign_id, "status": "cancelled"}))
+}
+
+#[post("/api/admin/notifications/campaigns/{id}/delete")]
+async fn admin_delete_campaign(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let campaign_id = *path;
+
+    // Recipient notices go with it (ON DELETE CASCADE); a running fan-out stops at its next batch
+    let row = sqlx::query("DELETE FROM notification_campaigns WHERE id = $1 RETURNING sender_id, attachment_path")
+        .bind(campaign_id)
+        .fetch_optional(pool_data.get_ref())
+        .await;
+    let (sender, attachment): (Option<i32>, Option<String>) = match row {
+        Ok(Some(r)) => (r.try_get("sender_id").ok().flatten(), r.try_get("attachment_path").ok()),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error":"not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+
+    if let Some(path) = attachment {
+        if let Some(sender) = sender {
+            let _ = quota::record(pool_data.get_ref(), sender, storage::NOTIFICATION.name, -quota::file_size(&path), -1).await;
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    HttpResponse::Ok().json(json!({"ok": true, "campaign_id": campaign_id}))
+}
+
 #[derive(Deserialize)]
 struct UpdateNotificationPayload {
     title: Option<String>,
     body: Option<String>,
     remove_attachment: Option<bool>,
+    expires_at: Option<String>,
+    clear_expiry: Option<bool>,
 }
 
 #[post("/api/admin/notifications/{id}/update")]
@@ -698,7 +1400,7 @@ async fn admin_update_notification(path: web::Path<i32>, payload: web::Json<Upda
     let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
 
     let notif_id = *path;
-    let row = sqlx::query("SELECT attachment_path FROM notifications WHERE id = $1")
+    let row = sqlx::query("SELECT sender_id, attachment_path FROM notifications WHERE id = $1")
         .bind(notif_id)
         .fetch_optional(pool_data.get_ref())
         .await;
@@ -710,18 +1412,25 @@ async fn admin_update_notification(path: web::Path<i32>, payload: web::Json<Upda
 
     let mut new_title = payload.title.as_ref().map(|s| s.trim().to_string()).unwrap_or_default();
     let mut new_body = payload.body.as_ref().map(|s| s.trim().to_string()).unwrap_or_default();
-    if new_title.is_empty() && new_body.is_empty() && payload.remove_attachment.unwrap_or(false)==false {
+    let new_expiry = match parse_time("expires_at", payload.expires_at.as_deref()) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    let clear_expiry = payload.clear_expiry.unwrap_or(false);
+    if new_title.is_empty() && new_body.is_empty() && payload.remove_attachment.unwrap_or(false)==false && new_expiry.is_none() && !clear_expiry {
         return HttpResponse::BadRequest().json(json!({"error":"nothing to update"}));
     }
 
     let remove_attach = payload.remove_attachment.unwrap_or(false);
     let existing_attach: Option<String> = existing.try_get("attachment_path").ok();
 
-    let res = sqlx::query("UPDATE notifications SET title = COALESCE(NULLIF($1,''), title), body = COALESCE(NULLIF($2,''), body), attachment_path = CASE WHEN $3 THEN NULL ELSE attachment_path END WHERE id = $4")
+    let res = sqlx::query("UPDATE notifications SET title = COALESCE(NULLIF($1,''), title), body = COALESCE(NULLIF($2,''), body), attachment_path = CASE WHEN $3 THEN NULL ELSE attachment_path END, expires_at = CASE WHEN $5 THEN NULL ELSE COALESCE($6, expires_at) END WHERE id = $4")
         .bind(&new_title)
         .bind(&new_body)
         .bind(remove_attach)
         .bind(notif_id)
+        .bind(clear_expiry)
+        .bind(new_expiry)
         .execute(pool_data.get_ref())
         .await;
 
@@ -731,6 +1440,9 @@ async fn admin_update_notification(path: web::Path<i32>, payload: web::Json<Upda
 
     if remove_attach {
         if let Some(path) = existing_attach {
+            if let Ok(Some(sender)) = existing.try_get::<Option<i32>, _>("sender_id") {
+                let _ = quota::record(pool_data.get_ref(), sender, storage::NOTIFICATION.name, -quota::file_size(&path), -1).await;
+            }
             let _ = std::fs::remove_file(path);
         }
     }
@@ -744,12 +1456,12 @@ async fn admin_delete_notification(path: web::Path<i32>, session: Session) -> im
     let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
     let notif_id = *path;
 
-    let row = sqlx::query("SELECT attachment_path FROM notifications WHERE id = $1")
+    let row = sqlx::query("SELECT sender_id, attachment_path FROM notifications WHERE id = $1")
         .bind(notif_id)
         .fetch_optional(pool_data.get_ref())
         .await;
-    let attachment: Option<String> = match row {
-        Ok(Some(r)) => r.try_get("attachment_path").ok(),
+    let (sender, attachment): (Option<i32>, Option<String>) = match row {
+        Ok(Some(r)) => (r.try_get("sender_id").ok().flatten(), r.try_get("attachment_path").ok()),
         Ok(None) => return HttpResponse::NotFound().json(json!({"error":"not found"})),
         Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     };
@@ -764,6 +1476,9 @@ async fn admin_delete_notification(path: web::Path<i32>, session: Session) -> im
     }
 
     if let Some(path) = attachment {
+        if let Some(sender) = sender {
+            let _ = quota::record(pool_data.get_ref(), sender, storage::NOTIFICATION.name, -quota::file_size(&path), -1).await;
+        }
         let _ = std::fs::remove_file(path);
     }
 
@@ -813,20 +1528,40 @@ pub fn init(cfg: &mut web::ServiceConfig) {
         .service(list_kyc)
         .service(decide_kyc)
         .service(bulk_decide)
+        .service(kyc_history)
+        .service(kyc_reasons)
+        .service(kyc_queue_summary)
+        .service(kyc_claim_next)
+        .service(kyc_claim)
+        .service(kyc_release)
+        .service(kyc_set_risk)
         .service(download_kyc_file)
         .service(export_kyc)
         .service(list_users)
         .service(update_role)
+        .service(list_guardians)
+        .service(add_guardian)
+        .service(remove_guardian)
+        .service(get_wallet)
+        .service(adjust_wallet)
+        .service(list_policies)
+        .service(save_policy)
+        .service(booking_decisions)
         .service(update_active)
         .service(reset_password)
+        .service(update_quota)
+        .service(storage_report)
+        .service(storage_recalculate)
         .service(impersonate)
     .service(stop_impersonate)
     .service(search_users)
         .service(admin_send_notification)
+        .service(admin_create_campaign)
         .service(admin_list_notifications)
+        .service(admin_get_campaign)
+        .service(admin_update_campaign)
+        .service(admin_cancel_campaign)
+        .service(admin_delete_campaign)
         .service(admin_update_notification)
-        .service(admin_delete_notification)
-        .service(list_support_requests)
-        .service(update_support_status)
-        .service(support_attachment);
+        .service(admin_delete_notification);
 }
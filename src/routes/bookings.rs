@@ -0,0 +1,414 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+
+use crate::services::availability::{self, Exception, Hours, Schedule};
+use crate::services::bookings;
+use crate::services::policy;
+use crate::POOL_DATA;
+
+// Teacher availability and bookings, see services/availability.rs and
+// services/bookings.rs.
+// Teachers:
+//   GET  /api/availability                      settings, weekly hours, upcoming exceptions and blackouts
+//   POST /api/availability                      settings (incl. policy key) and/or {rules: [{weekday, start, end}]} (replaces all)
+//   POST /api/availability/exceptions           {date, kind: available|unavailable, start?, end?, note?}
+//   POST /api/availability/blackouts            {starts_at, ends_at, reason?}, local times in the teacher's timezone or RFC 3339
+//   POST /api/availability/{exceptions|blackouts}/{id}/delete
+// Anyone:
+//   GET  /api/policies                          cancellation policy templates
+//   GET  /api/teachers/{id}/slots?from=YYYY-MM-DD&days=7&tz=Area/City
+//        open slots in `tz` (default: the user's timezone, else UTC), with the teacher's policy
+// Students (and the teacher, for cancel/reschedule):
+//   GET  /api/bookings
+//   POST /api/bookings                          {teacher_id, starts_at, package_id?} (package: paid from a prepaid package)
+//   GET  /api/bookings/{id}/policy              what cancelling/moving now would mean, past decisions
+//   POST /api/bookings/{id}/cancel              {reason?}
+//   POST /api/bookings/{id}/reschedule          {starts_at}
+
+fn user_id(session: &Session) -> Result<i32, HttpResponse> {
+    session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})))
+}
+
+/// The logged-in user, who must be a teacher.
+pub(crate) async fn teacher_id(session: &Session) -> Result<i32, HttpResponse> {
+    let uid = user_id(session)?;
+    let pool = POOL_DATA.get().ok_or_else(|| HttpResponse::InternalServerError().json(json!({"error": "no db"})))?;
+    let role: Option<String> = sqlx::query("SELECT role FROM users WHERE id = $1")
+        .bind(uid)
+        .fetch_optional(pool.get_ref())
+        .await
+        .map_err(|e| HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})))?
+        .and_then(|r| r.get("role"));
+    if role.as_deref() != Some("teacher") {
+        return Err(HttpResponse::Forbidden().json(json!({"error": "teachers only"})));
+    }
+    Ok(uid)
+}
+
+/// An RFC 3339 instant, or a local "YYYY-MM-DDTHH:MM" in `tz`.
+pub(crate) fn parse_instant(s: &str, tz: &Tz) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M").ok().map(|local| availability::resolve(tz, local, false))
+}
+
+#[get("/api/availability")]
+async fn get_availability(session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match availability::overview(pool_data.get_ref(), uid).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct RulePayload {
+    weekday: u32,
+    start: String,
+    end: String,
+}
+
+#[derive(Deserialize)]
+struct AvailabilityPayload {
+    timezone: Option<String>,
+    slot_minutes: Option<i32>,
+    buffer_before_minutes: Option<i32>,
+    buffer_after_minutes: Option<i32>,
+    min_notice_minutes: Option<i32>,
+    horizon_days: Option<i32>,
+    price_coins: Option<i64>,
+    /// cancellation policy template key
+    policy: Option<String>,
+    rules: Option<Vec<RulePayload>>,
+}
+
+#[post("/api/availability")]
+async fn save_availability(payload: web::Json<AvailabilityPayload>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let current = match availability::schedule(pool, uid).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let timezone = match payload.timezone.as_deref() {
+        Some(name) => match availability::parse_tz(name) {
+            Some(tz) => tz,
+            None => return HttpResponse::BadRequest().json(json!({"error": format!("unknown timezone '{}'", name)})),
+        },
+        None => current.timezone,
+    };
+    let policy_key = match payload.policy.as_deref().map(str::trim) {
+        Some(key) if key != current.policy_key => match policy::get(pool, key).await {
+            Ok(Some(p)) if p.active => p.key,
+            Ok(_) => return HttpResponse::BadRequest().json(json!({"error": format!("unknown policy '{}'", key)})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+        },
+        _ => current.policy_key.clone(),
+    };
+    let schedule = Schedule {
+        timezone,
+        slot_minutes: payload.slot_minutes.unwrap_or(current.slot_minutes),
+        buffer_before_minutes: payload.buffer_before_minutes.unwrap_or(current.buffer_before_minutes),
+        buffer_after_minutes: payload.buffer_after_minutes.unwrap_or(current.buffer_after_minutes),
+        min_notice_minutes: payload.min_notice_minutes.unwrap_or(current.min_notice_minutes),
+        horizon_days: payload.horizon_days.unwrap_or(current.horizon_days),
+        price_coins: payload.price_coins.unwrap_or(current.price_coins),
+        policy_key,
+    };
+    let rules = match &payload.rules {
+        Some(list) => {
+            let mut out = Vec::new();
+            for r in list {
+                let hours = availability::parse_time(&r.start).and_then(|s| availability::parse_time(&r.end).and_then(|e| Hours::new(s, e)));
+                match hours {
+                    Ok(h) => out.push((r.weekday, h)),
+                    Err(e) => return HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+                }
+            }
+            Some(out)
+        }
+        None => None,
+    };
+    if let Err(e) = availability::save_schedule(pool, uid, &schedule).await {
+        return HttpResponse::BadRequest().json(json!({"error": e.to_string()}));
+    }
+    if let Some(rules) = rules {
+        if let Err(e) = availability::replace_rules(pool, uid, &rules).await {
+            return HttpResponse::BadRequest().json(json!({"error": e.to_string()}));
+        }
+    }
+    match availability::overview(pool, uid).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct ExceptionPayload {
+    date: String,
+    kind: String,
+    start: Option<String>,
+    end: Option<String>,
+    note: Option<String>,
+}
+
+#[post("/api/availability/exceptions")]
+async fn add_exception(payload: web::Json<ExceptionPayload>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let on_date = match NaiveDate::parse_from_str(payload.date.trim(), "%Y-%m-%d") {
+        Ok(d) => d,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"error": "invalid date (use YYYY-MM-DD)"})),
+    };
+    let available = match payload.kind.as_str() {
+        "available" => true,
+        "unavailable" => false,
+        _ => return HttpResponse::BadRequest().json(json!({"error": "kind must be available or unavailable"})),
+    };
+    let hours = match (payload.start.as_deref().filter(|s| !s.trim().is_empty()), payload.end.as_deref().filter(|s| !s.trim().is_empty())) {
+        (Some(s), Some(e)) => match availability::parse_time(s).and_then(|s| availability::parse_time(e).and_then(|e| Hours::new(s, e))) {
+            Ok(h) => Some(h),
+            Err(e) => return HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+        },
+        (None, None) => None,
+        _ => return HttpResponse::BadRequest().json(json!({"error": "give both start and end, or neither"})),
+    };
+    let exception = Exception { id: 0, on_date, available, hours, note: payload.note.clone().filter(|n| !n.trim().is_empty()) };
+    match availability::add_exception(pool_data.get_ref(), uid, &exception).await {
+        Ok(id) => HttpResponse::Ok().json(json!({"ok": true, "id": id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct BlackoutPayload {
+    starts_at: String,
+    ends_at: String,
+    reason: Option<String>,
+}
+
+#[post("/api/availability/blackouts")]
+async fn add_blackout(payload: web::Json<BlackoutPayload>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let tz = match availability::schedule(pool, uid).await {
+        Ok(s) => s.timezone,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let (starts_at, ends_at) = match (parse_instant(&payload.starts_at, &tz), parse_instant(&payload.ends_at, &tz)) {
+        (Some(s), Some(e)) => (s, e),
+        _ => return HttpResponse::BadRequest().json(json!({"error": "invalid time (use YYYY-MM-DDTHH:MM or RFC 3339)"})),
+    };
+    let reason = payload.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    match availability::add_blackout(pool, uid, starts_at, ends_at, reason).await {
+        Ok(id) => HttpResponse::Ok().json(json!({"ok": true, "id": id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/availability/{table}/{id}/delete")]
+async fn delete_entry(path: web::Path<(String, i32)>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let (table, id) = path.into_inner();
+    match availability::delete_entry(pool_data.get_ref(), uid, &table, id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct SlotsQuery {
+    from: Option<String>,
+    days: Option<i64>,
+    tz: Option<String>,
+}
+
+#[get("/api/teachers/{id}/slots")]
+async fn teacher_slots(path: web::Path<i32>, query: web::Query<SlotsQuery>, session: Session) -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let teacher_id = *path;
+    let bookable = sqlx::query("SELECT 1 FROM users WHERE id = $1 AND role = 'teacher' AND kyc_verified")
+        .bind(teacher_id)
+        .fetch_optional(pool)
+        .await;
+    match bookable {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "teacher not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+
+    let tz = match query.tz.as_deref().filter(|s| !s.trim().is_empty()) {
+        Some(name) => match availability::parse_tz(name) {
+            Some(tz) => tz,
+            None => return HttpResponse::BadRequest().json(json!({"error": format!("unknown timezone '{}'", name)})),
+        },
+        None => match session.get::<i32>("user_id").unwrap_or(None) {
+            Some(uid) => availability::user_tz(pool, uid).await.unwrap_or(Tz::UTC),
+            None => Tz::UTC,
+        },
+    };
+    let schedule = match availability::schedule(pool, teacher_id).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let from_date = match query.from.as_deref() {
+        Some(s) => match NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d") {
+            Ok(d) => d,
+            Err(_) => return HttpResponse::BadRequest().json(json!({"error": "invalid from date (use YYYY-MM-DD)"})),
+        },
+        None => today,
+    };
+    // nothing is bookable past the horizon, and far-off dates overflow below
+    if today.checked_add_signed(ChronoDuration::days(schedule.horizon_days as i64)).is_none_or(|last| from_date > last) {
+        return HttpResponse::BadRequest().json(json!({"error": format!("from must be within {} days of today", schedule.horizon_days)}));
+    }
+    let days = query.days.unwrap_or(7).clamp(1, 31);
+    let Some(to_date) = from_date.checked_add_signed(ChronoDuration::days(days)) else {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid from date"}));
+    };
+    // whole days in the student's timezone, which may be 23 or 25 hours long
+    let from = availability::resolve(&tz, from_date.and_hms_opt(0, 0, 0).unwrap_or_default(), false);
+    let to = availability::resolve(&tz, to_date.and_hms_opt(0, 0, 0).unwrap_or_default(), false);
+    let terms = match policy::for_teacher(pool, teacher_id).await {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match availability::open_slots(pool, teacher_id, from, to, None).await {
+        Ok(slots) => HttpResponse::Ok().json(json!({
+            "teacher_id": teacher_id,
+            "timezone": tz.name(),
+            "teacher_timezone": schedule.timezone.name(),
+            "slot_minutes": schedule.slot_minutes,
+            "price_coins": schedule.price_coins,
+            "policy": terms.to_json(),
+            "slots": slots.iter().map(|s| {
+                let local = s.start.with_timezone(&tz);
+                json!({
+                    "start": local.to_rfc3339(),
+                    "end": s.end.with_timezone(&tz).to_rfc3339(),
+                    "date": local.format("%Y-%m-%d").to_string(),
+                    "time": local.format("%H:%M").to_string(),
+                })
+            }).collect::<Vec<_>>(),
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/bookings")]
+async fn list_bookings(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match bookings::list_for(pool_data.get_ref(), uid, 100).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct BookPayload {
+    teacher_id: i32,
+    starts_at: String,
+    package_id: Option<i32>,
+}
+
+#[post("/api/bookings")]
+async fn create_booking(payload: web::Json<BookPayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let starts_at = match DateTime::parse_from_rfc3339(payload.starts_at.trim()) {
+        Ok(t) => t.with_timezone(&Utc),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"error": "starts_at must be an RFC 3339 time, as given by the slots API"})),
+    };
+    let origin = bookings::Origin { package_id: payload.package_id, series: None };
+    match bookings::create(pool_data.get_ref(), uid, payload.teacher_id, starts_at, origin).await {
+        Ok(b) => HttpResponse::Ok().json(json!({"ok": true, "id": b.id, "status": b.status, "price_coins": b.price_coins})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct CancelPayload {
+    reason: Option<String>,
+}
+
+#[post("/api/bookings/{id}/cancel")]
+async fn cancel_booking(path: web::Path<i32>, payload: Option<web::Json<CancelPayload>>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let reason = payload.as_ref().and_then(|p| p.reason.as_deref()).map(str::trim).filter(|r| !r.is_empty()).map(str::to_string);
+    match bookings::cancel(pool_data.get_ref(), *path, uid, reason.as_deref()).await {
+        Ok((b, decision)) => HttpResponse::Ok().json(json!({"ok": true, "status": b.status, "decision": decision.to_json()})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/bookings/{id}/policy")]
+async fn booking_policy(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match bookings::policy_preview(pool_data.get_ref(), *path, uid).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/policies")]
+async fn list_policies() -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match policy::templates(pool_data.get_ref(), false).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items.iter().map(|p| p.to_json()).collect::<Vec<_>>()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReschedulePayload {
+    starts_at: String,
+}
+
+#[post("/api/bookings/{id}/reschedule")]
+async fn reschedule_booking(path: web::Path<i32>, payload: web::Json<ReschedulePayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let starts_at = match DateTime::parse_from_rfc3339(payload.starts_at.trim()) {
+        Ok(t) => t.with_timezone(&Utc),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"error": "starts_at must be an RFC 3339 time, as given by the slots API"})),
+    };
+    match bookings::reschedule(pool_data.get_ref(), *path, uid, starts_at).await {
+        Ok(b) => HttpResponse::Ok().json(json!({"ok": true, "starts_at": b.starts_at.to_rfc3339(), "ends_at": b.ends_at.to_rfc3339()})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_availability)
+        .service(save_availability)
+        .service(add_exception)
+        .service(add_blackout)
+        .service(delete_entry)
+        .service(teacher_slots)
+        .service(list_bookings)
+        .service(create_booking)
+        .service(cancel_booking)
+        .service(booking_policy)
+        .service(list_policies)
+        .service(reschedule_booking);
+}
@@ -0,0 +1,136 @@
+use actix_multipart::Multipart;
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use futures_util::StreamExt;
+use serde_json::json;
+
+use crate::routes::bookings::teacher_id;
+use crate::services::calendar;
+use crate::POOL_DATA;
+
+// Calendar feeds and imports, see services/calendar.rs.
+//   GET  /api/calendar                          {feed_url} (created on first call)
+//   POST /api/calendar/reset                    new feed URL; the old one stops working
+//   GET  /calendar/{token}.ics                  the feed itself, no login
+// Teachers:
+//   GET  /api/calendar/imports
+//   POST /api/calendar/imports                  multipart: file (.ics), name? (defaults to the file name)
+//   POST /api/calendar/imports/{id}/delete
+
+fn user_id(session: &Session) -> Result<i32, HttpResponse> {
+    session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})))
+}
+
+#[get("/api/calendar")]
+async fn get_feed(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match calendar::feed_token(pool_data.get_ref(), uid).await {
+        Ok(token) => HttpResponse::Ok().json(json!({"feed_url": calendar::feed_url(&token)})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/calendar/reset")]
+async fn reset_feed(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match calendar::reset_token(pool_data.get_ref(), uid).await {
+        Ok(token) => HttpResponse::Ok().json(json!({"feed_url": calendar::feed_url(&token)})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/calendar/{token}.ics")]
+async fn feed(path: web::Path<String>) -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match calendar::feed(pool_data.get_ref(), &path).await {
+        Ok(Some(ics)) => HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .insert_header(("Cache-Control", "private, max-age=300"))
+            .body(ics),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "unknown calendar"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/calendar/imports")]
+async fn list_imports(session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match calendar::imports(pool_data.get_ref(), uid).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/calendar/imports")]
+async fn import(session: Session, mut payload: Multipart) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let max = calendar::import_max_bytes();
+
+    let mut file: Option<Vec<u8>> = None;
+    let mut file_name = None;
+    let mut name = None;
+    while let Some(field_res) = payload.next().await {
+        let mut field = match field_res {
+            Ok(f) => f,
+            Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("multipart error: {}", e)})),
+        };
+        let field_name = field.name().to_string();
+        if field_name == "file" {
+            file_name = field.content_disposition().get_filename().map(|s| s.to_string());
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let data = match chunk {
+                Ok(d) => d,
+                Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("field read: {}", e)})),
+            };
+            if bytes.len() + data.len() > max {
+                return HttpResponse::BadRequest().json(json!({"error": format!("file too large (max {} KB)", max / 1024)}));
+            }
+            bytes.extend_from_slice(&data);
+        }
+        match field_name.as_str() {
+            "file" => file = Some(bytes),
+            "name" => name = Some(String::from_utf8(bytes).unwrap_or_default()),
+            _ => {}
+        }
+    }
+
+    let Some(bytes) = file else { return HttpResponse::BadRequest().json(json!({"error": "file required"})) };
+    let text = match String::from_utf8(bytes) {
+        Ok(t) => t,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"error": "the file is not UTF-8 text"})),
+    };
+    let name = name.filter(|n| !n.trim().is_empty()).or(file_name).unwrap_or_else(|| "calendar.ics".to_string());
+    match calendar::import(pool_data.get_ref(), uid, &name, &text).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/calendar/imports/{id}/delete")]
+async fn delete_import(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match calendar::delete_import(pool_data.get_ref(), uid, *path).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_feed)
+        .service(reset_feed)
+        .service(feed)
+        .service(list_imports)
+        .service(import)
+        .service(delete_import);
+}
@@ -0,0 +1,73 @@
+use actix_files::NamedFile;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::{route, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::Row;
+use std::path::PathBuf;
+
+use crate::services::signed_url;
+use crate::POOL_DATA;
+
+#[derive(Deserialize)]
+struct SignedQuery {
+    exp: i64,
+    sig: String,
+}
+
+// Resolve the stored path for a signed resource.
+async fn stored_path(kind: &str, id: i32) -> actix_web::Result<Option<String>> {
+    let pool_data = POOL_DATA
+        .get()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("no db"))?;
+    let sql = match kind {
+        "notification" => "SELECT COALESCE(n.attachment_path, c.attachment_path) AS p FROM notifications n LEFT JOIN notification_campaigns c ON c.id = n.campaign_id WHERE n.id = $1",
+        "support" => "SELECT attachment_path AS p FROM support_requests WHERE id = $1",
+        "support-message" => "SELECT attachment_path AS p FROM support_messages WHERE id = $1",
+        "avatar" => "SELECT avatar_path AS p FROM users WHERE id = $1",
+        "kyc-front" => "SELECT front_id_path AS p FROM teacher_verifications WHERE id = $1",
+        "kyc-back" => "SELECT back_id_path AS p FROM teacher_verifications WHERE id = $1",
+        _ => return Ok(None),
+    };
+    let row = sqlx::query(sql)
+        .bind(id)
+        .fetch_optional(pool_data.get_ref())
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    Ok(row.and_then(|r| r.try_get::<String, _>("p").ok()))
+}
+
+// Serve a file by signed URL. No session needed; NamedFile handles HEAD and Range
+// so media attachments can seek.
+#[route("/files/{kind}/{id}/{name}", method = "GET", method = "HEAD")]
+async fn signed_file(
+    path: web::Path<(String, i32, String)>,
+    query: web::Query<SignedQuery>,
+    req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let (kind, id, _name) = path.into_inner();
+    if !signed_url::KINDS.contains(&kind.as_str()) {
+        return Err(actix_web::error::ErrorNotFound("not found"));
+    }
+    if !signed_url::verify(&kind, id, query.exp, &query.sig) {
+        return Err(actix_web::error::ErrorForbidden("invalid or expired link"));
+    }
+
+    if let Some(p) = stored_path(&kind, id).await? {
+        let fs_path = PathBuf::from(p.replace('\\', "/"));
+        if fs_path.exists() {
+            let file = NamedFile::open_async(fs_path).await?;
+            let mut resp = file.into_response(&req);
+            // Private, and never cached past the link's own expiry
+            let max_age = (query.exp - chrono::Utc::now().timestamp()).clamp(0, 300);
+            if let Ok(v) = HeaderValue::from_str(&format!("private, max-age={}", max_age)) {
+                resp.headers_mut().insert(CACHE_CONTROL, v);
+            }
+            return Ok(resp);
+        }
+    }
+    Err(actix_web::error::ErrorNotFound("not found"))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(signed_file);
+}
@@ -0,0 +1,201 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::Duration as ChronoDuration;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::routes::bookings::{parse_instant, teacher_id};
+use crate::services::availability;
+use crate::services::groups::{self, NewGroup};
+use crate::POOL_DATA;
+
+// Group sessions, see services/groups.rs.
+// Anyone:
+//   GET  /api/groups?teacher_id=                upcoming sessions taking students
+//   GET  /api/groups/{id}                       details; with a login also the caller's seat or waitlist place
+// Logged in:
+//   GET  /api/groups/mine                       sessions taught, booked or waited for
+//   POST /api/groups/{id}/join                  a seat, or the waitlist when full
+//   POST /api/groups/{id}/claim                 the seat offered from the waitlist
+//   POST /api/groups/{id}/leave                 {reason?} gives up a seat or waitlist place
+// Teachers:
+//   POST /api/groups                            {title, description?, starts_at, ends_at? | duration_minutes?,
+//                                                min_seats, max_seats, seat_price_coins, cutoff_at?, claim_minutes?}
+//                                               times local to the teacher's timezone or RFC 3339
+//   POST /api/groups/{id}/cancel                {reason?} refunds every seat
+
+fn user_id(session: &Session) -> Result<i32, HttpResponse> {
+    session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    teacher_id: Option<i32>,
+}
+
+#[get("/api/groups")]
+async fn list_groups(query: web::Query<ListQuery>, session: Session) -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let viewer = session.get::<i32>("user_id").unwrap_or(None);
+    let tz = match viewer {
+        Some(uid) => availability::user_tz(pool, uid).await.unwrap_or(Tz::UTC),
+        None => Tz::UTC,
+    };
+    match groups::list(pool, query.teacher_id, viewer, tz).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/groups/mine")]
+async fn my_groups(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match groups::mine(pool_data.get_ref(), uid).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/groups/{id}")]
+async fn get_group(path: web::Path<i32>, session: Session) -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let viewer = session.get::<i32>("user_id").unwrap_or(None);
+    match groups::detail(pool_data.get_ref(), *path, viewer).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::NotFound().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct GroupPayload {
+    title: String,
+    description: Option<String>,
+    starts_at: String,
+    ends_at: Option<String>,
+    duration_minutes: Option<i64>,
+    min_seats: i32,
+    max_seats: i32,
+    seat_price_coins: i64,
+    cutoff_at: Option<String>,
+    claim_minutes: Option<i32>,
+}
+
+#[post("/api/groups")]
+async fn create_group(payload: web::Json<GroupPayload>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let schedule = match availability::schedule(pool, uid).await {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let tz = schedule.timezone;
+    let bad_time = || HttpResponse::BadRequest().json(json!({"error": "invalid time (use YYYY-MM-DDTHH:MM or RFC 3339)"}));
+    let Some(starts_at) = parse_instant(&payload.starts_at, &tz) else { return bad_time() };
+    // without an end the session lasts duration_minutes, else one of the teacher's slots
+    let ends_at = match payload.ends_at.as_deref() {
+        Some(s) => match parse_instant(s, &tz) {
+            Some(t) => t,
+            None => return bad_time(),
+        },
+        None => match payload.duration_minutes.unwrap_or(schedule.slot_minutes as i64) {
+            minutes @ 15..=720 => starts_at + ChronoDuration::minutes(minutes),
+            _ => return HttpResponse::BadRequest().json(json!({"error": "duration_minutes must be between 15 and 720"})),
+        },
+    };
+    let cutoff_at = match payload.cutoff_at.as_deref().filter(|s| !s.trim().is_empty()) {
+        Some(s) => match parse_instant(s, &tz) {
+            Some(t) => Some(t),
+            None => return bad_time(),
+        },
+        None => None,
+    };
+    let new = NewGroup {
+        title: payload.title.clone(),
+        description: payload.description.clone().unwrap_or_default(),
+        starts_at,
+        ends_at,
+        min_seats: payload.min_seats,
+        max_seats: payload.max_seats,
+        seat_price_coins: payload.seat_price_coins,
+        cutoff_at,
+        claim_minutes: payload.claim_minutes,
+    };
+    match groups::create(pool, uid, &new).await {
+        Ok(g) => HttpResponse::Ok().json(json!({"ok": true, "id": g.id, "cutoff_at": g.cutoff_at.with_timezone(&tz).to_rfc3339()})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/groups/{id}/join")]
+async fn join_group(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match groups::join(pool_data.get_ref(), *path, uid).await {
+        Ok(mut v) => {
+            v["ok"] = json!(true);
+            HttpResponse::Ok().json(v)
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/groups/{id}/claim")]
+async fn claim_seat(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match groups::claim(pool_data.get_ref(), *path, uid).await {
+        Ok(seat) => HttpResponse::Ok().json(json!({"ok": true, "seat_id": seat.id, "status": seat.status})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReasonPayload {
+    reason: Option<String>,
+}
+
+fn reason_of(payload: &Option<web::Json<ReasonPayload>>) -> Option<String> {
+    payload.as_ref().and_then(|p| p.reason.as_deref()).map(str::trim).filter(|r| !r.is_empty()).map(str::to_string)
+}
+
+#[post("/api/groups/{id}/leave")]
+async fn leave_group(path: web::Path<i32>, payload: Option<web::Json<ReasonPayload>>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match groups::leave(pool_data.get_ref(), *path, uid, reason_of(&payload).as_deref()).await {
+        Ok(mut v) => {
+            v["ok"] = json!(true);
+            HttpResponse::Ok().json(v)
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/groups/{id}/cancel")]
+async fn cancel_group(path: web::Path<i32>, payload: Option<web::Json<ReasonPayload>>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match groups::cancel(pool_data.get_ref(), *path, uid, reason_of(&payload).as_deref()).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    // /mine before /{id}
+    cfg.service(list_groups)
+        .service(my_groups)
+        .service(get_group)
+        .service(create_group)
+        .service(join_group)
+        .service(claim_seat)
+        .service(leave_group)
+        .service(cancel_group);
+}
@@ -0,0 +1,240 @@
+use actix_files::NamedFile;
+use actix_session::Session;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+
+use crate::services::guardian;
+use crate::POOL_DATA;
+
+// Guardian accounts, see services/guardian.rs.
+//   GET  /guardian_dashboard                          page
+//   GET  /api/guardian/invites                        sent and received invitations
+//   POST /api/guardian/invites                        {email, invite: "student"|"guardian"}
+//   POST /api/guardian/invites/{id}/accept|decline    as the invited account
+//   POST /api/guardian/invites/{id}/revoke            as the inviter
+//   GET  /api/guardian/children                       linked students with balances
+//   GET  /api/guardian/children/{id}                  one child: standing, wallet, approvals
+//   POST /api/guardian/children/{id}/top_up           {amount, note} from the guardian's wallet
+//   POST /api/guardian/children/{id}/spending_limit   {limit} (null for the default)
+//   POST /api/guardian/children/{id}/unlink
+//   GET  /api/guardian/approvals                      pending spending approvals
+//   POST /api/guardian/approvals/{id}/approve|decline {note}
+
+fn user_id(session: &Session) -> Result<i32, HttpResponse> {
+    session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})))
+}
+
+/// The logged-in user, who must be a guardian of student `minor_id`.
+async fn guardian_of(session: &Session, minor_id: i32) -> Result<i32, HttpResponse> {
+    let uid = user_id(session)?;
+    let pool = POOL_DATA.get().ok_or_else(|| HttpResponse::InternalServerError().json(json!({"error": "no db"})))?;
+    match guardian::is_guardian_of(pool.get_ref(), uid, minor_id).await {
+        Ok(true) => Ok(uid),
+        Ok(false) => Err(HttpResponse::NotFound().json(json!({"error": "not one of your children"}))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))),
+    }
+}
+
+#[get("/guardian_dashboard")]
+async fn guardian_dashboard(session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    if session.get::<i32>("user_id").unwrap_or(None).is_none() {
+        return Ok(HttpResponse::Found().append_header(("Location", "/login")).finish());
+    }
+    let file = NamedFile::open_async("./templates/guardian_dashboard.html").await?;
+    Ok(file.into_response(&req))
+}
+
+#[get("/api/guardian/invites")]
+async fn list_invites(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match guardian::invites_for(pool_data.get_ref(), uid).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct InvitePayload {
+    email: String,
+    // who is being invited; defaults by the inviter's role
+    invite: Option<String>,
+}
+
+#[post("/api/guardian/invites")]
+async fn create_invite(payload: web::Json<InvitePayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let inviting = match payload.invite.as_deref() {
+        Some(w) => w.to_string(),
+        None => {
+            let role: Option<String> = sqlx::query("SELECT role FROM users WHERE id = $1")
+                .bind(uid)
+                .fetch_optional(pool_data.get_ref())
+                .await
+                .ok()
+                .flatten()
+                .and_then(|r| r.get("role"));
+            if role.as_deref() == Some("guardian") { "student".to_string() } else { "guardian".to_string() }
+        }
+    };
+    // the inviter is on the other side of whoever is invited
+    let side = match inviting.as_str() {
+        "student" => "guardian",
+        "guardian" => "student",
+        _ => return HttpResponse::BadRequest().json(json!({"error": "invite must be 'student' or 'guardian'"})),
+    };
+    match guardian::invite(pool_data.get_ref(), uid, side, &payload.email).await {
+        Ok(id) => HttpResponse::Ok().json(json!({"ok": true, "id": id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/guardian/invites/{id}/{action}")]
+async fn invite_action(path: web::Path<(i32, String)>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let (id, action) = path.into_inner();
+    let pool = pool_data.get_ref();
+    let res = match action.as_str() {
+        "accept" => guardian::respond(pool, id, uid, true).await,
+        "decline" => guardian::respond(pool, id, uid, false).await,
+        "revoke" => match guardian::revoke(pool, id, uid).await {
+            Ok(true) => Ok(()),
+            Ok(false) => return HttpResponse::NotFound().json(json!({"error": "no pending invitation of yours with that id"})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+        },
+        _ => return HttpResponse::NotFound().json(json!({"error": "unknown action"})),
+    };
+    match res {
+        Ok(()) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/guardian/children")]
+async fn list_children(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let balance = match crate::services::wallet_service::balance(pool, uid).await {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match guardian::children(pool, uid).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items, "balance": balance})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/guardian/children/{id}")]
+async fn get_child(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = guardian_of(&session, *path).await { return resp; }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match guardian::child(pool_data.get_ref(), *path).await {
+        Ok(Some(v)) => HttpResponse::Ok().json(v),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct TopUpPayload {
+    amount: i64,
+    note: Option<String>,
+}
+
+#[post("/api/guardian/children/{id}/top_up")]
+async fn top_up(path: web::Path<i32>, payload: web::Json<TopUpPayload>, session: Session) -> impl Responder {
+    let uid = match guardian_of(&session, *path).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let note = payload.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    match guardian::top_up(pool_data.get_ref(), uid, *path, payload.amount, note).await {
+        Ok(balance) => HttpResponse::Ok().json(json!({"ok": true, "balance": balance})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct LimitPayload {
+    limit: Option<i64>,
+}
+
+#[post("/api/guardian/children/{id}/spending_limit")]
+async fn set_limit(path: web::Path<i32>, payload: web::Json<LimitPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = guardian_of(&session, *path).await { return resp; }
+    if payload.limit.map(|l| l < 0).unwrap_or(false) {
+        return HttpResponse::BadRequest().json(json!({"error": "limit cannot be negative"}));
+    }
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match guardian::set_spending_limit(pool_data.get_ref(), *path, payload.limit).await {
+        Ok(()) => HttpResponse::Ok().json(json!({"ok": true, "limit": payload.limit.unwrap_or_else(guardian::default_spending_limit)})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/guardian/children/{id}/unlink")]
+async fn unlink_child(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match guardian_of(&session, *path).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match crate::services::age::unlink_guardian(pool_data.get_ref(), *path, uid).await {
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/guardian/approvals")]
+async fn list_approvals(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let minor_ids: Vec<i32> = match sqlx::query("SELECT minor_id FROM guardian_links WHERE guardian_id = $1").bind(uid).fetch_all(pool).await {
+        Ok(rows) => rows.iter().map(|r| r.get("minor_id")).collect(),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match guardian::approvals(pool, &minor_ids, true).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct DecisionPayload {
+    note: Option<String>,
+}
+
+#[post("/api/guardian/approvals/{id}/{action}")]
+async fn decide_approval(path: web::Path<(i32, String)>, payload: Option<web::Json<DecisionPayload>>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let (id, action) = path.into_inner();
+    let approve = match action.as_str() {
+        "approve" => true,
+        "decline" => false,
+        _ => return HttpResponse::NotFound().json(json!({"error": "unknown action"})),
+    };
+    let note = payload.as_ref().and_then(|p| p.note.clone());
+    match guardian::decide(pool_data.get_ref(), id, uid, approve, note.as_deref()).await {
+        Ok(d) => HttpResponse::Ok().json(json!({"ok": true, "minor_id": d.minor_id, "approved": d.approved, "reason": d.reason, "kind": d.kind, "reference_id": d.reference_id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(guardian_dashboard)
+        .service(list_invites)
+        .service(create_invite)
+        .service(invite_action)
+        .service(list_children)
+        .service(get_child)
+        .service(top_up)
+        .service(set_limit)
+        .service(unlink_child)
+        .service(list_approvals)
+        .service(decide_approval);
+}
@@ -3,3 +3,13 @@ pub mod verify;
 pub mod profile;
 pub mod admin;
 pub mod notifications;
+pub mod files;
+pub mod uploads;
+pub mod push;
+pub mod support;
+pub mod guardian;
+pub mod wallet;
+pub mod bookings;
+pub mod calendar;
+pub mod groups;
+pub mod packages;
@@ -1,74 +1,117 @@
-use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, StreamHandler, WrapFuture};
 use actix_files::NamedFile;
 use actix_session::Session;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, post, web, web::Bytes, HttpRequest, HttpResponse, Responder};
 use actix_web_actors::ws;
 use chrono::{DateTime, NaiveDateTime, Utc};
-use once_cell::sync::OnceCell;
-use serde::Serialize;
+use futures_util::stream;
+use serde::Deserialize;
 use serde_json::json;
 use sqlx::Row;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::time::Duration;
-use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 
+use crate::services::notify_hub::{self, Connect, Disconnect, Push};
+use crate::services::notify_prefs;
+use crate::services::signed_url;
+use crate::services::web_push;
 use crate::POOL_DATA;
 
-#[derive(Clone, Debug, Serialize)]
-pub struct NotificationEvent {
-    pub id: i32,
-    pub user_id: i32,
-    pub title: String,
-    pub body: String,
-    pub attachment_url: Option<String>,
-    pub created_at: String,
-    pub read: bool,
-}
+pub use crate::services::notify_hub::NotificationEvent;
 
-static CHANNEL: OnceCell<broadcast::Sender<NotificationEvent>> = OnceCell::new();
+/// Ids recently sent on a socket, so catch-up and live pushes don't duplicate.
+const RECENT_IDS: usize = 512;
 
-fn channel() -> broadcast::Sender<NotificationEvent> {
-    CHANNEL
-        .get_or_init(|| {
-            let (tx, _rx) = broadcast::channel(200);
-            tx
-        })
-        .clone()
-}
+/// Seconds between keep-alive pings (WebSocket) or comments (SSE).
+const HEARTBEAT_SECS: u64 = 20;
 
-pub fn broadcast_notification(event: NotificationEvent) {
-    let _ = channel().send(event);
-}
+/// Ids recently sent on one connection.
+#[derive(Default)]
+struct RecentIds(VecDeque<i32>);
 
-fn subscribe() -> broadcast::Receiver<NotificationEvent> {
-    channel().subscribe()
+impl RecentIds {
+    /// Record `id`; false if it was already sent.
+    fn insert(&mut self, id: i32) -> bool {
+        if self.0.contains(&id) {
+            return false;
+        }
+        if self.0.len() == RECENT_IDS {
+            self.0.pop_front();
+        }
+        self.0.push_back(id);
+        true
+    }
 }
 
 struct NotificationWs {
     user_id: i32,
-    rx: broadcast::Receiver<NotificationEvent>,
+    conn_id: usize,
+    last_id: Option<i32>,
+    recent: RecentIds,
+}
+
+impl NotificationWs {
+    fn send_event(&mut self, evt: &NotificationEvent, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.recent.insert(evt.id) {
+            return;
+        }
+        if let Ok(text) = serde_json::to_string(evt) {
+            ctx.text(text);
+        }
+    }
 }
 
 impl Actor for NotificationWs {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        // Poll for events frequently so clients get near-real-time updates
-        ctx.run_interval(Duration::from_millis(800), |act, ctx| {
-            while let Ok(evt) = act.rx.try_recv() {
-                if evt.user_id == act.user_id {
-                    if let Ok(text) = serde_json::to_string(&evt) {
-                        ctx.text(text);
-                    }
-                }
-            }
-        });
+        // Register before catching up so nothing published in between is missed
+        if let Some(hub) = notify_hub::hub() {
+            hub.do_send(Connect {
+                user_id: self.user_id,
+                conn_id: self.conn_id,
+                addr: ctx.address().recipient(),
+            });
+        }
+
+        if let (Some(last_id), Some(pool_data)) = (self.last_id, POOL_DATA.get()) {
+            let pool = pool_data.get_ref().clone();
+            let user_id = self.user_id;
+            ctx.spawn(
+                async move { notify_hub::events_since(&pool, user_id, last_id).await }
+                    .into_actor(self)
+                    .map(|res, act, ctx| match res {
+                        Ok(events) => {
+                            for evt in &events {
+                                act.send_event(evt, ctx);
+                            }
+                        }
+                        Err(e) => eprintln!("ws catch-up failed: {}", e),
+                    }),
+            );
+        }
 
         // Heartbeat pings to keep the connection alive
-        ctx.run_interval(Duration::from_secs(20), |_act, ctx| {
+        ctx.run_interval(Duration::from_secs(HEARTBEAT_SECS), |_act, ctx| {
             ctx.ping(b"hb");
         });
     }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(hub) = notify_hub::hub() {
+            hub.do_send(Disconnect { user_id: self.user_id, conn_id: self.conn_id });
+        }
+    }
+}
+
+impl Handler<Push> for NotificationWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) {
+        self.send_event(&msg.0, ctx);
+    }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for NotificationWs {
@@ -88,11 +131,18 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for NotificationWs {
     }
 }
 
+#[derive(Deserialize)]
+struct WsQuery {
+    /// Id of the newest notification the client has already seen
+    last_id: Option<i32>,
+}
+
 #[get("/ws/notifications")]
 pub async fn ws_notifications(
     req: HttpRequest,
     stream: web::Payload,
     session: Session,
+    query: web::Query<WsQuery>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = session
         .get::<i32>("user_id")
@@ -101,14 +151,162 @@ pub async fn ws_notifications(
 
     let actor = NotificationWs {
         user_id,
-        rx: subscribe(),
+        conn_id: notify_hub::next_conn_id(),
+        last_id: query.last_id,
+        recent: RecentIds::default(),
     };
 
     ws::start(actor, &req, stream)
 }
 
+/// Bounded so a stalled client is dropped instead of buffering forever; it
+/// reconnects with Last-Event-ID and catches up from the DB.
+const SSE_BUFFER: usize = 256;
+
+/// Server-Sent Events connection. Registers with the hub like a socket and
+/// writes frames into the response body channel.
+struct NotificationSse {
+    user_id: i32,
+    conn_id: usize,
+    last_id: Option<i32>,
+    recent: RecentIds,
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl NotificationSse {
+    fn write(&mut self, frame: String, ctx: &mut Context<Self>) {
+        // Full or closed: the client is gone or too slow
+        if self.tx.try_send(Bytes::from(frame)).is_err() {
+            ctx.stop();
+        }
+    }
+
+    fn send_event(&mut self, evt: &NotificationEvent, ctx: &mut Context<Self>) {
+        if !self.recent.insert(evt.id) {
+            return;
+        }
+        if let Ok(data) = serde_json::to_string(evt) {
+            self.write(format!("id: {}\nevent: notification\ndata: {}\n\n", evt.id, data), ctx);
+        }
+    }
+}
+
+impl Actor for NotificationSse {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(hub) = notify_hub::hub() {
+            hub.do_send(Connect {
+                user_id: self.user_id,
+                conn_id: self.conn_id,
+                addr: ctx.address().recipient(),
+            });
+        }
+
+        // Reconnect delay hint for EventSource
+        self.write("retry: 3000\n\n".to_string(), ctx);
+
+        if let (Some(last_id), Some(pool_data)) = (self.last_id, POOL_DATA.get()) {
+            let pool = pool_data.get_ref().clone();
+            let user_id = self.user_id;
+            ctx.spawn(
+                async move { notify_hub::events_since(&pool, user_id, last_id).await }
+                    .into_actor(self)
+                    .map(|res, act, ctx| match res {
+                        Ok(events) => {
+                            for evt in &events {
+                                act.send_event(evt, ctx);
+                            }
+                        }
+                        Err(e) => eprintln!("sse catch-up failed: {}", e),
+                    }),
+            );
+        }
+
+        // Comment lines keep proxies from timing out the idle stream and
+        // let us notice a closed connection
+        ctx.run_interval(Duration::from_secs(HEARTBEAT_SECS), |act, ctx| {
+            act.write(": hb\n\n".to_string(), ctx);
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(hub) = notify_hub::hub() {
+            hub.do_send(Disconnect { user_id: self.user_id, conn_id: self.conn_id });
+        }
+    }
+}
+
+impl Handler<Push> for NotificationSse {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) {
+        self.send_event(&msg.0, ctx);
+    }
+}
+
+/// SSE fallback for clients whose proxies break WebSockets. Same events,
+/// session auth and hub as /ws/notifications. Resumes from the
+/// `Last-Event-ID` header that EventSource sends on reconnect, or `?last_id=`
+/// on the first connect.
+#[get("/sse/notifications")]
+pub async fn sse_notifications(
+    req: HttpRequest,
+    session: Session,
+    query: web::Query<WsQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("not logged in"))?;
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i32>().ok());
+
+    let (tx, rx) = mpsc::channel::<Bytes>(SSE_BUFFER);
+    NotificationSse {
+        user_id,
+        conn_id: notify_hub::next_conn_id(),
+        last_id: last_event_id.or(query.last_id),
+        recent: RecentIds::default(),
+        tx,
+    }
+    .start();
+
+    // The actor stops once the receiver (and with it the response) is dropped
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|b| (Ok::<_, actix_web::Error>(b), rx))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    /// Only notices older than this id (the `next_cursor` of the previous page)
+    cursor: Option<i32>,
+    limit: Option<i64>,
+    category: Option<String>,
+    /// Only unread notices
+    #[serde(default)]
+    unread: bool,
+    /// List the archive instead of the inbox
+    #[serde(default)]
+    archived: bool,
+}
+
+const PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
 #[get("/api/notifications")]
-async fn list_notifications(session: Session) -> impl Responder {
+async fn list_notifications(query: web::Query<ListQuery>, session: Session) -> impl Responder {
     let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
         Some(id) => id,
         None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
@@ -119,15 +317,38 @@ async fn list_notifications(session: Session) -> impl Responder {
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
 
+    if let Some(c) = &query.category {
+        if !notify_prefs::CATEGORIES.contains(&c.as_str()) {
+            return HttpResponse::BadRequest().json(json!({"error": format!("unknown category: {}", c)}));
+        }
+    }
+    let limit = query.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    // Fetch one extra row to know whether there is a next page
     let rows = sqlx::query(
-        "SELECT id, title, body, attachment_path, created_at, read FROM notifications WHERE user_id = $1 ORDER BY created_at DESC LIMIT 50",
+        "SELECT n.id, n.title, n.body, COALESCE(n.attachment_path, c.attachment_path) AS attachment_path, n.category, n.priority, n.created_at, n.expires_at, n.read, n.archived_at
+         FROM notifications n LEFT JOIN notification_campaigns c ON c.id = n.campaign_id
+         WHERE n.user_id = $1 AND n.deleted_at IS NULL AND (n.expires_at IS NULL OR n.expires_at > now())
+           AND (n.archived_at IS NOT NULL) = $2
+           AND ($3::int IS NULL OR n.id < $3)
+           AND ($4::text IS NULL OR n.category = $4)
+           AND (NOT $5 OR NOT n.read)
+         ORDER BY n.id DESC LIMIT $6",
     )
     .bind(user_id)
+    .bind(query.archived)
+    .bind(query.cursor)
+    .bind(&query.category)
+    .bind(query.unread)
+    .bind(limit + 1)
     .fetch_all(pool_data.get_ref())
     .await;
 
     match rows {
-        Ok(list) => {
+        Ok(mut list) => {
+            let has_more = list.len() as i64 > limit;
+            list.truncate(limit as usize);
+            let next_cursor = if has_more { list.last().map(|r| r.get::<i32,_>("id")) } else { None };
             let mapped: Vec<serde_json::Value> = list
                 .into_iter()
                 .map(|r| {
@@ -143,13 +364,51 @@ async fn list_notifications(session: Session) -> impl Responder {
                         "id": id,
                         "title": r.get::<String,_>("title"),
                         "body": r.get::<String,_>("body"),
-                        "attachment_url": attachment_path.map(|_| format!("/api/notifications/{}/attachment", id)),
+                        "category": r.get::<String,_>("category"),
+                        "priority": r.get::<String,_>("priority"),
+                        "attachment_url": attachment_path.as_ref().map(|_| format!("/api/notifications/{}/attachment", id)),
+                        "attachment_signed_url": attachment_path.as_ref().map(|p| signed_url::sign_default("notification", id, p)),
                         "created_at": created_at.to_rfc3339(),
+                        "expires_at": r.try_get::<Option<DateTime<Utc>>,_>("expires_at").ok().flatten().map(|d| d.to_rfc3339()),
+                        "archived_at": r.try_get::<Option<DateTime<Utc>>,_>("archived_at").ok().flatten().map(|d| d.to_rfc3339()),
                         "read": r.get::<bool,_>("read"),
                     })
                 })
                 .collect();
-            HttpResponse::Ok().json(json!({"items": mapped}))
+            HttpResponse::Ok().json(json!({"items": mapped, "next_cursor": next_cursor}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+/// Unread inbox count, total and per category; cheap enough to poll for the bell badge.
+#[get("/api/notifications/unread_count")]
+async fn unread_count(session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let rows = sqlx::query(
+        "SELECT category, COUNT(*) AS n FROM notifications
+         WHERE user_id = $1 AND NOT read AND archived_at IS NULL AND deleted_at IS NULL
+           AND (expires_at IS NULL OR expires_at > now())
+         GROUP BY category",
+    )
+    .bind(user_id)
+    .fetch_all(pool_data.get_ref())
+    .await;
+
+    match rows {
+        Ok(list) => {
+            let by_category: HashMap<String, i64> = list.iter().map(|r| (r.get("category"), r.get("n"))).collect();
+            let total: i64 = by_category.values().sum();
+            HttpResponse::Ok().json(json!({"unread": total, "by_category": by_category}))
         }
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
@@ -192,17 +451,58 @@ async fn mark_all_read(session: Session) -> impl Responder {
         None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
     };
 
-    let res = sqlx::query("UPDATE notifications SET read = TRUE WHERE user_id = $1")
+    let res = sqlx::query("UPDATE notifications SET read = TRUE WHERE user_id = $1 AND NOT read AND deleted_at IS NULL")
+        .bind(user_id)
+        .execute(pool_data.get_ref())
+        .await;
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+/// Archive, unarchive or delete one of the user's own notices. Deleting is
+/// soft, so campaign read stats stay intact, but the notice is gone for the user.
+async fn set_notice_state(session: &Session, notif_id: i32, sql: &str) -> HttpResponse {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let res = sqlx::query(sql)
+        .bind(notif_id)
         .bind(user_id)
         .execute(pool_data.get_ref())
         .await;
 
     match res {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "not found"})),
         Ok(_) => HttpResponse::Ok().json(json!({"ok": true})),
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
     }
 }
 
+#[post("/api/notifications/{id}/archive")]
+async fn archive_notification(path: web::Path<i32>, session: Session) -> impl Responder {
+    set_notice_state(&session, *path, "UPDATE notifications SET archived_at = COALESCE(archived_at, now()) WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL").await
+}
+
+#[post("/api/notifications/{id}/unarchive")]
+async fn unarchive_notification(path: web::Path<i32>, session: Session) -> impl Responder {
+    set_notice_state(&session, *path, "UPDATE notifications SET archived_at = NULL WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL").await
+}
+
+#[post("/api/notifications/{id}/delete")]
+async fn delete_notification(path: web::Path<i32>, session: Session) -> impl Responder {
+    set_notice_state(&session, *path, "UPDATE notifications SET deleted_at = now() WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL").await
+}
+
 #[get("/api/notifications/{id}/attachment")]
 async fn notification_attachment(
     path: web::Path<i32>,
@@ -221,7 +521,7 @@ async fn notification_attachment(
         .ok_or_else(|| actix_web::error::ErrorInternalServerError("no db"))?;
 
     let row = sqlx::query(
-        "SELECT user_id, attachment_path FROM notifications WHERE id = $1",
+        "SELECT n.user_id, n.deleted_at IS NOT NULL AS deleted, COALESCE(n.attachment_path, c.attachment_path) AS attachment_path FROM notifications n LEFT JOIN notification_campaigns c ON c.id = n.campaign_id WHERE n.id = $1",
     )
     .bind(notif_id)
     .fetch_optional(pool_data.get_ref())
@@ -233,6 +533,9 @@ async fn notification_attachment(
         if owner != user_id && role != "admin" {
             return Err(actix_web::error::ErrorForbidden("forbidden"));
         }
+        if owner == user_id && r.get::<bool, _>("deleted") {
+            return Err(actix_web::error::ErrorNotFound("not found"));
+        }
         let path: Option<String> = r.try_get("attachment_path").ok();
         if let Some(p) = path {
             let fs_path = PathBuf::from(&p);
@@ -246,12 +549,171 @@ async fn notification_attachment(
     Err(actix_web::error::ErrorNotFound("not found"))
 }
 
+#[get("/api/notifications/preferences")]
+async fn get_preferences(session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    match notify_prefs::preferences(pool_data.get_ref(), user_id).await {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .into_iter()
+                .map(|p| json!({"category": p.category, "mode": p.mode, "push": p.push, "default": p.is_default}))
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items, "modes": notify_prefs::MODES, "push_available": web_push::enabled()}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct PreferencesPayload {
+    /// category -> mode
+    #[serde(default)]
+    preferences: HashMap<String, String>,
+    /// category -> Web Push on/off
+    #[serde(default)]
+    push: HashMap<String, bool>,
+}
+
+#[post("/api/notifications/preferences")]
+async fn update_preferences(payload: web::Json<PreferencesPayload>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    for (category, mode) in &payload.preferences {
+        if !notify_prefs::CATEGORIES.contains(&category.as_str()) {
+            return HttpResponse::BadRequest().json(json!({"error": format!("unknown category: {}", category)}));
+        }
+        if !notify_prefs::MODES.contains(&mode.as_str()) {
+            return HttpResponse::BadRequest().json(json!({"error": format!("unknown mode: {}", mode)}));
+        }
+    }
+    if let Some(category) = payload.push.keys().find(|c| !notify_prefs::CATEGORIES.contains(&c.as_str())) {
+        return HttpResponse::BadRequest().json(json!({"error": format!("unknown category: {}", category)}));
+    }
+    for (category, mode) in &payload.preferences {
+        if let Err(e) = notify_prefs::set_mode(pool_data.get_ref(), user_id, category, mode).await {
+            return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+        }
+    }
+    for (category, push) in &payload.push {
+        if let Err(e) = notify_prefs::set_push(pool_data.get_ref(), user_id, category, *push).await {
+            return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+        }
+    }
+    HttpResponse::Ok().json(json!({"ok": true}))
+}
+
+#[get("/settings/notifications")]
+async fn preferences_page(session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    if session.get::<i32>("user_id").unwrap_or(None).is_none() {
+        return Ok(HttpResponse::Found().append_header(("Location", "/login")).finish());
+    }
+    let file = NamedFile::open_async("./templates/notification_settings.html").await?;
+    Ok(file.into_response(&req))
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeQuery {
+    u: i32,
+    c: String,
+    t: String,
+}
+
+fn unsubscribe_page(body: &str) -> HttpResponse {
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Unsubscribe | Skillvine</title></head>
+<body style="font-family:Arial,Helvetica,sans-serif; background:#f8fafc; padding:40px;">
+<div style="max-width:480px; margin:0 auto; background:#fff; border:1px solid #e5e7eb; border-radius:12px; padding:24px;">
+<h2 style="margin-top:0;">Email preferences</h2>{}
+<p><a href="/settings/notifications">Manage notification settings</a></p></div></body></html>"#,
+        body
+    ))
+}
+
+/// The category of a correctly signed unsubscribe link.
+fn unsubscribe_category(query: &UnsubscribeQuery) -> Option<&str> {
+    let category = query.c.as_str();
+    let known = category == "all" || notify_prefs::CATEGORIES.contains(&category);
+    (known && notify_prefs::verify_unsubscribe(query.u, category, &query.t)).then_some(category)
+}
+
+fn invalid_unsubscribe() -> HttpResponse {
+    HttpResponse::BadRequest().content_type("text/html; charset=utf-8").body("Invalid or broken unsubscribe link.")
+}
+
+/// The link in emails. It only asks for confirmation, since mail scanners and
+/// link prefetchers open links without the user; the form POSTs below.
+#[get("/unsubscribe")]
+async fn unsubscribe_confirm(query: web::Query<UnsubscribeQuery>) -> impl Responder {
+    let Some(category) = unsubscribe_category(&query) else { return invalid_unsubscribe() };
+    let what = if category == "all" {
+        "all notification emails".to_string()
+    } else {
+        format!("emails for {} notices", notify_prefs::escape_html(category))
+    };
+    unsubscribe_page(&format!(
+        r#"<p>Stop receiving {}?</p>
+<form method="post" action="/unsubscribe?u={}&amp;c={}&amp;t={}"><button type="submit">Unsubscribe</button></form>"#,
+        what,
+        query.u,
+        notify_prefs::escape_html(category),
+        notify_prefs::escape_html(&query.t)
+    ))
+}
+
+/// Unsubscribe: the confirm form above, or the RFC 8058 List-Unsubscribe-Post
+/// request mail clients send. No login needed.
+#[post("/unsubscribe")]
+async fn unsubscribe(query: web::Query<UnsubscribeQuery>) -> impl Responder {
+    let Some(category) = unsubscribe_category(&query) else { return invalid_unsubscribe() };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().body("Service unavailable, please try again later."),
+    };
+
+    match notify_prefs::unsubscribe(pool_data.get_ref(), query.u, category).await {
+        Ok(_) if category == "all" => unsubscribe_page("<p>You will no longer receive notification emails. Notices still appear in your dashboard.</p>"),
+        Ok(_) => unsubscribe_page(&format!(
+            "<p>You will no longer receive emails for {} notices. They still appear in your dashboard.</p>",
+            notify_prefs::escape_html(category)
+        )),
+        Err(e) => {
+            eprintln!("unsubscribe failed for user {}: {}", query.u, e);
+            HttpResponse::InternalServerError().body("Could not update your preferences, please try again later.")
+        }
+    }
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
-    // Ensure channel is created at startup
-    let _ = channel();
+    // Ensure the hub is running before the first socket connects
+    notify_hub::start();
     cfg.service(ws_notifications)
+        .service(sse_notifications)
         .service(list_notifications)
+        .service(unread_count)
+        .service(get_preferences)
+        .service(update_preferences)
+        .service(preferences_page)
+        .service(unsubscribe_confirm)
+        .service(unsubscribe)
         .service(mark_read)
         .service(mark_all_read)
+        .service(archive_notification)
+        .service(unarchive_notification)
+        .service(delete_notification)
         .service(notification_attachment);
 }
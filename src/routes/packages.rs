@@ -0,0 +1,224 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::routes::bookings::teacher_id;
+use crate::services::packages::{self, NewOffer};
+use crate::services::series::{self, NewSeries};
+use crate::POOL_DATA;
+
+// Prepaid packages and recurring bookings, see services/packages.rs and
+// services/series.rs.
+// Anyone:
+//   GET  /api/teachers/{id}/packages            the teacher's package offers at their current price
+// Teachers:
+//   GET  /api/packages/offers                   own offers, retired ones too
+//   POST /api/packages/offers                   {name, sessions, discount_percent, valid_days, unused_refund_percent}
+//   POST /api/packages/offers/{id}/retire
+// Students (lists also show what teachers sold or are booked for):
+//   GET  /api/packages
+//   POST /api/packages                          {offer_id}
+//   GET  /api/series
+//   POST /api/series                            {teacher_id, starts_at, interval_weeks?, occurrences?, package_id?}
+//   GET  /api/series/{id}                       with every occurrence
+//   POST /api/series/{id}/skip                  {index}
+//   POST /api/series/{id}/cancel                {reason?} (also the teacher)
+// Single occurrences are moved with POST /api/bookings/{id}/reschedule.
+
+fn user_id(session: &Session) -> Result<i32, HttpResponse> {
+    session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| HttpResponse::Unauthorized().json(json!({"error": "not logged in"})))
+}
+
+#[get("/api/teachers/{id}/packages")]
+async fn teacher_packages(path: web::Path<i32>) -> impl Responder {
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match packages::offers(pool_data.get_ref(), *path, false).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/packages/offers")]
+async fn my_offers(session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match packages::offers(pool_data.get_ref(), uid, true).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct OfferPayload {
+    name: String,
+    sessions: i32,
+    discount_percent: i32,
+    valid_days: i32,
+    unused_refund_percent: i32,
+}
+
+#[post("/api/packages/offers")]
+async fn create_offer(payload: web::Json<OfferPayload>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let new = NewOffer {
+        name: payload.name.clone(),
+        sessions: payload.sessions,
+        discount_percent: payload.discount_percent,
+        valid_days: payload.valid_days,
+        unused_refund_percent: payload.unused_refund_percent,
+    };
+    match packages::create_offer(pool_data.get_ref(), uid, &new).await {
+        Ok(offer) => HttpResponse::Ok().json(json!({"ok": true, "id": offer.id})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/packages/offers/{id}/retire")]
+async fn retire_offer(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match teacher_id(&session).await { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match packages::retire_offer(pool_data.get_ref(), uid, *path).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/packages")]
+async fn list_packages(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match packages::list_for(pool_data.get_ref(), uid).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct BuyPayload {
+    offer_id: i32,
+}
+
+#[post("/api/packages")]
+async fn buy_package(payload: web::Json<BuyPayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match packages::buy(pool_data.get_ref(), uid, payload.offer_id).await {
+        Ok(p) => HttpResponse::Ok().json(json!({
+            "ok": true,
+            "id": p.id,
+            "status": p.status,
+            "sessions": p.sessions_total,
+            "session_price_coins": p.session_price_coins,
+            "expires_at": p.expires_at.map(|t| t.to_rfc3339()),
+        })),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/series")]
+async fn list_series(session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match series::list_for(pool_data.get_ref(), uid).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct SeriesPayload {
+    teacher_id: i32,
+    starts_at: String,
+    interval_weeks: Option<i32>,
+    occurrences: Option<i32>,
+    package_id: Option<i32>,
+}
+
+#[post("/api/series")]
+async fn create_series(payload: web::Json<SeriesPayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let starts_at = match DateTime::parse_from_rfc3339(payload.starts_at.trim()) {
+        Ok(t) => t.with_timezone(&Utc),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"error": "starts_at must be an RFC 3339 time, as given by the slots API"})),
+    };
+    let new = NewSeries {
+        teacher_id: payload.teacher_id,
+        starts_at,
+        interval_weeks: payload.interval_weeks.unwrap_or(1),
+        occurrences: payload.occurrences,
+        package_id: payload.package_id,
+    };
+    match series::create(pool_data.get_ref(), uid, &new).await {
+        Ok((s, first)) => HttpResponse::Ok().json(json!({"ok": true, "id": s.id, "first_booking_id": first.id, "status": first.status})),
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/series/{id}")]
+async fn get_series(path: web::Path<i32>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match series::detail(pool_data.get_ref(), *path, uid).await {
+        Ok(v) => HttpResponse::Ok().json(v),
+        Err(e) => HttpResponse::NotFound().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct SkipPayload {
+    index: i32,
+}
+
+#[post("/api/series/{id}/skip")]
+async fn skip_occurrence(path: web::Path<i32>, payload: web::Json<SkipPayload>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    match series::skip(pool_data.get_ref(), *path, uid, payload.index).await {
+        Ok(mut v) => {
+            v["ok"] = json!(true);
+            HttpResponse::Ok().json(v)
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(Deserialize)]
+struct CancelPayload {
+    reason: Option<String>,
+}
+
+#[post("/api/series/{id}/cancel")]
+async fn cancel_series(path: web::Path<i32>, payload: Option<web::Json<CancelPayload>>, session: Session) -> impl Responder {
+    let uid = match user_id(&session) { Ok(id) => id, Err(resp) => return resp };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let reason = payload.as_ref().and_then(|p| p.reason.as_deref()).map(str::trim).filter(|r| !r.is_empty()).map(str::to_string);
+    match series::cancel(pool_data.get_ref(), *path, uid, reason.as_deref()).await {
+        Ok(mut v) => {
+            v["ok"] = json!(true);
+            HttpResponse::Ok().json(v)
+        }
+        Err(e) => HttpResponse::BadRequest().json(json!({"error": e.to_string()})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(teacher_packages)
+        .service(my_offers)
+        .service(create_offer)
+        .service(retire_offer)
+        .service(list_packages)
+        .service(buy_package)
+        .service(list_series)
+        .service(create_series)
+        .service(get_series)
+        .service(skip_occurrence)
+        .service(cancel_series);
+}
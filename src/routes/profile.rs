@@ -12,11 +12,11 @@ use chrono::{Utc, NaiveDate};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::services::{age, availability, kyc, kyc_expiry, quota, signed_url, storage};
 use crate::POOL_DATA;
 use argon2::{Argon2, PasswordHasher, PasswordVerifier};
 use password_hash::SaltString;
 use rand_core::OsRng;
-use sanitize_filename::sanitize;
 
 #[get("/manage_profile")]
 async fn manage_profile_page(_req: HttpRequest, _session: Session) -> actix_web::Result<actix_files::NamedFile> {
@@ -33,7 +33,7 @@ async fn api_get_profile(session: Session) -> impl Responder {
 
     if let Some(pool_data) = POOL_DATA.get() {
         let pool = pool_data.get_ref();
-        let row = sqlx::query("SELECT id, full_name, email, role, verified, birthday, avatar_path FROM users WHERE id = $1")
+        let row = sqlx::query("SELECT id, full_name, email, role, verified, birthday, avatar_path, timezone FROM users WHERE id = $1")
             .bind(user_id)
             .fetch_optional(pool)
             .await;
@@ -46,19 +46,22 @@ async fn api_get_profile(session: Session) -> impl Responder {
                 let email_verified: bool = r.get::<bool, _>("verified");
                 let birthday: Option<chrono::NaiveDate> = r.try_get("birthday").ok();
                 let avatar_path: Option<String> = r.try_get("avatar_path").ok();
+                let timezone: Option<String> = r.get("timezone");
 
                 // check teacher verification
-                let trow = sqlx::query("SELECT status, id_path, created_at FROM teacher_verifications WHERE user_id = $1 ORDER BY created_at DESC LIMIT 1")
-                    .bind(user_id)
-                    .fetch_optional(pool)
-                    .await
-                    .ok()
-                    .flatten();
-
-                let teacher_verification = if let Some(tr) = trow {
+                let case = kyc::latest_case(pool, user_id).await.ok().flatten();
+
+                let storage_usage = quota::usage(pool, user_id).await.ok();
+                let age_rules = age::standing(pool, user_id).await.ok().flatten().map(|s| s.to_json());
+                let guardians = age::guardians(pool, user_id).await.unwrap_or_default();
+
+                let teacher_verification = if let Some(c) = case {
                     serde_json::json!({
-                        "status": tr.get::<String, _>("status"),
-                        "id_path": tr.try_get::<String, _>("id_path").unwrap_or_default(),
+                        "status": c.status,
+                        "id_path": c.front_id_path.unwrap_or_default(),
+                        "reasons": kyc::reasons_json(&c.rejection_reasons),
+                        "requested_items": c.requested_items,
+                        "doc_expires_on": c.doc_expires_on.map(|d| d.to_string()),
                     })
                 } else {
                     serde_json::Value::Null
@@ -71,8 +74,13 @@ async fn api_get_profile(session: Session) -> impl Responder {
                     "role": role.unwrap_or_else(|| "student".to_string()),
                     "email_verified": email_verified,
                     "birthday": birthday.map(|d| d.to_string()),
+                    "timezone": timezone,
+                    "avatar_url": avatar_path.as_ref().map(|p| signed_url::sign_default("avatar", user_id, p)),
                     "avatar_path": avatar_path,
                     "teacher_verification": teacher_verification,
+                    "storage": storage_usage,
+                    "age": age_rules,
+                    "guardians": guardians,
                     "view_as": session.get::<String>("view_as").unwrap_or(None)
                 }));
             }
@@ -143,7 +151,10 @@ async fn api_change_name(session: Session, params: web::Json<serde_json::Value>)
     HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"}))
 }
 
-// Update basic profile fields (full_name, birthday)
+// Update basic profile fields (full_name, birthday, profile_visibility,
+// timezone).
+// A birthday confirmed by KYC, or one that makes the account a minor, can
+// only be corrected by support.
 #[post("/api/update_profile")]
 async fn api_update_profile(session: Session, params: web::Json<serde_json::Value>) -> impl Responder {
     let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
@@ -164,22 +175,59 @@ async fn api_update_profile(session: Session, params: web::Json<serde_json::Valu
         }
     };
 
-    if name_opt.is_none() && birthday_opt.is_none() {
+    let visibility = params.get("profile_visibility").and_then(|v| v.as_str()).map(|s| s.trim().to_lowercase());
+    if visibility.as_deref().map(|v| v != "public" && v != "private").unwrap_or(false) {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error":"profile_visibility must be public or private"}));
+    }
+
+    let timezone = match params.get("timezone").and_then(|v| v.as_str()).map(str::trim).filter(|s| !s.is_empty()) {
+        Some(name) => match availability::parse_tz(name) {
+            Some(tz) => Some(tz.name().to_string()),
+            None => return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("unknown timezone '{}'", name)})),
+        },
+        None => None,
+    };
+
+    if name_opt.is_none() && birthday_opt.is_none() && visibility.is_none() && timezone.is_none() {
         return HttpResponse::BadRequest().json(serde_json::json!({"error":"nothing to update"}));
     }
 
     if let Some(pool_data) = POOL_DATA.get() {
+        let pool = pool_data.get_ref();
+        if let Some(b) = birthday_opt {
+            let standing = match age::standing(pool, user_id).await {
+                Ok(Some(s)) => s,
+                Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error":"user not found"})),
+                Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+            };
+            if standing.birthday != Some(b) && (standing.birthday_verified || standing.is_minor()) {
+                return HttpResponse::Conflict().json(serde_json::json!({"error":"your date of birth is locked, contact support to correct it"}));
+            }
+            if b > chrono::Utc::now().date_naive() {
+                return HttpResponse::BadRequest().json(serde_json::json!({"error":"birthday is in the future"}));
+            }
+        }
         let res = sqlx::query(
-            "UPDATE users SET full_name = COALESCE($1, full_name), birthday = COALESCE($2, birthday) WHERE id = $3",
+            "UPDATE users SET full_name = COALESCE($1, full_name), birthday = COALESCE($2, birthday), profile_visibility = COALESCE($3, profile_visibility),
+                 timezone = COALESCE($5, timezone) WHERE id = $4",
         )
         .bind(name_opt)
         .bind(birthday_opt)
+        .bind(visibility)
         .bind(user_id)
-        .execute(pool_data.get_ref())
+        .bind(timezone)
+        .execute(pool)
         .await;
 
         match res {
-            Ok(_) => HttpResponse::Ok().json(serde_json::json!({"ok":true})),
+            Ok(_) => {
+                if birthday_opt.is_some() {
+                    if let Err(e) = age::refresh(pool, Some(user_id)).await {
+                        eprintln!("age refresh for user {} failed: {:?}", user_id, e);
+                    }
+                }
+                HttpResponse::Ok().json(serde_json::json!({"ok":true}))
+            }
             Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
         }
     } else {
@@ -233,85 +281,79 @@ async fn api_change_password(session: Session, params: web::Json<serde_json::Val
 }
 
 // Accept multipart upload for teacher ID
+/// The case a teacher's KYC upload goes to: their draft or needs_more_info
+/// case, or a new draft when they have none open or their approved case is
+/// due for renewal soon.
+async fn editable_case(pool: &sqlx::PgPool, user_id: i32) -> Result<kyc::Case, HttpResponse> {
+    match kyc::latest_case(pool, user_id).await {
+        Ok(Some(c)) if c.editable() => Ok(c),
+        Ok(Some(c)) if c.status == "submitted" || c.status == "under_review" => {
+            Err(HttpResponse::Conflict().json(serde_json::json!({"error":"verification is already under review"})))
+        }
+        Ok(Some(c)) if c.status == "approved" => match kyc_expiry::renewal_open(pool, user_id).await {
+            Ok(true) => kyc::create_draft(pool, user_id)
+                .await
+                .map_err(|e| HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)}))),
+            Ok(false) => Err(HttpResponse::Conflict().json(serde_json::json!({"error":"already verified"}))),
+            Err(e) => Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)}))),
+        },
+        Ok(_) => kyc::create_draft(pool, user_id)
+            .await
+            .map_err(|e| HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)}))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)}))),
+    }
+}
+
+async fn remove_files(paths: &[String]) {
+    for p in paths {
+        let _ = tokio::fs::remove_file(p.replace('\\', "/")).await;
+    }
+}
+
+// Legacy single-file ID upload from the profile manager. The file becomes the
+// front ID of the teacher's draft; submitting goes through /api/teacher_verify_submit.
 #[post("/api/upload_id")]
 async fn api_upload_id(session: Session, mut payload: Multipart) -> impl Responder {
     let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
         Some(id) => id,
         None => return HttpResponse::Unauthorized().json(serde_json::json!({"error":"not logged in"})),
     };
+    let pool = match POOL_DATA.get() {
+        Some(p) => p.get_ref(),
+        None => return HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"})),
+    };
+    let case = match editable_case(pool, user_id).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if !case.may_edit("front_id") {
+        return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("only {} can be resent", case.requested_items.join(", "))}));
+    }
 
-    // ensure uploads dir
-    let upload_dir = std::path::Path::new("private/teacher_ids");
-    if !upload_dir.exists() { let _ = fs::create_dir_all(upload_dir); }
-
-    // create teacher_verifications table if not exists
-    if let Some(pool_data) = POOL_DATA.get() {
-        let pool = pool_data.get_ref();
-        let _ = sqlx::query("CREATE TABLE IF NOT EXISTS teacher_verifications (id SERIAL PRIMARY KEY, user_id INTEGER NOT NULL, status VARCHAR(32) NOT NULL, id_path TEXT, created_at TIMESTAMP DEFAULT now())").execute(pool).await;
-
-        // process multipart - use tokio async file writes
-        while let Some(field_res) = payload.next().await {
-            let mut field = match field_res {
-                Ok(f) => f,
-                Err(e) => { eprintln!("multipart field error: {}", e); continue; }
-            };
-            let filename = {
-                let default = format!("upload-{}.dat", Uuid::new_v4());
-                match field.headers().get("content-disposition") {
-                    Some(hv) => match hv.to_str() {
-                        Ok(s) => {
-                            if let Some(pos) = s.find("filename=") {
-                                let mut rest = &s[pos + "filename=".len()..];
-                                // trim possible surrounding quotes
-                                rest = rest.trim();
-                                rest = rest.trim_matches(|c| c == '"' || c == '\'');
-                                if !rest.is_empty() { rest.to_string() } else { default }
-                            } else { default }
-                        }
-                        Err(_) => default,
-                    },
-                    None => default,
-                }
-            };
-            let ts = Utc::now().timestamp();
-            let filepath = upload_dir.join(format!("{}_{}", ts, sanitize_filename::sanitize(&filename)));
-
-            // create file asynchronously
-            match TokioFile::create(&filepath).await {
-                Ok(mut f) => {
-                    while let Some(chunk_res) = field.next().await {
-                        match chunk_res {
-                            Ok(chunk) => {
-                                if let Err(e) = f.write_all(&chunk).await {
-                                    eprintln!("write error: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("chunk read error: {}", e);
-                                continue;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("failed to create file {}: {}", filepath.display(), e);
-                    continue;
-                }
+    while let Some(field_res) = payload.next().await {
+        let mut field = match field_res {
+            Ok(f) => f,
+            Err(e) => { eprintln!("multipart field error: {}", e); continue; }
+        };
+        // KYC documents do not count towards the storage quota
+        let stored = match storage::save_field(&mut field, &storage::KYC, u64::MAX).await {
+            Ok(s) => s,
+            Err(e) => return e.to_response(),
+        };
+        let update = kyc::Update { front_id_path: Some(stored.path.clone()), ..Default::default() };
+        return match kyc::save(pool, &case, &update).await {
+            Ok(replaced) => {
+                remove_files(&replaced).await;
+                HttpResponse::Ok().json(serde_json::json!({"ok":true, "id": case.id, "status": case.status}))
             }
-
-            // insert or update verification record
-            let _ = sqlx::query("INSERT INTO teacher_verifications (user_id, status, id_path, created_at) VALUES ($1, $2, $3, now())")
-                .bind(user_id)
-                .bind("pending")
-                .bind(filepath.to_string_lossy().to_string())
-                .execute(pool)
-                .await;
-        }
-
-        return HttpResponse::Ok().json(serde_json::json!({"ok":true}));
+            Err(e) => {
+                remove_files(&[stored.path]).await;
+                HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}))
+            }
+        };
     }
 
-    HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"}))
+    HttpResponse::BadRequest().json(serde_json::json!({"error":"no file uploaded"}))
 }
 
 // Upload avatar photo
@@ -331,6 +373,21 @@ async fn api_upload_avatar(session: Session, mut payload: Multipart) -> impl Res
         let pool = pool_data.get_ref();
 
         let mut saved_path: Option<String> = None;
+        let mut saved_bytes: usize = 0;
+
+        // The avatar being replaced no longer counts once the new one is saved
+        let old_path: Option<String> = sqlx::query("SELECT avatar_path FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|r| r.try_get::<String, _>("avatar_path").ok());
+        let old_bytes = old_path.as_deref().map(quota::file_size).unwrap_or(0);
+        let quota_left = match quota::remaining(pool, user_id, storage::AVATAR.name).await {
+            Ok(n) => n as usize + old_bytes as usize,
+            Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+        };
 
         while let Some(field_res) = payload.next().await {
             let mut field = match field_res {
@@ -364,6 +421,10 @@ async fn api_upload_avatar(session: Session, mut payload: Multipart) -> impl Res
                                     let _ = tokio::fs::remove_file(&filepath).await;
                                     return HttpResponse::BadRequest().json(serde_json::json!({"error":"file too large"}));
                                 }
+                                if total_bytes > quota_left {
+                                    let _ = tokio::fs::remove_file(&filepath).await;
+                                    return storage::UploadError::QuotaExceeded(quota_left as u64).to_response();
+                                }
                                 if let Err(e) = f.write_all(&chunk).await {
                                     eprintln!("write error: {}", e);
                                 }
@@ -382,6 +443,7 @@ async fn api_upload_avatar(session: Session, mut payload: Multipart) -> impl Res
             }
 
             saved_path = Some(filepath.to_string_lossy().to_string());
+            saved_bytes = total_bytes;
             break; // handle one file only
         }
 
@@ -392,7 +454,14 @@ async fn api_upload_avatar(session: Session, mut payload: Multipart) -> impl Res
                 .execute(pool)
                 .await;
             match res {
-                Ok(_) => return HttpResponse::Ok().json(serde_json::json!({"ok":true, "avatar_path": path})),
+                Ok(_) => {
+                    let files_delta = if old_path.is_some() { 0 } else { 1 };
+                    let _ = quota::record(pool, user_id, storage::AVATAR.name, saved_bytes as i64 - old_bytes, files_delta).await;
+                    if let Some(old) = old_path {
+                        let _ = tokio::fs::remove_file(old.replace('\\', "/")).await;
+                    }
+                    return HttpResponse::Ok().json(serde_json::json!({"ok":true, "avatar_path": path}));
+                }
                 Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
             }
         }
@@ -402,24 +471,30 @@ async fn api_upload_avatar(session: Session, mut payload: Multipart) -> impl Res
     HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"}))
 }
 
-// Submit teacher verification with KYC details and front/back IDs
+// Save teacher verification details and front/back IDs, then send the case
+// for review unless `action` is "save". Fields left out keep their saved
+// value; after a needs_more_info decision only the requested items are taken.
 #[post("/api/teacher_verify_submit")]
 async fn api_teacher_verify_submit(session: Session, mut payload: Multipart) -> impl Responder {
     let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
         Some(id) => id,
         None => return HttpResponse::Unauthorized().json(serde_json::json!({"error":"not logged in"})),
     };
+    let pool = match POOL_DATA.get() {
+        Some(p) => p.get_ref(),
+        None => return HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"})),
+    };
+    let case = match editable_case(pool, user_id).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
 
-    // ensure uploads dir
-    let upload_dir = std::path::Path::new("uploads/teacher_ids");
-    if !upload_dir.exists() { let _ = fs::create_dir_all(upload_dir); }
-
-    let mut full_name: Option<String> = None;
-    let mut dob: Option<NaiveDate> = None;
-    let mut gender: Option<String> = None;
-    let mut address: Option<String> = None;
-    let mut front_path: Option<String> = None;
-    let mut back_path: Option<String> = None;
+    let mut update = kyc::Update::default();
+    let mut action = "submit".to_string();
+    // hashed below, never stored as entered
+    let mut doc_number: Option<String> = None;
+    // files written by this request, removed again if it fails
+    let mut stored: Vec<String> = Vec::new();
 
     while let Some(field_res) = payload.next().await {
         let mut field = match field_res {
@@ -427,10 +502,10 @@ async fn api_teacher_verify_submit(session: Session, mut payload: Multipart) ->
             Err(e) => { eprintln!("multipart field error: {}", e); continue; }
         };
 
-            let name = field.name().to_string();
+        let name = field.name().to_string();
 
         // handle text fields streamed as a single chunk
-        if name == "full_name" || name == "gender" || name == "address" || name == "dob" {
+        if ["full_name", "gender", "address", "dob", "doc_type", "doc_number", "doc_expires_on", "action"].contains(&name.as_str()) {
             let mut data = Vec::new();
             while let Some(chunk_res) = field.next().await {
                 match chunk_res {
@@ -439,19 +514,30 @@ async fn api_teacher_verify_submit(session: Session, mut payload: Multipart) ->
                 }
             }
             let s = String::from_utf8_lossy(&data).trim().to_string();
+            if s.is_empty() {
+                continue;
+            }
             match name.as_str() {
-                "full_name" => full_name = Some(s),
-                "gender" => gender = Some(s),
-                "address" => address = Some(s),
-                "dob" => {
-                    if s.is_empty() { dob = None; }
-                    else {
-                        match NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
-                            Ok(d) => dob = Some(d),
-                            Err(_) => return HttpResponse::BadRequest().json(serde_json::json!({"error":"invalid dob format"})),
-                        }
+                "full_name" => update.full_name = Some(s),
+                "gender" => update.gender = Some(s),
+                "address" => update.address = Some(s),
+                "action" => action = s,
+                "doc_type" => update.doc_type = Some(s.to_lowercase()),
+                "doc_number" => doc_number = Some(s),
+                "doc_expires_on" => match NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
+                    Ok(d) => update.doc_expires_on = Some(d),
+                    Err(_) => {
+                        remove_files(&stored).await;
+                        return HttpResponse::BadRequest().json(serde_json::json!({"error":"invalid document expiry date"}));
                     }
-                }
+                },
+                "dob" => match NaiveDate::parse_from_str(&s, "%Y-%m-%d") {
+                    Ok(d) => update.dob = Some(d),
+                    Err(_) => {
+                        remove_files(&stored).await;
+                        return HttpResponse::BadRequest().json(serde_json::json!({"error":"invalid dob format"}));
+                    }
+                },
                 _ => {}
             }
             continue;
@@ -459,75 +545,128 @@ async fn api_teacher_verify_submit(session: Session, mut payload: Multipart) ->
 
         // file fields: front_id, back_id
         if name == "front_id" || name == "back_id" {
-            let ct = field.content_type();
-            if !ct.type_().as_str().eq_ignore_ascii_case("image") {
-                return HttpResponse::BadRequest().json(serde_json::json!({"error":"only image uploads allowed"}));
+            if !case.may_edit(&name) {
+                remove_files(&stored).await;
+                return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("only {} can be resent", case.requested_items.join(", "))}));
             }
-            let ts = Utc::now().timestamp();
-            let fname = format!("{}-{}-{}.dat", name, user_id, ts);
-            let filepath = upload_dir.join(&fname);
-            let mut total_bytes: usize = 0;
-
-            match TokioFile::create(&filepath).await {
-                Ok(mut f) => {
-                    while let Some(chunk_res) = field.next().await {
-                        match chunk_res {
-                            Ok(chunk) => {
-                                total_bytes += chunk.len();
-                                if total_bytes > 25 * 1024 * 1024 { // 25MB cap
-                                    let _ = tokio::fs::remove_file(&filepath).await;
-                                    return HttpResponse::BadRequest().json(serde_json::json!({"error":"file too large (max 25MB)"}));
-                                }
-                                if let Err(e) = f.write_all(&chunk).await {
-                                    eprintln!("write error: {}", e);
-                                }
-                            }
-                            Err(e) => { eprintln!("chunk read error: {}", e); continue; }
-                        }
-                    }
-                }
+            // KYC documents do not count towards the storage quota
+            let file = match storage::save_field(&mut field, &storage::KYC, u64::MAX).await {
+                Ok(f) => f,
                 Err(e) => {
-                    eprintln!("failed to create file {}: {}", filepath.display(), e);
-                    continue;
+                    remove_files(&stored).await;
+                    return e.to_response();
                 }
-            }
+            };
+            stored.push(file.path.clone());
+            if name == "front_id" { update.front_id_path = Some(file.path); }
+            else { update.back_id_path = Some(file.path); }
+        }
+    }
 
-            let rel_path = filepath.to_string_lossy().to_string();
-            if name == "front_id" { front_path = Some(rel_path.clone()); }
-            if name == "back_id" { back_path = Some(rel_path); }
+    if action != "submit" && action != "save" {
+        remove_files(&stored).await;
+        return HttpResponse::BadRequest().json(serde_json::json!({"error":"action must be submit or save"}));
+    }
+    // the hash covers the document type, so a new type needs the number again
+    let doc_type = update.doc_type.clone().or_else(|| case.doc_type.clone());
+    match (doc_number, doc_type) {
+        (Some(n), Some(t)) => match kyc::hash_document_number(&t, &n) {
+            Ok(hash) => update.doc_number_hash = hash,
+            Err(e) => {
+                eprintln!("KYC upload for user {} refused: {}", user_id, e);
+                remove_files(&stored).await;
+                return HttpResponse::ServiceUnavailable().json(serde_json::json!({"error":"verification is temporarily unavailable"}));
+            }
+        },
+        (Some(_), None) => {
+            remove_files(&stored).await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error":"choose the document type"}));
+        }
+        (None, _) if update.doc_type.is_some() && update.doc_type != case.doc_type => {
+            remove_files(&stored).await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error":"enter the document number for the new document type"}));
         }
+        _ => {}
     }
 
-    // validations
-    if full_name.as_deref().unwrap_or("").is_empty() || dob.is_none() || gender.as_deref().unwrap_or("").is_empty() || address.as_deref().unwrap_or("").is_empty() {
-        return HttpResponse::BadRequest().json(serde_json::json!({"error":"all fields are required"}));
+    match kyc::save(pool, &case, &update).await {
+        Ok(replaced) => remove_files(&replaced).await,
+        Err(e) => {
+            remove_files(&stored).await;
+            return HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string()}));
+        }
     }
-    if front_path.is_none() || back_path.is_none() {
-        return HttpResponse::BadRequest().json(serde_json::json!({"error":"front and back ID required"}));
+    if action == "save" {
+        return HttpResponse::Ok().json(serde_json::json!({"ok":true, "id": case.id, "status": case.status}));
     }
 
-    if let Some(pool_data) = POOL_DATA.get() {
-        let pool = pool_data.get_ref();
-        let res = sqlx::query("INSERT INTO teacher_verifications (user_id, status, id_path, full_name, dob, gender, address, front_id_path, back_id_path, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())")
-            .bind(user_id)
-            .bind("pending")
-            .bind(front_path.as_ref().unwrap())
-            .bind(full_name.as_ref().unwrap())
-            .bind(dob.unwrap())
-            .bind(gender.as_ref().unwrap())
-            .bind(address.as_ref().unwrap())
-            .bind(front_path.as_ref().unwrap())
-            .bind(back_path.as_ref().unwrap())
-            .execute(pool)
-            .await;
+    let case = match kyc::case(pool, case.id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::NotFound().json(serde_json::json!({"error":"verification not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+    };
+    match kyc::submit(pool, &case).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"ok":true, "id": case.id, "status": "submitted"})),
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"error": e.to_string(), "missing_items": case.missing_items(), "id": case.id, "status": case.status})),
+    }
+}
 
-        match res {
-            Ok(_) => return HttpResponse::Ok().json(serde_json::json!({"ok":true})),
-            Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+// The teacher's current verification case: what reviewers said, what is
+// still missing, and how it got here.
+#[get("/api/kyc")]
+async fn api_kyc_status(session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(serde_json::json!({"error":"not logged in"})),
+    };
+    let pool = match POOL_DATA.get() {
+        Some(p) => p.get_ref(),
+        None => return HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"})),
+    };
+    let case = match kyc::latest_case(pool, user_id).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::Ok().json(serde_json::json!({"case": null, "history": []})),
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+    };
+    // also while a renewal case is open next to the approved one
+    let reverify_by = kyc_expiry::due_on(pool, user_id).await.ok().flatten();
+    let mut history = match kyc::history(pool, case.id).await {
+        Ok(h) => h,
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+    };
+    // reviewers stay anonymous to the teacher
+    for h in history.iter_mut() {
+        if let Some(obj) = h.as_object_mut() {
+            obj.remove("actor_id");
+            obj.remove("actor_name");
         }
     }
-
-    HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"}))
+    HttpResponse::Ok().json(serde_json::json!({
+        "case": {
+            "id": case.id,
+            "status": case.status,
+            "editable": case.editable(),
+            "full_name": case.full_name,
+            "dob": case.dob.map(|d| d.to_string()),
+            "gender": case.gender,
+            "address": case.address,
+            "doc_type": case.doc_type,
+            "has_doc_number": case.doc_number_hash.is_some(),
+            "doc_expires_on": case.doc_expires_on.map(|d| d.to_string()),
+            "reverify_by": reverify_by.map(|d| d.to_string()),
+            "has_front_id": case.front_id_path.is_some(),
+            "has_back_id": case.back_id_path.is_some(),
+            "reasons": kyc::reasons_json(&case.rejection_reasons),
+            "requested_items": case.requested_items,
+            "resent_items": case.resent_items,
+            "missing_items": if case.editable() { case.missing_items() } else { Vec::new() },
+            "note": case.decided_at.and(case.admin_note.clone()).filter(|n| !n.is_empty()),
+            "submission_count": case.submission_count,
+            "submitted_at": case.submitted_at.map(|d| d.to_rfc3339()),
+            "decided_at": case.decided_at.map(|d| d.to_rfc3339()),
+        },
+        "history": history,
+    }))
 }
 
 #[post("/api/request_email_verification")]
@@ -553,105 +692,6 @@ async fn api_request_email_verification(session: Session) -> impl Responder {
     HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"}))
 }
 
-// Allow user to submit a support/request ticket to admins with optional attachment
-#[post("/api/support_request")]
-async fn api_support_request(session: Session, mut payload: Multipart) -> impl Responder {
-    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
-        Some(id) => id,
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({"error":"not logged in"})),
-    };
-
-    let role = session.get::<String>("role").unwrap_or(None).unwrap_or_else(|| "student".to_string());
-
-    let pool_data = match POOL_DATA.get() {
-        Some(p) => p,
-        None => return HttpResponse::InternalServerError().json(serde_json::json!({"error":"no db"})),
-    };
-
-    // derive latest kyc status
-    let kyc_status = match sqlx::query("SELECT status FROM teacher_verifications WHERE user_id = $1 ORDER BY updated_at DESC NULLS LAST, created_at DESC LIMIT 1")
-        .bind(user_id)
-        .fetch_optional(pool_data.get_ref())
-        .await
-    {
-        Ok(Some(r)) => r.try_get::<String,_>("status").unwrap_or_else(|_| "unverified".to_string()),
-        _ => "unverified".to_string(),
-    };
-
-    let mut body_text: Option<String> = None;
-    let mut attachment_path: Option<String> = None;
-    let upload_dir = std::path::Path::new("uploads/support_requests");
-    if !upload_dir.exists() { let _ = fs::create_dir_all(upload_dir); }
-
-    while let Some(field_res) = payload.next().await {
-        let mut field = match field_res {
-            Ok(f) => f,
-            Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("multipart error: {}", e)})),
-        };
-        let name = field.name().to_string();
-        if name == "attachment" {
-            let mut total: u64 = 0;
-            let original = field.content_disposition().get_filename().map(|v| sanitize(v));
-            let safe_name = original.unwrap_or_else(|| format!("attach-{}.bin", Uuid::new_v4()));
-            let full_path = upload_dir.join(format!("{}-{}", Uuid::new_v4(), safe_name));
-            let mut f = match TokioFile::create(&full_path).await {
-                Ok(file) => file,
-                Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("file create: {}", e)})),
-            };
-            while let Some(chunk) = field.next().await {
-                let data = match chunk {
-                    Ok(d) => d,
-                    Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("upload chunk: {}", e)})),
-                };
-                total += data.len() as u64;
-                if total > 25 * 1024 * 1024 {
-                    let _ = fs::remove_file(&full_path);
-                    return HttpResponse::BadRequest().json(serde_json::json!({"error": "attachment too large (max 25MB)"}));
-                }
-                if let Err(e) = f.write_all(&data).await {
-                    return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("write failed: {}", e)}));
-                }
-            }
-            attachment_path = Some(full_path.to_string_lossy().to_string());
-        } else {
-            let mut bytes = Vec::new();
-            while let Some(chunk) = field.next().await {
-                let data = match chunk {
-                    Ok(d) => d,
-                    Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({"error": format!("field read: {}", e)})),
-                };
-                bytes.extend_from_slice(&data);
-            }
-            let text = String::from_utf8(bytes).unwrap_or_default();
-            if name == "body" || name == "message" {
-                body_text = Some(text.trim().to_string());
-            }
-        }
-    }
-
-    let body_val = match body_text {
-        Some(b) if !b.is_empty() => b,
-        _ => return HttpResponse::BadRequest().json(serde_json::json!({"error": "message required"})),
-    };
-
-    let row = sqlx::query("INSERT INTO support_requests (user_id, role, kyc_status, body, attachment_path) VALUES ($1, $2, $3, $4, $5) RETURNING id")
-        .bind(user_id)
-        .bind(&role)
-        .bind(&kyc_status)
-        .bind(&body_val)
-        .bind(&attachment_path)
-        .fetch_one(pool_data.get_ref())
-        .await;
-
-    match row {
-        Ok(r) => {
-            let id: i32 = r.get("id");
-            HttpResponse::Ok().json(serde_json::json!({"ok": true, "id": id}))
-        }
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
-    }
-}
-
 #[post("/api/view_as")]
 async fn api_view_as(session: Session, params: web::Json<serde_json::Value>) -> impl Responder {
     let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
@@ -666,6 +706,15 @@ async fn api_view_as(session: Session, params: web::Json<serde_json::Value>) ->
     if save {
         if let Some(pool_data) = POOL_DATA.get() {
             let pool = pool_data.get_ref();
+            if view_as == "teacher" {
+                match age::standing(pool, user_id).await {
+                    Ok(Some(s)) if !s.may_teach() => {
+                        return HttpResponse::Forbidden().json(serde_json::json!({"error": format!("teachers must be at least {} years old", age::adult_age())}));
+                    }
+                    Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({"error": format!("db: {}", e)})),
+                    _ => {}
+                }
+            }
             let upd = sqlx::query("UPDATE users SET role = $1 WHERE id = $2").bind(view_as).bind(user_id).execute(pool).await;
             match upd {
                 Ok(_) => return HttpResponse::Ok().json(serde_json::json!({"ok":true})),
@@ -690,7 +739,7 @@ pub fn init(cfg: &mut web::ServiceConfig) {
         .service(api_upload_id)
     .service(api_upload_avatar)
         .service(api_teacher_verify_submit)
+        .service(api_kyc_status)
         .service(api_request_email_verification)
-        .service(api_support_request)
         .service(api_view_as);
 }
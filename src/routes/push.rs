@@ -0,0 +1,323 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::SecretKey;
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::services::email;
+use crate::services::web_push::{self, Outcome};
+use crate::POOL_DATA;
+
+// Web Push subscription management:
+//   GET  /api/push/public_key               VAPID key for pushManager.subscribe (null when push is off)
+//   GET  /api/push/subscriptions            the user's registered browsers
+//   POST /api/push/subscriptions            register PushSubscription.toJSON()
+//   POST /api/push/subscriptions/delete     unregister by endpoint
+//   POST /api/push/test                     send a test message to all of the user's browsers
+// With WEB_PUSH_MOCK=1 the server also acts as a push service for local testing:
+//   POST /api/push/mock/subscribe?status=   register a server-held subscription for the user
+//   POST /push-mock/{token}                 the mock endpoint; checks VAPID, decrypts, answers `status`
+//   GET  /api/push/mock/{token}             what the mock endpoint received
+
+#[derive(Deserialize)]
+struct SubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+#[derive(Deserialize)]
+struct SubscribePayload {
+    endpoint: String,
+    keys: SubscriptionKeys,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribePayload {
+    endpoint: String,
+}
+
+#[get("/api/push/public_key")]
+async fn push_public_key() -> impl Responder {
+    HttpResponse::Ok().json(json!({"enabled": web_push::enabled(), "public_key": web_push::public_key()}))
+}
+
+#[get("/api/push/subscriptions")]
+async fn list_subscriptions(session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let rows = sqlx::query(
+        "SELECT id, endpoint, user_agent, created_at, last_success_at, failure_count FROM push_subscriptions WHERE user_id = $1 ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(pool_data.get_ref())
+    .await;
+
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .iter()
+                .map(|r| {
+                    json!({
+                        "id": r.get::<i32,_>("id"),
+                        "endpoint": r.get::<String,_>("endpoint"),
+                        "user_agent": r.get::<Option<String>,_>("user_agent"),
+                        "created_at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+                        "last_success_at": r.get::<Option<DateTime<Utc>>,_>("last_success_at").map(|d| d.to_rfc3339()),
+                        "failure_count": r.get::<i32,_>("failure_count"),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+async fn register_subscription(pool: &sqlx::PgPool, user_id: i32, endpoint: &str, p256dh: &str, auth: &str, user_agent: Option<&str>) -> sqlx::Result<i32> {
+    // An endpoint belongs to one browser; re-registering moves it to the current user
+    let row = sqlx::query(
+        "INSERT INTO push_subscriptions (user_id, endpoint, p256dh, auth, user_agent) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (endpoint) DO UPDATE SET user_id = EXCLUDED.user_id, p256dh = EXCLUDED.p256dh, auth = EXCLUDED.auth,
+             user_agent = EXCLUDED.user_agent, failure_count = 0
+         RETURNING id",
+    )
+    .bind(user_id)
+    .bind(endpoint)
+    .bind(p256dh)
+    .bind(auth)
+    .bind(user_agent)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.get("id"))
+}
+
+#[post("/api/push/subscriptions")]
+async fn subscribe(payload: web::Json<SubscribePayload>, session: Session, req: HttpRequest) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    if !web_push::enabled() {
+        return HttpResponse::ServiceUnavailable().json(json!({"error": "web push is not configured"}));
+    }
+    if let Err(e) = web_push::validate_endpoint(&payload.endpoint)
+        .and_then(|_| web_push::validate_keys(&payload.keys.p256dh, &payload.keys.auth))
+    {
+        return HttpResponse::BadRequest().json(json!({"error": e.to_string()}));
+    }
+
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.chars().take(300).collect::<String>());
+    match register_subscription(pool_data.get_ref(), user_id, &payload.endpoint, &payload.keys.p256dh, &payload.keys.auth, user_agent.as_deref()).await {
+        Ok(id) => HttpResponse::Ok().json(json!({"ok": true, "id": id})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/push/subscriptions/delete")]
+async fn unsubscribe(payload: web::Json<UnsubscribePayload>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let res = sqlx::query("DELETE FROM push_subscriptions WHERE endpoint = $1 AND user_id = $2")
+        .bind(&payload.endpoint)
+        .bind(user_id)
+        .execute(pool_data.get_ref())
+        .await;
+    match res {
+        Ok(r) => HttpResponse::Ok().json(json!({"ok": true, "deleted": r.rows_affected()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/push/test")]
+async fn send_test(session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    if !web_push::enabled() {
+        return HttpResponse::ServiceUnavailable().json(json!({"error": "web push is not configured"}));
+    }
+
+    let payload = json!({
+        "title": "Test notification",
+        "body": "Push notifications are working on this device.",
+        "category": "system",
+        "priority": "normal",
+        "url": "/settings/notifications",
+    })
+    .to_string();
+    match web_push::send_to_user(pool_data.get_ref(), user_id, payload.as_bytes()).await {
+        Ok(results) => {
+            let items: Vec<serde_json::Value> = results
+                .into_iter()
+                .map(|(id, outcome)| match outcome {
+                    Outcome::Delivered => json!({"id": id, "result": "delivered"}),
+                    Outcome::Gone => json!({"id": id, "result": "removed"}),
+                    Outcome::Failed(e) => json!({"id": id, "result": "failed", "error": e}),
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// A subscription whose browser side lives in this process.
+struct MockEndpoint {
+    user_id: i32,
+    secret: SecretKey,
+    auth: [u8; 16],
+    /// HTTP status the mock answers with, e.g. 410 to exercise cleanup
+    status: u16,
+    received: Vec<serde_json::Value>,
+}
+
+static MOCK_ENDPOINTS: Lazy<Mutex<HashMap<String, MockEndpoint>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Most messages kept per mock endpoint.
+const MOCK_KEEP: usize = 100;
+
+#[derive(Deserialize)]
+struct MockSubscribeQuery {
+    status: Option<u16>,
+}
+
+#[post("/api/push/mock/subscribe")]
+async fn mock_subscribe(query: web::Query<MockSubscribeQuery>, session: Session) -> impl Responder {
+    if !web_push::mock_enabled() {
+        return HttpResponse::NotFound().json(json!({"error": "not found"}));
+    }
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    if !web_push::enabled() {
+        return HttpResponse::ServiceUnavailable().json(json!({"error": "web push is not configured"}));
+    }
+
+    let secret = SecretKey::random(&mut OsRng);
+    let mut auth = [0u8; 16];
+    OsRng.fill_bytes(&mut auth);
+    let mut token_bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = hex::encode(token_bytes);
+    let endpoint = format!("{}/push-mock/{}", email::base_url(), token);
+    let p256dh = web_push::b64_encode(secret.public_key().to_encoded_point(false).as_bytes());
+
+    if let Err(e) = register_subscription(pool_data.get_ref(), user_id, &endpoint, &p256dh, &web_push::b64_encode(&auth), Some("mock")).await {
+        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+    }
+    let status = query.status.unwrap_or(201);
+    if let Ok(mut mocks) = MOCK_ENDPOINTS.lock() {
+        mocks.insert(token.clone(), MockEndpoint { user_id, secret, auth, status, received: Vec::new() });
+    }
+    HttpResponse::Ok().json(json!({"token": token, "endpoint": endpoint, "status": status}))
+}
+
+#[post("/push-mock/{token}")]
+async fn mock_receive(path: web::Path<String>, body: web::Bytes, req: HttpRequest) -> impl Responder {
+    if !web_push::mock_enabled() {
+        return HttpResponse::NotFound().finish();
+    }
+    let token = path.into_inner();
+    let endpoint = format!("{}/push-mock/{}", email::base_url(), token);
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+    let mut mocks = match MOCK_ENDPOINTS.lock() {
+        Ok(m) => m,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let mock = match mocks.get_mut(&token) {
+        Some(m) => m,
+        None => return HttpResponse::Gone().finish(),
+    };
+
+    // Record what a real push service would check, then answer with the configured status
+    let vapid = header("Authorization")
+        .ok_or_else(|| anyhow::anyhow!("missing Authorization"))
+        .and_then(|a| web_push::verify_vapid(&a, &endpoint));
+    let decrypted = web_push::decrypt(&mock.secret, &mock.auth, &body);
+    let entry = json!({
+        "received_at": Utc::now().to_rfc3339(),
+        "vapid_ok": vapid.is_ok(),
+        "vapid_error": vapid.err().map(|e| e.to_string()),
+        "content_encoding": header("Content-Encoding"),
+        "ttl": header("TTL"),
+        "urgency": header("Urgency"),
+        "payload": decrypted.as_ref().ok().and_then(|p| serde_json::from_slice::<serde_json::Value>(p).ok()),
+        "decrypt_error": decrypted.err().map(|e| e.to_string()),
+    });
+    if mock.received.len() == MOCK_KEEP {
+        mock.received.remove(0);
+    }
+    mock.received.push(entry);
+
+    HttpResponse::build(actix_web::http::StatusCode::from_u16(mock.status).unwrap_or(actix_web::http::StatusCode::CREATED)).finish()
+}
+
+#[get("/api/push/mock/{token}")]
+async fn mock_received(path: web::Path<String>, session: Session) -> impl Responder {
+    if !web_push::mock_enabled() {
+        return HttpResponse::NotFound().json(json!({"error": "not found"}));
+    }
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let mocks = match MOCK_ENDPOINTS.lock() {
+        Ok(m) => m,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"error": "lock poisoned"})),
+    };
+    match mocks.get(path.as_str()) {
+        Some(m) if m.user_id == user_id => HttpResponse::Ok().json(json!({"status": m.status, "items": m.received})),
+        _ => HttpResponse::NotFound().json(json!({"error": "not found"})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(push_public_key)
+        .service(list_subscriptions)
+        .service(subscribe)
+        .service(unsubscribe)
+        .service(send_test)
+        .service(mock_subscribe)
+        .service(mock_receive)
+        .service(mock_received);
+}
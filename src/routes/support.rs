@@ -0,0 +1,1035 @@
+use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_session::Session;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::postgres::PgRow;
+use sqlx::Row;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::routes::admin::{ensure_admin, ensure_staff};
+use crate::services::{support, support_mail, support_sla};
+use crate::services::{quota, resumable, signed_url, storage};
+use crate::POOL_DATA;
+
+// Support tickets.
+// User side:
+//   POST /api/support/tickets               open a ticket (multipart: subject, body, attachment/upload_id)
+//   GET  /api/support/tickets               my tickets
+//   GET  /api/support/tickets/{id}          one ticket with its thread
+//   POST /api/support/tickets/{id}/messages reply (multipart)
+//   POST /api/support/tickets/{id}/close    confirm a resolved ticket
+// Staff (admins and agents):
+//   GET  /api/admin/support_requests        queue, filtered by status/assignee
+//   GET  /api/admin/support_requests/{id}   ticket with thread
+//   POST /api/admin/support_requests/{id}/messages  reply (multipart, optional `status`)
+//   POST /api/admin/support_requests/{id}/assign    {assignee_id} (null to unassign)
+//   POST /api/admin/support_requests/{id}/status    {status}, per the workflow in services/support.rs
+//   POST /api/admin/support_requests/{id}/priority  {priority}, moves the SLA due times
+//   GET  /api/admin/support_requests/{id}/canned/{canned_id}  canned reply filled in for the ticket
+//   GET/POST /api/admin/support/canned, POST /api/admin/support/canned/{id}/update|delete
+//   GET  /api/admin/support/metrics?days=30        backlog, median response times, SLA breaches
+//   POST /api/admin/support/inbound                 file a raw email (admin only), GET lists received mail
+// POST /api/support_request is the original form endpoint and opens a ticket too.
+
+/// Text fields and attachment of a ticket message form.
+struct MessageForm {
+    fields: HashMap<String, String>,
+    attachment_path: Option<String>,
+    attachment_bytes: i64,
+}
+
+impl MessageForm {
+    fn text(&self, name: &str) -> Option<String> {
+        self.fields.get(name).map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+    }
+
+    /// The message text (`body`, or `message` as the old form sent it).
+    fn body(&self) -> Result<String, HttpResponse> {
+        self.text("body")
+            .or_else(|| self.text("message"))
+            .ok_or_else(|| HttpResponse::BadRequest().json(json!({"error": "message required"})))
+    }
+}
+
+/// Read a message form, storing the attachment (charged to `uploader`) or
+/// taking a finished resumable upload given as `upload_id`.
+async fn read_message_form(pool: &sqlx::PgPool, uploader: i32, payload: &mut Multipart) -> Result<MessageForm, HttpResponse> {
+    let mut form = MessageForm { fields: HashMap::new(), attachment_path: None, attachment_bytes: 0 };
+
+    let quota_left = match quota::remaining(pool, uploader, storage::SUPPORT.name).await {
+        Ok(n) => n,
+        Err(e) => return Err(HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))),
+    };
+
+    while let Some(field_res) = payload.next().await {
+        let mut field = match field_res {
+            Ok(f) => f,
+            Err(e) => return Err(HttpResponse::BadRequest().json(json!({"error": format!("multipart error: {}", e)}))),
+        };
+        let name = field.name().to_string();
+        if name == "attachment" {
+            match storage::save_field(&mut field, &storage::SUPPORT, quota_left).await {
+                Ok(stored) => {
+                    form.attachment_bytes = stored.bytes as i64;
+                    form.attachment_path = Some(stored.path);
+                }
+                Err(e) => return Err(e.to_response()),
+            }
+        } else {
+            let mut bytes = Vec::new();
+            while let Some(chunk) = field.next().await {
+                let data = match chunk {
+                    Ok(d) => d,
+                    Err(e) => return Err(HttpResponse::BadRequest().json(json!({"error": format!("field read: {}", e)}))),
+                };
+                bytes.extend_from_slice(&data);
+            }
+            form.fields.insert(name, String::from_utf8(bytes).unwrap_or_default());
+        }
+    }
+
+    // Attachment sent earlier through the resumable upload endpoints
+    if form.attachment_path.is_none() {
+        if let Some(id) = form.text("upload_id") {
+            match resumable::take_completed(pool, &id, uploader, storage::SUPPORT.name).await {
+                Ok(Some(p)) => {
+                    form.attachment_bytes = quota::file_size(&p);
+                    form.attachment_path = Some(p);
+                }
+                Ok(None) => return Err(HttpResponse::BadRequest().json(json!({"error": "upload_id is not a completed upload"}))),
+                Err(e) => return Err(HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))),
+            }
+        }
+    }
+
+    Ok(form)
+}
+
+fn timestamp(r: &PgRow, col: &str) -> Option<String> {
+    r.try_get::<Option<DateTime<Utc>>, _>(col)
+        .ok()
+        .flatten()
+        .or_else(|| {
+            r.try_get::<Option<NaiveDateTime>, _>(col)
+                .ok()
+                .flatten()
+                .map(|d| DateTime::<Utc>::from_naive_utc_and_offset(d, Utc))
+        })
+        .map(|d| d.to_rfc3339())
+}
+
+const TICKET_SELECT: &str = "SELECT sr.id, sr.user_id, sr.role, sr.kyc_status, sr.subject, sr.body, sr.status, sr.priority, sr.assignee_id,
+        sr.created_at, sr.updated_at, sr.last_message_at, sr.resolved_at, sr.closed_at,
+        sr.first_response_at, sr.first_response_due_at, sr.resolution_due_at, sr.sla_paused_at,
+        COALESCE(sr.first_response_breached_at IS NOT NULL OR sr.first_response_at > sr.first_response_due_at
+            OR (sr.first_response_at IS NULL AND sr.first_response_due_at < now()), FALSE) AS first_response_breached,
+        COALESCE(sr.resolution_breached_at IS NOT NULL
+            OR (sr.status IN ('new', 'open') AND sr.sla_paused_at IS NULL AND sr.resolution_due_at < now()), FALSE) AS resolution_breached,
+        u.full_name, u.email, a.full_name AS assignee_name,
+        (SELECT COUNT(*) FROM support_messages m WHERE m.request_id = sr.id) AS message_count,
+        (SELECT m.author_kind FROM support_messages m WHERE m.request_id = sr.id ORDER BY m.id DESC LIMIT 1) AS last_author
+     FROM support_requests sr
+     JOIN users u ON u.id = sr.user_id
+     LEFT JOIN users a ON a.id = sr.assignee_id";
+
+fn ticket_json(r: &PgRow) -> serde_json::Value {
+    json!({
+        "id": r.get::<i32,_>("id"),
+        "user_id": r.get::<i32,_>("user_id"),
+        "role": r.get::<String,_>("role"),
+        "kyc_status": r.get::<String,_>("kyc_status"),
+        "subject": r.get::<Option<String>,_>("subject"),
+        "body": r.get::<String,_>("body"),
+        "status": r.get::<String,_>("status"),
+        "priority": r.get::<String,_>("priority"),
+        "assignee_id": r.get::<Option<i32>,_>("assignee_id"),
+        "assignee_name": r.get::<Option<String>,_>("assignee_name"),
+        "user_name": r.get::<String,_>("full_name"),
+        "user_email": r.get::<String,_>("email"),
+        "message_count": r.get::<i64,_>("message_count"),
+        "last_author": r.get::<Option<String>,_>("last_author"),
+        "created_at": timestamp(r, "created_at"),
+        "updated_at": timestamp(r, "updated_at"),
+        "last_message_at": timestamp(r, "last_message_at"),
+        "resolved_at": timestamp(r, "resolved_at"),
+        "closed_at": timestamp(r, "closed_at"),
+        "sla": {
+            "first_response_at": timestamp(r, "first_response_at"),
+            "first_response_due_at": timestamp(r, "first_response_due_at"),
+            "resolution_due_at": timestamp(r, "resolution_due_at"),
+            "paused": r.get::<Option<DateTime<Utc>>,_>("sla_paused_at").is_some(),
+            "first_response_breached": r.get::<bool,_>("first_response_breached"),
+            "resolution_breached": r.get::<bool,_>("resolution_breached"),
+        },
+    })
+}
+
+/// A ticket as its owner sees it: who handles it, but not staff-only details.
+fn user_view(mut t: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = t.as_object_mut() {
+        obj.remove("kyc_status");
+        obj.remove("user_email");
+        obj.remove("sla");
+    }
+    t
+}
+
+/// Messages of a ticket, oldest first.
+async fn thread(pool: &sqlx::PgPool, request_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(
+        "SELECT m.id, m.author_id, m.author_kind, m.body, m.attachment_path, m.created_at, u.full_name
+         FROM support_messages m LEFT JOIN users u ON u.id = m.author_id
+         WHERE m.request_id = $1 ORDER BY m.id",
+    )
+    .bind(request_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let id: i32 = r.get("id");
+            let attachment_path: Option<String> = r.get("attachment_path");
+            json!({
+                "id": id,
+                "author_id": r.get::<Option<i32>,_>("author_id"),
+                "author_kind": r.get::<String,_>("author_kind"),
+                "author_name": r.get::<Option<String>,_>("full_name"),
+                "body": r.get::<String,_>("body"),
+                "attachment_url": attachment_path.as_ref().map(|_| format!("/api/support/messages/{}/attachment", id)),
+                "attachment_signed_url": attachment_path.as_ref().map(|p| signed_url::sign_default("support-message", id, p)),
+                "created_at": timestamp(r, "created_at"),
+            })
+        })
+        .collect())
+}
+
+async fn open_ticket(session: Session, mut payload: Multipart) -> HttpResponse {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let role = session.get::<String>("role").unwrap_or(None).unwrap_or_else(|| "student".to_string());
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let form = match read_message_form(pool, user_id, &mut payload).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let body = match form.body() {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+    let subject = form.text("subject").map(|s| s.chars().take(200).collect::<String>());
+    let priority = form.text("priority").unwrap_or_else(|| "normal".to_string()).to_lowercase();
+    if !support::USER_PRIORITIES.contains(&priority.as_str()) {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid priority"}));
+    }
+    let kyc_status = support::latest_kyc_status(pool, user_id).await;
+
+    let new = support::NewTicket {
+        user_id,
+        role: &role,
+        kyc_status: &kyc_status,
+        priority: &priority,
+        subject: subject.as_deref(),
+        body: &body,
+        attachment_path: form.attachment_path.as_deref(),
+    };
+    match support::create_ticket(pool, &new).await {
+        Ok(id) => {
+            if form.attachment_path.is_some() {
+                let _ = quota::record(pool, user_id, storage::SUPPORT.name, form.attachment_bytes, 1).await;
+            }
+            HttpResponse::Ok().json(json!({"ok": true, "id": id}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// Original dashboard form endpoint
+#[post("/api/support_request")]
+async fn api_support_request(session: Session, payload: Multipart) -> impl Responder {
+    open_ticket(session, payload).await
+}
+
+#[post("/api/support/tickets")]
+async fn create_ticket(session: Session, payload: Multipart) -> impl Responder {
+    open_ticket(session, payload).await
+}
+
+#[derive(Deserialize)]
+struct MyTicketsQuery {
+    status: Option<String>,
+}
+
+#[get("/api/support/tickets")]
+async fn my_tickets(query: web::Query<MyTicketsQuery>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let rows = sqlx::query(&format!(
+        "{} WHERE sr.user_id = $1 AND ($2::text IS NULL OR sr.status = $2) ORDER BY sr.last_message_at DESC NULLS LAST, sr.id DESC LIMIT 100",
+        TICKET_SELECT
+    ))
+    .bind(user_id)
+    .bind(&query.status)
+    .fetch_all(pool_data.get_ref())
+    .await;
+
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .iter()
+                .map(|r| user_view(ticket_json(r)))
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/support/tickets/{id}")]
+async fn my_ticket(path: web::Path<i32>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let row = sqlx::query(&format!("{} WHERE sr.id = $1 AND sr.user_id = $2", TICKET_SELECT))
+        .bind(*path)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await;
+    let ticket = match row {
+        Ok(Some(r)) => user_view(ticket_json(&r)),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match thread(pool, *path).await {
+        Ok(messages) => HttpResponse::Ok().json(json!({"ticket": ticket, "messages": messages})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/support/tickets/{id}/messages")]
+async fn user_reply(path: web::Path<i32>, session: Session, mut payload: Multipart) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let ticket = match support::ticket(pool, *path).await {
+        Ok(Some(t)) if t.user_id == user_id => t,
+        Ok(_) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let next = match support::status_after_user_reply(&ticket.status) {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::Conflict().json(json!({"error": e.to_string()})),
+    };
+
+    let form = match read_message_form(pool, user_id, &mut payload).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let body = match form.body() {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+
+    let message_id = match support::add_message(pool, ticket.id, Some(user_id), "user", &body, form.attachment_path.as_deref()).await {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    if form.attachment_path.is_some() {
+        let _ = quota::record(pool, user_id, storage::SUPPORT.name, form.attachment_bytes, 1).await;
+    }
+    if next != ticket.status {
+        if let Err(e) = support::set_status(pool, ticket.id, &ticket.status, next).await {
+            return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+        }
+    }
+    HttpResponse::Ok().json(json!({"ok": true, "id": message_id, "status": next}))
+}
+
+#[post("/api/support/tickets/{id}/close")]
+async fn user_close(path: web::Path<i32>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let ticket = match support::ticket(pool, *path).await {
+        Ok(Some(t)) if t.user_id == user_id => t,
+        Ok(_) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    if !support::can_transition(&ticket.status, "closed") {
+        return HttpResponse::Conflict().json(json!({"error": format!("cannot close a ticket that is {}", ticket.status)}));
+    }
+    match support::set_status(pool, ticket.id, &ticket.status, "closed").await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true, "status": "closed"})),
+        Ok(false) => HttpResponse::Conflict().json(json!({"error": "ticket changed, reload and try again"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/support/messages/{id}/attachment")]
+async fn message_attachment(path: web::Path<i32>, session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    let user_id = session
+        .get::<i32>("user_id")
+        .unwrap_or(None)
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("not logged in"))?;
+    let staff = ensure_staff(&session).is_ok();
+    let pool_data = POOL_DATA.get().ok_or_else(|| actix_web::error::ErrorInternalServerError("no db"))?;
+
+    let row = sqlx::query(
+        "SELECT m.attachment_path, sr.user_id FROM support_messages m JOIN support_requests sr ON sr.id = m.request_id WHERE m.id = $1",
+    )
+    .bind(*path)
+    .fetch_optional(pool_data.get_ref())
+    .await
+    .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+
+    if let Some(r) = row {
+        if r.get::<i32, _>("user_id") != user_id && !staff {
+            return Err(actix_web::error::ErrorForbidden("forbidden"));
+        }
+        if let Some(p) = r.get::<Option<String>, _>("attachment_path") {
+            let fs_path = PathBuf::from(&p);
+            if fs_path.exists() {
+                let file = NamedFile::open_async(fs_path).await?;
+                return Ok(file.into_response(&req));
+            }
+        }
+    }
+    Err(actix_web::error::ErrorNotFound("not found"))
+}
+
+#[derive(Deserialize)]
+struct SupportQuery {
+    /// One status, or "active" for everything not closed (default: new)
+    status: Option<String>,
+    /// "me", "unassigned" or a staff user id
+    assignee: Option<String>,
+    priority: Option<String>,
+    /// only tickets past a first-response or resolution due time
+    breached: Option<bool>,
+}
+
+#[get("/api/admin/support_requests")]
+async fn list_support_requests(query: web::Query<SupportQuery>, session: Session) -> impl Responder {
+    let staff_id = match ensure_staff(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let status = query.status.clone().unwrap_or_else(|| "new".to_string());
+    if status != "active" && !support::STATUSES.contains(&status.as_str()) {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid status"}));
+    }
+    let (unassigned, assignee_id) = match query.assignee.as_deref() {
+        None | Some("") | Some("all") => (false, None),
+        Some("unassigned") => (true, None),
+        Some("me") => (false, Some(staff_id)),
+        Some(other) => match other.parse::<i32>() {
+            Ok(id) => (false, Some(id)),
+            Err(_) => return HttpResponse::BadRequest().json(json!({"error": "invalid assignee"})),
+        },
+    };
+
+    if let Some(p) = &query.priority {
+        if !support::PRIORITIES.contains(&p.as_str()) {
+            return HttpResponse::BadRequest().json(json!({"error": "invalid priority"}));
+        }
+    }
+
+    // Most urgent first, then whichever due time comes next
+    let rows = sqlx::query(&format!(
+        "SELECT * FROM ({}) t
+         WHERE (CASE WHEN $1 = 'active' THEN t.status <> 'closed' ELSE t.status = $1 END)
+           AND (NOT $2 OR t.assignee_id IS NULL)
+           AND ($3::int IS NULL OR t.assignee_id = $3)
+           AND ($4::text IS NULL OR t.priority = $4)
+           AND (NOT $5 OR t.first_response_breached OR t.resolution_breached)
+         ORDER BY CASE t.priority WHEN 'urgent' THEN 0 WHEN 'high' THEN 1 WHEN 'normal' THEN 2 ELSE 3 END,
+                  CASE WHEN t.first_response_at IS NULL THEN t.first_response_due_at ELSE t.resolution_due_at END NULLS LAST,
+                  t.created_at DESC
+         LIMIT 200",
+        TICKET_SELECT
+    ))
+    .bind(&status)
+    .bind(unassigned)
+    .bind(assignee_id)
+    .bind(&query.priority)
+    .bind(query.breached.unwrap_or(false))
+    .fetch_all(pool_data.get_ref())
+    .await;
+
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list.iter().map(ticket_json).collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[get("/api/admin/support_requests/{id}")]
+async fn get_support_request(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let ticket = match sqlx::query(&format!("{} WHERE sr.id = $1", TICKET_SELECT))
+        .bind(*path)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(Some(r)) => ticket_json(&r),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match thread(pool, *path).await {
+        Ok(messages) => HttpResponse::Ok().json(json!({"ticket": ticket, "messages": messages})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/support_requests/{id}/messages")]
+async fn staff_reply(path: web::Path<i32>, session: Session, mut payload: Multipart) -> impl Responder {
+    let staff_id = match ensure_staff(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    let ticket = match support::ticket(pool, *path).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let form = match read_message_form(pool, staff_id, &mut payload).await {
+        Ok(f) => f,
+        Err(resp) => return resp,
+    };
+    let body = match form.body() {
+        Ok(b) => b,
+        Err(resp) => return resp,
+    };
+    let next = match support::status_after_staff_reply(&ticket.status, form.text("status").as_deref()) {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::Conflict().json(json!({"error": e.to_string()})),
+    };
+
+    let message_id = match support::add_message(pool, ticket.id, Some(staff_id), "staff", &body, form.attachment_path.as_deref()).await {
+        Ok(id) => id,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    if form.attachment_path.is_some() {
+        let _ = quota::record(pool, staff_id, storage::SUPPORT.name, form.attachment_bytes, 1).await;
+    }
+    // A new ticket is opened by the first answer (new -> open -> next)
+    let mut from = ticket.status.clone();
+    if from == "new" {
+        let _ = support::set_status(pool, ticket.id, "new", "open").await;
+        from = "open".to_string();
+    }
+    if next != from {
+        if let Err(e) = support::set_status(pool, ticket.id, &from, next).await {
+            return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+        }
+    }
+    if ticket.assignee_id.is_none() {
+        let _ = sqlx::query("UPDATE support_requests SET assignee_id = $1 WHERE id = $2 AND assignee_id IS NULL")
+            .bind(staff_id)
+            .bind(ticket.id)
+            .execute(pool)
+            .await;
+    }
+    if let Err(e) = support::notify_reply(pool, &ticket, staff_id, &body).await {
+        eprintln!("Support reply notification for ticket {} failed: {:?}", ticket.id, e);
+    }
+    HttpResponse::Ok().json(json!({"ok": true, "id": message_id, "status": next}))
+}
+
+#[derive(Deserialize)]
+struct AssignPayload {
+    assignee_id: Option<i32>,
+}
+
+#[post("/api/admin/support_requests/{id}/assign")]
+async fn assign_support_request(path: web::Path<i32>, payload: web::Json<AssignPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+
+    if let Some(assignee) = payload.assignee_id {
+        match support::is_staff(pool, assignee).await {
+            Ok(true) => {}
+            Ok(false) => return HttpResponse::BadRequest().json(json!({"error": "assignee must be an admin or agent"})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+        }
+    }
+    let ticket = match support::ticket(pool, *path).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    if ticket.status == "closed" {
+        return HttpResponse::Conflict().json(json!({"error": "ticket is closed"}));
+    }
+
+    let res = sqlx::query("UPDATE support_requests SET assignee_id = $1, updated_at = now() WHERE id = $2")
+        .bind(payload.assignee_id)
+        .bind(ticket.id)
+        .execute(pool)
+        .await;
+    if let Err(e) = res {
+        return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+    }
+    // Picking up a new ticket opens it
+    let mut status = ticket.status.clone();
+    if payload.assignee_id.is_some() && status == "new" && support::set_status(pool, ticket.id, "new", "open").await.unwrap_or(false) {
+        status = "open".to_string();
+    }
+    HttpResponse::Ok().json(json!({"ok": true, "assignee_id": payload.assignee_id, "status": status}))
+}
+
+#[derive(Deserialize)]
+struct StatusPayload {
+    status: String,
+}
+
+#[post("/api/admin/support_requests/{id}/status")]
+async fn update_support_status(path: web::Path<i32>, payload: web::Json<StatusPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+    let status = payload.status.trim().to_lowercase();
+    if !support::STATUSES.contains(&status.as_str()) {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid status"}));
+    }
+
+    let ticket = match support::ticket(pool, *path).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    if !support::can_transition(&ticket.status, &status) {
+        return HttpResponse::Conflict().json(json!({"error": format!("cannot move ticket from {} to {}", ticket.status, status)}));
+    }
+    match support::set_status(pool, ticket.id, &ticket.status, &status).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true, "status": status})),
+        Ok(false) => HttpResponse::Conflict().json(json!({"error": "ticket changed, reload and try again"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// Attachment of the opening message, for the original list view
+#[get("/api/admin/support_requests/{id}/attachment")]
+async fn support_attachment(path: web::Path<i32>, session: Session, req: HttpRequest) -> actix_web::Result<HttpResponse> {
+    if ensure_staff(&session).is_err() {
+        return Err(actix_web::error::ErrorUnauthorized("unauthorized"));
+    }
+
+    let pool_data = POOL_DATA.get().ok_or_else(|| actix_web::error::ErrorInternalServerError("no db"))?;
+    let row = sqlx::query("SELECT attachment_path FROM support_messages WHERE request_id = $1 AND attachment_path IS NOT NULL ORDER BY id LIMIT 1")
+        .bind(*path)
+        .fetch_optional(pool_data.get_ref())
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+
+    if let Some(r) = row {
+        let path_str: Option<String> = r.try_get("attachment_path").ok();
+        if let Some(p) = path_str {
+            let fs_path = PathBuf::from(&p);
+            if fs_path.exists() {
+                let file = NamedFile::open_async(fs_path).await?;
+                return Ok(file.into_response(&req));
+            }
+        }
+    }
+    Err(actix_web::error::ErrorNotFound("file not found"))
+}
+
+#[get("/api/admin/support/staff")]
+async fn list_staff(session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let rows = sqlx::query("SELECT id, full_name, email, role FROM users WHERE role IN ('admin', 'agent') ORDER BY full_name")
+        .fetch_all(pool_data.get_ref())
+        .await;
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .iter()
+                .map(|r| {
+                    json!({
+                        "id": r.get::<i32,_>("id"),
+                        "name": r.get::<String,_>("full_name"),
+                        "email": r.get::<String,_>("email"),
+                        "role": r.get::<Option<String>,_>("role"),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct PriorityPayload {
+    priority: String,
+}
+
+#[post("/api/admin/support_requests/{id}/priority")]
+async fn update_support_priority(path: web::Path<i32>, payload: web::Json<PriorityPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let priority = payload.priority.trim().to_lowercase();
+    if !support::PRIORITIES.contains(&priority.as_str()) {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid priority"}));
+    }
+    match support::set_priority(pool_data.get_ref(), *path, &priority).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"ok": true, "priority": priority})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "not found or closed"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct MetricsQuery {
+    days: Option<i64>,
+}
+
+#[get("/api/admin/support/metrics")]
+async fn support_metrics(query: web::Query<MetricsQuery>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+    match support_sla::metrics(pool_data.get_ref(), days).await {
+        Ok(m) => HttpResponse::Ok().json(m),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct CannedPayload {
+    title: String,
+    body: String,
+}
+
+impl CannedPayload {
+    fn validate(&self) -> Result<(String, String), HttpResponse> {
+        let title = self.title.trim();
+        let body = self.body.trim();
+        if title.is_empty() || body.is_empty() {
+            return Err(HttpResponse::BadRequest().json(json!({"error": "title and body required"})));
+        }
+        Ok((title.chars().take(200).collect(), body.to_string()))
+    }
+}
+
+#[get("/api/admin/support/canned")]
+async fn list_canned(session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let rows = sqlx::query("SELECT id, title, body, updated_at FROM support_canned_replies ORDER BY title")
+        .fetch_all(pool_data.get_ref())
+        .await;
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .iter()
+                .map(|r| {
+                    json!({
+                        "id": r.get::<i32,_>("id"),
+                        "title": r.get::<String,_>("title"),
+                        "body": r.get::<String,_>("body"),
+                        "updated_at": timestamp(r, "updated_at"),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items, "variables": ["user_name", "first_name", "user_email", "role", "kyc_status", "ticket_id", "subject", "agent_name"]}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/support/canned")]
+async fn create_canned(payload: web::Json<CannedPayload>, session: Session) -> impl Responder {
+    let staff_id = match ensure_staff(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let (title, body) = match payload.validate() {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let row = sqlx::query("INSERT INTO support_canned_replies (title, body, created_by) VALUES ($1, $2, $3) RETURNING id")
+        .bind(&title)
+        .bind(&body)
+        .bind(staff_id)
+        .fetch_one(pool_data.get_ref())
+        .await;
+    match row {
+        Ok(r) => HttpResponse::Ok().json(json!({"ok": true, "id": r.get::<i32,_>("id")})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/support/canned/{id}/update")]
+async fn update_canned(path: web::Path<i32>, payload: web::Json<CannedPayload>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let (title, body) = match payload.validate() {
+        Ok(v) => v,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let res = sqlx::query("UPDATE support_canned_replies SET title = $1, body = $2, updated_at = now() WHERE id = $3")
+        .bind(&title)
+        .bind(&body)
+        .bind(*path)
+        .execute(pool_data.get_ref())
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/admin/support/canned/{id}/delete")]
+async fn delete_canned(path: web::Path<i32>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let res = sqlx::query("DELETE FROM support_canned_replies WHERE id = $1")
+        .bind(*path)
+        .execute(pool_data.get_ref())
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"ok": true})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// A canned reply filled in for one ticket, as a draft for the reply box
+#[get("/api/admin/support_requests/{id}/canned/{canned_id}")]
+async fn render_canned(path: web::Path<(i32, i32)>, session: Session) -> impl Responder {
+    let staff_id = match ensure_staff(&session) {
+        Ok(id) => id,
+        Err(resp) => return resp,
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+    let (request_id, canned_id) = path.into_inner();
+
+    let template = match sqlx::query("SELECT title, body FROM support_canned_replies WHERE id = $1")
+        .bind(canned_id)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(Some(r)) => (r.get::<String, _>("title"), r.get::<String, _>("body")),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "canned reply not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match support::canned_vars(pool, request_id, staff_id).await {
+        Ok(Some(vars)) => HttpResponse::Ok().json(json!({"title": template.0, "body": support::render_canned(&template.1, &vars)})),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+// File a raw RFC 5322 message (body: message/rfc822) as if it had been
+// mailed in; for forwarding webhooks and trying out .eml fixtures
+#[post("/api/admin/support/inbound")]
+async fn inbound_mail(session: Session, mut payload: web::Payload) -> impl Responder {
+    if let Err(resp) = ensure_admin(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let max = support_mail::max_message_bytes();
+    let mut raw: Vec<u8> = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(data) => {
+                if raw.len() + data.len() > max {
+                    return HttpResponse::PayloadTooLarge().json(json!({"error": "message too large"}));
+                }
+                raw.extend_from_slice(&data);
+            }
+            Err(e) => return HttpResponse::BadRequest().json(json!({"error": format!("read: {}", e)})),
+        }
+    }
+    match support_mail::ingest(pool_data.get_ref(), &raw, "api").await {
+        Ok(outcome) => HttpResponse::Ok().json(outcome),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[derive(Deserialize)]
+struct InboundLogQuery {
+    outcome: Option<String>,
+}
+
+#[get("/api/admin/support/inbound")]
+async fn inbound_log(query: web::Query<InboundLogQuery>, session: Session) -> impl Responder {
+    if let Err(resp) = ensure_staff(&session) {
+        return resp;
+    }
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let rows = sqlx::query(
+        "SELECT id, message_id, source, from_address, subject, outcome, ticket_id, detail, received_at
+         FROM support_inbound_emails WHERE ($1::text IS NULL OR outcome = $1) ORDER BY id DESC LIMIT 200",
+    )
+    .bind(&query.outcome)
+    .fetch_all(pool_data.get_ref())
+    .await;
+    match rows {
+        Ok(list) => {
+            let items: Vec<serde_json::Value> = list
+                .iter()
+                .map(|r| {
+                    json!({
+                        "id": r.get::<i32,_>("id"),
+                        "message_id": r.get::<Option<String>,_>("message_id"),
+                        "source": r.get::<String,_>("source"),
+                        "from_address": r.get::<Option<String>,_>("from_address"),
+                        "subject": r.get::<Option<String>,_>("subject"),
+                        "outcome": r.get::<String,_>("outcome"),
+                        "ticket_id": r.get::<Option<i32>,_>("ticket_id"),
+                        "detail": r.get::<Option<String>,_>("detail"),
+                        "received_at": timestamp(r, "received_at"),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"items": items}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(api_support_request)
+        .service(create_ticket)
+        .service(my_tickets)
+        .service(my_ticket)
+        .service(user_reply)
+        .service(user_close)
+        .service(message_attachment)
+        .service(list_staff)
+        .service(support_metrics)
+        .service(list_canned)
+        .service(inbound_mail)
+        .service(inbound_log)
+        .service(create_canned)
+        .service(update_canned)
+        .service(delete_canned)
+        .service(list_support_requests)
+        .service(get_support_request)
+        .service(staff_reply)
+        .service(assign_support_request)
+        .service(update_support_status)
+        .service(update_support_priority)
+        .service(render_canned)
+        .service(support_attachment);
+}
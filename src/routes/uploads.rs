@@ -0,0 +1,407 @@
+use actix_session::Session;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::routes::admin::ensure_admin;
+use crate::services::resumable::{self, MAX_CHUNK_BYTES, RESUMABLE_CATEGORIES};
+use crate::services::{quota, storage};
+use crate::POOL_DATA;
+
+// Resumable upload protocol:
+//   POST /api/uploads                  create a session (category, file_name, size, optional sha256)
+//   POST /api/uploads/{id}/chunk       append raw bytes at Upload-Offset (optional X-Chunk-Sha256)
+//   GET  /api/uploads/{id}             current offset/status, to resume after a dropped connection
+//   POST /api/uploads/{id}/finalize    verify size/checksum and move into the category's upload dir
+//   POST /api/uploads/{id}/cancel      drop the session and its partial file
+// A finalized upload is attached by passing `upload_id` to the regular multipart endpoint.
+
+#[derive(Deserialize)]
+struct CreateUploadPayload {
+    category: String,
+    file_name: String,
+    content_type: Option<String>,
+    size: u64,
+    sha256: Option<String>,
+}
+
+#[post("/api/uploads")]
+async fn create_upload(session: Session, payload: web::Json<CreateUploadPayload>) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+
+    let category = payload.category.trim().to_lowercase();
+    if !RESUMABLE_CATEGORIES.contains(&category.as_str()) {
+        return HttpResponse::BadRequest().json(json!({"error": "invalid category"}));
+    }
+    if category == "notification" {
+        if let Err(resp) = ensure_admin(&session) { return resp; }
+    }
+    let cat = match storage::category(&category) {
+        Some(c) => c,
+        None => return HttpResponse::BadRequest().json(json!({"error": "invalid category"})),
+    };
+
+    let content_type = payload.content_type.clone().unwrap_or_else(|| "application/octet-stream".to_string());
+    if payload.size == 0 {
+        return HttpResponse::BadRequest().json(json!({"error": "size required"}));
+    }
+    if let Err(e) = storage::validate(cat, &content_type, payload.size) {
+        return e.to_response();
+    }
+    let sha256 = payload.sha256.as_ref().map(|s| s.trim().to_lowercase()).filter(|s| !s.is_empty());
+    if let Some(s) = &sha256 {
+        if s.len() != 64 || hex::decode(s).is_err() {
+            return HttpResponse::BadRequest().json(json!({"error": "sha256 must be 64 hex chars"}));
+        }
+    }
+
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    match quota::remaining(pool_data.get_ref(), user_id, cat.name).await {
+        Ok(left) if payload.size > left => return storage::UploadError::QuotaExceeded(left).to_response(),
+        Ok(_) => {}
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+
+    let id = Uuid::new_v4().simple().to_string();
+    if let Err(e) = tokio::fs::File::create(resumable::partial_path(&id)).await {
+        return HttpResponse::InternalServerError().json(json!({"error": format!("file create: {}", e)}));
+    }
+    let expires_at = Utc::now() + ChronoDuration::seconds(resumable::ttl_secs());
+
+    let res = sqlx::query("INSERT INTO upload_sessions (id, user_id, category, file_name, content_type, total_size, sha256, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+        .bind(&id)
+        .bind(user_id)
+        .bind(&category)
+        .bind(payload.file_name.trim())
+        .bind(&content_type)
+        .bind(payload.size as i64)
+        .bind(&sha256)
+        .bind(expires_at)
+        .execute(pool_data.get_ref())
+        .await;
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "id": id,
+            "offset": 0,
+            "size": payload.size,
+            "max_chunk_bytes": MAX_CHUNK_BYTES,
+            "expires_at": expires_at.to_rfc3339(),
+        })),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(resumable::partial_path(&id)).await;
+            HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))
+        }
+    }
+}
+
+#[get("/api/uploads/{id}")]
+async fn upload_status(path: web::Path<String>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let row = sqlx::query("SELECT id, category, file_name, total_size, received, status, expires_at FROM upload_sessions WHERE id = $1 AND user_id = $2")
+        .bind(path.as_str())
+        .bind(user_id)
+        .fetch_optional(pool_data.get_ref())
+        .await;
+
+    match row {
+        Ok(Some(r)) => HttpResponse::Ok().json(json!({
+            "id": r.get::<String,_>("id"),
+            "category": r.get::<String,_>("category"),
+            "file_name": r.get::<String,_>("file_name"),
+            "size": r.get::<i64,_>("total_size"),
+            "offset": r.get::<i64,_>("received"),
+            "status": r.get::<String,_>("status"),
+            "expires_at": r.get::<DateTime<Utc>,_>("expires_at").to_rfc3339(),
+        })),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "upload not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+fn header_str<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok()).map(|s| s.trim())
+}
+
+#[post("/api/uploads/{id}/chunk")]
+async fn append_chunk(path: web::Path<String>, req: HttpRequest, session: Session, mut body: web::Payload) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let offset: i64 = match header_str(&req, "Upload-Offset").and_then(|v| v.parse().ok()) {
+        Some(o) => o,
+        None => return HttpResponse::BadRequest().json(json!({"error": "Upload-Offset header required"})),
+    };
+    let expected_sha = header_str(&req, "X-Chunk-Sha256").map(|s| s.to_lowercase());
+
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+    let id = path.into_inner();
+
+    // Take a short lock so two clients can't append to the same session at once.
+    let claimed = sqlx::query(
+        "UPDATE upload_sessions SET locked_until = now() + interval '2 minutes'
+         WHERE id = $1 AND user_id = $2 AND status = 'pending' AND expires_at > now()
+           AND (locked_until IS NULL OR locked_until < now())
+         RETURNING received, total_size",
+    )
+    .bind(&id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await;
+
+    let (received, total_size) = match claimed {
+        Ok(Some(r)) => (r.get::<i64, _>("received"), r.get::<i64, _>("total_size")),
+        Ok(None) => return HttpResponse::Conflict().json(json!({"error": "upload not found, expired, finalized or busy"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+
+    let release = |new_received: i64| {
+        sqlx::query("UPDATE upload_sessions SET received = $1, locked_until = NULL, updated_at = now(), expires_at = now() + make_interval(secs => $2) WHERE id = $3")
+            .bind(new_received)
+            .bind(resumable::ttl_secs() as f64)
+            .bind(id.clone())
+            .execute(pool)
+    };
+
+    if offset != received {
+        let _ = release(received).await;
+        return HttpResponse::Conflict().json(json!({"error": "offset mismatch", "offset": received}));
+    }
+
+    let partial = resumable::partial_path(&id);
+    let mut f = match tokio::fs::OpenOptions::new().write(true).create(true).truncate(false).open(&partial).await {
+        Ok(f) => f,
+        Err(e) => {
+            let _ = release(received).await;
+            return HttpResponse::InternalServerError().json(json!({"error": format!("file open: {}", e)}));
+        }
+    };
+    // Drop anything past the committed offset left over from an interrupted append.
+    if let Err(e) = f.set_len(received as u64).await {
+        let _ = release(received).await;
+        return HttpResponse::InternalServerError().json(json!({"error": format!("file truncate: {}", e)}));
+    }
+    if let Err(e) = f.seek(std::io::SeekFrom::Start(received as u64)).await {
+        let _ = release(received).await;
+        return HttpResponse::InternalServerError().json(json!({"error": format!("file seek: {}", e)}));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut written: u64 = 0;
+    let mut failure: Option<HttpResponse> = None;
+    while let Some(chunk) = body.next().await {
+        let data = match chunk {
+            Ok(d) => d,
+            Err(e) => {
+                failure = Some(HttpResponse::BadRequest().json(json!({"error": format!("upload chunk: {}", e)})));
+                break;
+            }
+        };
+        written += data.len() as u64;
+        if written > MAX_CHUNK_BYTES {
+            failure = Some(HttpResponse::PayloadTooLarge().json(json!({"error": format!("chunk too large (max {} bytes)", MAX_CHUNK_BYTES)})));
+            break;
+        }
+        if received as u64 + written > total_size as u64 {
+            failure = Some(HttpResponse::BadRequest().json(json!({"error": "chunk exceeds declared size"})));
+            break;
+        }
+        hasher.update(&data);
+        if let Err(e) = f.write_all(&data).await {
+            failure = Some(HttpResponse::InternalServerError().json(json!({"error": format!("write failed: {}", e)})));
+            break;
+        }
+    }
+
+    if failure.is_none() {
+        if let Some(expected) = &expected_sha {
+            if hex::encode(hasher.finalize()) != *expected {
+                failure = Some(HttpResponse::BadRequest().json(json!({"error": "chunk checksum mismatch", "offset": received})));
+            }
+        }
+    }
+
+    if let Some(resp) = failure {
+        let _ = f.set_len(received as u64).await;
+        let _ = release(received).await;
+        return resp;
+    }
+
+    if let Err(e) = f.flush().await {
+        let _ = f.set_len(received as u64).await;
+        let _ = release(received).await;
+        return HttpResponse::InternalServerError().json(json!({"error": format!("write failed: {}", e)}));
+    }
+
+    let new_offset = received + written as i64;
+    match release(new_offset).await {
+        Ok(_) => HttpResponse::Ok().json(json!({"offset": new_offset, "size": total_size, "complete": new_offset == total_size})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+#[post("/api/uploads/{id}/finalize")]
+async fn finalize_upload(path: web::Path<String>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+    let pool = pool_data.get_ref();
+    let id = path.into_inner();
+
+    // Lock the session for the duration of checksum + move.
+    let row = sqlx::query(
+        "UPDATE upload_sessions SET locked_until = now() + interval '10 minutes'
+         WHERE id = $1 AND user_id = $2 AND status = 'pending' AND (locked_until IS NULL OR locked_until < now())
+         RETURNING category, file_name, content_type, total_size, received, sha256",
+    )
+    .bind(&id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await;
+    let r = match row {
+        Ok(Some(r)) => r,
+        Ok(None) => return HttpResponse::Conflict().json(json!({"error": "upload not found, already finalized or busy"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    let unlock = || sqlx::query("UPDATE upload_sessions SET locked_until = NULL WHERE id = $1").bind(id.clone()).execute(pool);
+
+    let total_size: i64 = r.get("total_size");
+    let received: i64 = r.get("received");
+    if received != total_size {
+        let _ = unlock().await;
+        return HttpResponse::Conflict().json(json!({"error": "upload incomplete", "offset": received, "size": total_size}));
+    }
+
+    let category: String = r.get("category");
+    let cat = match storage::category(&category) {
+        Some(c) => c,
+        None => {
+            let _ = unlock().await;
+            return HttpResponse::BadRequest().json(json!({"error": "invalid category"}));
+        }
+    };
+    let content_type: String = r.get("content_type");
+    if let Err(e) = storage::validate(cat, &content_type, total_size as u64) {
+        let _ = unlock().await;
+        return e.to_response();
+    }
+    // Quota may have been used up by other uploads since the session was created
+    match quota::remaining(pool, user_id, cat.name).await {
+        Ok(left) if total_size as u64 > left => {
+            let _ = unlock().await;
+            return storage::UploadError::QuotaExceeded(left).to_response();
+        }
+        Ok(_) => {}
+        Err(e) => {
+            let _ = unlock().await;
+            return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}));
+        }
+    }
+
+    let partial = resumable::partial_path(&id);
+    let digest = match resumable::sha256_file(&partial).await {
+        Ok(d) => d,
+        Err(e) => {
+            let _ = unlock().await;
+            return HttpResponse::InternalServerError().json(json!({"error": format!("read failed: {}", e)}));
+        }
+    };
+    if let Ok(expected) = r.try_get::<String, _>("sha256") {
+        if expected != digest {
+            let _ = unlock().await;
+            return HttpResponse::BadRequest().json(json!({"error": "checksum mismatch", "sha256": digest}));
+        }
+    }
+
+    let file_name: String = r.get("file_name");
+    let dest = storage::destination(cat, Some(&file_name));
+    if let Err(e) = tokio::fs::rename(&partial, &dest).await {
+        let _ = unlock().await;
+        return HttpResponse::InternalServerError().json(json!({"error": format!("move failed: {}", e)}));
+    }
+    let stored_path = dest.to_string_lossy().to_string();
+
+    let res = sqlx::query("UPDATE upload_sessions SET status = 'completed', stored_path = $1, locked_until = NULL, updated_at = now(), expires_at = now() + make_interval(secs => $2) WHERE id = $3")
+        .bind(&stored_path)
+        .bind(resumable::ttl_secs() as f64)
+        .bind(&id)
+        .execute(pool)
+        .await;
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(json!({"id": id, "status": "completed", "size": total_size, "sha256": digest})),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&dest).await;
+            HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)}))
+        }
+    }
+}
+
+#[post("/api/uploads/{id}/cancel")]
+async fn cancel_upload(path: web::Path<String>, session: Session) -> impl Responder {
+    let user_id = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() {
+        Some(p) => p,
+        None => return HttpResponse::InternalServerError().json(json!({"error": "no db"})),
+    };
+
+    let row = sqlx::query("UPDATE upload_sessions SET status = 'expired', updated_at = now() WHERE id = $1 AND user_id = $2 AND status IN ('pending', 'completed') RETURNING stored_path")
+        .bind(path.as_str())
+        .bind(user_id)
+        .fetch_optional(pool_data.get_ref())
+        .await;
+
+    match row {
+        Ok(Some(r)) => {
+            let _ = tokio::fs::remove_file(resumable::partial_path(path.as_str())).await;
+            if let Ok(p) = r.try_get::<String, _>("stored_path") {
+                let _ = tokio::fs::remove_file(p).await;
+            }
+            HttpResponse::Ok().json(json!({"ok": true}))
+        }
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "upload not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_upload)
+        .service(upload_status)
+        .service(append_chunk)
+        .service(finalize_upload)
+        .service(cancel_upload);
+}
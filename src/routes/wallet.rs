@@ -0,0 +1,29 @@
+use actix_session::Session;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+
+use crate::services::wallet_service;
+use crate::POOL_DATA;
+
+// GET /api/wallet  the logged-in user's coin balance and latest ledger entries.
+#[get("/api/wallet")]
+async fn my_wallet(session: Session) -> impl Responder {
+    let uid = match session.get::<i32>("user_id").unwrap_or(None) {
+        Some(id) => id,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "not logged in"})),
+    };
+    let pool_data = match POOL_DATA.get() { Some(p)=>p, None=>return HttpResponse::InternalServerError().json(json!({"error":"no db"}))};
+    let pool = pool_data.get_ref();
+    let balance = match wallet_service::balance(pool, uid).await {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    };
+    match wallet_service::history(pool, uid, 50).await {
+        Ok(items) => HttpResponse::Ok().json(json!({"balance": balance, "items": items})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": format!("db: {}", e)})),
+    }
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(my_wallet);
+}
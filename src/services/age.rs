@@ -0,0 +1,222 @@
+// Age-aware rules, from users.birthday (verified from the KYC date of birth
+// once a teacher is approved).
+//
+// - Teachers must be at least ADULT_AGE (default 18): checked when a KYC case
+//   is submitted and when an account switches to the teacher role.
+// - Students under GUARDIAN_REQUIRED_UNDER (default ADULT_AGE) need a linked
+//   guardian (guardian_links) before they can book or pay; see
+//   `Standing::may_transact`. Booking and payment code calls it.
+// - Minor accounts are private whatever profile_visibility says, and only
+//   staff and their guardians can send them notices (`may_message`).
+//   Wherever one user is named to another (bookings, series, packages, group
+//   rosters, calendar feeds and invites, notices) queries go through the SQL
+//   function shown_name(user, viewer): a private profile shows its first name
+//   and last initial to anyone but the user, their guardians and staff.
+//
+// users.is_minor caches the age check so listings and searches can filter on
+// it; it is refreshed when a birthday changes and daily by `spawn_background`.
+
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDate, Utc};
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+
+pub fn adult_age() -> i32 {
+    std::env::var("ADULT_AGE").ok().and_then(|s| s.parse().ok()).filter(|a: &i32| *a > 0).unwrap_or(18)
+}
+
+pub fn guardian_required_under() -> i32 {
+    std::env::var("GUARDIAN_REQUIRED_UNDER").ok().and_then(|s| s.parse().ok()).unwrap_or_else(adult_age)
+}
+
+/// Whole years between `birthday` and `on`.
+pub fn age_on(birthday: NaiveDate, on: NaiveDate) -> i32 {
+    let mut age = on.year() - birthday.year();
+    if (on.month(), on.day()) < (birthday.month(), birthday.day()) {
+        age -= 1;
+    }
+    age
+}
+
+pub fn is_adult(birthday: NaiveDate) -> bool {
+    age_on(birthday, Utc::now().date_naive()) >= adult_age()
+}
+
+fn is_staff(role: &str) -> bool {
+    role == "admin" || role == "agent"
+}
+
+/// What the age rules allow one account.
+pub struct Standing {
+    pub role: String,
+    pub birthday: Option<NaiveDate>,
+    pub birthday_verified: bool,
+    pub guardians: i64,
+    pub profile_visibility: String,
+}
+
+impl Standing {
+    pub fn age(&self) -> Option<i32> {
+        self.birthday.map(|b| age_on(b, Utc::now().date_naive()))
+    }
+
+    pub fn is_minor(&self) -> bool {
+        self.age().map(|a| a < adult_age()).unwrap_or(false)
+    }
+
+    pub fn needs_guardian(&self) -> bool {
+        self.age().map(|a| a < guardian_required_under()).unwrap_or(false)
+    }
+
+    /// Known minors cannot teach; an unknown age is settled by KYC.
+    pub fn may_teach(&self) -> bool {
+        !self.is_minor()
+    }
+
+    /// Whether the account may book or pay, with the reason if not.
+    pub fn may_transact(&self) -> Result<(), String> {
+        if is_staff(&self.role) {
+            return Ok(());
+        }
+        if self.birthday.is_none() {
+            return Err("add your date of birth to your profile first".to_string());
+        }
+        if self.needs_guardian() && self.guardians == 0 {
+            return Err("a parent or guardian has to link their account before you can book or pay".to_string());
+        }
+        Ok(())
+    }
+
+    /// The visibility that applies: minors are always private.
+    pub fn visibility(&self) -> &str {
+        if self.is_minor() { "private" } else { self.profile_visibility.as_str() }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let transact = self.may_transact();
+        json!({
+            "age": self.age(),
+            "is_minor": self.is_minor(),
+            "birthday_verified": self.birthday_verified,
+            "needs_guardian": self.needs_guardian(),
+            "guardians": self.guardians,
+            "may_transact": transact.is_ok(),
+            "transact_blocked_reason": transact.err(),
+            "may_teach": self.may_teach(),
+            "profile_visibility": self.visibility(),
+            "messaging": if self.is_minor() { "staff_and_guardians" } else { "open" },
+        })
+    }
+}
+
+pub async fn standing(pool: &PgPool, user_id: i32) -> sqlx::Result<Option<Standing>> {
+    let row = sqlx::query(
+        "SELECT COALESCE(u.role, 'student') AS role, u.birthday, u.birthday_verified, u.profile_visibility,
+             (SELECT COUNT(*) FROM guardian_links g WHERE g.minor_id = u.id) AS guardians
+         FROM users u WHERE u.id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| Standing {
+        role: r.get("role"),
+        birthday: r.get("birthday"),
+        birthday_verified: r.get("birthday_verified"),
+        guardians: r.get("guardians"),
+        profile_visibility: r.get("profile_visibility"),
+    }))
+}
+
+/// Whether `sender_id` may send `recipient_id` a notice: anyone may write to
+/// an adult, only staff and the minor's guardians to a minor.
+pub async fn may_message(pool: &PgPool, sender_id: i32, recipient_id: i32) -> sqlx::Result<bool> {
+    if sender_id == recipient_id {
+        return Ok(true);
+    }
+    let row = sqlx::query(
+        "SELECT r.is_minor,
+             COALESCE(s.role, 'student') AS sender_role,
+             EXISTS (SELECT 1 FROM guardian_links g WHERE g.minor_id = r.id AND g.guardian_id = s.id) AS is_guardian
+         FROM users r, users s WHERE r.id = $1 AND s.id = $2",
+    )
+    .bind(recipient_id)
+    .bind(sender_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(match row {
+        Some(r) => !r.get::<bool, _>("is_minor") || is_staff(&r.get::<String, _>("sender_role")) || r.get::<bool, _>("is_guardian"),
+        None => false,
+    })
+}
+
+/// Recompute users.is_minor, for one user or everyone. Returns the number of
+/// accounts that changed.
+pub async fn refresh(pool: &PgPool, user_id: Option<i32>) -> sqlx::Result<u64> {
+    let res = sqlx::query(
+        "UPDATE users SET is_minor = m.minor
+         FROM (SELECT id, COALESCE(birthday > CURRENT_DATE - make_interval(years => $1), FALSE) AS minor FROM users) m
+         WHERE m.id = users.id AND users.is_minor IS DISTINCT FROM m.minor AND ($2::INTEGER IS NULL OR users.id = $2)",
+    )
+    .bind(adult_age())
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// Link `guardian_id` to the minor account `minor_id`.
+pub async fn link_guardian(pool: &PgPool, minor_id: i32, guardian_id: i32, created_by: Option<i32>) -> anyhow::Result<()> {
+    let guardian = standing(pool, guardian_id).await?.ok_or_else(|| anyhow!("guardian account not found"))?;
+    if guardian.birthday.map(|b| !is_adult(b)).unwrap_or(false) {
+        return Err(anyhow!("a guardian must be an adult"));
+    }
+    if minor_id == guardian_id {
+        return Err(anyhow!("an account cannot be its own guardian"));
+    }
+    sqlx::query("INSERT INTO guardian_links (minor_id, guardian_id, created_by) VALUES ($1, $2, $3) ON CONFLICT (minor_id, guardian_id) DO NOTHING")
+        .bind(minor_id)
+        .bind(guardian_id)
+        .bind(created_by)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn unlink_guardian(pool: &PgPool, minor_id: i32, guardian_id: i32) -> sqlx::Result<bool> {
+    let res = sqlx::query("DELETE FROM guardian_links WHERE minor_id = $1 AND guardian_id = $2")
+        .bind(minor_id)
+        .bind(guardian_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Guardians of `minor_id` as `{id, full_name, email}`.
+pub async fn guardians(pool: &PgPool, minor_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(
+        "SELECT u.id, u.full_name, u.email FROM guardian_links g JOIN users u ON u.id = g.guardian_id
+         WHERE g.minor_id = $1 ORDER BY g.id",
+    )
+    .bind(minor_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| json!({"id": r.get::<i32,_>("id"), "full_name": r.get::<String,_>("full_name"), "email": r.get::<String,_>("email")}))
+        .collect())
+}
+
+pub fn spawn_background(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(24 * 3600));
+        loop {
+            ticker.tick().await;
+            match refresh(&pool, None).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Age check changed for {} account(s)", n),
+                Err(e) => eprintln!("Age refresh failed: {:?}", e),
+            }
+        }
+    });
+}
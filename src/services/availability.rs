@@ -0,0 +1,573 @@
+// Teacher availability and open slots.
+//
+// A teacher writes their hours in their own IANA timezone
+// (teacher_schedules.timezone):
+// - weekly rules: hours per ISO weekday, e.g. Monday 09:00-12:00;
+// - exceptions for one local date: 'available' hours replace that day's
+//   weekly hours, 'unavailable' removes hours (the whole day without times);
+// - blackouts: absolute periods with nothing available, plus busy times
+//   imported from the teacher's other calendars (calendar.rs).
+//
+// Open slots are cut from the resulting windows, slot_minutes long, and skip
+// anything overlapping a blackout, a held booking (awaiting approval or
+// confirmed) or an upcoming group session (groups.rs). Every session keeps buffer_before_minutes free before it and
+// buffer_after_minutes after it. Slots start no sooner than
+// min_notice_minutes from now and no later than horizon_days ahead.
+//
+// Windows are built in local wall time and only then turned into instants,
+// so a Monday 09:00 rule stays 09:00 across DST changes. A local time skipped
+// by a DST jump moves forward to when the clocks resume; a repeated one
+// starts at its first occurrence and ends at its second. Slots are stepped
+// in real time, so each lasts exactly slot_minutes.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+
+use crate::services::calendar;
+use crate::services::policy;
+
+pub fn parse_tz(name: &str) -> Option<Tz> {
+    name.trim().parse::<Tz>().ok()
+}
+
+/// Timezone of `user_id`, UTC when unset.
+pub async fn user_tz(pool: &PgPool, user_id: i32) -> sqlx::Result<Tz> {
+    let row = sqlx::query("SELECT timezone FROM users WHERE id = $1").bind(user_id).fetch_optional(pool).await?;
+    Ok(row.and_then(|r| r.get::<Option<String>, _>("timezone")).and_then(|s| parse_tz(&s)).unwrap_or(Tz::UTC))
+}
+
+/// A teacher's booking settings.
+pub struct Schedule {
+    pub timezone: Tz,
+    pub slot_minutes: i32,
+    pub buffer_before_minutes: i32,
+    pub buffer_after_minutes: i32,
+    pub min_notice_minutes: i32,
+    pub horizon_days: i32,
+    pub price_coins: i64,
+    /// Cancellation policy template (see policy.rs)
+    pub policy_key: String,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            timezone: Tz::UTC,
+            slot_minutes: 60,
+            buffer_before_minutes: 0,
+            buffer_after_minutes: 0,
+            min_notice_minutes: 720,
+            horizon_days: 60,
+            price_coins: 0,
+            policy_key: policy::DEFAULT_KEY.to_string(),
+        }
+    }
+}
+
+impl Schedule {
+    pub fn slot(&self) -> ChronoDuration {
+        ChronoDuration::minutes(self.slot_minutes as i64)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "timezone": self.timezone.name(),
+            "slot_minutes": self.slot_minutes,
+            "buffer_before_minutes": self.buffer_before_minutes,
+            "buffer_after_minutes": self.buffer_after_minutes,
+            "min_notice_minutes": self.min_notice_minutes,
+            "horizon_days": self.horizon_days,
+            "price_coins": self.price_coins,
+            "policy_key": self.policy_key,
+        })
+    }
+}
+
+/// The teacher's settings, or the defaults when they have none yet.
+pub async fn schedule(pool: &PgPool, teacher_id: i32) -> sqlx::Result<Schedule> {
+    let row = sqlx::query("SELECT * FROM teacher_schedules WHERE teacher_id = $1").bind(teacher_id).fetch_optional(pool).await?;
+    Ok(match row {
+        Some(r) => Schedule {
+            timezone: parse_tz(&r.get::<String, _>("timezone")).unwrap_or(Tz::UTC),
+            slot_minutes: r.get("slot_minutes"),
+            buffer_before_minutes: r.get("buffer_before_minutes"),
+            buffer_after_minutes: r.get("buffer_after_minutes"),
+            min_notice_minutes: r.get("min_notice_minutes"),
+            horizon_days: r.get("horizon_days"),
+            price_coins: r.get("price_coins"),
+            policy_key: r.get("policy_key"),
+        },
+        None => Schedule::default(),
+    })
+}
+
+pub async fn save_schedule(pool: &PgPool, teacher_id: i32, s: &Schedule) -> anyhow::Result<()> {
+    if !(15..=480).contains(&s.slot_minutes) {
+        return Err(anyhow!("slot_minutes must be between 15 and 480"));
+    }
+    if !(0..=240).contains(&s.buffer_before_minutes) || !(0..=240).contains(&s.buffer_after_minutes) {
+        return Err(anyhow!("buffers must be between 0 and 240 minutes"));
+    }
+    if s.min_notice_minutes < 0 || !(1..=365).contains(&s.horizon_days) || s.price_coins < 0 {
+        return Err(anyhow!("invalid notice, horizon or price"));
+    }
+    sqlx::query(
+        "INSERT INTO teacher_schedules (teacher_id, timezone, slot_minutes, buffer_before_minutes, buffer_after_minutes, min_notice_minutes, horizon_days, price_coins, policy_key)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         ON CONFLICT (teacher_id) DO UPDATE SET timezone = $2, slot_minutes = $3, buffer_before_minutes = $4, buffer_after_minutes = $5,
+             min_notice_minutes = $6, horizon_days = $7, price_coins = $8, policy_key = $9, updated_at = now()",
+    )
+    .bind(teacher_id)
+    .bind(s.timezone.name())
+    .bind(s.slot_minutes)
+    .bind(s.buffer_before_minutes)
+    .bind(s.buffer_after_minutes)
+    .bind(s.min_notice_minutes)
+    .bind(s.horizon_days)
+    .bind(s.price_coins)
+    .bind(&s.policy_key)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Local hours; an end of 00:00 means midnight at the end of the day.
+#[derive(Clone, Copy)]
+pub struct Hours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Hours {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> anyhow::Result<Hours> {
+        if end <= start && end != NaiveTime::MIN {
+            return Err(anyhow!("hours must end after they start"));
+        }
+        Ok(Hours { start, end })
+    }
+
+    fn on(&self, date: NaiveDate) -> (NaiveDateTime, NaiveDateTime) {
+        let end_date = if self.end == NaiveTime::MIN { date.succ_opt().unwrap_or(date) } else { date };
+        (date.and_time(self.start), end_date.and_time(self.end))
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        json!({"start": self.start.format("%H:%M").to_string(), "end": self.end.format("%H:%M").to_string()})
+    }
+}
+
+pub fn parse_time(s: &str) -> anyhow::Result<NaiveTime> {
+    let s = s.trim();
+    if s == "24:00" {
+        return Ok(NaiveTime::MIN);
+    }
+    NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| anyhow!("invalid time '{}' (use HH:MM)", s))
+}
+
+/// Weekly rules as (ISO weekday, hours).
+pub async fn rules(pool: &PgPool, teacher_id: i32) -> sqlx::Result<Vec<(u32, Hours)>> {
+    let rows = sqlx::query("SELECT weekday, start_time, end_time FROM availability_rules WHERE teacher_id = $1 ORDER BY weekday, start_time")
+        .bind(teacher_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get::<i16, _>("weekday") as u32, Hours { start: r.get("start_time"), end: r.get("end_time") }))
+        .collect())
+}
+
+/// Replace all weekly rules of the teacher.
+pub async fn replace_rules(pool: &PgPool, teacher_id: i32, new_rules: &[(u32, Hours)]) -> anyhow::Result<()> {
+    if new_rules.iter().any(|(d, _)| !(1..=7).contains(d)) {
+        return Err(anyhow!("weekday must be 1 (Monday) to 7 (Sunday)"));
+    }
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM availability_rules WHERE teacher_id = $1").bind(teacher_id).execute(&mut *tx).await?;
+    for (day, h) in new_rules {
+        sqlx::query("INSERT INTO availability_rules (teacher_id, weekday, start_time, end_time) VALUES ($1, $2, $3, $4)")
+            .bind(teacher_id)
+            .bind(*day as i16)
+            .bind(h.start)
+            .bind(h.end)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A change to one local date.
+pub struct Exception {
+    pub id: i32,
+    pub on_date: NaiveDate,
+    pub available: bool,
+    // None: the whole day
+    pub hours: Option<Hours>,
+    pub note: Option<String>,
+}
+
+impl Exception {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "date": self.on_date.to_string(),
+            "kind": if self.available { "available" } else { "unavailable" },
+            "hours": self.hours.map(|h| h.to_json()),
+            "note": self.note,
+        })
+    }
+}
+
+pub async fn exceptions(pool: &PgPool, teacher_id: i32, from: NaiveDate, to: NaiveDate) -> sqlx::Result<Vec<Exception>> {
+    let rows = sqlx::query(
+        "SELECT id, on_date, kind, start_time, end_time, note FROM availability_exceptions
+         WHERE teacher_id = $1 AND on_date BETWEEN $2 AND $3 ORDER BY on_date, start_time NULLS FIRST",
+    )
+    .bind(teacher_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let start: Option<NaiveTime> = r.get("start_time");
+            let end: Option<NaiveTime> = r.get("end_time");
+            Exception {
+                id: r.get("id"),
+                on_date: r.get("on_date"),
+                available: r.get::<String, _>("kind") == "available",
+                hours: start.zip(end).map(|(start, end)| Hours { start, end }),
+                note: r.get("note"),
+            }
+        })
+        .collect())
+}
+
+pub async fn add_exception(pool: &PgPool, teacher_id: i32, e: &Exception) -> anyhow::Result<i32> {
+    if e.available && e.hours.is_none() {
+        return Err(anyhow!("extra availability needs start and end times"));
+    }
+    let row = sqlx::query(
+        "INSERT INTO availability_exceptions (teacher_id, on_date, kind, start_time, end_time, note) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+    )
+    .bind(teacher_id)
+    .bind(e.on_date)
+    .bind(if e.available { "available" } else { "unavailable" })
+    .bind(e.hours.map(|h| h.start))
+    .bind(e.hours.map(|h| h.end))
+    .bind(&e.note)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.get("id"))
+}
+
+/// A period with nothing available.
+pub struct Blackout {
+    pub id: i32,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+pub async fn blackouts(pool: &PgPool, teacher_id: i32, from: DateTime<Utc>, to: DateTime<Utc>) -> sqlx::Result<Vec<Blackout>> {
+    let rows = sqlx::query(
+        "SELECT id, starts_at, ends_at, reason FROM availability_blackouts
+         WHERE teacher_id = $1 AND ends_at > $2 AND starts_at < $3 ORDER BY starts_at",
+    )
+    .bind(teacher_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| Blackout { id: r.get("id"), starts_at: r.get("starts_at"), ends_at: r.get("ends_at"), reason: r.get("reason") })
+        .collect())
+}
+
+pub async fn add_blackout(pool: &PgPool, teacher_id: i32, starts_at: DateTime<Utc>, ends_at: DateTime<Utc>, reason: Option<&str>) -> anyhow::Result<i32> {
+    if ends_at <= starts_at {
+        return Err(anyhow!("a blackout must end after it starts"));
+    }
+    let row = sqlx::query("INSERT INTO availability_blackouts (teacher_id, starts_at, ends_at, reason) VALUES ($1, $2, $3, $4) RETURNING id")
+        .bind(teacher_id)
+        .bind(starts_at)
+        .bind(ends_at)
+        .bind(reason)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("id"))
+}
+
+/// Delete the teacher's exception or blackout `id` (`table` is one of the two).
+pub async fn delete_entry(pool: &PgPool, teacher_id: i32, table: &str, id: i32) -> sqlx::Result<bool> {
+    let table = match table {
+        "exceptions" => "availability_exceptions",
+        "blackouts" => "availability_blackouts",
+        _ => return Ok(false),
+    };
+    let res = sqlx::query(&format!("DELETE FROM {} WHERE id = $1 AND teacher_id = $2", table))
+        .bind(id)
+        .bind(teacher_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// The instant of local time `local` in `tz`. See the module notes for
+/// skipped and repeated times; `later` picks the second of repeated ones.
+pub fn resolve(tz: &Tz, local: NaiveDateTime, later: bool) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) => t.with_timezone(&Utc),
+        LocalResult::Ambiguous(a, b) => if later { b } else { a }.with_timezone(&Utc),
+        LocalResult::None => {
+            let mut t = local;
+            for _ in 0..16 {
+                t += ChronoDuration::minutes(15);
+                if let Some(x) = tz.from_local_datetime(&t).earliest() {
+                    return x.with_timezone(&Utc);
+                }
+            }
+            Utc.from_utc_datetime(&local)
+        }
+    }
+}
+
+/// `ranges` minus `cut`.
+fn subtract<T: Ord + Copy>(ranges: Vec<(T, T)>, cut: (T, T)) -> Vec<(T, T)> {
+    let mut out = Vec::new();
+    for (s, e) in ranges {
+        if cut.1 <= s || cut.0 >= e {
+            out.push((s, e));
+            continue;
+        }
+        if s < cut.0 {
+            out.push((s, cut.0));
+        }
+        if cut.1 < e {
+            out.push((cut.1, e));
+        }
+    }
+    out
+}
+
+/// Local windows of `date` from the weekly rules and that date's exceptions.
+fn local_windows(date: NaiveDate, weekly: &[(u32, Hours)], exceptions: &[Exception]) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let todays: Vec<&Exception> = exceptions.iter().filter(|e| e.on_date == date).collect();
+    let extra: Vec<(NaiveDateTime, NaiveDateTime)> = todays.iter().filter(|e| e.available).filter_map(|e| e.hours.map(|h| h.on(date))).collect();
+    let mut windows = if extra.is_empty() {
+        let weekday = date.weekday().number_from_monday();
+        weekly.iter().filter(|(d, _)| *d == weekday).map(|(_, h)| h.on(date)).collect()
+    } else {
+        extra
+    };
+    for e in todays.iter().filter(|e| !e.available) {
+        let cut = match e.hours {
+            Some(h) => h.on(date),
+            None => Hours { start: NaiveTime::MIN, end: NaiveTime::MIN }.on(date),
+        };
+        windows = subtract(windows, cut);
+    }
+    windows
+}
+
+/// A bookable slot.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Open slots of the teacher starting in [from, to). Booking `ignore` does
+/// not count as busy (when it is being moved).
+pub async fn open_slots(pool: &PgPool, teacher_id: i32, from: DateTime<Utc>, to: DateTime<Utc>, ignore: Option<i32>) -> sqlx::Result<Vec<Slot>> {
+    let s = schedule(pool, teacher_id).await?;
+    let now = Utc::now();
+    let from = from.max(now + ChronoDuration::minutes(s.min_notice_minutes as i64));
+    let to = to.min(now + ChronoDuration::days(s.horizon_days as i64));
+    if from >= to {
+        return Ok(Vec::new());
+    }
+    let tz = s.timezone;
+    let slot = s.slot();
+    let before = ChronoDuration::minutes(s.buffer_before_minutes as i64);
+    let after = ChronoDuration::minutes(s.buffer_after_minutes as i64);
+
+    // a day either side covers windows that cross midnight or a DST shift
+    let first_day = from.with_timezone(&tz).date_naive().pred_opt().unwrap_or(NaiveDate::MIN);
+    let last_day = to.with_timezone(&tz).date_naive().succ_opt().unwrap_or(NaiveDate::MAX);
+    let weekly = rules(pool, teacher_id).await?;
+    let exc = exceptions(pool, teacher_id, first_day, last_day).await?;
+    let blocked = blackouts(pool, teacher_id, from - slot, to + slot).await?;
+    let imported = calendar::busy_between(pool, teacher_id, from - slot, to + slot).await?;
+    let held: Vec<(DateTime<Utc>, DateTime<Utc>)> = sqlx::query(
+        "SELECT starts_at, ends_at FROM bookings
+         WHERE teacher_id = $1 AND status IN ('awaiting_approval', 'confirmed') AND id IS DISTINCT FROM $4
+           AND starts_at < $3 AND ends_at > $2
+         UNION ALL
+         SELECT starts_at, ends_at FROM group_sessions
+         WHERE teacher_id = $1 AND status IN ('open', 'confirmed') AND starts_at < $3 AND ends_at > $2",
+    )
+    .bind(teacher_id)
+    .bind(from - slot - before - after)
+    .bind(to + slot + before + after)
+    .bind(ignore)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|r| (r.get("starts_at"), r.get("ends_at")))
+    .collect();
+
+    let mut out = Vec::new();
+    let mut day = first_day;
+    while day <= last_day {
+        for (ls, le) in local_windows(day, &weekly, &exc) {
+            let (ws, we) = (resolve(&tz, ls, false), resolve(&tz, le, true));
+            let mut start = ws;
+            while start + slot <= we {
+                let end = start + slot;
+                // sessions keep buffer_after free after them and buffer_before
+                // before them; one gap can serve as both
+                let clash = held
+                    .iter()
+                    .any(|(bs, be)| (start < *be + after && *bs < end + after) || (start - before < *be && *bs - before < end))
+                    || blocked.iter().any(|b| start < b.ends_at && b.starts_at < end)
+                    || imported.iter().any(|(bs, be)| start < *be && *bs < end);
+                if start >= from && start < to && !clash {
+                    out.push(Slot { start, end });
+                }
+                start = end;
+            }
+        }
+        day = match day.succ_opt() {
+            Some(d) => d,
+            None => break,
+        };
+    }
+    out.sort_by_key(|s| s.start);
+    out.dedup();
+    Ok(out)
+}
+
+/// The open slot starting exactly at `start`, if there is one.
+pub async fn slot_at(pool: &PgPool, teacher_id: i32, start: DateTime<Utc>, ignore: Option<i32>) -> sqlx::Result<Option<Slot>> {
+    let slots = open_slots(pool, teacher_id, start, start + ChronoDuration::minutes(1), ignore).await?;
+    Ok(slots.into_iter().find(|s| s.start == start))
+}
+
+/// Everything the teacher has set up, for their editor.
+pub async fn overview(pool: &PgPool, teacher_id: i32) -> sqlx::Result<serde_json::Value> {
+    let s = schedule(pool, teacher_id).await?;
+    let today = Utc::now().with_timezone(&s.timezone).date_naive();
+    let weekly = rules(pool, teacher_id).await?;
+    let exc = exceptions(pool, teacher_id, today, NaiveDate::MAX).await?;
+    let blocked = blackouts(pool, teacher_id, Utc::now(), DateTime::<Utc>::MAX_UTC).await?;
+    let policies = policy::templates(pool, false).await?;
+    Ok(json!({
+        "schedule": s.to_json(),
+        "policies": policies.iter().map(|p| p.to_json()).collect::<Vec<_>>(),
+        "rules": weekly.iter().map(|(d, h)| { let mut v = h.to_json(); v["weekday"] = json!(d); v }).collect::<Vec<_>>(),
+        "exceptions": exc.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+        "blackouts": blocked.iter().map(|b| json!({
+            "id": b.id,
+            "starts_at": b.starts_at.with_timezone(&s.timezone).to_rfc3339(),
+            "ends_at": b.ends_at.with_timezone(&s.timezone).to_rfc3339(),
+            "reason": b.reason,
+        })).collect::<Vec<_>>(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&local(s))
+    }
+
+    fn hours(start: &str, end: &str) -> Hours {
+        Hours::new(parse_time(start).unwrap(), parse_time(end).unwrap()).unwrap()
+    }
+
+    fn unavailable(date: &str, hours: Option<Hours>) -> Exception {
+        Exception { id: 0, on_date: date.parse().unwrap(), available: false, hours, note: None }
+    }
+
+    #[test]
+    fn skipped_hour_moves_forward() {
+        // Berlin jumps from 02:00 to 03:00 CEST (01:00 UTC) on 2026-03-29
+        let tz = chrono_tz::Europe::Berlin;
+        assert_eq!(resolve(&tz, local("2026-03-29 02:30"), false), utc("2026-03-29 01:00"));
+        assert_eq!(resolve(&tz, local("2026-03-29 02:00"), true), utc("2026-03-29 01:00"));
+        assert_eq!(resolve(&tz, local("2026-03-29 03:00"), false), utc("2026-03-29 01:00"));
+        // the day is 23 hours long
+        let day = resolve(&tz, local("2026-03-30 00:00"), true) - resolve(&tz, local("2026-03-29 00:00"), false);
+        assert_eq!(day, ChronoDuration::hours(23));
+    }
+
+    #[test]
+    fn repeated_hour_spans_both_occurrences() {
+        // Berlin goes from 03:00 CEST back to 02:00 CET on 2026-10-25
+        let tz = chrono_tz::Europe::Berlin;
+        assert_eq!(resolve(&tz, local("2026-10-25 02:30"), false), utc("2026-10-25 00:30"));
+        assert_eq!(resolve(&tz, local("2026-10-25 02:30"), true), utc("2026-10-25 01:30"));
+        // a 02:00-03:00 window starts at the first 02:00 and lasts two hours
+        let (s, e) = hours("02:00", "03:00").on("2026-10-25".parse().unwrap());
+        assert_eq!(resolve(&tz, e, true) - resolve(&tz, s, false), ChronoDuration::hours(2));
+    }
+
+    #[test]
+    fn end_of_24_00_is_next_midnight() {
+        assert_eq!(parse_time("24:00").unwrap(), NaiveTime::MIN);
+        assert!(Hours::new(parse_time("18:00").unwrap(), parse_time("17:00").unwrap()).is_err());
+        assert!(Hours::new(parse_time("00:00").unwrap(), parse_time("00:00").unwrap()).is_ok());
+
+        // 2026-10-19 is a Monday
+        let weekly = [(1, hours("18:00", "24:00"))];
+        let windows = local_windows("2026-10-19".parse().unwrap(), &weekly, &[]);
+        assert_eq!(windows, vec![(local("2026-10-19 18:00"), local("2026-10-20 00:00"))]);
+        assert!(local_windows("2026-10-20".parse().unwrap(), &weekly, &[]).is_empty());
+
+        // a whole day on the day Berlin falls back is 25 hours long
+        let tz = chrono_tz::Europe::Berlin;
+        let (s, e) = hours("00:00", "24:00").on("2026-10-25".parse().unwrap());
+        assert_eq!(resolve(&tz, e, true) - resolve(&tz, s, false), ChronoDuration::hours(25));
+    }
+
+    #[test]
+    fn exceptions_cut_and_replace_weekly_hours() {
+        let weekly = [(1, hours("09:00", "17:00"))];
+        let monday: NaiveDate = "2026-10-19".parse().unwrap();
+
+        let lunch = [unavailable("2026-10-19", Some(hours("12:00", "13:00")))];
+        assert_eq!(
+            local_windows(monday, &weekly, &lunch),
+            vec![(local("2026-10-19 09:00"), local("2026-10-19 12:00")), (local("2026-10-19 13:00"), local("2026-10-19 17:00"))]
+        );
+        let morning = [unavailable("2026-10-19", Some(hours("08:00", "10:00")))];
+        assert_eq!(local_windows(monday, &weekly, &morning), vec![(local("2026-10-19 10:00"), local("2026-10-19 17:00"))]);
+        assert!(local_windows(monday, &weekly, &[unavailable("2026-10-19", None)]).is_empty());
+        // another date's exception changes nothing
+        let next_week = [unavailable("2026-10-26", None)];
+        assert_eq!(local_windows(monday, &weekly, &next_week), vec![(local("2026-10-19 09:00"), local("2026-10-19 17:00"))]);
+
+        let extra = [Exception { id: 0, on_date: monday, available: true, hours: Some(hours("19:00", "21:00")), note: None }];
+        assert_eq!(local_windows(monday, &weekly, &extra), vec![(local("2026-10-19 19:00"), local("2026-10-19 21:00"))]);
+    }
+
+    #[test]
+    fn subtract_keeps_what_is_left() {
+        assert_eq!(subtract(vec![(0, 10)], (3, 5)), vec![(0, 3), (5, 10)]);
+        assert_eq!(subtract(vec![(0, 10)], (10, 12)), vec![(0, 10)]);
+        assert_eq!(subtract(vec![(0, 10), (20, 30)], (5, 25)), vec![(0, 5), (25, 30)]);
+        assert!(subtract(vec![(0, 10)], (0, 10)).is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+// One-to-one bookings of a teacher's open slots (see availability.rs).
+//
+// A student books a slot; the price (teacher_schedules.price_coins) moves
+// from their wallet into escrow on the booking. If the student has a guardian
+// and the price is above their spending limit, the booking is held as
+// awaiting_approval with nothing charged until a guardian decides (see
+// guardian.rs); undecided holds are declined after BOOKING_APPROVAL_HOURS
+// (default 24) or when the session starts. Confirmed sessions are paid out to
+// the teacher once they end. Cancelling and rescheduling follow the policy
+// agreed at booking time (see policy.rs), which splits the escrow and limits
+// moves. Notices about confirmed sessions carry them as calendar invites
+// (see calendar.rs). Bookings paid from a prepaid package take their escrow
+// from it instead of the wallet (packages.rs); recurring bookings are made
+// one occurrence at a time (series.rs).
+//
+// Creating and moving bookings takes a per-teacher advisory lock, so two
+// students cannot take the same slot. Runs every BOOKINGS_INTERVAL_SECONDS
+// (default 300).
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use std::time::Duration;
+
+use crate::services::age;
+use crate::services::availability;
+use crate::services::guardian::{self, Settlement};
+use crate::services::calendar;
+use crate::services::notify_prefs::{self, Notice};
+use crate::services::packages;
+use crate::services::policy::{self, Decision, Policy, Side};
+use crate::services::series;
+use crate::services::wallet_service::{self, Entry};
+
+pub fn approval_hours() -> i64 {
+    std::env::var("BOOKING_APPROVAL_HOURS").ok().and_then(|s| s.parse().ok()).filter(|h: &i64| *h > 0).unwrap_or(24)
+}
+
+pub struct Booking {
+    pub id: i32,
+    pub teacher_id: i32,
+    pub student_id: i32,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub status: String,
+    pub price_coins: i64,
+    pub escrow_coins: i64,
+    pub approval_id: Option<i32>,
+    pub ical_sequence: i32,
+    /// The cancellation policy agreed at booking time (see policy.rs)
+    pub policy: Option<serde_json::Value>,
+    pub reschedule_count: i32,
+    pub package_id: Option<i32>,
+    pub series_id: Option<i32>,
+    pub series_index: Option<i32>,
+}
+
+impl Booking {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Booking {
+        Booking {
+            id: r.get("id"),
+            teacher_id: r.get("teacher_id"),
+            student_id: r.get("student_id"),
+            starts_at: r.get("starts_at"),
+            ends_at: r.get("ends_at"),
+            status: r.get("status"),
+            price_coins: r.get("price_coins"),
+            escrow_coins: r.get("escrow_coins"),
+            approval_id: r.get("approval_id"),
+            ical_sequence: r.get("ical_sequence"),
+            policy: r.get("policy"),
+            reschedule_count: r.get("reschedule_count"),
+            package_id: r.get("package_id"),
+            series_id: r.get("series_id"),
+            series_index: r.get("series_index"),
+        }
+    }
+
+    fn reference(&self) -> String {
+        format!("booking:{}", self.id)
+    }
+
+    fn is_active(&self) -> bool {
+        self.status == "awaiting_approval" || self.status == "confirmed"
+    }
+}
+
+/// Which side of the booking `actor_id` acts for; guardians act for the student.
+async fn side_of(pool: &PgPool, b: &Booking, actor_id: i32) -> anyhow::Result<Side> {
+    if actor_id == b.teacher_id {
+        Ok(Side::Teacher)
+    } else if actor_id == b.student_id || guardian::is_guardian_of(pool, actor_id, b.student_id).await? {
+        Ok(Side::Student)
+    } else {
+        Err(anyhow!("booking not found"))
+    }
+}
+
+/// The policy agreed for `b`, or the teacher's current one for bookings made
+/// before policies were kept.
+async fn policy_of(pool: &PgPool, b: &Booking) -> sqlx::Result<Policy> {
+    match Policy::from_snapshot(b.policy.as_ref()) {
+        Some(p) => Ok(p),
+        None => policy::for_teacher(pool, b.teacher_id).await,
+    }
+}
+
+fn minutes_until(at: DateTime<Utc>) -> i64 {
+    (at - Utc::now()).num_minutes()
+}
+
+async fn lock(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Option<Booking>> {
+    let row = sqlx::query("SELECT * FROM bookings WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut **tx).await?;
+    Ok(row.as_ref().map(Booking::from_row))
+}
+
+/// Serialize slot changes of one teacher.
+pub(crate) async fn lock_teacher(tx: &mut Transaction<'_, Postgres>, teacher_id: i32) -> sqlx::Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('bookings'), $1)").bind(teacher_id).execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// `at` as the user reads it, e.g. "Mon 2 Mar 2026, 14:00 CET".
+pub fn when(at: DateTime<Utc>, tz: Tz) -> String {
+    at.with_timezone(&tz).format("%a %-d %b %Y, %H:%M %Z").to_string()
+}
+
+/// `user_id`'s name as `viewer` may see it (private profiles and minors are
+/// shortened, see age.rs).
+async fn name(pool: &PgPool, user_id: i32, viewer: i32) -> sqlx::Result<String> {
+    let row = sqlx::query("SELECT shown_name(u, $2) AS name FROM users u WHERE u.id = $1").bind(user_id).bind(viewer).fetch_optional(pool).await?;
+    Ok(row.map(|r| r.get("name")).unwrap_or_default())
+}
+
+/// Send `user_id` a booking notice; `{when}` in the body becomes the session
+/// start in their timezone. With `invite`, the email carries the session as
+/// a calendar invite or cancellation (see calendar.rs).
+async fn tell(pool: &PgPool, user_id: i32, b: &Booking, priority: &str, title: &str, body: &str, invite: bool) {
+    let tz = availability::user_tz(pool, user_id).await.unwrap_or(Tz::UTC);
+    let body = body.replace("{when}", &when(b.starts_at, tz));
+    let ics = if invite {
+        match calendar::invite(pool, b, user_id).await {
+            Ok(ics) => Some(ics),
+            Err(e) => {
+                eprintln!("Booking {} calendar invite failed: {:?}", b.id, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let notice = Notice { user_id, sender_id: None, category: "booking", priority, title, body: &body, ics: ics.as_deref() };
+    if let Err(e) = notify_prefs::deliver(pool, &notice).await {
+        eprintln!("Booking {} notification for user {} failed: {:?}", b.id, user_id, e);
+    }
+}
+
+/// How a booking is paid for and where it belongs.
+#[derive(Default, Clone, Copy)]
+pub struct Origin {
+    /// paid from this package instead of the wallet
+    pub package_id: Option<i32>,
+    /// occurrence (series id, index) of a recurring booking
+    pub series: Option<(i32, i32)>,
+}
+
+/// Book the teacher's open slot starting at `starts_at` for `student_id`.
+pub async fn create(pool: &PgPool, student_id: i32, teacher_id: i32, starts_at: DateTime<Utc>, origin: Origin) -> anyhow::Result<Booking> {
+    if student_id == teacher_id {
+        return Err(anyhow!("you cannot book yourself"));
+    }
+    let teacher = sqlx::query("SELECT full_name, role, kyc_verified FROM users WHERE id = $1")
+        .bind(teacher_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow!("teacher not found"))?;
+    if teacher.get::<Option<String>, _>("role").as_deref() != Some("teacher") || !teacher.get::<bool, _>("kyc_verified") {
+        return Err(anyhow!("this teacher cannot be booked"));
+    }
+    let standing = age::standing(pool, student_id).await?.ok_or_else(|| anyhow!("account not found"))?;
+    standing.may_transact().map_err(|e| anyhow!(e))?;
+
+    let schedule = availability::schedule(pool, teacher_id).await?;
+    let terms = policy::for_teacher(pool, teacher_id).await?.to_json();
+    // package sessions were paid (and approved) when the package was bought
+    let mut price = schedule.price_coins;
+    let needs_approval = origin.package_id.is_none() && price > 0 && guardian::approval_limit(pool, student_id).await?.map(|l| price > l).unwrap_or(false);
+
+    let mut tx = pool.begin().await?;
+    lock_teacher(&mut tx, teacher_id).await?;
+    let slot = availability::slot_at(pool, teacher_id, starts_at, None).await?.ok_or_else(|| anyhow!("that time is not available"))?;
+    if let Some(package_id) = origin.package_id {
+        price = packages::draw(&mut tx, package_id, student_id, teacher_id, slot.end).await?;
+    }
+    let row = sqlx::query(
+        "INSERT INTO bookings (teacher_id, student_id, starts_at, ends_at, status, price_coins, escrow_coins, policy, package_id, series_id, series_index)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING *",
+    )
+    .bind(teacher_id)
+    .bind(student_id)
+    .bind(slot.start)
+    .bind(slot.end)
+    .bind(if needs_approval { "awaiting_approval" } else { "confirmed" })
+    .bind(price)
+    .bind(if origin.package_id.is_some() { price } else { 0 })
+    .bind(&terms)
+    .bind(origin.package_id)
+    .bind(origin.series.map(|s| s.0))
+    .bind(origin.series.map(|s| s.1))
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut booking = Booking::from_row(&row);
+    let teacher_name: String = teacher.get("full_name");
+    if needs_approval {
+        let student_tz = availability::user_tz(pool, student_id).await?;
+        let description = format!("a {}-minute session with {} on {}", schedule.slot_minutes, teacher_name, when(slot.start, student_tz));
+        let approval_id = guardian::request_approval(&mut tx, student_id, price, "booking", Some(booking.id), &description).await?;
+        sqlx::query("UPDATE bookings SET approval_id = $2 WHERE id = $1").bind(booking.id).bind(approval_id).execute(&mut *tx).await?;
+        booking.approval_id = Some(approval_id);
+    } else if price > 0 && origin.package_id.is_none() {
+        hold(&mut tx, &mut booking).await?;
+    }
+    tx.commit().await?;
+
+    if let Some(approval_id) = booking.approval_id {
+        if let Err(e) = guardian::notify_approval_request(pool, approval_id).await {
+            eprintln!("Approval request {} notification failed: {:?}", approval_id, e);
+        }
+        let body = format!("Your session with {} on {{when}} is held until a parent or guardian approves it.", teacher_name);
+        tell(pool, student_id, &booking, "normal", "Booking waiting for approval", &body, false).await;
+    } else {
+        announce_confirmed(pool, &booking).await?;
+    }
+    Ok(booking)
+}
+
+/// Take the price from the student into escrow.
+async fn hold(tx: &mut Transaction<'_, Postgres>, b: &mut Booking) -> anyhow::Result<()> {
+    let reference = b.reference();
+    let entry = Entry { kind: "booking_escrow", reference: Some(&reference), actor_id: Some(b.student_id), note: None };
+    wallet_service::post(tx, b.student_id, -b.price_coins, &entry).await?;
+    sqlx::query("UPDATE bookings SET escrow_coins = $2, updated_at = now() WHERE id = $1").bind(b.id).bind(b.price_coins).execute(&mut **tx).await?;
+    b.escrow_coins = b.price_coins;
+    Ok(())
+}
+
+/// Split the escrow between student and teacher and move any penalty, as a
+/// cancel decision says. A full refund of a package session gives the
+/// session back to the package while it is valid.
+async fn settle(tx: &mut Transaction<'_, Postgres>, b: &mut Booking, d: &Decision, actor_id: i32) -> anyhow::Result<()> {
+    let reference = b.reference();
+    let entry = |kind| Entry { kind, reference: Some(&reference), actor_id: Some(actor_id), note: Some(&d.reason) };
+    let back_to_package = match b.package_id {
+        Some(package_id) if d.refund_coins > 0 && d.refund_coins == b.escrow_coins => packages::restore(tx, package_id).await?,
+        _ => false,
+    };
+    if d.refund_coins > 0 && !back_to_package {
+        wallet_service::post(tx, b.student_id, d.refund_coins, &entry("booking_refund")).await?;
+    }
+    if d.teacher_fee_coins > 0 {
+        wallet_service::post(tx, b.teacher_id, d.teacher_fee_coins, &entry("late_cancel_fee")).await?;
+    }
+    if d.penalty_coins > 0 {
+        wallet_service::post(tx, b.teacher_id, -d.penalty_coins, &entry("cancel_penalty")).await?;
+        wallet_service::post(tx, b.student_id, d.penalty_coins, &entry("cancel_compensation")).await?;
+    }
+    if b.escrow_coins > 0 {
+        sqlx::query("UPDATE bookings SET escrow_coins = 0, updated_at = now() WHERE id = $1").bind(b.id).execute(&mut **tx).await?;
+        b.escrow_coins = 0;
+    }
+    Ok(())
+}
+
+async fn announce_confirmed(pool: &PgPool, b: &Booking) -> sqlx::Result<()> {
+    let teacher = name(pool, b.teacher_id, b.student_id).await?;
+    let student = name(pool, b.student_id, b.teacher_id).await?;
+    tell(pool, b.student_id, b, "normal", "Booking confirmed", &format!("Your session with {} on {{when}} is confirmed.", teacher), true).await;
+    tell(pool, b.teacher_id, b, "normal", "New booking", &format!("{} booked a session with you on {{when}}.", student), true).await;
+    Ok(())
+}
+
+/// Settle a booking held for approval: charge and confirm it, or decline it.
+pub async fn apply_approval(pool: &PgPool, booking_id: i32, approved: bool) -> anyhow::Result<Settlement> {
+    let mut tx = pool.begin().await?;
+    let mut b = match lock(&mut tx, booking_id).await? {
+        Some(b) if b.status == "awaiting_approval" => b,
+        _ => return Ok(Settlement::Stale),
+    };
+    let mut reason = None;
+    if approved && b.starts_at <= Utc::now() {
+        reason = Some("the session time has passed".to_string());
+    } else if approved && b.price_coins > 0 {
+        // a failed charge leaves the transaction usable: post checks before writing
+        if let Err(e) = hold(&mut tx, &mut b).await {
+            reason = Some(e.to_string());
+        }
+    } else if !approved {
+        reason = Some("a guardian declined it".to_string());
+    }
+    let status = if reason.is_none() { "confirmed" } else { "declined" };
+    sqlx::query("UPDATE bookings SET status = $2, cancel_reason = $3, updated_at = now() WHERE id = $1")
+        .bind(b.id)
+        .bind(status)
+        .bind(&reason)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    b.status = status.to_string();
+
+    match reason {
+        None => {
+            announce_confirmed(pool, &b).await?;
+            Ok(Settlement::Done)
+        }
+        Some(r) => {
+            tell(pool, b.student_id, &b, "normal", "Booking declined", &format!("Your session on {{when}} was not booked: {}.", r), false).await;
+            Ok(Settlement::Refused(r))
+        }
+    }
+}
+
+/// Cancel an upcoming booking as its student, teacher or one of the
+/// student's guardians. The booking's policy decides who gets the escrow.
+pub async fn cancel(pool: &PgPool, id: i32, actor_id: i32, reason: Option<&str>) -> anyhow::Result<(Booking, Decision)> {
+    let mut tx = pool.begin().await?;
+    let mut b = lock(&mut tx, id).await?.ok_or_else(|| anyhow!("booking not found"))?;
+    let side = side_of(pool, &b, actor_id).await?;
+    if !b.is_active() || b.starts_at <= Utc::now() {
+        return Err(anyhow!("only upcoming bookings can be cancelled"));
+    }
+    let terms = policy_of(pool, &b).await?;
+    let mut decision = policy::evaluate_cancel(&terms, side, b.escrow_coins, minutes_until(b.starts_at));
+    if decision.penalty_coins > 0 {
+        decision.cap_penalty(wallet_service::lock_balance(&mut tx, b.teacher_id).await?);
+    }
+    settle(&mut tx, &mut b, &decision, actor_id).await?;
+    policy::record(&mut tx, b.id, actor_id, &terms.key, &decision).await?;
+    if let Some(approval_id) = b.approval_id {
+        guardian::cancel_approval(&mut tx, approval_id).await?;
+    }
+    sqlx::query(
+        "UPDATE bookings SET status = 'cancelled', cancelled_by = $2, cancel_reason = $3, ical_sequence = ical_sequence + 1, updated_at = now() WHERE id = $1",
+    )
+    .bind(b.id)
+    .bind(actor_id)
+    .bind(reason)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    // only confirmed sessions were ever sent as invites
+    let invited = b.status == "confirmed";
+    b.status = "cancelled".to_string();
+    b.ical_sequence += 1;
+
+    let note = reason.map(|r| format!(" Reason: {}", r)).unwrap_or_default();
+    for user in [b.student_id, b.teacher_id] {
+        if user != actor_id {
+            let by = name(pool, actor_id, user).await?;
+            let body = format!("{} cancelled the session on {{when}}.{} {}", by, note, decision.reason);
+            tell(pool, user, &b, "high", "Booking cancelled", &body, invited).await;
+        } else if invited {
+            tell(pool, user, &b, "low", "Booking cancelled", &format!("You cancelled the session on {{when}}. {}", decision.reason), true).await;
+        }
+    }
+    Ok((b, decision))
+}
+
+/// Move an upcoming booking to the teacher's open slot at `starts_at`, if
+/// the booking's policy allows it. Refusals are recorded too.
+pub async fn reschedule(pool: &PgPool, id: i32, actor_id: i32, starts_at: DateTime<Utc>) -> anyhow::Result<Booking> {
+    let mut tx = pool.begin().await?;
+    let mut b = lock(&mut tx, id).await?.ok_or_else(|| anyhow!("booking not found"))?;
+    let side = side_of(pool, &b, actor_id).await?;
+    if !b.is_active() || b.starts_at <= Utc::now() {
+        return Err(anyhow!("only upcoming bookings can be rescheduled"));
+    }
+    let terms = policy_of(pool, &b).await?;
+    let decision = policy::evaluate_reschedule(&terms, side, b.reschedule_count, minutes_until(b.starts_at));
+    if !decision.allowed {
+        policy::record(&mut tx, b.id, actor_id, &terms.key, &decision).await?;
+        tx.commit().await?;
+        return Err(anyhow!(decision.reason));
+    }
+    lock_teacher(&mut tx, b.teacher_id).await?;
+    let slot = availability::slot_at(pool, b.teacher_id, starts_at, Some(b.id)).await?.ok_or_else(|| anyhow!("that time is not available"))?;
+    if let Some(package_id) = b.package_id {
+        let expires_at = packages::get(pool, package_id).await?.and_then(|p| p.expires_at);
+        if expires_at.is_some_and(|t| slot.end > t) {
+            return Err(anyhow!("the package this session is paid from expires before that time"));
+        }
+    }
+    let previous = b.starts_at;
+    let counted = i32::from(side == Side::Student);
+    sqlx::query(
+        "UPDATE bookings SET starts_at = $2, ends_at = $3, reschedule_count = reschedule_count + $4, ical_sequence = ical_sequence + 1, updated_at = now()
+         WHERE id = $1",
+    )
+    .bind(b.id)
+    .bind(slot.start)
+    .bind(slot.end)
+    .bind(counted)
+    .execute(&mut *tx)
+    .await?;
+    policy::record(&mut tx, b.id, actor_id, &terms.key, &decision).await?;
+    tx.commit().await?;
+    b.starts_at = slot.start;
+    b.ends_at = slot.end;
+    b.reschedule_count += counted;
+    b.ical_sequence += 1;
+
+    let invited = b.status == "confirmed";
+    for user in [b.student_id, b.teacher_id] {
+        let tz = availability::user_tz(pool, user).await.unwrap_or(Tz::UTC);
+        if user != actor_id {
+            let by = name(pool, actor_id, user).await?;
+            let body = format!("{} moved the session from {} to {{when}}.", by, when(previous, tz));
+            tell(pool, user, &b, "high", "Booking rescheduled", &body, invited).await;
+        } else if invited {
+            let body = format!("You moved the session from {} to {{when}}.", when(previous, tz));
+            tell(pool, user, &b, "low", "Booking rescheduled", &body, true).await;
+        }
+    }
+    Ok(b)
+}
+
+/// What cancelling or moving booking `id` now would mean for `actor_id`,
+/// with the decisions recorded so far.
+pub async fn policy_preview(pool: &PgPool, id: i32, actor_id: i32) -> anyhow::Result<serde_json::Value> {
+    let row = sqlx::query("SELECT * FROM bookings WHERE id = $1").bind(id).fetch_optional(pool).await?;
+    let b = row.as_ref().map(Booking::from_row).ok_or_else(|| anyhow!("booking not found"))?;
+    let side = side_of(pool, &b, actor_id).await?;
+    let terms = policy_of(pool, &b).await?;
+    let minutes = minutes_until(b.starts_at);
+    Ok(json!({
+        "booking_id": b.id,
+        "policy": terms.to_json(),
+        "cancel": policy::evaluate_cancel(&terms, side, b.escrow_coins, minutes).to_json(),
+        "reschedule": policy::evaluate_reschedule(&terms, side, b.reschedule_count, minutes).to_json(),
+        "decisions": policy::decisions(pool, b.id).await?,
+    }))
+}
+
+/// Bookings of `user_id` as student or teacher, newest first.
+pub async fn list_for(pool: &PgPool, user_id: i32, limit: i64) -> sqlx::Result<Vec<serde_json::Value>> {
+    let tz = availability::user_tz(pool, user_id).await?;
+    let rows = sqlx::query(
+        "SELECT b.*, shown_name(t, $1) AS teacher_name, shown_name(s, $1) AS student_name
+         FROM bookings b JOIN users t ON t.id = b.teacher_id JOIN users s ON s.id = b.student_id
+         WHERE b.student_id = $1 OR b.teacher_id = $1
+         ORDER BY b.starts_at DESC LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let b = Booking::from_row(r);
+            json!({
+                "id": b.id,
+                "teacher_id": b.teacher_id,
+                "teacher_name": r.get::<String,_>("teacher_name"),
+                "student_id": b.student_id,
+                "student_name": r.get::<String,_>("student_name"),
+                "starts_at": b.starts_at.with_timezone(&tz).to_rfc3339(),
+                "ends_at": b.ends_at.with_timezone(&tz).to_rfc3339(),
+                "status": b.status,
+                "price_coins": b.price_coins,
+                "escrow_coins": b.escrow_coins,
+                "package_id": b.package_id,
+                "series_id": b.series_id,
+                "series_index": b.series_index,
+                "cancel_reason": r.get::<Option<String>,_>("cancel_reason"),
+            })
+        })
+        .collect())
+}
+
+/// Sessions a student has taken and has coming up.
+pub async fn progress(pool: &PgPool, student_id: i32) -> sqlx::Result<serde_json::Value> {
+    let r = sqlx::query(
+        "SELECT COUNT(*) FILTER (WHERE status = 'completed') AS completed,
+             COALESCE(SUM(EXTRACT(EPOCH FROM ends_at - starts_at) / 60) FILTER (WHERE status = 'completed'), 0)::BIGINT AS minutes,
+             COUNT(*) FILTER (WHERE status IN ('confirmed', 'awaiting_approval') AND starts_at > now()) AS upcoming,
+             COUNT(DISTINCT teacher_id) FILTER (WHERE status = 'completed') AS teachers
+         FROM bookings WHERE student_id = $1",
+    )
+    .bind(student_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(json!({
+        "completed_sessions": r.get::<i64,_>("completed"),
+        "completed_minutes": r.get::<i64,_>("minutes"),
+        "upcoming_sessions": r.get::<i64,_>("upcoming"),
+        "teachers": r.get::<i64,_>("teachers"),
+    }))
+}
+
+/// Mark ended sessions completed and pay their escrow to the teacher.
+pub async fn complete_due(pool: &PgPool) -> anyhow::Result<usize> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM bookings WHERE status = 'confirmed' AND ends_at <= now() ORDER BY ends_at LIMIT 500")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    let mut done = 0;
+    for id in ids {
+        let mut tx = pool.begin().await?;
+        let b = match lock(&mut tx, id).await? {
+            Some(b) if b.status == "confirmed" => b,
+            _ => continue,
+        };
+        if b.escrow_coins > 0 {
+            let reference = b.reference();
+            let entry = Entry { kind: "booking_payout", reference: Some(&reference), actor_id: None, note: None };
+            wallet_service::post(&mut tx, b.teacher_id, b.escrow_coins, &entry).await?;
+        }
+        sqlx::query("UPDATE bookings SET status = 'completed', escrow_coins = 0, updated_at = now() WHERE id = $1")
+            .bind(b.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        done += 1;
+    }
+    Ok(done)
+}
+
+/// Decline bookings whose approval took too long.
+pub async fn expire_approvals(pool: &PgPool) -> anyhow::Result<usize> {
+    let cutoff = Utc::now() - ChronoDuration::hours(approval_hours());
+    let rows = sqlx::query("SELECT id FROM bookings WHERE status = 'awaiting_approval' AND (created_at < $1 OR starts_at <= now())")
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await?;
+    let mut expired = 0;
+    for r in &rows {
+        let mut tx = pool.begin().await?;
+        let b = match lock(&mut tx, r.get("id")).await? {
+            Some(b) if b.status == "awaiting_approval" => b,
+            _ => continue,
+        };
+        if let Some(approval_id) = b.approval_id {
+            guardian::cancel_approval(&mut tx, approval_id).await?;
+        }
+        sqlx::query("UPDATE bookings SET status = 'declined', cancel_reason = 'not approved in time', updated_at = now() WHERE id = $1")
+            .bind(b.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        tell(pool, b.student_id, &b, "normal", "Booking expired", "Your session on {when} was released because no guardian approved it in time.", false).await;
+        expired += 1;
+    }
+    Ok(expired)
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("BOOKINGS_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(300).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match expire_approvals(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Released {} booking(s) not approved in time", n),
+                Err(e) => eprintln!("Booking approval expiry failed: {:?}", e),
+            }
+            match complete_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Completed {} booking(s)", n),
+                Err(e) => eprintln!("Booking completion failed: {:?}", e),
+            }
+            match series::extend_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Booked {} recurring occurrence(s)", n),
+                Err(e) => eprintln!("Recurring bookings failed: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,321 @@
+// Sessions in users' own calendar apps.
+//
+// - Feed: every user can subscribe to a private iCalendar feed of their
+//   sessions at /calendar/{token}.ics. The token is the only credential, so
+//   it can be reset, which breaks the old URL.
+// - Invites: booking notices carry the session as METHOD:REQUEST (confirmed,
+//   moved) or METHOD:CANCEL; the event UID is stable per booking and
+//   bookings.ical_sequence orders the updates. Group seat notices do the
+//   same with one UID per seat; group times never change, so the request is
+//   sequence 0 and the cancellation 1.
+// - Feeds and invites of group sessions list only the teacher and the
+//   student themselves, never the other students.
+// - Imports: teachers upload .ics exports of their other calendars; the busy
+//   times in them (see ical.rs) block open slots like blackouts. Uploading a
+//   file under the same name replaces the earlier import.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use rand::RngCore;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+
+use crate::services::availability;
+use crate::services::bookings::Booking;
+use crate::services::email;
+use crate::services::groups::{Group, Seat};
+use crate::services::ical::{self, Event, Person};
+
+/// Days of past sessions kept in feeds (CALENDAR_FEED_PAST_DAYS, default 30).
+pub fn feed_past_days() -> i64 {
+    std::env::var("CALENDAR_FEED_PAST_DAYS").ok().and_then(|s| s.parse().ok()).filter(|d: &i64| *d >= 0).unwrap_or(30)
+}
+
+/// Largest accepted .ics upload (ICS_IMPORT_MAX_BYTES, default 2 MB).
+pub fn import_max_bytes() -> usize {
+    std::env::var("ICS_IMPORT_MAX_BYTES").ok().and_then(|s| s.parse().ok()).unwrap_or(2 * 1024 * 1024)
+}
+
+/// How far ahead imported recurring events are expanded.
+const IMPORT_DAYS: i64 = 365;
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn feed_url(token: &str) -> String {
+    format!("{}/calendar/{}.ics", email::base_url(), token)
+}
+
+/// The user's feed token, created on first use.
+pub async fn feed_token(pool: &PgPool, user_id: i32) -> sqlx::Result<String> {
+    let row = sqlx::query("UPDATE users SET calendar_token = COALESCE(calendar_token, $2) WHERE id = $1 RETURNING calendar_token")
+        .bind(user_id)
+        .bind(new_token())
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("calendar_token"))
+}
+
+/// Replace the user's feed token; the old feed URL stops working.
+pub async fn reset_token(pool: &PgPool, user_id: i32) -> sqlx::Result<String> {
+    let token = new_token();
+    sqlx::query("UPDATE users SET calendar_token = $2 WHERE id = $1").bind(user_id).bind(&token).execute(pool).await?;
+    Ok(token)
+}
+
+/// Event UID of a `kind` ("booking", "group" or "group-seat") with `id`.
+fn uid(kind: &str, id: i32) -> String {
+    let base = email::base_url();
+    let host = base.split("://").nth(1).unwrap_or(&base).split(['/', ':']).next().unwrap_or("skillvine");
+    format!("{}-{}@{}", kind, id, host)
+}
+
+struct Party {
+    id: i32,
+    name: String,
+    email: String,
+}
+
+/// Teacher and student, named as `viewer` may see them (see age.rs).
+async fn parties(pool: &PgPool, teacher_id: i32, student_id: i32, viewer: i32) -> sqlx::Result<(Party, Party)> {
+    let rows = sqlx::query("SELECT id, shown_name(u, $2) AS full_name, email FROM users u WHERE id = ANY($1)")
+        .bind(vec![teacher_id, student_id])
+        .bind(viewer)
+        .fetch_all(pool)
+        .await?;
+    let party = |id: i32| {
+        rows.iter()
+            .find(|r| r.get::<i32, _>("id") == id)
+            .map(|r| Party { id, name: r.get("full_name"), email: r.get("email") })
+            .unwrap_or(Party { id, name: String::new(), email: String::new() })
+    };
+    Ok((party(teacher_id), party(student_id)))
+}
+
+struct Session {
+    uid: String,
+    sequence: i32,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    /// iCalendar STATUS
+    status: &'static str,
+    /// group sessions only
+    title: Option<String>,
+}
+
+/// The session as `viewer` sees it in their calendar. A teacher's own group
+/// session has no `student`.
+fn session_event(s: &Session, teacher: &Party, student: Option<&Party>, viewer: i32) -> Event {
+    let summary = match (&s.title, student) {
+        (Some(title), _) => format!("Skillvine group session: {}", title),
+        (None, Some(student)) if viewer == teacher.id => format!("Skillvine session with {}", student.name),
+        (None, _) => format!("Skillvine session with {}", teacher.name),
+    };
+    Event {
+        uid: s.uid.clone(),
+        sequence: s.sequence,
+        start: s.start,
+        end: s.end,
+        status: s.status,
+        summary,
+        description: format!("Manage this session at {}", email::base_url()),
+        organizer: Person { name: teacher.name.clone(), email: teacher.email.clone() },
+        attendees: student.map(|p| Person { name: p.name.clone(), email: p.email.clone() }).into_iter().collect(),
+        url: Some(email::base_url()),
+    }
+}
+
+/// Calendar object for booking `b` sent to `viewer`: METHOD:REQUEST with the
+/// current time, or METHOD:CANCEL once cancelled.
+pub async fn invite(pool: &PgPool, b: &Booking, viewer: i32) -> sqlx::Result<String> {
+    let (teacher, student) = parties(pool, b.teacher_id, b.student_id, viewer).await?;
+    let cancelled = b.status == "cancelled" || b.status == "declined";
+    let (method, status) = if cancelled { ("CANCEL", "CANCELLED") } else { ("REQUEST", "CONFIRMED") };
+    let session = Session { uid: uid("booking", b.id), sequence: b.ical_sequence, start: b.starts_at, end: b.ends_at, status, title: None };
+    let event = session_event(&session, &teacher, Some(&student), viewer);
+    Ok(ical::calendar(Some(method), None, &[event]))
+}
+
+/// Calendar object for `seat` in group session `g`, sent to its student:
+/// METHOD:REQUEST while the seat is held, METHOD:CANCEL once it or the
+/// session is cancelled.
+pub async fn group_invite(pool: &PgPool, g: &Group, seat: &Seat) -> sqlx::Result<String> {
+    let (teacher, student) = parties(pool, g.teacher_id, seat.student_id, seat.student_id).await?;
+    let cancelled = g.status == "cancelled" || seat.status == "cancelled" || seat.status == "declined";
+    let (method, status, sequence) = match (cancelled, seat.status.as_str()) {
+        (true, _) => ("CANCEL", "CANCELLED", 1),
+        (false, "awaiting_approval") => ("REQUEST", "TENTATIVE", 0),
+        (false, _) => ("REQUEST", "CONFIRMED", 0),
+    };
+    let session = Session { uid: uid("group-seat", seat.id), sequence, start: g.starts_at, end: g.ends_at, status, title: Some(g.title.clone()) };
+    let event = session_event(&session, &teacher, Some(&student), seat.student_id);
+    Ok(ical::calendar(Some(method), None, &[event]))
+}
+
+/// The feed behind `token`, or None for an unknown token.
+pub async fn feed(pool: &PgPool, token: &str) -> sqlx::Result<Option<String>> {
+    let user = sqlx::query("SELECT id, full_name, email FROM users WHERE calendar_token = $1 AND COALESCE(active, TRUE)")
+        .bind(token)
+        .fetch_optional(pool)
+        .await?;
+    let Some(user) = user else { return Ok(None) };
+    let user_id: i32 = user.get("id");
+    let since = Utc::now() - ChronoDuration::days(feed_past_days());
+    let rows = sqlx::query(
+        "SELECT b.id, b.starts_at, b.ends_at, b.status, b.ical_sequence,
+                t.id AS teacher_id, shown_name(t, $1) AS teacher_name, t.email AS teacher_email,
+                s.id AS student_id, shown_name(s, $1) AS student_name, s.email AS student_email
+         FROM bookings b JOIN users t ON t.id = b.teacher_id JOIN users s ON s.id = b.student_id
+         WHERE (b.student_id = $1 OR b.teacher_id = $1)
+           AND b.status IN ('awaiting_approval', 'confirmed', 'completed') AND b.ends_at > $2
+         ORDER BY b.starts_at",
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    let mut events: Vec<Event> = rows
+        .iter()
+        .map(|r| {
+            let teacher = Party { id: r.get("teacher_id"), name: r.get("teacher_name"), email: r.get("teacher_email") };
+            let student = Party { id: r.get("student_id"), name: r.get("student_name"), email: r.get("student_email") };
+            let status = if r.get::<String, _>("status") == "awaiting_approval" { "TENTATIVE" } else { "CONFIRMED" };
+            let session = Session { uid: uid("booking", r.get("id")), sequence: r.get("ical_sequence"), start: r.get("starts_at"), end: r.get("ends_at"), status, title: None };
+            session_event(&session, &teacher, Some(&student), user_id)
+        })
+        .collect();
+
+    // group sessions the user teaches, and seats they hold
+    let groups = sqlx::query(
+        "SELECT g.id, g.title, g.starts_at, g.ends_at, NULL::INTEGER AS seat_id, NULL::TEXT AS seat_status,
+                t.id AS teacher_id, shown_name(t, $1) AS teacher_name, t.email AS teacher_email
+         FROM group_sessions g JOIN users t ON t.id = g.teacher_id
+         WHERE g.teacher_id = $1 AND g.status IN ('open', 'confirmed', 'completed') AND g.ends_at > $2
+         UNION ALL
+         SELECT g.id, g.title, g.starts_at, g.ends_at, st.id, st.status,
+                t.id, shown_name(t, $1), t.email
+         FROM group_seats st JOIN group_sessions g ON g.id = st.session_id JOIN users t ON t.id = g.teacher_id
+         WHERE st.student_id = $1 AND st.status IN ('awaiting_approval', 'booked')
+           AND g.status IN ('open', 'confirmed', 'completed') AND g.ends_at > $2",
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    let me = Party { id: user_id, name: user.get("full_name"), email: user.get("email") };
+    events.extend(groups.iter().map(|r| {
+        let teacher = Party { id: r.get("teacher_id"), name: r.get("teacher_name"), email: r.get("teacher_email") };
+        let seat: Option<i32> = r.get("seat_id");
+        let status = if r.get::<Option<String>, _>("seat_status").as_deref() == Some("awaiting_approval") { "TENTATIVE" } else { "CONFIRMED" };
+        let uid = match seat {
+            Some(id) => uid("group-seat", id),
+            None => uid("group", r.get("id")),
+        };
+        let session = Session { uid, sequence: 0, start: r.get("starts_at"), end: r.get("ends_at"), status, title: Some(r.get("title")) };
+        session_event(&session, &teacher, seat.map(|_| &me), user_id)
+    }));
+    events.sort_by_key(|e| e.start);
+    Ok(Some(ical::calendar(None, Some("Skillvine sessions"), &events)))
+}
+
+/// Busy periods imported by the teacher overlapping [from, to).
+pub async fn busy_between(pool: &PgPool, teacher_id: i32, from: DateTime<Utc>, to: DateTime<Utc>) -> sqlx::Result<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+    let rows = sqlx::query("SELECT starts_at, ends_at FROM calendar_busy WHERE teacher_id = $1 AND ends_at > $2 AND starts_at < $3")
+        .bind(teacher_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|r| (r.get("starts_at"), r.get("ends_at"))).collect())
+}
+
+/// Store the busy times of an uploaded calendar under `name`, replacing an
+/// earlier upload with the same name.
+pub async fn import(pool: &PgPool, teacher_id: i32, name: &str, text: &str) -> anyhow::Result<serde_json::Value> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 200 {
+        return Err(anyhow!("name must be 1-200 characters"));
+    }
+    let tz = availability::schedule(pool, teacher_id).await?.timezone;
+    let now = Utc::now();
+    let parsed = ical::parse_busy(text, tz, now, now + ChronoDuration::days(IMPORT_DAYS))?;
+
+    let starts: Vec<DateTime<Utc>> = parsed.busy.iter().map(|b| b.start).collect();
+    let ends: Vec<DateTime<Utc>> = parsed.busy.iter().map(|b| b.end).collect();
+    let summaries: Vec<Option<String>> = parsed.busy.iter().map(|b| b.summary.clone()).collect();
+
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query(
+        "INSERT INTO calendar_imports (teacher_id, name, busy_count, skipped_count) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (teacher_id, name) DO UPDATE SET busy_count = EXCLUDED.busy_count, skipped_count = EXCLUDED.skipped_count, created_at = now()
+         RETURNING id",
+    )
+    .bind(teacher_id)
+    .bind(name)
+    .bind(parsed.busy.len() as i32)
+    .bind(parsed.skipped as i32)
+    .fetch_one(&mut *tx)
+    .await?;
+    let id: i32 = row.get("id");
+    sqlx::query("DELETE FROM calendar_busy WHERE import_id = $1").bind(id).execute(&mut *tx).await?;
+    sqlx::query(
+        "INSERT INTO calendar_busy (import_id, teacher_id, starts_at, ends_at, summary)
+         SELECT $1, $2, s, e, m FROM UNNEST($3::timestamptz[], $4::timestamptz[], $5::text[]) AS t(s, e, m)",
+    )
+    .bind(id)
+    .bind(teacher_id)
+    .bind(&starts)
+    .bind(&ends)
+    .bind(&summaries)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(json!({"id": id, "name": name, "busy": parsed.busy.len(), "skipped": parsed.skipped}))
+}
+
+/// The teacher's imports with their upcoming busy times, in the teacher's timezone.
+pub async fn imports(pool: &PgPool, teacher_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let tz = availability::schedule(pool, teacher_id).await?.timezone;
+    let rows = sqlx::query("SELECT id, name, busy_count, skipped_count, created_at FROM calendar_imports WHERE teacher_id = $1 ORDER BY name")
+        .bind(teacher_id)
+        .fetch_all(pool)
+        .await?;
+    let busy = sqlx::query(
+        "SELECT import_id, starts_at, ends_at, summary FROM calendar_busy
+         WHERE teacher_id = $1 AND ends_at > now() ORDER BY starts_at LIMIT 500",
+    )
+    .bind(teacher_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let id: i32 = r.get("id");
+            let upcoming: Vec<serde_json::Value> = busy
+                .iter()
+                .filter(|b| b.get::<i32, _>("import_id") == id)
+                .map(|b| json!({
+                    "starts_at": b.get::<DateTime<Utc>, _>("starts_at").with_timezone(&tz).to_rfc3339(),
+                    "ends_at": b.get::<DateTime<Utc>, _>("ends_at").with_timezone(&tz).to_rfc3339(),
+                    "summary": b.get::<Option<String>, _>("summary"),
+                }))
+                .collect();
+            json!({
+                "id": id,
+                "name": r.get::<String, _>("name"),
+                "busy_count": r.get::<i32, _>("busy_count"),
+                "skipped_count": r.get::<i32, _>("skipped_count"),
+                "imported_at": r.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+                "upcoming": upcoming,
+            })
+        })
+        .collect())
+}
+
+pub async fn delete_import(pool: &PgPool, teacher_id: i32, id: i32) -> sqlx::Result<bool> {
+    let res = sqlx::query("DELETE FROM calendar_imports WHERE id = $1 AND teacher_id = $2").bind(id).bind(teacher_id).execute(pool).await?;
+    Ok(res.rows_affected() == 1)
+}
@@ -0,0 +1,211 @@
+// Bulk/segmented notices.
+//
+// A campaign is one `notification_campaigns` row holding the title, body,
+// attachment and audience filter. A background job resolves the audience and
+// inserts one `notifications` row per recipient in batches, dispatching each
+// per the recipient's preferences and updating `delivered` as it goes. Inserts are idempotent per
+// (campaign, user), so campaigns left `pending`/`sending` by a restart are
+// simply resumed at startup.
+//
+// Scheduled notices (single or segmented) are campaigns in status `scheduled`
+// with a `send_at`. The scheduler polls the table, so pending deliveries
+// survive restarts and only one instance claims each due campaign.
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+
+use crate::services::{notify_hub, notify_prefs};
+
+const BATCH_SIZE: usize = 500;
+
+/// Audience filter. All given criteria must match; an empty filter only
+/// matches everyone when `all` is set.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Audience {
+    #[serde(default)]
+    pub all: bool,
+    /// e.g. ["teacher"]; users without a role count as "student"
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Status of the user's latest KYC submission, or "none"
+    pub kyc_status: Option<String>,
+    pub active: Option<bool>,
+    /// Inclusive signup date range
+    pub signup_from: Option<NaiveDate>,
+    pub signup_to: Option<NaiveDate>,
+    #[serde(default)]
+    pub user_ids: Vec<i32>,
+}
+
+impl Audience {
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty()
+            && self.kyc_status.is_none()
+            && self.active.is_none()
+            && self.signup_from.is_none()
+            && self.signup_to.is_none()
+            && self.user_ids.is_empty()
+    }
+}
+
+/// Ids of the users matching `audience`.
+pub async fn resolve_audience(pool: &PgPool, audience: &Audience) -> sqlx::Result<Vec<i32>> {
+    let roles: Option<Vec<String>> = Some(audience.roles.clone()).filter(|r| !r.is_empty());
+    let ids: Option<Vec<i32>> = Some(audience.user_ids.clone()).filter(|r| !r.is_empty());
+    let rows = sqlx::query(
+        "SELECT u.id FROM users u
+         LEFT JOIN LATERAL (
+             SELECT status FROM teacher_verifications tv WHERE tv.user_id = u.id ORDER BY tv.created_at DESC, tv.id DESC LIMIT 1
+         ) kyc ON TRUE
+         WHERE ($1::text[] IS NULL OR COALESCE(u.role, 'student') = ANY($1))
+           AND ($2::text IS NULL OR COALESCE(kyc.status, 'none') = $2)
+           AND ($3::bool IS NULL OR COALESCE(u.active, TRUE) = $3)
+           AND ($4::date IS NULL OR u.created_at >= $4::date)
+           AND ($5::date IS NULL OR u.created_at < $5::date + 1)
+           AND ($6::int[] IS NULL OR u.id = ANY($6))
+         ORDER BY u.id",
+    )
+    .bind(roles)
+    .bind(&audience.kyc_status)
+    .bind(audience.active)
+    .bind(audience.signup_from)
+    .bind(audience.signup_to)
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(|r| r.get("id")).collect())
+}
+
+async fn deliver(pool: &PgPool, campaign_id: i32) -> Result<()> {
+    let row = sqlx::query(
+        "UPDATE notification_campaigns SET status = 'sending', started_at = COALESCE(started_at, now())
+         WHERE id = $1 AND status IN ('pending', 'sending')
+         RETURNING sender_id, title, body, audience, expires_at, category, priority",
+    )
+    .bind(campaign_id)
+    .fetch_optional(pool)
+    .await?;
+    let row = match row {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    let sender_id: Option<i32> = row.try_get("sender_id").ok().flatten();
+    let title: String = row.get("title");
+    let body: String = row.get("body");
+    let audience: Audience = serde_json::from_value(row.get("audience")).unwrap_or_default();
+    let expires_at: Option<DateTime<Utc>> = row.try_get("expires_at").ok().flatten();
+    let category: String = row.get("category");
+    let priority: String = row.get("priority");
+
+    let recipients = resolve_audience(pool, &audience).await?;
+    sqlx::query("UPDATE notification_campaigns SET total_recipients = $1 WHERE id = $2")
+        .bind(recipients.len() as i32)
+        .bind(campaign_id)
+        .execute(pool)
+        .await?;
+
+    for batch in recipients.chunks(BATCH_SIZE) {
+        let inserted = sqlx::query(
+            "INSERT INTO notifications (user_id, sender_id, title, body, campaign_id, expires_at, category, priority)
+             SELECT uid, $2, $3, $4, $5, $6, $7, $8 FROM UNNEST($1::int[]) AS uid
+             ON CONFLICT (campaign_id, user_id) WHERE campaign_id IS NOT NULL DO NOTHING
+             RETURNING id",
+        )
+        .bind(batch)
+        .bind(sender_id)
+        .bind(&title)
+        .bind(&body)
+        .bind(campaign_id)
+        .bind(expires_at)
+        .bind(&category)
+        .bind(&priority)
+        .fetch_all(pool)
+        .await?;
+
+        let ids: Vec<i32> = inserted.iter().map(|r| r.get("id")).collect();
+        if !ids.is_empty() {
+            let events = notify_hub::events_by_ids(pool, &ids).await?;
+            notify_prefs::dispatch(pool, events).await?;
+        }
+
+        sqlx::query(
+            "UPDATE notification_campaigns
+             SET delivered = (SELECT COUNT(*) FROM notifications WHERE campaign_id = $1)
+             WHERE id = $1",
+        )
+        .bind(campaign_id)
+        .execute(pool)
+        .await?;
+    }
+
+    sqlx::query("UPDATE notification_campaigns SET status = 'completed', completed_at = now() WHERE id = $1")
+        .bind(campaign_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Run the fan-out for `campaign_id` in the background.
+pub fn spawn_delivery(pool: PgPool, campaign_id: i32) {
+    tokio::spawn(async move {
+        if let Err(e) = deliver(&pool, campaign_id).await {
+            eprintln!("Campaign {} delivery failed: {:?}", campaign_id, e);
+            let _ = sqlx::query("UPDATE notification_campaigns SET status = 'failed', error = $1 WHERE id = $2")
+                .bind(e.to_string())
+                .bind(campaign_id)
+                .execute(&pool)
+                .await;
+        }
+    });
+}
+
+/// Resume campaigns interrupted by a restart.
+pub async fn resume_unfinished(pool: &PgPool) -> Result<usize> {
+    let rows = sqlx::query("SELECT id FROM notification_campaigns WHERE status IN ('pending', 'sending') ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    for r in &rows {
+        spawn_delivery(pool.clone(), r.get("id"));
+    }
+    Ok(rows.len())
+}
+
+/// Claim scheduled campaigns whose `send_at` has passed and start delivering them.
+pub async fn release_due(pool: &PgPool) -> Result<usize> {
+    let rows = sqlx::query(
+        "UPDATE notification_campaigns SET status = 'pending'
+         WHERE status = 'scheduled' AND send_at <= now()
+         RETURNING id",
+    )
+    .fetch_all(pool)
+    .await?;
+    for r in &rows {
+        spawn_delivery(pool.clone(), r.get("id"));
+    }
+    Ok(rows.len())
+}
+
+/// Resume interrupted campaigns, then release scheduled ones every
+/// NOTICE_SCHEDULER_INTERVAL_SECONDS (default 15).
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("NOTICE_SCHEDULER_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(15).max(1);
+    tokio::spawn(async move {
+        match resume_unfinished(&pool).await {
+            Ok(0) => {}
+            Ok(n) => eprintln!("Resuming {} unfinished notification campaign(s)", n),
+            Err(e) => eprintln!("Campaign resume failed: {:?}", e),
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match release_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Releasing {} scheduled notice(s)", n),
+                Err(e) => eprintln!("Notice scheduler failed: {:?}", e),
+            }
+        }
+    });
+}
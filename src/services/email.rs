@@ -1,6 +1,8 @@
 use anyhow::Result;
 use dotenv::dotenv;
 use lettre::transport::smtp::authentication::Credentials;
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use lettre::message::{header::ContentType, MultiPart, SinglePart};
 use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
 use std::env; // Make sure to add `dotenv = "0.15"` in Cargo.toml
 
@@ -56,3 +58,92 @@ pub fn send_verification_email(to: &str, token: &str) -> Result<()> {
     mailer.send(&email)?;
     Ok(())
 }
+
+/// Public base URL used in links inside emails (APP_BASE_URL, default http://127.0.0.1:8080).
+pub fn base_url() -> String {
+    env::var("APP_BASE_URL")
+        .map(|s| s.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| "http://127.0.0.1:8080".to_string())
+}
+
+#[derive(Clone)]
+struct ListUnsubscribe(String);
+
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(ListUnsubscribe(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), format!("<{}>", self.0))
+    }
+}
+
+#[derive(Clone)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(ListUnsubscribePost)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_string())
+    }
+}
+
+/// A file attached to an email.
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+/// Send an HTML email through the configured Gmail account. With an
+/// `unsubscribe_url`, RFC 8058 one-click unsubscribe headers are added.
+/// Blocking; call from `spawn_blocking` in async code.
+pub fn send_html(to: &str, subject: &str, html_body: String, unsubscribe_url: Option<&str>, reply_to: Option<&str>) -> Result<()> {
+    send_html_with(to, subject, html_body, unsubscribe_url, reply_to, &[])
+}
+
+/// `send_html` with `attachments` after the HTML part.
+pub fn send_html_with(to: &str, subject: &str, html_body: String, unsubscribe_url: Option<&str>, reply_to: Option<&str>, attachments: &[Attachment]) -> Result<()> {
+    dotenv().ok();
+    let gmail_username = env::var("GMAIL_USERNAME")?;
+    let gmail_app_password = env::var("GMAIL_APP_PASSWORD")?;
+
+    let mut builder = Message::builder()
+        .from(gmail_username.parse::<Mailbox>()?)
+        .to(to.parse::<Mailbox>()?)
+        .subject(subject);
+    if let Some(url) = unsubscribe_url {
+        builder = builder.header(ListUnsubscribe(url.to_string())).header(ListUnsubscribePost);
+    }
+    if let Some(addr) = reply_to {
+        builder = builder.reply_to(addr.parse::<Mailbox>()?);
+    }
+    let email = if attachments.is_empty() {
+        builder.header(ContentType::TEXT_HTML).body(html_body)?
+    } else {
+        let mut parts = MultiPart::mixed().singlepart(SinglePart::html(html_body));
+        for a in attachments {
+            let part = lettre::message::Attachment::new(a.filename.clone()).body(a.body.clone(), ContentType::parse(&a.content_type)?);
+            parts = parts.singlepart(part);
+        }
+        builder.multipart(parts)?
+    };
+
+    let mailer = SmtpTransport::relay("smtp.gmail.com")?
+        .credentials(Credentials::new(gmail_username, gmail_app_password))
+        .build();
+    mailer.send(&email)?;
+    Ok(())
+}
@@ -0,0 +1,1005 @@
+// Group sessions: one teacher, several students, paid per seat.
+//
+// A teacher offers a session at a fixed time with min_seats..max_seats seats
+// and a seat price. Taking a seat moves the price into escrow on the seat,
+// or holds it for a guardian's approval like a booking (see bookings.rs).
+// At cutoff_at a session with fewer than min_seats booked seats is cancelled
+// and refunded in full; otherwise it is confirmed. Once it has ended the
+// escrow of every seat is paid to the teacher.
+//
+// A full session keeps a waitlist. When a seat frees up (a student leaves, a
+// guardian declines, an offer runs out) the next waiting student is offered
+// it and it is kept for them for claim_minutes, never past the start; an
+// unclaimed offer moves on to the next in line. Offered seats count as taken.
+//
+// Leaving before the session is confirmed refunds in full; after that the
+// teacher's policy at creation time decides (see policy.rs). A teacher
+// cancelling refunds every seat in full. Students, teachers and waitlisted
+// students are told at every step. Runs every GROUPS_INTERVAL_SECONDS
+// (default 60).
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use std::time::Duration;
+
+use crate::services::age;
+use crate::services::availability;
+use crate::services::bookings::{self, when};
+use crate::services::calendar;
+use crate::services::guardian::{self, Settlement};
+use crate::services::notify_prefs::{self, Notice};
+use crate::services::policy::{self, Decision, Policy, Side};
+use crate::services::wallet_service::{self, Entry};
+
+/// Largest group a teacher may offer (GROUP_MAX_SEATS, default 30).
+pub fn max_seats() -> i32 {
+    std::env::var("GROUP_MAX_SEATS").ok().and_then(|s| s.parse().ok()).filter(|n: &i32| *n > 0).unwrap_or(30)
+}
+
+/// Default cutoff, hours before the start (GROUP_CUTOFF_HOURS, default 24).
+pub fn cutoff_hours() -> i64 {
+    std::env::var("GROUP_CUTOFF_HOURS").ok().and_then(|s| s.parse().ok()).filter(|h: &i64| *h >= 0).unwrap_or(24)
+}
+
+/// Default claim window for waitlist offers (GROUP_CLAIM_MINUTES, default 120).
+pub fn claim_minutes() -> i32 {
+    std::env::var("GROUP_CLAIM_MINUTES").ok().and_then(|s| s.parse().ok()).filter(|m: &i32| *m > 0).unwrap_or(120)
+}
+
+/// Seat statuses that hold a place.
+const HELD: [&str; 2] = ["awaiting_approval", "booked"];
+
+pub struct Group {
+    pub id: i32,
+    pub teacher_id: i32,
+    pub title: String,
+    pub description: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub min_seats: i32,
+    pub max_seats: i32,
+    pub seat_price_coins: i64,
+    pub cutoff_at: DateTime<Utc>,
+    pub claim_minutes: i32,
+    pub status: String,
+    pub cancel_reason: Option<String>,
+    pub policy: Option<serde_json::Value>,
+}
+
+impl Group {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Group {
+        Group {
+            id: r.get("id"),
+            teacher_id: r.get("teacher_id"),
+            title: r.get("title"),
+            description: r.get("description"),
+            starts_at: r.get("starts_at"),
+            ends_at: r.get("ends_at"),
+            min_seats: r.get("min_seats"),
+            max_seats: r.get("max_seats"),
+            seat_price_coins: r.get("seat_price_coins"),
+            cutoff_at: r.get("cutoff_at"),
+            claim_minutes: r.get("claim_minutes"),
+            status: r.get("status"),
+            cancel_reason: r.get("cancel_reason"),
+            policy: r.get("policy"),
+        }
+    }
+
+    /// Whether students can still take, leave and claim seats.
+    fn is_live(&self) -> bool {
+        (self.status == "open" || self.status == "confirmed") && self.starts_at > Utc::now()
+    }
+
+    fn to_json(&self, tz: Tz) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "teacher_id": self.teacher_id,
+            "title": self.title,
+            "description": self.description,
+            "starts_at": self.starts_at.with_timezone(&tz).to_rfc3339(),
+            "ends_at": self.ends_at.with_timezone(&tz).to_rfc3339(),
+            "min_seats": self.min_seats,
+            "max_seats": self.max_seats,
+            "seat_price_coins": self.seat_price_coins,
+            "cutoff_at": self.cutoff_at.with_timezone(&tz).to_rfc3339(),
+            "claim_minutes": self.claim_minutes,
+            "status": self.status,
+            "cancel_reason": self.cancel_reason,
+        })
+    }
+}
+
+pub struct Seat {
+    pub id: i32,
+    pub student_id: i32,
+    pub status: String,
+    pub price_coins: i64,
+    pub escrow_coins: i64,
+    pub approval_id: Option<i32>,
+}
+
+impl Seat {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Seat {
+        Seat {
+            id: r.get("id"),
+            student_id: r.get("student_id"),
+            status: r.get("status"),
+            price_coins: r.get("price_coins"),
+            escrow_coins: r.get("escrow_coins"),
+            approval_id: r.get("approval_id"),
+        }
+    }
+
+    fn reference(&self) -> String {
+        format!("group_seat:{}", self.id)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({"id": self.id, "status": self.status, "price_coins": self.price_coins, "escrow_coins": self.escrow_coins})
+    }
+}
+
+/// A freed seat kept for a waitlisted student.
+struct Offer {
+    student_id: i32,
+    expires_at: DateTime<Utc>,
+}
+
+/// Who was in a cancelled session.
+struct Closed {
+    /// seats as they were before the session was cancelled
+    seated: Vec<Seat>,
+    waitlisted: Vec<i32>,
+}
+
+async fn lock(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Option<Group>> {
+    let row = sqlx::query("SELECT * FROM group_sessions WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut **tx).await?;
+    Ok(row.as_ref().map(Group::from_row))
+}
+
+/// The student's seat holding a place in session `id`, locked.
+async fn seat_of(tx: &mut Transaction<'_, Postgres>, id: i32, student_id: i32) -> sqlx::Result<Option<Seat>> {
+    let row = sqlx::query("SELECT * FROM group_seats WHERE session_id = $1 AND student_id = $2 AND status = ANY($3) FOR UPDATE")
+        .bind(id)
+        .bind(student_id)
+        .bind(&HELD[..])
+        .fetch_optional(&mut **tx)
+        .await?;
+    Ok(row.as_ref().map(Seat::from_row))
+}
+
+/// Seats held by students plus seats kept for waitlist offers.
+async fn taken(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<i64> {
+    let row = sqlx::query(
+        "SELECT (SELECT COUNT(*) FROM group_seats WHERE session_id = $1 AND status = ANY($2))
+              + (SELECT COUNT(*) FROM group_waitlist WHERE session_id = $1 AND status = 'offered') AS taken",
+    )
+    .bind(id)
+    .bind(&HELD[..])
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(row.get::<Option<i64>, _>("taken").unwrap_or(0))
+}
+
+/// `user_id`'s name as `viewer` may see it (see age.rs).
+async fn name(pool: &PgPool, user_id: i32, viewer: i32) -> sqlx::Result<String> {
+    let row = sqlx::query("SELECT shown_name(u, $2) AS name FROM users u WHERE u.id = $1").bind(user_id).bind(viewer).fetch_optional(pool).await?;
+    Ok(row.map(|r| r.get("name")).unwrap_or_default())
+}
+
+/// Send `user_id` a notice about `g`; `{when}` and `{cutoff}` in the body
+/// become times in their timezone and `{title}` the session title.
+async fn tell(pool: &PgPool, user_id: i32, g: &Group, priority: &str, title: &str, body: &str) {
+    send(pool, user_id, g, priority, title, body, None).await
+}
+
+/// `tell` the seat's student, with the seat attached as a calendar invite
+/// (see calendar::group_invite).
+async fn tell_seat(pool: &PgPool, g: &Group, seat: &Seat, priority: &str, title: &str, body: &str) {
+    let ics = match calendar::group_invite(pool, g, seat).await {
+        Ok(ics) => Some(ics),
+        Err(e) => {
+            eprintln!("Group seat {} calendar invite failed: {:?}", seat.id, e);
+            None
+        }
+    };
+    send(pool, seat.student_id, g, priority, title, body, ics.as_deref()).await
+}
+
+async fn send(pool: &PgPool, user_id: i32, g: &Group, priority: &str, title: &str, body: &str, ics: Option<&str>) {
+    let tz = availability::user_tz(pool, user_id).await.unwrap_or(Tz::UTC);
+    let body = body.replace("{when}", &when(g.starts_at, tz)).replace("{cutoff}", &when(g.cutoff_at, tz)).replace("{title}", &g.title);
+    let notice = Notice { user_id, sender_id: None, category: "booking", priority, title, body: &body, ics };
+    if let Err(e) = notify_prefs::deliver(pool, &notice).await {
+        eprintln!("Group session {} notification for user {} failed: {:?}", g.id, user_id, e);
+    }
+}
+
+/// Details of a new group session.
+pub struct NewGroup {
+    pub title: String,
+    pub description: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub min_seats: i32,
+    pub max_seats: i32,
+    pub seat_price_coins: i64,
+    /// defaults to GROUP_CUTOFF_HOURS before the start
+    pub cutoff_at: Option<DateTime<Utc>>,
+    /// defaults to GROUP_CLAIM_MINUTES
+    pub claim_minutes: Option<i32>,
+}
+
+/// Offer a group session; its time must not clash with the teacher's held
+/// bookings or other group sessions.
+pub async fn create(pool: &PgPool, teacher_id: i32, n: &NewGroup) -> anyhow::Result<Group> {
+    let title = n.title.trim();
+    if title.is_empty() || title.chars().count() > 200 {
+        return Err(anyhow!("title must be 1-200 characters"));
+    }
+    if n.description.chars().count() > 5000 {
+        return Err(anyhow!("description is too long (max 5000 characters)"));
+    }
+    let now = Utc::now();
+    if n.starts_at <= now {
+        return Err(anyhow!("the session must start in the future"));
+    }
+    if n.ends_at <= n.starts_at || n.ends_at - n.starts_at > ChronoDuration::hours(12) {
+        return Err(anyhow!("the session must end after it starts and last at most 12 hours"));
+    }
+    if n.min_seats < 1 || n.max_seats < n.min_seats || n.max_seats > max_seats() {
+        return Err(anyhow!("seats must satisfy 1 <= min_seats <= max_seats <= {}", max_seats()));
+    }
+    if n.seat_price_coins < 0 {
+        return Err(anyhow!("seat_price_coins must not be negative"));
+    }
+    let cutoff_at = n.cutoff_at.unwrap_or(n.starts_at - ChronoDuration::hours(cutoff_hours()));
+    if cutoff_at <= now || cutoff_at > n.starts_at {
+        return Err(anyhow!("the cutoff must be between now and the start; give cutoff_at for sessions starting soon"));
+    }
+    let claim = n.claim_minutes.unwrap_or_else(claim_minutes);
+    if !(5..=7 * 24 * 60).contains(&claim) {
+        return Err(anyhow!("claim_minutes must be between 5 and 10080"));
+    }
+    let verified = sqlx::query("SELECT 1 FROM users WHERE id = $1 AND role = 'teacher' AND kyc_verified")
+        .bind(teacher_id)
+        .fetch_optional(pool)
+        .await?;
+    if verified.is_none() {
+        return Err(anyhow!("only verified teachers can offer group sessions"));
+    }
+    let terms = policy::for_teacher(pool, teacher_id).await?.to_json();
+
+    let mut tx = pool.begin().await?;
+    bookings::lock_teacher(&mut tx, teacher_id).await?;
+    let clash: bool = sqlx::query(
+        "SELECT EXISTS (SELECT 1 FROM bookings WHERE teacher_id = $1 AND status IN ('awaiting_approval', 'confirmed') AND starts_at < $3 AND ends_at > $2)
+             OR EXISTS (SELECT 1 FROM group_sessions WHERE teacher_id = $1 AND status IN ('open', 'confirmed') AND starts_at < $3 AND ends_at > $2) AS clash",
+    )
+    .bind(teacher_id)
+    .bind(n.starts_at)
+    .bind(n.ends_at)
+    .fetch_one(&mut *tx)
+    .await?
+    .get("clash");
+    if clash {
+        return Err(anyhow!("you already have a session at that time"));
+    }
+    let row = sqlx::query(
+        "INSERT INTO group_sessions (teacher_id, title, description, starts_at, ends_at, min_seats, max_seats, seat_price_coins, cutoff_at, claim_minutes, policy)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING *",
+    )
+    .bind(teacher_id)
+    .bind(title)
+    .bind(n.description.trim())
+    .bind(n.starts_at)
+    .bind(n.ends_at)
+    .bind(n.min_seats)
+    .bind(n.max_seats)
+    .bind(n.seat_price_coins)
+    .bind(cutoff_at)
+    .bind(claim)
+    .bind(&terms)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Group::from_row(&row))
+}
+
+/// Take the seat price from the student into escrow.
+async fn hold(tx: &mut Transaction<'_, Postgres>, seat: &mut Seat) -> anyhow::Result<()> {
+    let reference = seat.reference();
+    let entry = Entry { kind: "group_seat_escrow", reference: Some(&reference), actor_id: Some(seat.student_id), note: None };
+    wallet_service::post(tx, seat.student_id, -seat.price_coins, &entry).await?;
+    sqlx::query("UPDATE group_seats SET escrow_coins = $2, updated_at = now() WHERE id = $1")
+        .bind(seat.id)
+        .bind(seat.price_coins)
+        .execute(&mut **tx)
+        .await?;
+    seat.escrow_coins = seat.price_coins;
+    Ok(())
+}
+
+/// Give `student_id` a seat, charged now or held for a guardian's approval.
+async fn take_seat(tx: &mut Transaction<'_, Postgres>, pool: &PgPool, g: &Group, student_id: i32) -> anyhow::Result<Seat> {
+    let price = g.seat_price_coins;
+    let needs_approval = price > 0 && guardian::approval_limit(pool, student_id).await?.map(|l| price > l).unwrap_or(false);
+    let row = sqlx::query("INSERT INTO group_seats (session_id, student_id, status, price_coins) VALUES ($1, $2, $3, $4) RETURNING *")
+        .bind(g.id)
+        .bind(student_id)
+        .bind(if needs_approval { "awaiting_approval" } else { "booked" })
+        .bind(price)
+        .fetch_one(&mut **tx)
+        .await?;
+    let mut seat = Seat::from_row(&row);
+    if needs_approval {
+        let tz = availability::user_tz(pool, student_id).await?;
+        let description = format!("a seat in the group session \"{}\" on {}", g.title, when(g.starts_at, tz));
+        let approval_id = guardian::request_approval(tx, student_id, price, "group_seat", Some(seat.id), &description).await?;
+        sqlx::query("UPDATE group_seats SET approval_id = $2 WHERE id = $1").bind(seat.id).bind(approval_id).execute(&mut **tx).await?;
+        seat.approval_id = Some(approval_id);
+    } else if price > 0 {
+        hold(tx, &mut seat).await?;
+    }
+    Ok(seat)
+}
+
+async fn announce_seat(pool: &PgPool, g: &Group, seat: &Seat) -> sqlx::Result<()> {
+    if let Some(approval_id) = seat.approval_id.filter(|_| seat.status == "awaiting_approval") {
+        if let Err(e) = guardian::notify_approval_request(pool, approval_id).await {
+            eprintln!("Approval request {} notification failed: {:?}", approval_id, e);
+        }
+        let body = "Your seat in {title} on {when} is held until a parent or guardian approves it.";
+        tell(pool, seat.student_id, g, "normal", "Seat waiting for approval", body).await;
+        return Ok(());
+    }
+    let mut body = "You have a seat in {title} on {when}.".to_string();
+    if g.status == "open" {
+        body.push_str(&format!(" It goes ahead if at least {} students have booked by {{cutoff}}; otherwise you get a full refund.", g.min_seats));
+    }
+    tell_seat(pool, g, seat, "normal", "Seat booked", &body).await;
+    let student = name(pool, seat.student_id, g.teacher_id).await?;
+    tell(pool, g.teacher_id, g, "low", "New student", &format!("{} took a seat in {{title}} on {{when}}.", student)).await;
+    Ok(())
+}
+
+/// Offer free seats of a live session to the next waiting students.
+async fn offer_free_seats(tx: &mut Transaction<'_, Postgres>, g: &Group) -> sqlx::Result<Vec<Offer>> {
+    if !g.is_live() {
+        return Ok(Vec::new());
+    }
+    let free = g.max_seats as i64 - taken(tx, g.id).await?;
+    if free <= 0 {
+        return Ok(Vec::new());
+    }
+    let expires_at = (Utc::now() + ChronoDuration::minutes(g.claim_minutes as i64)).min(g.starts_at);
+    let rows = sqlx::query(
+        "UPDATE group_waitlist SET status = 'offered', offer_expires_at = $2, updated_at = now()
+         WHERE id IN (SELECT id FROM group_waitlist WHERE session_id = $1 AND status = 'waiting' ORDER BY id LIMIT $3)
+         RETURNING student_id",
+    )
+    .bind(g.id)
+    .bind(expires_at)
+    .bind(free)
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(rows.iter().map(|r| Offer { student_id: r.get("student_id"), expires_at }).collect())
+}
+
+async fn announce_offers(pool: &PgPool, g: &Group, offers: &[Offer]) {
+    for o in offers {
+        let tz = availability::user_tz(pool, o.student_id).await.unwrap_or(Tz::UTC);
+        let body = format!(
+            "A seat in {{title}} on {{when}} is free and kept for you until {}. Claim it before then or it goes to the next student on the waitlist.",
+            when(o.expires_at, tz)
+        );
+        tell(pool, o.student_id, g, "high", "A seat is free for you", &body).await;
+    }
+}
+
+/// Take a seat in session `id`, or join its waitlist when it is full or
+/// others are already waiting.
+pub async fn join(pool: &PgPool, id: i32, student_id: i32) -> anyhow::Result<serde_json::Value> {
+    let standing = age::standing(pool, student_id).await?.ok_or_else(|| anyhow!("account not found"))?;
+    standing.may_transact().map_err(|e| anyhow!(e))?;
+
+    let mut tx = pool.begin().await?;
+    let g = lock(&mut tx, id).await?.filter(Group::is_live).ok_or_else(|| anyhow!("this group session is not taking students"))?;
+    if g.teacher_id == student_id {
+        return Err(anyhow!("you cannot join your own group session"));
+    }
+    if seat_of(&mut tx, g.id, student_id).await?.is_some() {
+        return Err(anyhow!("you already have a seat in this session"));
+    }
+    let queued = sqlx::query("SELECT status FROM group_waitlist WHERE session_id = $1 AND student_id = $2 AND status IN ('waiting', 'offered')")
+        .bind(g.id)
+        .bind(student_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    match queued.map(|r| r.get::<String, _>("status")).as_deref() {
+        Some("offered") => return Err(anyhow!("a seat is kept for you; claim it instead")),
+        Some(_) => return Err(anyhow!("you are already on the waitlist")),
+        None => {}
+    }
+    let waiting: i64 = sqlx::query("SELECT COUNT(*) AS n FROM group_waitlist WHERE session_id = $1 AND status = 'waiting'")
+        .bind(g.id)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("n");
+    if waiting == 0 && taken(&mut tx, g.id).await? < g.max_seats as i64 {
+        let seat = take_seat(&mut tx, pool, &g, student_id).await?;
+        tx.commit().await?;
+        announce_seat(pool, &g, &seat).await?;
+        return Ok(json!({"seat": seat.to_json()}));
+    }
+    sqlx::query("INSERT INTO group_waitlist (session_id, student_id) VALUES ($1, $2)").bind(g.id).bind(student_id).execute(&mut *tx).await?;
+    tx.commit().await?;
+    let position = waiting + 1;
+    let body = format!(
+        "{{title}} on {{when}} is full. You are number {} on the waitlist; we'll tell you as soon as a seat is free for you.",
+        position
+    );
+    tell(pool, student_id, &g, "normal", "On the waitlist", &body).await;
+    Ok(json!({"waitlist": {"status": "waiting", "position": position}}))
+}
+
+/// Claim the seat offered to `student_id` from the waitlist.
+pub async fn claim(pool: &PgPool, id: i32, student_id: i32) -> anyhow::Result<Seat> {
+    // the account may have been restricted since it joined the waitlist
+    let standing = age::standing(pool, student_id).await?.ok_or_else(|| anyhow!("account not found"))?;
+    standing.may_transact().map_err(|e| anyhow!(e))?;
+
+    let mut tx = pool.begin().await?;
+    let g = lock(&mut tx, id).await?.filter(Group::is_live).ok_or_else(|| anyhow!("this group session is not taking students"))?;
+    let offer = sqlx::query(
+        "SELECT id, offer_expires_at FROM group_waitlist WHERE session_id = $1 AND student_id = $2 AND status = 'offered' FOR UPDATE",
+    )
+    .bind(g.id)
+    .bind(student_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| anyhow!("no seat is kept for you in this session"))?;
+    if offer.get::<Option<DateTime<Utc>>, _>("offer_expires_at").is_some_and(|t| t <= Utc::now()) {
+        return Err(anyhow!("your claim window has closed"));
+    }
+    sqlx::query("UPDATE group_waitlist SET status = 'claimed', updated_at = now() WHERE id = $1")
+        .bind(offer.get::<i32, _>("id"))
+        .execute(&mut *tx)
+        .await?;
+    // a failed charge rolls back and the offer stays open until it expires
+    let seat = take_seat(&mut tx, pool, &g, student_id).await?;
+    tx.commit().await?;
+    announce_seat(pool, &g, &seat).await?;
+    Ok(seat)
+}
+
+/// What leaving `g` now means for a seat holding `escrow` coins.
+async fn leave_decision(pool: &PgPool, g: &Group, escrow: i64) -> sqlx::Result<Decision> {
+    let minutes = (g.starts_at - Utc::now()).num_minutes();
+    if g.status == "open" {
+        return Ok(Decision {
+            action: "cancel",
+            side: Side::Student,
+            minutes_before: minutes,
+            allowed: true,
+            refund_coins: escrow,
+            teacher_fee_coins: 0,
+            penalty_coins: 0,
+            reason: "Left before the session was confirmed: full refund.".to_string(),
+        });
+    }
+    let terms = match Policy::from_snapshot(g.policy.as_ref()) {
+        Some(p) => p,
+        None => policy::for_teacher(pool, g.teacher_id).await?,
+    };
+    Ok(policy::evaluate_cancel(&terms, Side::Student, escrow, minutes))
+}
+
+/// Give up a seat or a waitlist place in session `id`. A freed seat is
+/// offered to the next waiting student.
+pub async fn leave(pool: &PgPool, id: i32, student_id: i32, reason: Option<&str>) -> anyhow::Result<serde_json::Value> {
+    let mut tx = pool.begin().await?;
+    let g = lock(&mut tx, id).await?.ok_or_else(|| anyhow!("group session not found"))?;
+    if !g.is_live() {
+        return Err(anyhow!("only upcoming group sessions can be left"));
+    }
+    if let Some(mut seat) = seat_of(&mut tx, g.id, student_id).await? {
+        let decision = leave_decision(pool, &g, seat.escrow_coins).await?;
+        let reference = seat.reference();
+        let entry = |kind| Entry { kind, reference: Some(&reference), actor_id: Some(student_id), note: Some(&decision.reason) };
+        if decision.refund_coins > 0 {
+            wallet_service::post(&mut tx, student_id, decision.refund_coins, &entry("group_seat_refund")).await?;
+        }
+        if decision.teacher_fee_coins > 0 {
+            wallet_service::post(&mut tx, g.teacher_id, decision.teacher_fee_coins, &entry("late_cancel_fee")).await?;
+        }
+        if let Some(approval_id) = seat.approval_id {
+            guardian::cancel_approval(&mut tx, approval_id).await?;
+        }
+        sqlx::query("UPDATE group_seats SET status = 'cancelled', escrow_coins = 0, cancel_reason = $2, updated_at = now() WHERE id = $1")
+            .bind(seat.id)
+            .bind(reason)
+            .execute(&mut *tx)
+            .await?;
+        let offers = offer_free_seats(&mut tx, &g).await?;
+        tx.commit().await?;
+        seat.status = "cancelled".to_string();
+        seat.escrow_coins = 0;
+
+        tell_seat(pool, &g, &seat, "low", "Seat cancelled", &format!("You left {{title}} on {{when}}. {}", decision.reason)).await;
+        let student = name(pool, student_id, g.teacher_id).await?;
+        let note = reason.map(|r| format!(" Reason: {}", r)).unwrap_or_default();
+        tell(pool, g.teacher_id, &g, "normal", "Student left", &format!("{} left {{title}} on {{when}}.{}", student, note)).await;
+        announce_offers(pool, &g, &offers).await;
+        return Ok(json!({"left": "seat", "seat": seat.to_json(), "decision": decision.to_json()}));
+    }
+
+    let place = sqlx::query("SELECT id, status FROM group_waitlist WHERE session_id = $1 AND student_id = $2 AND status IN ('waiting', 'offered') FOR UPDATE")
+        .bind(g.id)
+        .bind(student_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow!("you have no seat and are not on the waitlist"))?;
+    sqlx::query("UPDATE group_waitlist SET status = 'left', updated_at = now() WHERE id = $1")
+        .bind(place.get::<i32, _>("id"))
+        .execute(&mut *tx)
+        .await?;
+    let offers = if place.get::<String, _>("status") == "offered" { offer_free_seats(&mut tx, &g).await? } else { Vec::new() };
+    tx.commit().await?;
+    announce_offers(pool, &g, &offers).await;
+    Ok(json!({"left": "waitlist"}))
+}
+
+/// Refund every seat of `g` in full, clear its waitlist and mark it cancelled.
+async fn close(tx: &mut Transaction<'_, Postgres>, g: &mut Group, reason: &str, actor_id: Option<i32>) -> anyhow::Result<Closed> {
+    let rows = sqlx::query("SELECT * FROM group_seats WHERE session_id = $1 AND status = ANY($2) FOR UPDATE")
+        .bind(g.id)
+        .bind(&HELD[..])
+        .fetch_all(&mut **tx)
+        .await?;
+    let seats: Vec<Seat> = rows.iter().map(Seat::from_row).collect();
+    let note = format!("Group session cancelled: {}. Full refund.", reason);
+    for seat in &seats {
+        if seat.escrow_coins > 0 {
+            let reference = seat.reference();
+            let entry = Entry { kind: "group_seat_refund", reference: Some(&reference), actor_id, note: Some(&note) };
+            wallet_service::post(tx, seat.student_id, seat.escrow_coins, &entry).await?;
+        }
+        if let Some(approval_id) = seat.approval_id {
+            guardian::cancel_approval(tx, approval_id).await?;
+        }
+    }
+    sqlx::query(
+        "UPDATE group_seats SET status = 'cancelled', escrow_coins = 0, cancel_reason = $3, updated_at = now() WHERE session_id = $1 AND status = ANY($2)",
+    )
+    .bind(g.id)
+    .bind(&HELD[..])
+    .bind(reason)
+    .execute(&mut **tx)
+    .await?;
+    let waitlisted = sqlx::query(
+        "UPDATE group_waitlist SET status = 'expired', updated_at = now() WHERE session_id = $1 AND status IN ('waiting', 'offered') RETURNING student_id",
+    )
+    .bind(g.id)
+    .fetch_all(&mut **tx)
+    .await?
+    .iter()
+    .map(|r| r.get("student_id"))
+    .collect();
+    sqlx::query("UPDATE group_sessions SET status = 'cancelled', cancel_reason = $2, updated_at = now() WHERE id = $1")
+        .bind(g.id)
+        .bind(reason)
+        .execute(&mut **tx)
+        .await?;
+    g.status = "cancelled".to_string();
+    Ok(Closed { seated: seats, waitlisted })
+}
+
+async fn announce_cancelled(pool: &PgPool, g: &Group, closed: &Closed, reason: &str) {
+    let body = format!("{{title}} on {{when}} was cancelled: {}. Your seat was refunded in full.", reason);
+    for seat in &closed.seated {
+        // only booked seats were sent an invite to take back
+        if seat.status == "booked" {
+            tell_seat(pool, g, seat, "high", "Group session cancelled", &body).await;
+        } else {
+            tell(pool, seat.student_id, g, "high", "Group session cancelled", &body).await;
+        }
+    }
+    for &user in &closed.waitlisted {
+        let body = format!("{{title}} on {{when}} was cancelled: {}. Its waitlist is closed.", reason);
+        tell(pool, user, g, "normal", "Group session cancelled", &body).await;
+    }
+}
+
+/// Cancel an upcoming session as its teacher; every seat is refunded in full.
+pub async fn cancel(pool: &PgPool, id: i32, teacher_id: i32, reason: Option<&str>) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    let mut g = lock(&mut tx, id).await?.filter(|g| g.teacher_id == teacher_id).ok_or_else(|| anyhow!("group session not found"))?;
+    if !g.is_live() {
+        return Err(anyhow!("only upcoming group sessions can be cancelled"));
+    }
+    let why = match reason {
+        Some(r) => format!("the teacher cancelled it ({})", r),
+        None => "the teacher cancelled it".to_string(),
+    };
+    let closed = close(&mut tx, &mut g, &why, Some(teacher_id)).await?;
+    tx.commit().await?;
+    announce_cancelled(pool, &g, &closed, &why).await;
+    Ok(())
+}
+
+/// Charge and book a seat held for approval, or decline it with `refusal`
+/// and offer it on. Returns false if the seat was no longer waiting.
+async fn decide_seat(pool: &PgPool, seat_id: i32, refusal: Option<String>) -> anyhow::Result<Settlement> {
+    let session_id: Option<i32> = sqlx::query("SELECT session_id FROM group_seats WHERE id = $1")
+        .bind(seat_id)
+        .fetch_optional(pool)
+        .await?
+        .map(|r| r.get("session_id"));
+    let Some(session_id) = session_id else { return Ok(Settlement::Stale) };
+    let mut tx = pool.begin().await?;
+    let Some(g) = lock(&mut tx, session_id).await? else { return Ok(Settlement::Stale) };
+    let row = sqlx::query("SELECT * FROM group_seats WHERE id = $1 FOR UPDATE").bind(seat_id).fetch_one(&mut *tx).await?;
+    let mut seat = Seat::from_row(&row);
+    if seat.status != "awaiting_approval" {
+        return Ok(Settlement::Stale);
+    }
+    let mut reason = refusal;
+    if reason.is_none() && !g.is_live() {
+        reason = Some("the session is no longer taking students".to_string());
+    } else if reason.is_none() && seat.price_coins > 0 {
+        // a failed charge leaves the transaction usable: post checks before writing
+        if let Err(e) = hold(&mut tx, &mut seat).await {
+            reason = Some(e.to_string());
+        }
+    }
+    if reason.is_some() {
+        if let Some(approval_id) = seat.approval_id {
+            guardian::cancel_approval(&mut tx, approval_id).await?;
+        }
+    }
+    let status = if reason.is_none() { "booked" } else { "declined" };
+    sqlx::query("UPDATE group_seats SET status = $2, cancel_reason = $3, updated_at = now() WHERE id = $1")
+        .bind(seat.id)
+        .bind(status)
+        .bind(&reason)
+        .execute(&mut *tx)
+        .await?;
+    let offers = if reason.is_some() { offer_free_seats(&mut tx, &g).await? } else { Vec::new() };
+    tx.commit().await?;
+    seat.status = status.to_string();
+
+    match reason {
+        None => {
+            announce_seat(pool, &g, &seat).await?;
+            Ok(Settlement::Done)
+        }
+        Some(r) => {
+            tell(pool, seat.student_id, &g, "normal", "Seat declined", &format!("Your seat in {{title}} on {{when}} was not booked: {}.", r)).await;
+            announce_offers(pool, &g, &offers).await;
+            Ok(Settlement::Refused(r))
+        }
+    }
+}
+
+/// Settle a seat held for a guardian's approval (see guardian::decide).
+pub async fn apply_approval(pool: &PgPool, seat_id: i32, approved: bool) -> anyhow::Result<Settlement> {
+    let refusal = if approved { None } else { Some("a guardian declined it".to_string()) };
+    decide_seat(pool, seat_id, refusal).await
+}
+
+/// Decline seats whose approval took too long (BOOKING_APPROVAL_HOURS) or
+/// whose session has started.
+pub async fn expire_approvals(pool: &PgPool) -> anyhow::Result<usize> {
+    let rows = sqlx::query(
+        "SELECT s.id FROM group_seats s JOIN group_sessions g ON g.id = s.session_id
+         WHERE s.status = 'awaiting_approval' AND (s.created_at < $1 OR g.starts_at <= now())",
+    )
+    .bind(Utc::now() - ChronoDuration::hours(bookings::approval_hours()))
+    .fetch_all(pool)
+    .await?;
+    let mut expired = 0;
+    for r in &rows {
+        if decide_seat(pool, r.get("id"), Some("no guardian approved it in time".to_string())).await? != Settlement::Stale {
+            expired += 1;
+        }
+    }
+    Ok(expired)
+}
+
+/// Pass unclaimed offers on to the next waiting students.
+pub async fn expire_offers(pool: &PgPool) -> anyhow::Result<usize> {
+    let rows = sqlx::query("SELECT DISTINCT session_id FROM group_waitlist WHERE status = 'offered' AND offer_expires_at <= now()")
+        .fetch_all(pool)
+        .await?;
+    let mut expired = 0;
+    for r in &rows {
+        let mut tx = pool.begin().await?;
+        let Some(g) = lock(&mut tx, r.get("session_id")).await? else { continue };
+        let lapsed: Vec<i32> = sqlx::query(
+            "UPDATE group_waitlist SET status = 'expired', updated_at = now()
+             WHERE session_id = $1 AND status = 'offered' AND offer_expires_at <= now() RETURNING student_id",
+        )
+        .bind(g.id)
+        .fetch_all(&mut *tx)
+        .await?
+        .iter()
+        .map(|r| r.get("student_id"))
+        .collect();
+        let offers = offer_free_seats(&mut tx, &g).await?;
+        tx.commit().await?;
+        for &user in &lapsed {
+            let body = "The seat kept for you in {title} on {when} was not claimed in time, so it was passed on.";
+            tell(pool, user, &g, "normal", "Seat offer expired", body).await;
+        }
+        announce_offers(pool, &g, &offers).await;
+        expired += lapsed.len();
+    }
+    Ok(expired)
+}
+
+/// Settle open sessions past their cutoff: cancel those below the minimum,
+/// confirm the rest. Returns (cancelled, confirmed).
+pub async fn run_cutoffs(pool: &PgPool) -> anyhow::Result<(usize, usize)> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM group_sessions WHERE status = 'open' AND cutoff_at <= now() ORDER BY cutoff_at LIMIT 500")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    let (mut cancelled, mut confirmed) = (0, 0);
+    for id in ids {
+        let mut tx = pool.begin().await?;
+        let mut g = match lock(&mut tx, id).await? {
+            Some(g) if g.status == "open" => g,
+            _ => continue,
+        };
+        let booked: Vec<i32> = sqlx::query("SELECT student_id FROM group_seats WHERE session_id = $1 AND status = 'booked'")
+            .bind(g.id)
+            .fetch_all(&mut *tx)
+            .await?
+            .iter()
+            .map(|r| r.get("student_id"))
+            .collect();
+        if (booked.len() as i32) < g.min_seats {
+            let why = format!("only {} of the {} seats needed were booked by the cutoff", booked.len(), g.min_seats);
+            let closed = close(&mut tx, &mut g, &why, None).await?;
+            tx.commit().await?;
+            announce_cancelled(pool, &g, &closed, &why).await;
+            tell(pool, g.teacher_id, &g, "high", "Group session cancelled", &format!("{{title}} on {{when}} was cancelled: {}. All seats were refunded.", why)).await;
+            cancelled += 1;
+        } else {
+            sqlx::query("UPDATE group_sessions SET status = 'confirmed', updated_at = now() WHERE id = $1").bind(g.id).execute(&mut *tx).await?;
+            tx.commit().await?;
+            let body = format!("{{title}} on {{when}} is going ahead with {} students.", booked.len());
+            for &user in booked.iter().chain([g.teacher_id].iter()) {
+                tell(pool, user, &g, "normal", "Group session confirmed", &body).await;
+            }
+            confirmed += 1;
+        }
+    }
+    Ok((cancelled, confirmed))
+}
+
+/// Mark ended sessions completed and pay the seats' escrow to the teacher.
+pub async fn complete_due(pool: &PgPool) -> anyhow::Result<usize> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM group_sessions WHERE status = 'confirmed' AND ends_at <= now() ORDER BY ends_at LIMIT 500")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    let mut done = 0;
+    for id in ids {
+        let mut tx = pool.begin().await?;
+        let g = match lock(&mut tx, id).await? {
+            Some(g) if g.status == "confirmed" => g,
+            _ => continue,
+        };
+        let rows = sqlx::query("SELECT * FROM group_seats WHERE session_id = $1 AND status = 'booked' AND escrow_coins > 0 FOR UPDATE")
+            .bind(g.id)
+            .fetch_all(&mut *tx)
+            .await?;
+        for seat in rows.iter().map(Seat::from_row) {
+            let reference = seat.reference();
+            let entry = Entry { kind: "group_payout", reference: Some(&reference), actor_id: None, note: None };
+            wallet_service::post(&mut tx, g.teacher_id, seat.escrow_coins, &entry).await?;
+        }
+        sqlx::query("UPDATE group_seats SET escrow_coins = 0, updated_at = now() WHERE session_id = $1 AND status = 'booked'")
+            .bind(g.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE group_sessions SET status = 'completed', updated_at = now() WHERE id = $1").bind(g.id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        done += 1;
+    }
+    Ok(done)
+}
+
+// $1 = the viewer, if any
+const SUMMARY: &str = "SELECT g.*, shown_name(u, $1) AS teacher_name,
+        (SELECT COUNT(*) FROM group_seats s WHERE s.session_id = g.id AND s.status IN ('awaiting_approval', 'booked')) AS seated,
+        (SELECT COUNT(*) FROM group_waitlist w WHERE w.session_id = g.id AND w.status = 'offered') AS offered,
+        (SELECT COUNT(*) FROM group_waitlist w WHERE w.session_id = g.id AND w.status IN ('waiting', 'offered')) AS waitlisted
+    FROM group_sessions g JOIN users u ON u.id = g.teacher_id";
+
+fn summary(r: &sqlx::postgres::PgRow, tz: Tz) -> serde_json::Value {
+    let g = Group::from_row(r);
+    let seated: i64 = r.get("seated");
+    let offered: i64 = r.get("offered");
+    let mut v = g.to_json(tz);
+    v["teacher_name"] = json!(r.get::<String, _>("teacher_name"));
+    v["seats_taken"] = json!(seated);
+    v["seats_left"] = json!((g.max_seats as i64 - seated - offered).max(0));
+    v["waitlist"] = json!(r.get::<i64, _>("waitlisted"));
+    v
+}
+
+/// Upcoming sessions taking students, of one teacher or all verified ones.
+pub async fn list(pool: &PgPool, teacher_id: Option<i32>, viewer: Option<i32>, tz: Tz) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(&format!(
+        "{} WHERE g.status IN ('open', 'confirmed') AND g.starts_at > now() AND u.kyc_verified AND ($2::INTEGER IS NULL OR g.teacher_id = $2)
+         ORDER BY g.starts_at LIMIT 200",
+        SUMMARY
+    ))
+    .bind(viewer)
+    .bind(teacher_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(|r| summary(r, tz)).collect())
+}
+
+/// Session `id` as `viewer` sees it: their own seat or waitlist place, and
+/// for its teacher the roster and the waitlist.
+pub async fn detail(pool: &PgPool, id: i32, viewer: Option<i32>) -> anyhow::Result<serde_json::Value> {
+    let tz = match viewer {
+        Some(uid) => availability::user_tz(pool, uid).await?,
+        None => Tz::UTC,
+    };
+    let row = sqlx::query(&format!("{} WHERE g.id = $2", SUMMARY)).bind(viewer).bind(id).fetch_optional(pool).await?.ok_or_else(|| anyhow!("group session not found"))?;
+    let g = Group::from_row(&row);
+    let mut v = summary(&row, tz);
+    v["policy"] = g.policy.clone().unwrap_or(serde_json::Value::Null);
+    let Some(uid) = viewer else { return Ok(v) };
+
+    let seat = sqlx::query("SELECT * FROM group_seats WHERE session_id = $1 AND student_id = $2 ORDER BY id DESC LIMIT 1")
+        .bind(g.id)
+        .bind(uid)
+        .fetch_optional(pool)
+        .await?;
+    v["my_seat"] = seat.as_ref().map(|r| Seat::from_row(r).to_json()).unwrap_or(serde_json::Value::Null);
+    let place = sqlx::query(
+        "SELECT w.status, w.offer_expires_at,
+                (SELECT COUNT(*) FROM group_waitlist o WHERE o.session_id = w.session_id AND o.status IN ('waiting', 'offered') AND o.id <= w.id) AS position
+         FROM group_waitlist w WHERE w.session_id = $1 AND w.student_id = $2 ORDER BY w.id DESC LIMIT 1",
+    )
+    .bind(g.id)
+    .bind(uid)
+    .fetch_optional(pool)
+    .await?;
+    v["my_waitlist"] = place
+        .map(|r| {
+            let status: String = r.get("status");
+            let queued = status == "waiting" || status == "offered";
+            json!({
+                "status": status,
+                "position": if queued { Some(r.get::<i64, _>("position")) } else { None },
+                "offer_expires_at": r.get::<Option<DateTime<Utc>>, _>("offer_expires_at").filter(|_| status == "offered").map(|t| t.with_timezone(&tz).to_rfc3339()),
+            })
+        })
+        .unwrap_or(serde_json::Value::Null);
+
+    if uid == g.teacher_id {
+        let seats = sqlx::query(
+            "SELECT s.student_id, shown_name(u, $2) AS full_name, s.status, s.created_at FROM group_seats s JOIN users u ON u.id = s.student_id
+             WHERE s.session_id = $1 AND s.status IN ('awaiting_approval', 'booked') ORDER BY s.id",
+        )
+        .bind(g.id)
+        .bind(uid)
+        .fetch_all(pool)
+        .await?;
+        let queue = sqlx::query(
+            "SELECT w.student_id, shown_name(u, $2) AS full_name, w.status, w.offer_expires_at FROM group_waitlist w JOIN users u ON u.id = w.student_id
+             WHERE w.session_id = $1 AND w.status IN ('waiting', 'offered') ORDER BY w.id",
+        )
+        .bind(g.id)
+        .bind(uid)
+        .fetch_all(pool)
+        .await?;
+        v["roster"] = json!(seats
+            .iter()
+            .map(|r| json!({
+                "student_id": r.get::<i32, _>("student_id"),
+                "name": r.get::<String, _>("full_name"),
+                "status": r.get::<String, _>("status"),
+                "joined_at": r.get::<DateTime<Utc>, _>("created_at").to_rfc3339(),
+            }))
+            .collect::<Vec<_>>());
+        v["waitlist_entries"] = json!(queue
+            .iter()
+            .map(|r| json!({
+                "student_id": r.get::<i32, _>("student_id"),
+                "name": r.get::<String, _>("full_name"),
+                "status": r.get::<String, _>("status"),
+                "offer_expires_at": r.get::<Option<DateTime<Utc>>, _>("offer_expires_at").map(|t| t.with_timezone(&tz).to_rfc3339()),
+            }))
+            .collect::<Vec<_>>());
+    }
+    Ok(v)
+}
+
+/// Sessions `user_id` teaches, has a seat in or is waiting for, from the
+/// last 30 days on, newest first.
+pub async fn mine(pool: &PgPool, user_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let tz = availability::user_tz(pool, user_id).await?;
+    let rows = sqlx::query(&format!(
+        "{} WHERE g.ends_at > now() - INTERVAL '30 days'
+           AND (g.teacher_id = $1
+                OR EXISTS (SELECT 1 FROM group_seats s WHERE s.session_id = g.id AND s.student_id = $1)
+                OR EXISTS (SELECT 1 FROM group_waitlist w WHERE w.session_id = g.id AND w.student_id = $1))
+         ORDER BY g.starts_at DESC LIMIT 100",
+        SUMMARY
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let ids: Vec<i32> = rows.iter().map(|r| r.get("id")).collect();
+    let seats = sqlx::query("SELECT DISTINCT ON (session_id) session_id, status FROM group_seats WHERE session_id = ANY($1) AND student_id = $2 ORDER BY session_id, id DESC")
+        .bind(&ids)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    let places = sqlx::query("SELECT DISTINCT ON (session_id) session_id, status FROM group_waitlist WHERE session_id = ANY($1) AND student_id = $2 ORDER BY session_id, id DESC")
+        .bind(&ids)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    let status_in = |list: &[sqlx::postgres::PgRow], id: i32| {
+        list.iter().find(|r| r.get::<i32, _>("session_id") == id).map(|r| r.get::<String, _>("status"))
+    };
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let id: i32 = r.get("id");
+            let mut v = summary(r, tz);
+            v["role"] = json!(if r.get::<i32, _>("teacher_id") == user_id { "teacher" } else { "student" });
+            v["my_seat_status"] = json!(status_in(&seats, id));
+            v["my_waitlist_status"] = json!(status_in(&places, id));
+            v
+        })
+        .collect())
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("GROUPS_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(60).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match expire_offers(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Passed on {} unclaimed group seat offer(s)", n),
+                Err(e) => eprintln!("Group seat offer expiry failed: {:?}", e),
+            }
+            match expire_approvals(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Released {} group seat(s) not approved in time", n),
+                Err(e) => eprintln!("Group seat approval expiry failed: {:?}", e),
+            }
+            match run_cutoffs(&pool).await {
+                Ok((0, 0)) => {}
+                Ok((c, k)) => eprintln!("Group cutoffs: {} cancelled, {} confirmed", c, k),
+                Err(e) => eprintln!("Group cutoffs failed: {:?}", e),
+            }
+            match complete_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Completed {} group session(s)", n),
+                Err(e) => eprintln!("Group session completion failed: {:?}", e),
+            }
+        }
+    });
+}
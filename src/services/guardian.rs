@@ -0,0 +1,552 @@
+// Guardian accounts: parents or guardians responsible for student accounts.
+//
+// A guardian and a student are linked (guardian_links, see age.rs) through an
+// invitation that either side sends to the other's email; the invitee accepts
+// it while logged in with that email. Invitations expire after
+// GUARDIAN_INVITE_DAYS (default 14). A guardian may have several children and
+// a student several guardians. Capabilities follow the links, not the
+// account's role; the `guardian` role only picks the dashboard.
+//
+// Guardians can:
+// - see each child's bookings, progress (sessions taken), wallet activity,
+//   spending approvals and standing;
+// - top up a child's coin wallet from their own (`top_up`);
+// - approve or decline spending above the child's limit
+//   (users.guardian_spending_limit, else GUARDIAN_SPENDING_LIMIT, default 0:
+//   every paid booking);
+// - receive copies of the child's important notices: high and urgent ones,
+//   and those in GUARDIAN_COPY_CATEGORIES (default "booking,payment").
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+
+use crate::services::age;
+use crate::services::bookings;
+use crate::services::email;
+use crate::services::groups;
+use crate::services::notify_hub;
+use crate::services::notify_prefs::{self, escape_html};
+use crate::services::packages;
+use crate::services::wallet_service::{self, Entry};
+
+pub fn invite_days() -> i64 {
+    std::env::var("GUARDIAN_INVITE_DAYS").ok().and_then(|s| s.parse().ok()).filter(|d: &i64| *d > 0).unwrap_or(14)
+}
+
+pub fn default_spending_limit() -> i64 {
+    std::env::var("GUARDIAN_SPENDING_LIMIT").ok().and_then(|s| s.parse().ok()).filter(|l: &i64| *l >= 0).unwrap_or(0)
+}
+
+fn copy_categories() -> Vec<String> {
+    std::env::var("GUARDIAN_COPY_CATEGORIES")
+        .unwrap_or_else(|_| "booking,payment".to_string())
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+pub async fn is_guardian_of(pool: &PgPool, guardian_id: i32, minor_id: i32) -> sqlx::Result<bool> {
+    let row = sqlx::query("SELECT 1 FROM guardian_links WHERE guardian_id = $1 AND minor_id = $2")
+        .bind(guardian_id)
+        .bind(minor_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+async fn user_name(pool: &PgPool, user_id: i32) -> sqlx::Result<String> {
+    let row = sqlx::query("SELECT full_name FROM users WHERE id = $1").bind(user_id).fetch_optional(pool).await?;
+    Ok(row.map(|r| r.get("full_name")).unwrap_or_else(|| format!("user {}", user_id)))
+}
+
+// ------------------- invitations -------------------
+
+/// Invite `email` to link with `inviter_id`. `inviter_side` is 'guardian'
+/// when a guardian invites a student and 'student' the other way round.
+/// Returns the invitation id; a pending invitation to the same address is
+/// renewed rather than duplicated.
+pub async fn invite(pool: &PgPool, inviter_id: i32, inviter_side: &str, email_addr: &str) -> anyhow::Result<i32> {
+    let email_addr = email_addr.trim().to_lowercase();
+    if !email_addr.contains('@') {
+        return Err(anyhow!("enter a valid email address"));
+    }
+    let inviter = sqlx::query("SELECT full_name, lower(email) AS email, COALESCE(role, 'student') AS role FROM users WHERE id = $1")
+        .bind(inviter_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow!("account not found"))?;
+    if inviter.get::<String, _>("email") == email_addr {
+        return Err(anyhow!("you cannot invite yourself"));
+    }
+    let role: String = inviter.get("role");
+    match inviter_side {
+        "guardian" => {
+            let standing = age::standing(pool, inviter_id).await?.ok_or_else(|| anyhow!("account not found"))?;
+            if standing.is_minor() {
+                return Err(anyhow!("a guardian must be an adult"));
+            }
+        }
+        "student" if role == "student" => {}
+        "student" => return Err(anyhow!("only student accounts can invite a guardian")),
+        _ => return Err(anyhow!("invalid invitation")),
+    }
+
+    let already = sqlx::query(
+        "SELECT 1 FROM guardian_links g JOIN users u ON u.id = CASE WHEN $2 = 'guardian' THEN g.minor_id ELSE g.guardian_id END
+         WHERE (CASE WHEN $2 = 'guardian' THEN g.guardian_id ELSE g.minor_id END) = $1 AND lower(u.email) = $3",
+    )
+    .bind(inviter_id)
+    .bind(inviter_side)
+    .bind(&email_addr)
+    .fetch_optional(pool)
+    .await?;
+    if already.is_some() {
+        return Err(anyhow!("that account is already linked"));
+    }
+
+    let expires_at = Utc::now() + ChronoDuration::days(invite_days());
+    let renewed = sqlx::query(
+        "UPDATE guardian_invites SET expires_at = $4, created_at = now()
+         WHERE inviter_id = $1 AND inviter_side = $2 AND lower(email) = $3 AND status = 'pending' RETURNING id",
+    )
+    .bind(inviter_id)
+    .bind(inviter_side)
+    .bind(&email_addr)
+    .bind(expires_at)
+    .fetch_optional(pool)
+    .await?;
+    let id: i32 = match renewed {
+        Some(r) => r.get("id"),
+        None => sqlx::query("INSERT INTO guardian_invites (inviter_id, inviter_side, email, expires_at) VALUES ($1, $2, $3, $4) RETURNING id")
+            .bind(inviter_id)
+            .bind(inviter_side)
+            .bind(&email_addr)
+            .bind(expires_at)
+            .fetch_one(pool)
+            .await?
+            .get("id"),
+    };
+
+    let inviter_name: String = inviter.get("full_name");
+    let title = "Guardian invitation";
+    let body = if inviter_side == "guardian" {
+        format!("{} invited you to link your account to theirs as your parent or guardian. Open your dashboard to accept or decline.", inviter_name)
+    } else {
+        format!("{} asked you to be their parent or guardian on the platform. Open your guardian dashboard to accept or decline.", inviter_name)
+    };
+    let invitee = sqlx::query("SELECT id FROM users WHERE lower(email) = $1").bind(&email_addr).fetch_optional(pool).await?;
+    match invitee {
+        Some(r) => {
+            notify_prefs::notify(pool, r.get("id"), None, "system", "normal", title, &body).await?;
+        }
+        None => {
+            // no account yet: the invitation waits for a signup with this address
+            let html = format!(
+                "<p>{}</p><p><a href=\"{}/signup\">Create your account</a> with this email address to accept. The invitation expires on {}.</p>",
+                escape_html(&body),
+                email::base_url(),
+                expires_at.format("%Y-%m-%d")
+            );
+            let to = email_addr.clone();
+            match tokio::task::spawn_blocking(move || email::send_html(&to, title, html, None, None)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Guardian invitation email to {} failed: {:?}", email_addr, e),
+                Err(e) => eprintln!("Guardian invitation email to {} failed: {:?}", email_addr, e),
+            }
+        }
+    }
+    Ok(id)
+}
+
+fn invite_json(r: &sqlx::postgres::PgRow) -> serde_json::Value {
+    json!({
+        "id": r.get::<i32,_>("id"),
+        "inviter_id": r.get::<i32,_>("inviter_id"),
+        "inviter_name": r.get::<String,_>("inviter_name"),
+        "inviter_side": r.get::<String,_>("inviter_side"),
+        "email": r.get::<String,_>("email"),
+        "status": r.get::<String,_>("status"),
+        "expired": r.get::<bool,_>("expired"),
+        "expires_at": r.get::<DateTime<Utc>,_>("expires_at").to_rfc3339(),
+        "created_at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+    })
+}
+
+/// Invitations sent by `user_id` and pending ones addressed to their email,
+/// once they have confirmed that email (anyone can register with an address).
+pub async fn invites_for(pool: &PgPool, user_id: i32) -> sqlx::Result<serde_json::Value> {
+    let rows = sqlx::query(
+        "SELECT i.*, u.full_name AS inviter_name, i.expires_at < now() AS expired,
+             i.inviter_id = $1 AS sent
+         FROM guardian_invites i JOIN users u ON u.id = i.inviter_id
+         WHERE i.inviter_id = $1
+            OR (i.status = 'pending' AND i.expires_at >= now()
+                AND lower(i.email) = (SELECT lower(email) FROM users WHERE id = $1 AND COALESCE(verified, FALSE)))
+         ORDER BY i.id DESC LIMIT 100",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let (sent, received): (Vec<_>, Vec<_>) = rows.iter().partition(|r| r.get::<bool, _>("sent"));
+    Ok(json!({
+        "sent": sent.into_iter().map(invite_json).collect::<Vec<_>>(),
+        "received": received.into_iter().map(invite_json).collect::<Vec<_>>(),
+    }))
+}
+
+/// Accept or decline invitation `id` as `user_id`, who must own the invited
+/// email and have confirmed it. Accepting links the two accounts.
+pub async fn respond(pool: &PgPool, id: i32, user_id: i32, accept: bool) -> anyhow::Result<()> {
+    let inv = sqlx::query(
+        "SELECT i.inviter_id, i.inviter_side, i.expires_at < now() AS expired, COALESCE(u.role, 'student') AS role,
+             COALESCE(u.verified, FALSE) AS verified
+         FROM guardian_invites i JOIN users u ON u.id = $2
+         WHERE i.id = $1 AND i.status = 'pending' AND lower(i.email) = lower(u.email)",
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow!("invitation not found"))?;
+    if !inv.get::<bool, _>("verified") {
+        return Err(anyhow!("confirm your email address before answering this invitation"));
+    }
+    if inv.get::<bool, _>("expired") {
+        return Err(anyhow!("this invitation has expired; ask for a new one"));
+    }
+    let inviter_id: i32 = inv.get("inviter_id");
+    let inviter_side: String = inv.get("inviter_side");
+
+    if accept {
+        let (minor_id, guardian_id) = if inviter_side == "guardian" {
+            if inv.get::<String, _>("role") != "student" {
+                return Err(anyhow!("only student accounts can be linked to a guardian"));
+            }
+            (user_id, inviter_id)
+        } else {
+            (inviter_id, user_id)
+        };
+        age::link_guardian(pool, minor_id, guardian_id, Some(user_id)).await?;
+    }
+    let status = if accept { "accepted" } else { "declined" };
+    sqlx::query("UPDATE guardian_invites SET status = $2, accepted_by = $3, decided_at = now() WHERE id = $1 AND status = 'pending'")
+        .bind(id)
+        .bind(status)
+        .bind(if accept { Some(user_id) } else { None })
+        .execute(pool)
+        .await?;
+
+    let name = user_name(pool, user_id).await?;
+    let body = format!("{} {} your guardian invitation.", name, status);
+    notify_prefs::notify(pool, inviter_id, None, "system", "normal", "Guardian invitation answered", &body).await?;
+    Ok(())
+}
+
+/// Withdraw a pending invitation sent by `inviter_id`.
+pub async fn revoke(pool: &PgPool, id: i32, inviter_id: i32) -> sqlx::Result<bool> {
+    let res = sqlx::query("UPDATE guardian_invites SET status = 'revoked', decided_at = now() WHERE id = $1 AND inviter_id = $2 AND status = 'pending'")
+        .bind(id)
+        .bind(inviter_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+// ------------------- children -------------------
+
+// columns for one child row `u`
+const CHILD_COLUMNS: &str = "u.id, u.full_name, u.email, u.is_minor, u.guardian_spending_limit,
+    COALESCE((SELECT balance FROM wallets w WHERE w.user_id = u.id), 0) AS balance,
+    (SELECT COUNT(*) FROM spending_approvals a WHERE a.minor_id = u.id AND a.status = 'pending') AS pending_approvals";
+
+fn child_json(r: &sqlx::postgres::PgRow) -> serde_json::Value {
+    let limit: Option<i64> = r.get("guardian_spending_limit");
+    json!({
+        "id": r.get::<i32,_>("id"),
+        "full_name": r.get::<String,_>("full_name"),
+        "email": r.get::<String,_>("email"),
+        "is_minor": r.get::<bool,_>("is_minor"),
+        "balance": r.get::<i64,_>("balance"),
+        "spending_limit": limit.unwrap_or_else(default_spending_limit),
+        "spending_limit_is_default": limit.is_none(),
+        "pending_approvals": r.get::<i64,_>("pending_approvals"),
+    })
+}
+
+/// Students linked to `guardian_id`.
+pub async fn children(pool: &PgPool, guardian_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM guardian_links g JOIN users u ON u.id = g.minor_id WHERE g.guardian_id = $1 ORDER BY u.full_name",
+        CHILD_COLUMNS
+    ))
+    .bind(guardian_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(child_json).collect())
+}
+
+/// One child with their standing, wallet activity and spending approvals.
+pub async fn child(pool: &PgPool, minor_id: i32) -> sqlx::Result<Option<serde_json::Value>> {
+    let row = match sqlx::query(&format!("SELECT {} FROM users u WHERE u.id = $1", CHILD_COLUMNS)).bind(minor_id).fetch_optional(pool).await? {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let mut out = child_json(&row);
+    if let Some(s) = age::standing(pool, minor_id).await? {
+        out["standing"] = s.to_json();
+    }
+    out["wallet"] = json!(wallet_service::history(pool, minor_id, 50).await?);
+    out["bookings"] = json!(bookings::list_for(pool, minor_id, 50).await?);
+    out["progress"] = bookings::progress(pool, minor_id).await?;
+    out["approvals"] = json!(approvals(pool, &[minor_id], false).await?);
+    Ok(Some(out))
+}
+
+pub async fn set_spending_limit(pool: &PgPool, minor_id: i32, limit: Option<i64>) -> sqlx::Result<()> {
+    sqlx::query("UPDATE users SET guardian_spending_limit = $2 WHERE id = $1").bind(minor_id).bind(limit).execute(pool).await?;
+    Ok(())
+}
+
+/// Move `amount` coins from the guardian's wallet to the child's. Returns the
+/// child's new balance.
+pub async fn top_up(pool: &PgPool, guardian_id: i32, minor_id: i32, amount: i64, note: Option<&str>) -> anyhow::Result<i64> {
+    if amount <= 0 {
+        return Err(anyhow!("amount must be positive"));
+    }
+    let mut tx = pool.begin().await?;
+    let sent_ref = format!("user:{}", minor_id);
+    let received_ref = format!("user:{}", guardian_id);
+    wallet_service::post(&mut tx, guardian_id, -amount, &Entry { kind: "top_up_sent", reference: Some(&sent_ref), actor_id: Some(guardian_id), note }).await?;
+    let balance = wallet_service::post(&mut tx, minor_id, amount, &Entry { kind: "top_up_received", reference: Some(&received_ref), actor_id: Some(guardian_id), note }).await?;
+    tx.commit().await?;
+
+    let body = format!("{} added {} coins to your wallet. Your balance is now {} coins.", user_name(pool, guardian_id).await?, amount, balance);
+    if let Err(e) = notify_prefs::notify(pool, minor_id, Some(guardian_id), "payment", "normal", "Wallet topped up", &body).await {
+        eprintln!("Top-up notification for user {} failed: {:?}", minor_id, e);
+    }
+    Ok(balance)
+}
+
+// ------------------- spending approvals -------------------
+
+/// The spending limit of `minor_id` when they have a guardian, else None
+/// (no approval needed).
+pub async fn approval_limit(pool: &PgPool, minor_id: i32) -> sqlx::Result<Option<i64>> {
+    let row = sqlx::query(
+        "SELECT u.guardian_spending_limit, EXISTS (SELECT 1 FROM guardian_links g WHERE g.minor_id = u.id) AS guarded
+         FROM users u WHERE u.id = $1",
+    )
+    .bind(minor_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row
+        .filter(|r| r.get::<bool, _>("guarded"))
+        .map(|r| r.get::<Option<i64>, _>("guardian_spending_limit").unwrap_or_else(default_spending_limit)))
+}
+
+/// Open an approval for spending by `minor_id`; the caller tells the
+/// guardians with `notify_approval_request` once committed.
+pub async fn request_approval(
+    tx: &mut Transaction<'_, Postgres>,
+    minor_id: i32,
+    amount: i64,
+    kind: &str,
+    reference_id: Option<i32>,
+    description: &str,
+) -> sqlx::Result<i32> {
+    let row = sqlx::query(
+        "INSERT INTO spending_approvals (minor_id, amount, kind, reference_id, description) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(minor_id)
+    .bind(amount)
+    .bind(kind)
+    .bind(reference_id)
+    .bind(description)
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(row.get("id"))
+}
+
+pub async fn notify_approval_request(pool: &PgPool, approval_id: i32) -> sqlx::Result<()> {
+    let rows = sqlx::query(
+        "SELECT g.guardian_id, u.full_name, a.amount, a.description FROM spending_approvals a
+         JOIN guardian_links g ON g.minor_id = a.minor_id JOIN users u ON u.id = a.minor_id
+         WHERE a.id = $1",
+    )
+    .bind(approval_id)
+    .fetch_all(pool)
+    .await?;
+    for r in &rows {
+        let body = format!(
+            "{} wants to spend {} coins: {}. Approve or decline it on your guardian dashboard.",
+            r.get::<String, _>("full_name"),
+            r.get::<i64, _>("amount"),
+            r.get::<String, _>("description")
+        );
+        notify_prefs::notify(pool, r.get("guardian_id"), None, "payment", "high", "Approval needed", &body).await?;
+    }
+    Ok(())
+}
+
+/// Cancel the approval of something that went away before a decision.
+pub async fn cancel_approval(tx: &mut Transaction<'_, Postgres>, approval_id: i32) -> sqlx::Result<()> {
+    sqlx::query("UPDATE spending_approvals SET status = 'cancelled', decided_at = now() WHERE id = $1 AND status = 'pending'")
+        .bind(approval_id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Approvals of the given students, pending ones only or the latest of any
+/// status.
+pub async fn approvals(pool: &PgPool, minor_ids: &[i32], pending_only: bool) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(
+        "SELECT a.*, u.full_name AS minor_name FROM spending_approvals a JOIN users u ON u.id = a.minor_id
+         WHERE a.minor_id = ANY($1) AND (NOT $2 OR a.status = 'pending')
+         ORDER BY a.id DESC LIMIT 100",
+    )
+    .bind(minor_ids)
+    .bind(pending_only)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.get::<i32,_>("id"),
+                "minor_id": r.get::<i32,_>("minor_id"),
+                "minor_name": r.get::<String,_>("minor_name"),
+                "amount": r.get::<i64,_>("amount"),
+                "kind": r.get::<String,_>("kind"),
+                "reference_id": r.get::<Option<i32>,_>("reference_id"),
+                "description": r.get::<String,_>("description"),
+                "status": r.get::<String,_>("status"),
+                "decided_by": r.get::<Option<i32>,_>("decided_by"),
+                "decided_at": r.get::<Option<DateTime<Utc>>,_>("decided_at").map(|d| d.to_rfc3339()),
+                "note": r.get::<Option<String>,_>("note"),
+                "created_at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+            })
+        })
+        .collect())
+}
+
+/// How settling something held for a guardian's approval ended.
+#[derive(Debug, PartialEq)]
+pub enum Settlement {
+    /// Charged and booked.
+    Done,
+    /// Not booked, for this reason; the student has been told.
+    Refused(String),
+    /// It was no longer waiting for approval.
+    Stale,
+}
+
+/// A decided spending approval. `approved` is whether the spending went
+/// through; `reason` says why not when a guardian approved it but the charge
+/// or booking failed.
+pub struct Decision {
+    pub minor_id: i32,
+    pub kind: String,
+    pub reference_id: Option<i32>,
+    pub approved: bool,
+    pub reason: Option<String>,
+}
+
+/// Approve or decline pending approval `id` as `guardian_id`; the first
+/// guardian to decide settles it. What is being paid for is settled first,
+/// so the approval records, and the student hears, what actually happened.
+pub async fn decide(pool: &PgPool, id: i32, guardian_id: i32, approve: bool, note: Option<&str>) -> anyhow::Result<Decision> {
+    let row = sqlx::query(
+        "SELECT a.minor_id, a.kind, a.reference_id, a.amount, a.description FROM spending_approvals a
+         WHERE a.id = $1 AND a.status = 'pending'
+           AND EXISTS (SELECT 1 FROM guardian_links g WHERE g.minor_id = a.minor_id AND g.guardian_id = $2)",
+    )
+    .bind(id)
+    .bind(guardian_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow!("no pending approval with that id"))?;
+    let kind: String = row.get("kind");
+    let reference_id: Option<i32> = row.get("reference_id");
+
+    // settling locks the booking, seat or package, so of two guardians
+    // deciding at once the second finds it stale
+    let settlement = match (kind.as_str(), reference_id) {
+        ("booking", Some(booking_id)) => bookings::apply_approval(pool, booking_id, approve).await?,
+        ("group_seat", Some(seat_id)) => groups::apply_approval(pool, seat_id, approve).await?,
+        ("package", Some(package_id)) => packages::apply_approval(pool, package_id, approve).await?,
+        _ if approve => Settlement::Done,
+        _ => Settlement::Refused("a guardian declined it".to_string()),
+    };
+    let (status, reason) = match settlement {
+        Settlement::Stale => return Err(anyhow!("this approval was already settled")),
+        Settlement::Done => ("approved", None),
+        Settlement::Refused(_) if !approve => ("declined", None),
+        Settlement::Refused(r) => ("cancelled", Some(r)),
+    };
+    sqlx::query("UPDATE spending_approvals SET status = $3, decided_by = $2, decided_at = now(), note = $4 WHERE id = $1 AND decided_by IS NULL")
+        .bind(id)
+        .bind(guardian_id)
+        .bind(status)
+        .bind(note)
+        .execute(pool)
+        .await?;
+    let decision = Decision { minor_id: row.get("minor_id"), kind, reference_id, approved: status == "approved", reason };
+    if decision.reason.is_some() {
+        // the student was already told why it could not be booked
+        return Ok(decision);
+    }
+
+    let description: String = row.get("description");
+    let title = if approve { "Spending approved" } else { "Spending declined" };
+    let mut body = format!(
+        "{} {} {} ({} coins).",
+        user_name(pool, guardian_id).await?,
+        if approve { "approved" } else { "declined" },
+        description,
+        row.get::<i64, _>("amount")
+    );
+    if let Some(n) = note.filter(|n| !n.trim().is_empty()) {
+        body.push_str(&format!(" Note: {}", n.trim()));
+    }
+    if let Err(e) = notify_prefs::notify(pool, decision.minor_id, Some(guardian_id), "payment", "normal", title, &body).await {
+        eprintln!("Approval notification for user {} failed: {:?}", decision.minor_id, e);
+    }
+    Ok(decision)
+}
+
+// ------------------- notification copies -------------------
+
+/// Copy an important notice sent to `user_id` to their guardians, except the
+/// guardian who sent it. Copies are delivered like any notice but not copied
+/// again.
+pub async fn copy_to_guardians(pool: &PgPool, user_id: i32, sender_id: Option<i32>, category: &str, priority: &str, title: &str, body: &str) -> sqlx::Result<()> {
+    let important = priority == "high" || priority == "urgent" || copy_categories().iter().any(|c| c == category);
+    if !important {
+        return Ok(());
+    }
+    let rows = sqlx::query(
+        "INSERT INTO notifications (user_id, sender_id, title, body, category, priority)
+         SELECT g.guardian_id, $2, u.full_name || ': ' || $3, $4, $5, $6
+         FROM guardian_links g JOIN users u ON u.id = g.minor_id
+         WHERE g.minor_id = $1 AND g.guardian_id IS DISTINCT FROM $2
+         RETURNING id",
+    )
+    .bind(user_id)
+    .bind(sender_id)
+    .bind(title)
+    .bind(body)
+    .bind(category)
+    .bind(priority)
+    .fetch_all(pool)
+    .await?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = rows.iter().map(|r| r.get("id")).collect();
+    let events = notify_hub::events_by_ids(pool, &ids).await?;
+    notify_prefs::dispatch(pool, events).await
+}
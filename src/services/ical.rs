@@ -0,0 +1,555 @@
+// iCalendar (RFC 5545) writing and reading.
+//
+// Writing covers what session invites and feeds need: VEVENTs in UTC with
+// organizer, attendees, STATUS and SEQUENCE, text escaped and lines folded at
+// 75 octets.
+//
+// Reading only extracts busy time: VEVENTs (without STATUS:CANCELLED or
+// TRANSP:TRANSPARENT) and VFREEBUSY periods. TZID must be an IANA name;
+// anything else, and floating times, are read in the caller's timezone.
+// Recurring events are expanded for FREQ=DAILY and FREQ=WEEKLY (INTERVAL,
+// COUNT, UNTIL, BYDAY) minus EXDATEs and moved instances (RECURRENCE-ID);
+// other rules are counted as skipped rather than guessed, as are INTERVALs
+// and durations too large to compute with.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use std::collections::{HashMap, HashSet};
+
+use crate::services::availability;
+use crate::services::email;
+
+const PRODID: &str = "-//Skillvine//Sessions//EN";
+
+/// Most occurrences generated for one recurring event within the range read.
+const MAX_OCCURRENCES: usize = 5000;
+
+/// Largest RRULE INTERVAL read; events with larger ones are skipped.
+const MAX_INTERVAL: u32 = 1000;
+
+pub struct Person {
+    pub name: String,
+    pub email: String,
+}
+
+pub struct Event {
+    pub uid: String,
+    pub sequence: i32,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: String,
+    pub description: String,
+    /// CONFIRMED, TENTATIVE or CANCELLED
+    pub status: &'static str,
+    pub organizer: Person,
+    pub attendees: Vec<Person>,
+    pub url: Option<String>,
+}
+
+fn escape_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Parameter values may not contain DQUOTE; quoting covers ':', ';' and ','.
+fn quote_param(s: &str) -> String {
+    format!("\"{}\"", s.replace(['"', '\r', '\n'], ""))
+}
+
+fn utc(t: DateTime<Utc>) -> String {
+    t.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Append `line` folded to 75 octets per physical line.
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// A VCALENDAR with `events`. `method` is set for invites (REQUEST, CANCEL)
+/// and left out for subscription feeds, which `name` titles.
+pub fn calendar(method: Option<&str>, name: Option<&str>, events: &[Event]) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, &format!("PRODID:{}", PRODID));
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    if let Some(m) = method {
+        push_line(&mut out, &format!("METHOD:{}", m));
+    }
+    if let Some(n) = name {
+        push_line(&mut out, &format!("X-WR-CALNAME:{}", escape_text(n)));
+    }
+    let stamp = utc(Utc::now());
+    for e in events {
+        push_line(&mut out, "BEGIN:VEVENT");
+        push_line(&mut out, &format!("UID:{}", e.uid));
+        push_line(&mut out, &format!("DTSTAMP:{}", stamp));
+        push_line(&mut out, &format!("SEQUENCE:{}", e.sequence));
+        push_line(&mut out, &format!("DTSTART:{}", utc(e.start)));
+        push_line(&mut out, &format!("DTEND:{}", utc(e.end)));
+        push_line(&mut out, &format!("SUMMARY:{}", escape_text(&e.summary)));
+        if !e.description.is_empty() {
+            push_line(&mut out, &format!("DESCRIPTION:{}", escape_text(&e.description)));
+        }
+        push_line(&mut out, &format!("STATUS:{}", e.status));
+        push_line(&mut out, &format!("ORGANIZER;CN={}:mailto:{}", quote_param(&e.organizer.name), e.organizer.email));
+        for a in &e.attendees {
+            push_line(
+                &mut out,
+                &format!("ATTENDEE;CN={};ROLE=REQ-PARTICIPANT;PARTSTAT=ACCEPTED:mailto:{}", quote_param(&a.name), a.email),
+            );
+        }
+        if let Some(url) = &e.url {
+            push_line(&mut out, &format!("URL:{}", url));
+        }
+        push_line(&mut out, "END:VEVENT");
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// The METHOD of a calendar object written by `calendar`.
+pub fn method_of(ics: &str) -> Option<&str> {
+    ics.lines().find_map(|l| l.trim_end().strip_prefix("METHOD:"))
+}
+
+/// `ics` as an email attachment, typed with its METHOD so mail clients offer
+/// to add, update or remove the event.
+pub fn attachment(ics: &str) -> email::Attachment {
+    let content_type = match method_of(ics) {
+        Some(m) => format!("text/calendar; charset=UTF-8; method={}", m),
+        None => "text/calendar; charset=UTF-8".to_string(),
+    };
+    let filename = if method_of(ics) == Some("CANCEL") { "cancel.ics" } else { "invite.ics" };
+    email::Attachment { filename: filename.to_string(), content_type, body: ics.as_bytes().to_vec() }
+}
+
+/// One property of a content line: `NAME;PARAM=x:value`.
+struct Prop {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Prop {
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in text.split('\n') {
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        match (raw.strip_prefix(' ').or_else(|| raw.strip_prefix('\t')), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if raw.is_empty() => {}
+            _ => lines.push(raw.to_string()),
+        }
+    }
+    lines
+}
+
+fn parse_prop(line: &str) -> Option<Prop> {
+    // split at the first ':' and each ';' outside quoted parameter values
+    let mut quoted = false;
+    let mut parts = Vec::new();
+    let mut cur = String::new();
+    let mut value = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => parts.push(std::mem::take(&mut cur)),
+            ':' if !quoted => {
+                parts.push(std::mem::take(&mut cur));
+                value = Some(line[i + 1..].to_string());
+                break;
+            }
+            _ => cur.push(c),
+        }
+    }
+    let value = value?;
+    let mut parts = parts.into_iter();
+    let name = parts.next()?.to_ascii_uppercase();
+    let params = parts
+        .filter_map(|p| {
+            let (k, v) = p.split_once('=')?;
+            Some((k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+        })
+        .collect();
+    Some(Prop { name, params, value })
+}
+
+fn unescape_text(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+/// A DATE or DATE-TIME value: all-day dates and wall times in a timezone
+/// (UTC for values ending in Z).
+#[derive(Clone, Copy)]
+enum When {
+    Date(NaiveDate),
+    Local(NaiveDateTime, Tz),
+}
+
+impl When {
+    fn parse(value: &str, tzid: Option<&str>, default_tz: Tz) -> Option<When> {
+        let value = value.trim();
+        if value.len() == 8 {
+            return NaiveDate::parse_from_str(value, "%Y%m%d").ok().map(When::Date);
+        }
+        if let Some(v) = value.strip_suffix('Z') {
+            return NaiveDateTime::parse_from_str(v, "%Y%m%dT%H%M%S").ok().map(|t| When::Local(t, Tz::UTC));
+        }
+        let tz = tzid.and_then(availability::parse_tz).unwrap_or(default_tz);
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok().map(|t| When::Local(t, tz))
+    }
+
+    fn of(p: &Prop, default_tz: Tz) -> Option<When> {
+        When::parse(&p.value, p.param("TZID"), default_tz)
+    }
+
+    /// Wall time and the timezone it is read in (all-day dates start at local midnight).
+    fn local(self, default_tz: Tz) -> (NaiveDateTime, Tz) {
+        match self {
+            When::Date(d) => (d.and_time(NaiveTime::MIN), default_tz),
+            When::Local(t, tz) => (t, tz),
+        }
+    }
+
+    fn instant(self, default_tz: Tz) -> DateTime<Utc> {
+        let (t, tz) = self.local(default_tz);
+        availability::resolve(&tz, t, false)
+    }
+}
+
+/// An RFC 5545 DURATION such as `PT1H30M`, `P1D` or `-P1W`.
+fn parse_duration(s: &str) -> Option<ChronoDuration> {
+    let s = s.trim();
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let s = s.strip_prefix('P')?;
+    let mut total = ChronoDuration::zero();
+    let mut num = String::new();
+    let mut in_time = false;
+    for c in s.chars() {
+        match c {
+            '0'..='9' => num.push(c),
+            'T' => in_time = true,
+            _ => {
+                let n: i64 = num.parse().ok()?;
+                num.clear();
+                // None also when out of range
+                let part = match (c, in_time) {
+                    ('W', false) => ChronoDuration::try_weeks(n),
+                    ('D', false) => ChronoDuration::try_days(n),
+                    ('H', true) => ChronoDuration::try_hours(n),
+                    ('M', true) => ChronoDuration::try_minutes(n),
+                    ('S', true) => ChronoDuration::try_seconds(n),
+                    _ => None,
+                };
+                total = total.checked_add(&part?)?;
+            }
+        }
+    }
+    if !num.is_empty() {
+        return None;
+    }
+    Some(if negative { -total } else { total })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Freq {
+    Daily,
+    Weekly,
+}
+
+struct Rule {
+    freq: Freq,
+    interval: u32,
+    count: Option<usize>,
+    until: Option<DateTime<Utc>>,
+    by_day: Vec<Weekday>,
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+impl Rule {
+    /// The supported subset of RRULE, or None.
+    fn parse(value: &str, default_tz: Tz) -> Option<Rule> {
+        let mut rule = Rule { freq: Freq::Daily, interval: 1, count: None, until: None, by_day: Vec::new() };
+        let mut freq = None;
+        for part in value.split(';').filter(|p| !p.is_empty()) {
+            let (k, v) = part.split_once('=')?;
+            match k.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match v.to_ascii_uppercase().as_str() {
+                        "DAILY" => Freq::Daily,
+                        "WEEKLY" => Freq::Weekly,
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => rule.interval = v.parse().ok().filter(|n| (1..=MAX_INTERVAL).contains(n))?,
+                "COUNT" => rule.count = Some(v.parse().ok()?),
+                "UNTIL" => rule.until = Some(When::parse(v, None, default_tz)?.instant(default_tz)),
+                "BYDAY" => {
+                    for d in v.split(',') {
+                        rule.by_day.push(parse_weekday(&d.to_ascii_uppercase())?);
+                    }
+                }
+                "WKST" => {}
+                _ => return None,
+            }
+        }
+        rule.freq = freq?;
+        if rule.freq == Freq::Daily && !rule.by_day.is_empty() {
+            return None;
+        }
+        Some(rule)
+    }
+
+    /// Local start times of the occurrences from `first` that start in
+    /// [from, to]. Earlier ones are not generated but still count toward
+    /// COUNT.
+    fn expand(&self, first: NaiveDateTime, tz: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<NaiveDateTime> {
+        let mut out = Vec::new();
+        let time = first.time();
+        let first_day = first.date();
+        let mut days = self.by_day.clone();
+        if days.is_empty() {
+            days.push(first_day.weekday());
+        }
+        days.sort_by_key(|d| d.num_days_from_monday());
+        // each period starts on `base`, `step` days after the previous one
+        let (base, step) = match self.freq {
+            Freq::Daily => (first_day, self.interval as i64),
+            Freq::Weekly => (first_day.week(Weekday::Mon).first_day(), 7 * self.interval as i64),
+        };
+        let dates_in = |period: i64| -> Option<Vec<NaiveDate>> {
+            let start = base.checked_add_signed(ChronoDuration::try_days(period.checked_mul(step)?)?)?;
+            Some(match self.freq {
+                Freq::Daily => vec![start],
+                Freq::Weekly => days
+                    .iter()
+                    .filter_map(|d| start.checked_add_signed(ChronoDuration::days(d.num_days_from_monday() as i64)))
+                    .filter(|d| *d >= first_day)
+                    .collect(),
+            })
+        };
+
+        // jump to the period before `from`, counting what is passed over
+        let mut period = ((from.with_timezone(&tz).date_naive() - base).num_days() / step - 1).max(0);
+        let mut seen = match (self.freq, period) {
+            (_, 0) => 0,
+            (Freq::Daily, n) => n as usize,
+            (Freq::Weekly, n) => dates_in(0).map_or(0, |d| d.len()) + (n as usize - 1) * days.len(),
+        };
+        loop {
+            // past the last representable date
+            let Some(dates) = dates_in(period) else { return out };
+            for date in dates {
+                let local = date.and_time(time);
+                let at = availability::resolve(&tz, local, false);
+                if self.until.is_some_and(|u| at > u) || at > to || self.count.is_some_and(|c| seen >= c) || out.len() >= MAX_OCCURRENCES {
+                    return out;
+                }
+                seen += 1;
+                if at >= from {
+                    out.push(local);
+                }
+            }
+            period += 1;
+        }
+    }
+}
+
+/// A busy period read from a calendar.
+pub struct Busy {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: Option<String>,
+}
+
+pub struct Imported {
+    pub busy: Vec<Busy>,
+    /// Events that could not be read or use recurrence rules not supported here
+    pub skipped: usize,
+}
+
+#[derive(Default)]
+struct RawEvent {
+    props: Vec<Prop>,
+}
+
+impl RawEvent {
+    fn get(&self, name: &str) -> Option<&Prop> {
+        self.props.iter().find(|p| p.name == name)
+    }
+
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Prop> + 'a {
+        self.props.iter().filter(move |p| p.name == name)
+    }
+}
+
+/// Busy periods in `text` overlapping [from, to). Times without a known
+/// timezone are read in `default_tz`.
+pub fn parse_busy(text: &str, default_tz: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> anyhow::Result<Imported> {
+    let lines = unfold(text);
+    if !lines.first().is_some_and(|l| l.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(anyhow!("not an iCalendar file"));
+    }
+
+    let mut events = Vec::new();
+    let mut periods = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut current = RawEvent::default();
+    for line in &lines {
+        let Some(p) = parse_prop(line) else { continue };
+        match p.name.as_str() {
+            "BEGIN" => {
+                stack.push(p.value.trim().to_ascii_uppercase());
+                if stack.last().is_some_and(|c| c == "VEVENT") {
+                    current = RawEvent::default();
+                }
+            }
+            "END" if stack.pop().is_some_and(|c| c == "VEVENT") => events.push(std::mem::take(&mut current)),
+            "END" => {}
+            "FREEBUSY" if stack.last().is_some_and(|c| c == "VFREEBUSY") => {
+                if p.param("FBTYPE").is_some_and(|t| t.eq_ignore_ascii_case("FREE")) {
+                    continue;
+                }
+                for period in p.value.split(',') {
+                    let Some((s, e)) = period.split_once('/') else { continue };
+                    let Some(start) = When::parse(s, None, default_tz).map(|w| w.instant(default_tz)) else { continue };
+                    let end = match parse_duration(e) {
+                        Some(d) => match start.checked_add_signed(d) {
+                            Some(end) => end,
+                            None => continue,
+                        },
+                        None => match When::parse(e, None, default_tz) {
+                            Some(w) => w.instant(default_tz),
+                            None => continue,
+                        },
+                    };
+                    periods.push(Busy { start, end, summary: None });
+                }
+            }
+            _ if stack.last().is_some_and(|c| c == "VEVENT") => current.props.push(p),
+            _ => {}
+        }
+    }
+
+    // instances moved or cancelled by a RECURRENCE-ID override, per UID
+    let mut overridden: HashMap<String, HashSet<DateTime<Utc>>> = HashMap::new();
+    for e in &events {
+        if let (Some(uid), Some(rid)) = (e.get("UID"), e.get("RECURRENCE-ID").and_then(|p| When::of(p, default_tz))) {
+            overridden.entry(uid.value.clone()).or_default().insert(rid.instant(default_tz));
+        }
+    }
+
+    let mut skipped = 0;
+    let mut busy = periods;
+    for e in &events {
+        let free = e.get("TRANSP").is_some_and(|p| p.value.eq_ignore_ascii_case("TRANSPARENT"))
+            || e.get("STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED"));
+        if free {
+            continue;
+        }
+        let Some(start) = e.get("DTSTART").and_then(|p| When::of(p, default_tz)) else {
+            skipped += 1;
+            continue;
+        };
+        // all-day events last whole local days, others a fixed length
+        let span = match (start, e.get("DTEND").and_then(|p| When::of(p, default_tz)), e.get("DURATION")) {
+            (When::Date(s), Some(When::Date(end)), _) => ChronoDuration::try_days((end - s).num_days()).map(|d| (d, ChronoDuration::zero())),
+            (When::Date(_), None, None) => Some((ChronoDuration::days(1), ChronoDuration::zero())),
+            (When::Date(_), _, Some(d)) => parse_duration(&d.value).map(|d| {
+                let days = ChronoDuration::days(d.num_days());
+                (days, d - days)
+            }),
+            (_, Some(end), _) => Some((ChronoDuration::zero(), end.instant(default_tz) - start.instant(default_tz))),
+            (_, None, Some(d)) => parse_duration(&d.value).map(|d| (ChronoDuration::zero(), d)),
+            (_, None, None) => Some((ChronoDuration::zero(), ChronoDuration::zero())),
+        };
+        // unreadable or out-of-range lengths
+        let Some((days, length)) = span.filter(|(d, l)| d.checked_add(l).is_some()) else {
+            skipped += 1;
+            continue;
+        };
+        // the earliest start that can still reach into [from, to)
+        let earliest = from.checked_sub_signed(days + length).unwrap_or_else(|| start.instant(default_tz));
+        let (first, tz) = start.local(default_tz);
+        let occurrences = match e.get("RRULE") {
+            None => vec![first],
+            Some(r) => match Rule::parse(&r.value, default_tz) {
+                Some(rule) => rule.expand(first, tz, earliest, to),
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            },
+        };
+        let mut excluded: HashSet<DateTime<Utc>> = e
+            .all("EXDATE")
+            .flat_map(|p| p.value.split(',').filter_map(|v| When::parse(v, p.param("TZID"), default_tz)).map(|w| w.instant(default_tz)).collect::<Vec<_>>())
+            .collect();
+        if e.get("RECURRENCE-ID").is_none() {
+            if let Some(moved) = e.get("UID").and_then(|u| overridden.get(&u.value)) {
+                excluded.extend(moved);
+            }
+        }
+        let summary = e.get("SUMMARY").map(|p| unescape_text(&p.value)).filter(|s| !s.trim().is_empty());
+        for local in occurrences {
+            let s = availability::resolve(&tz, local, false);
+            let Some(end) = local.checked_add_signed(days).and_then(|l| availability::resolve(&tz, l, false).checked_add_signed(length)) else {
+                continue;
+            };
+            if end <= s || excluded.contains(&s) || end <= from || s >= to {
+                continue;
+            }
+            busy.push(Busy { start: s, end, summary: summary.clone() });
+        }
+    }
+    busy.retain(|b| b.end > b.start && b.end > from && b.start < to);
+    busy.sort_by_key(|b| b.start);
+    Ok(Imported { busy, skipped })
+}
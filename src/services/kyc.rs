@@ -0,0 +1,557 @@
+// Teacher KYC review workflow.
+//
+//   draft -> submitted -> under_review -> approved | rejected | needs_more_info
+//   under_review -> submitted (claim released or expired, or first of two approvals)
+//   needs_more_info -> submitted
+//   approved -> reverification_required (document expired or periodic re-check)
+//
+// A case is one teacher_verifications row. The teacher fills a draft and
+// submits it; a reviewer claims it from the queue (services/kyc_queue.rs),
+// which puts it under review, and decides. Rejections and
+// requests for more information carry reasons from REASONS. A needs_more_info
+// decision names the items (ITEMS) the teacher has to resend; everything else
+// on the case is kept. Approved and rejected are final for the case, after a
+// rejection, or when re-verification is required (services/kyc_expiry.rs),
+// the teacher starts a new one.
+//
+// Every change goes through `transition`, which appends to kyc_status_history
+// and recomputes users.kyc_verified from the user's latest case in the same
+// transaction. Nothing else writes kyc_verified.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+
+use crate::services::{age, kyc_duplicates, kyc_queue, notify_prefs};
+
+pub const STATUSES: &[&str] = &["draft", "submitted", "under_review", "approved", "rejected", "needs_more_info", "reverification_required"];
+
+/// Outcomes a reviewer can pick.
+pub const DECISIONS: &[&str] = &["approved", "rejected", "needs_more_info"];
+
+/// Parts of a case that can be asked for again: the personal details form
+/// and the two sides of the ID.
+pub const ITEMS: &[&str] = &["details", "front_id", "back_id"];
+
+/// Accepted ID documents.
+pub const DOC_TYPES: &[&str] = &["passport", "national_id", "driving_licence", "residence_permit"];
+
+/// Key for `hash_document_number`: KYC_DOC_HASH_KEY, else SECRET_KEY. It must
+/// stay the same for hashes to keep matching.
+fn doc_hash_key() -> Option<String> {
+    ["KYC_DOC_HASH_KEY", "SECRET_KEY"].iter().filter_map(|k| std::env::var(k).ok()).map(|k| k.trim().to_string()).find(|k| !k.is_empty())
+}
+
+/// Keyed hash of a document number, so equal numbers can be matched without
+/// storing them. `Ok(None)` for a number without letters or digits. Without a
+/// key the hash could be brute-forced offline, so there is none.
+pub fn hash_document_number(doc_type: &str, number: &str) -> Result<Option<String>> {
+    let normalized: String = number.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_uppercase()).collect();
+    if normalized.is_empty() {
+        return Ok(None);
+    }
+    let key = doc_hash_key().ok_or_else(|| anyhow!("document numbers cannot be stored: KYC_DOC_HASH_KEY is not configured"))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes any key length");
+    mac.update(format!("{}:{}", doc_type, normalized).as_bytes());
+    Ok(Some(hex::encode(mac.finalize().into_bytes())))
+}
+
+pub struct Reason {
+    pub code: &'static str,
+    pub label: &'static str,
+    /// Items requested by default when this reason is used for needs_more_info
+    pub items: &'static [&'static str],
+}
+
+pub const REASONS: &[Reason] = &[
+    Reason { code: "document_unreadable", label: "ID photo is blurry, dark or unreadable", items: &["front_id", "back_id"] },
+    Reason { code: "document_cropped", label: "ID edges are cut off or covered", items: &["front_id", "back_id"] },
+    Reason { code: "document_expired", label: "ID document has expired", items: &["front_id", "back_id"] },
+    Reason { code: "document_unsupported", label: "This type of document is not accepted", items: &["front_id", "back_id"] },
+    Reason { code: "back_side_missing", label: "Back side is missing or shows the front again", items: &["back_id"] },
+    Reason { code: "name_mismatch", label: "Name does not match the document", items: &["details"] },
+    Reason { code: "dob_mismatch", label: "Date of birth does not match the document", items: &["details"] },
+    Reason { code: "address_incomplete", label: "Address is missing or incomplete", items: &["details"] },
+    Reason { code: "suspected_fraud", label: "Document appears altered or not genuine", items: &[] },
+    Reason { code: "other", label: "Other (see note)", items: &[] },
+];
+
+pub fn reason(code: &str) -> Option<&'static Reason> {
+    REASONS.iter().find(|r| r.code == code)
+}
+
+/// Reasons as `{code, label}` objects for API responses.
+pub fn reasons_json(codes: &[String]) -> serde_json::Value {
+    json!(codes
+        .iter()
+        .map(|c| json!({"code": c, "label": reason(c).map(|r| r.label).unwrap_or(c.as_str())}))
+        .collect::<Vec<_>>())
+}
+
+/// Allowed single-step status changes.
+pub fn can_transition(from: &str, to: &str) -> bool {
+    matches!(
+        (from, to),
+        ("draft", "submitted")
+            | ("submitted", "under_review")
+            | ("under_review", "approved")
+            | ("under_review", "rejected")
+            | ("under_review", "needs_more_info")
+            | ("under_review", "submitted")
+            | ("needs_more_info", "submitted")
+            | ("approved", "reverification_required")
+    )
+}
+
+/// Check a reviewer decision. Returns the items to request again, which is
+/// empty unless `status` is needs_more_info (then `items`, or the defaults of
+/// the given reasons).
+pub fn validate_decision(status: &str, reasons: &[String], items: &[String], note: Option<&str>) -> Result<Vec<String>> {
+    if !DECISIONS.contains(&status) {
+        return Err(anyhow!("invalid status"));
+    }
+    if status == "approved" {
+        if !reasons.is_empty() {
+            return Err(anyhow!("an approval takes no reasons"));
+        }
+        return Ok(Vec::new());
+    }
+    if reasons.is_empty() {
+        return Err(anyhow!("at least one reason is required"));
+    }
+    for code in reasons {
+        if reason(code).is_none() {
+            return Err(anyhow!("unknown reason: {}", code));
+        }
+    }
+    if reasons.iter().any(|r| r == "other") && note.map(|n| n.trim().is_empty()).unwrap_or(true) {
+        return Err(anyhow!("reason \"other\" needs a note"));
+    }
+    if status == "rejected" {
+        return Ok(Vec::new());
+    }
+    if reasons.iter().any(|r| r == "suspected_fraud") {
+        return Err(anyhow!("suspected fraud cannot be resubmitted, reject instead"));
+    }
+    let mut requested: Vec<String> = Vec::new();
+    let wanted: Vec<&str> = if items.is_empty() {
+        reasons.iter().filter_map(|c| reason(c)).flat_map(|r| r.items.iter().copied()).collect()
+    } else {
+        items.iter().map(|s| s.as_str()).collect()
+    };
+    for item in wanted {
+        if !ITEMS.contains(&item) {
+            return Err(anyhow!("unknown item: {}", item));
+        }
+        if !requested.iter().any(|r| r == item) {
+            requested.push(item.to_string());
+        }
+    }
+    if requested.is_empty() {
+        return Err(anyhow!("choose the items the teacher has to resend"));
+    }
+    Ok(requested)
+}
+
+pub struct Case {
+    pub id: i32,
+    pub user_id: i32,
+    pub status: String,
+    pub full_name: Option<String>,
+    pub dob: Option<NaiveDate>,
+    pub gender: Option<String>,
+    pub address: Option<String>,
+    pub doc_type: Option<String>,
+    pub doc_number_hash: Option<String>,
+    pub doc_expires_on: Option<NaiveDate>,
+    pub front_id_path: Option<String>,
+    pub back_id_path: Option<String>,
+    pub rejection_reasons: Vec<String>,
+    pub requested_items: Vec<String>,
+    pub resent_items: Vec<String>,
+    pub admin_note: Option<String>,
+    pub submitted_at: Option<DateTime<Utc>>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub submission_count: i32,
+    pub claimed_by: Option<i32>,
+    pub claim_expires_at: Option<DateTime<Utc>>,
+    pub risk_flags: Vec<String>,
+    pub first_approved_by: Option<i32>,
+}
+
+const CASE_SELECT: &str = "SELECT id, user_id, status, full_name, dob, gender, address, doc_type, doc_number_hash, doc_expires_on, front_id_path, back_id_path,
+        rejection_reasons, requested_items, resent_items, admin_note, submitted_at, decided_at,
+        submission_count, claimed_by, claim_expires_at, risk_flags, first_approved_by
+    FROM teacher_verifications";
+
+fn case_from_row(r: &sqlx::postgres::PgRow) -> Case {
+    Case {
+        id: r.get("id"),
+        user_id: r.get("user_id"),
+        status: r.get("status"),
+        full_name: r.get("full_name"),
+        dob: r.get("dob"),
+        gender: r.get("gender"),
+        address: r.get("address"),
+        doc_type: r.get("doc_type"),
+        doc_number_hash: r.get("doc_number_hash"),
+        doc_expires_on: r.get("doc_expires_on"),
+        front_id_path: r.get("front_id_path"),
+        back_id_path: r.get("back_id_path"),
+        rejection_reasons: r.get("rejection_reasons"),
+        requested_items: r.get("requested_items"),
+        resent_items: r.get("resent_items"),
+        admin_note: r.get("admin_note"),
+        submitted_at: r.get("submitted_at"),
+        decided_at: r.get("decided_at"),
+        submission_count: r.get("submission_count"),
+        claimed_by: r.get("claimed_by"),
+        claim_expires_at: r.get("claim_expires_at"),
+        risk_flags: r.get("risk_flags"),
+        first_approved_by: r.get("first_approved_by"),
+    }
+}
+
+pub async fn case(pool: &PgPool, id: i32) -> sqlx::Result<Option<Case>> {
+    let row = sqlx::query(&format!("{} WHERE id = $1", CASE_SELECT)).bind(id).fetch_optional(pool).await?;
+    Ok(row.as_ref().map(case_from_row))
+}
+
+/// Case `id`, locked for the rest of the transaction.
+pub async fn case_for_update(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Option<Case>> {
+    let row = sqlx::query(&format!("{} WHERE id = $1 FOR UPDATE", CASE_SELECT)).bind(id).fetch_optional(&mut **tx).await?;
+    Ok(row.as_ref().map(case_from_row))
+}
+
+/// The user's most recent case, whatever its status.
+pub async fn latest_case(pool: &PgPool, user_id: i32) -> sqlx::Result<Option<Case>> {
+    let row = sqlx::query(&format!("{} WHERE user_id = $1 ORDER BY id DESC LIMIT 1", CASE_SELECT))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(case_from_row))
+}
+
+impl Case {
+    /// Whether the teacher may change the case.
+    pub fn editable(&self) -> bool {
+        self.status == "draft" || self.status == "needs_more_info"
+    }
+
+    /// Items the teacher may change: everything on a draft, only the
+    /// requested ones after needs_more_info.
+    pub fn may_edit(&self, item: &str) -> bool {
+        match self.status.as_str() {
+            "draft" => true,
+            "needs_more_info" => self.requested_items.iter().any(|i| i == item),
+            _ => false,
+        }
+    }
+
+    fn has_item(&self, item: &str) -> bool {
+        let filled = |s: &Option<String>| s.as_deref().map(|v| !v.trim().is_empty()).unwrap_or(false);
+        match item {
+            "details" => {
+                filled(&self.full_name)
+                    && self.dob.is_some()
+                    && filled(&self.gender)
+                    && filled(&self.address)
+                    && filled(&self.doc_type)
+                    && filled(&self.doc_number_hash)
+                    && self.doc_expires_on.is_some()
+            }
+            "front_id" => filled(&self.front_id_path),
+            "back_id" => filled(&self.back_id_path),
+            _ => false,
+        }
+    }
+
+    /// What still has to be provided before the case can be submitted.
+    pub fn missing_items(&self) -> Vec<&'static str> {
+        ITEMS
+            .iter()
+            .copied()
+            .filter(|item| match self.status.as_str() {
+                "needs_more_info" => self.requested_items.iter().any(|i| i == item) && !self.resent_items.iter().any(|i| i == item),
+                _ => !self.has_item(item),
+            })
+            .collect()
+    }
+}
+
+/// Start an empty draft for `user_id`.
+pub async fn create_draft(pool: &PgPool, user_id: i32) -> sqlx::Result<Case> {
+    let mut tx = pool.begin().await?;
+    let id: i32 = sqlx::query("INSERT INTO teacher_verifications (user_id, status, created_at, updated_at) VALUES ($1, 'draft', now(), now()) RETURNING id")
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("id");
+    record(&mut tx, id, None, "draft", Some(user_id), &Change::default()).await?;
+    tx.commit().await?;
+    Ok(case(pool, id).await?.expect("draft just created"))
+}
+
+/// New values for a case; `None` leaves a field as it is.
+#[derive(Default)]
+pub struct Update {
+    pub full_name: Option<String>,
+    pub dob: Option<NaiveDate>,
+    pub gender: Option<String>,
+    pub address: Option<String>,
+    pub doc_type: Option<String>,
+    /// See `hash_document_number`
+    pub doc_number_hash: Option<String>,
+    pub doc_expires_on: Option<NaiveDate>,
+    pub front_id_path: Option<String>,
+    pub back_id_path: Option<String>,
+}
+
+impl Update {
+    /// Items touched by this update.
+    pub fn items(&self) -> Vec<&'static str> {
+        let mut items = Vec::new();
+        if self.full_name.is_some()
+            || self.dob.is_some()
+            || self.gender.is_some()
+            || self.address.is_some()
+            || self.doc_type.is_some()
+            || self.doc_number_hash.is_some()
+            || self.doc_expires_on.is_some()
+        {
+            items.push("details");
+        }
+        if self.front_id_path.is_some() {
+            items.push("front_id");
+        }
+        if self.back_id_path.is_some() {
+            items.push("back_id");
+        }
+        items
+    }
+}
+
+/// Store teacher changes on an editable case. Returns the file paths that
+/// were replaced so the caller can remove them.
+pub async fn save(pool: &PgPool, c: &Case, u: &Update) -> Result<Vec<String>> {
+    if !c.editable() {
+        return Err(anyhow!("verification is {} and cannot be changed", c.status));
+    }
+    let items = u.items();
+    if items.iter().any(|i| !c.may_edit(i)) {
+        return Err(anyhow!("only {} can be resent", c.requested_items.join(", ")));
+    }
+    if u.doc_type.as_deref().map(|t| !DOC_TYPES.contains(&t)).unwrap_or(false) {
+        return Err(anyhow!("document type must be one of: {}", DOC_TYPES.join(", ")));
+    }
+    let resent: Vec<String> = if c.status == "needs_more_info" { items.iter().map(|s| s.to_string()).collect() } else { Vec::new() };
+    let res = sqlx::query(
+        "UPDATE teacher_verifications SET
+             full_name = COALESCE($3, full_name), dob = COALESCE($4, dob), gender = COALESCE($5, gender), address = COALESCE($6, address),
+             front_id_path = COALESCE($7, front_id_path), back_id_path = COALESCE($8, back_id_path),
+             id_path = COALESCE($7, id_path),
+             doc_type = COALESCE($10, doc_type), doc_number_hash = COALESCE($11, doc_number_hash), doc_expires_on = COALESCE($12, doc_expires_on),
+             resent_items = ARRAY(SELECT DISTINCT unnest(resent_items || $9::TEXT[])),
+             updated_at = now()
+         WHERE id = $1 AND status = $2",
+    )
+    .bind(c.id)
+    .bind(&c.status)
+    .bind(&u.full_name)
+    .bind(u.dob)
+    .bind(&u.gender)
+    .bind(&u.address)
+    .bind(&u.front_id_path)
+    .bind(&u.back_id_path)
+    .bind(&resent)
+    .bind(&u.doc_type)
+    .bind(&u.doc_number_hash)
+    .bind(u.doc_expires_on)
+    .execute(pool)
+    .await?;
+    if res.rows_affected() != 1 {
+        return Err(anyhow!("verification changed, reload and try again"));
+    }
+    let mut replaced = Vec::new();
+    if u.front_id_path.is_some() {
+        replaced.extend(c.front_id_path.clone());
+    }
+    if u.back_id_path.is_some() {
+        replaced.extend(c.back_id_path.clone());
+    }
+    Ok(replaced)
+}
+
+/// Send a complete draft, or the requested items, for review.
+pub async fn submit(pool: &PgPool, c: &Case) -> Result<()> {
+    let missing = c.missing_items();
+    if !missing.is_empty() {
+        return Err(anyhow!("missing: {}", missing.join(", ")));
+    }
+    if c.dob.map(|d| !age::is_adult(d)).unwrap_or(false) {
+        return Err(anyhow!("teachers must be at least {} years old", age::adult_age()));
+    }
+    if c.doc_expires_on.map(|d| d < Utc::now().date_naive()).unwrap_or(false) {
+        return Err(anyhow!("the ID document has expired"));
+    }
+    let items: Vec<String> = if c.status == "needs_more_info" { c.resent_items.clone() } else { Vec::new() };
+    kyc_duplicates::fingerprint(pool, c).await?;
+    let mut tx = pool.begin().await?;
+    if !transition(&mut tx, c.id, &c.status, "submitted", Some(c.user_id), &Change { items: &items, ..Default::default() }).await? {
+        return Err(anyhow!("verification is no longer {}", c.status));
+    }
+    let flags = kyc_queue::risk_flags(&mut tx, c.id).await?;
+    sqlx::query("UPDATE teacher_verifications SET risk_flags = $2 WHERE id = $1").bind(c.id).bind(&flags).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// What comes with a status change: decision reasons, requested or resent
+/// items, and the reviewer's note.
+#[derive(Default)]
+pub struct Change<'a> {
+    pub reasons: &'a [String],
+    pub items: &'a [String],
+    pub note: Option<&'a str>,
+}
+
+/// Move case `id` from `from` to `to`, record it and re-derive the owner's
+/// kyc_verified. False if the case is not in `from`.
+pub async fn transition(tx: &mut Transaction<'_, Postgres>, id: i32, from: &str, to: &str, actor_id: Option<i32>, change: &Change<'_>) -> sqlx::Result<bool> {
+    if !can_transition(from, to) {
+        return Ok(false);
+    }
+    let requested: &[String] = if to == "needs_more_info" { change.items } else { &[] };
+    let row = sqlx::query(
+        "UPDATE teacher_verifications SET status = $3, updated_at = now(),
+             submitted_at = CASE WHEN $3 = 'submitted' THEN now() ELSE submitted_at END,
+             submission_count = submission_count + CASE WHEN $3 = 'submitted' THEN 1 ELSE 0 END,
+             review_started_at = CASE WHEN $3 = 'under_review' THEN now() ELSE review_started_at END,
+             decided_at = CASE WHEN $3 IN ('approved', 'rejected', 'needs_more_info') THEN now() ELSE decided_at END,
+             reviewed_by = CASE WHEN $3 IN ('under_review', 'approved', 'rejected', 'needs_more_info') THEN $4 ELSE reviewed_by END,
+             rejection_reasons = CASE WHEN $3 IN ('approved', 'rejected', 'needs_more_info') THEN $5 ELSE rejection_reasons END,
+             requested_items = CASE WHEN $3 IN ('approved', 'rejected', 'needs_more_info') THEN $6 ELSE requested_items END,
+             resent_items = CASE WHEN $3 = 'needs_more_info' THEN '{}' ELSE resent_items END,
+             admin_note = CASE WHEN $3 IN ('approved', 'rejected', 'needs_more_info') THEN COALESCE($7, '') ELSE admin_note END,
+             claimed_by = CASE WHEN $3 = 'under_review' THEN $4 END,
+             claim_expires_at = CASE WHEN $3 = 'under_review' THEN now() + $8 * interval '1 minute' END,
+             first_approved_by = CASE WHEN $3 IN ('rejected', 'needs_more_info') THEN NULL ELSE first_approved_by END,
+             first_approved_at = CASE WHEN $3 IN ('rejected', 'needs_more_info') THEN NULL ELSE first_approved_at END
+         WHERE id = $1 AND status = $2
+         RETURNING user_id",
+    )
+    .bind(id)
+    .bind(from)
+    .bind(to)
+    .bind(actor_id)
+    .bind(change.reasons)
+    .bind(requested)
+    .bind(change.note)
+    .bind(kyc_queue::claim_minutes() as f64)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let user_id: i32 = match row {
+        Some(r) => r.get("user_id"),
+        None => return Ok(false),
+    };
+    record(tx, id, Some(from), to, actor_id, change).await?;
+    sync_verified(tx, user_id).await?;
+    if to == "approved" {
+        // the checked date of birth becomes the account's birthday
+        sqlx::query(
+            "UPDATE users u SET birthday = tv.dob, birthday_verified = TRUE,
+                 is_minor = tv.dob > CURRENT_DATE - make_interval(years => $2)
+             FROM teacher_verifications tv WHERE tv.id = $1 AND u.id = tv.user_id AND tv.dob IS NOT NULL",
+        )
+        .bind(id)
+        .bind(age::adult_age())
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(true)
+}
+
+async fn record(tx: &mut Transaction<'_, Postgres>, id: i32, from: Option<&str>, to: &str, actor_id: Option<i32>, change: &Change<'_>) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO kyc_status_history (verification_id, from_status, to_status, actor_id, reasons, items, note) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(id)
+    .bind(from)
+    .bind(to)
+    .bind(actor_id)
+    .bind(change.reasons)
+    .bind(change.items)
+    .bind(change.note.filter(|n| !n.trim().is_empty()))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// kyc_verified is true while the user's latest decided case is approved, so
+/// a renewal case in progress keeps the teacher verified until it is decided.
+async fn sync_verified(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE users u SET
+             kyc_verified = COALESCE(l.status = 'approved', FALSE),
+             kyc_verified_at = CASE WHEN l.status = 'approved' THEN l.decided_at END
+         FROM (SELECT $1::INTEGER AS user_id) me
+         LEFT JOIN LATERAL (SELECT status, decided_at FROM teacher_verifications
+                            WHERE user_id = $1 AND status NOT IN ('draft', 'submitted', 'under_review', 'needs_more_info')
+                            ORDER BY id DESC LIMIT 1) l ON TRUE
+         WHERE u.id = me.user_id",
+    )
+    .bind(user_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub async fn notify_decision(pool: &PgPool, user_id: i32, reviewer_id: i32, status: &str, reasons: &[String], requested: &[String], note: Option<&str>) {
+    let labels: Vec<&str> = reasons.iter().map(|c| reason(c).map(|r| r.label).unwrap_or(c.as_str())).collect();
+    let (title, priority, mut body) = match status {
+        "approved" => ("Your teacher verification was approved", "normal", "You can now teach on the platform.".to_string()),
+        "rejected" => ("Your teacher verification was rejected", "high", format!("Reasons: {}.", labels.join("; "))),
+        _ => (
+            "More information needed for your teacher verification",
+            "high",
+            format!("Please resend: {}. Reasons: {}.", requested.join(", "), labels.join("; ")),
+        ),
+    };
+    if let Some(n) = note.map(str::trim).filter(|n| !n.is_empty()) {
+        body.push_str(&format!("\n\nReviewer note: {}", n));
+    }
+    if let Err(e) = notify_prefs::notify(pool, user_id, Some(reviewer_id), "kyc", priority, title, &body).await {
+        eprintln!("KYC decision notification for user {} failed: {:?}", user_id, e);
+    }
+}
+
+/// Status changes of a case, oldest first.
+pub async fn history(pool: &PgPool, id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(
+        "SELECT h.from_status, h.to_status, h.actor_id, u.full_name AS actor_name, h.reasons, h.items, h.note, h.created_at
+         FROM kyc_status_history h LEFT JOIN users u ON u.id = h.actor_id
+         WHERE h.verification_id = $1 ORDER BY h.id",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let reasons: Vec<String> = r.get("reasons");
+            json!({
+                "from": r.get::<Option<String>,_>("from_status"),
+                "to": r.get::<String,_>("to_status"),
+                "actor_id": r.get::<Option<i32>,_>("actor_id"),
+                "actor_name": r.get::<Option<String>,_>("actor_name"),
+                "reasons": reasons_json(&reasons),
+                "items": r.get::<Vec<String>,_>("items"),
+                "note": r.get::<Option<String>,_>("note"),
+                "at": r.get::<DateTime<Utc>,_>("created_at").to_rfc3339(),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,275 @@
+// Duplicate identity detection across teacher KYC cases.
+//
+// When a case is submitted it gets a fingerprint: a 64-bit difference hash
+// (dHash) of each ID image and normalized name and address keys. It is then
+// compared with the cases of every other user:
+//
+//   same_document         an ID image within KYC_PHASH_DISTANCE bits
+//                         (default 6) of one of theirs, either side
+//   same_document_number  same document type and number (compared hashed)
+//   same_name_dob         same normalized name and date of birth
+//   same_address          same normalized address (shown, but not a risk on
+//                         its own)
+//
+// Matches are kept in kyc_duplicate_matches for the reviewer list and turn
+// into risk flags (see kyc_queue::risk_flags), so the case needs a second
+// approver. Reusing a document from a rejected case adds
+// reused_rejected_document. Open cases on the other side of a match get the
+// flag too. Cases submitted before this existed are fingerprinted once by
+// `spawn_background`.
+
+use image::imageops::FilterType;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+
+use crate::services::kyc::{self, Case};
+
+pub fn max_distance() -> i32 {
+    std::env::var("KYC_PHASH_DISTANCE").ok().and_then(|s| s.parse().ok()).unwrap_or(6)
+}
+
+/// dHash of the image at `path`: each bit says whether a pixel of the 9x8
+/// grayscale thumbnail is darker than its right neighbour. None if the file
+/// cannot be decoded.
+fn dhash(path: &str) -> Option<i64> {
+    let img = image::ImageReader::open(path).ok()?.with_guessed_format().ok()?.decode().ok()?;
+    let small = img.grayscale().resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Some(hash as i64)
+}
+
+async fn hash_file(path: Option<String>) -> Option<i64> {
+    let path = path.filter(|p| !p.trim().is_empty())?;
+    let shown = path.clone();
+    match tokio::task::spawn_blocking(move || dhash(&path)).await {
+        Ok(Some(h)) => Some(h),
+        _ => {
+            eprintln!("KYC image could not be hashed: {}", shown);
+            None
+        }
+    }
+}
+
+/// Lowercase, strip accents from common Latin letters and turn everything
+/// that is not a letter or digit into a space.
+fn fold(s: &str) -> String {
+    s.chars()
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+            'ç' | 'ć' | 'č' => 'c',
+            'ď' | 'đ' => 'd',
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => 'i',
+            'ł' | 'ľ' => 'l',
+            'ñ' | 'ń' | 'ň' => 'n',
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+            'ř' => 'r',
+            'ś' | 'š' | 'ş' => 's',
+            'ť' | 'ţ' => 't',
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'ź' | 'ż' | 'ž' => 'z',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect()
+}
+
+/// Name words in a fixed order, so "Doe, John" and "john doe" match.
+pub fn name_key(name: &str) -> Option<String> {
+    let folded = fold(name);
+    let mut words: Vec<&str> = folded.split_whitespace().collect();
+    words.sort_unstable();
+    Some(words.join(" ")).filter(|k| !k.is_empty())
+}
+
+/// Address words with the usual abbreviations spelled one way.
+pub fn address_key(address: &str) -> Option<String> {
+    let folded = fold(address);
+    let words: Vec<&str> = folded
+        .split_whitespace()
+        .map(|w| match w {
+            "street" | "str" => "st",
+            "avenue" | "av" => "ave",
+            "road" => "rd",
+            "boulevard" => "blvd",
+            "drive" => "dr",
+            "lane" => "ln",
+            "court" => "ct",
+            "place" => "pl",
+            "square" => "sq",
+            "apartment" | "flat" | "unit" => "apt",
+            "suite" => "ste",
+            "floor" => "fl",
+            "north" => "n",
+            "south" => "s",
+            "east" => "e",
+            "west" => "w",
+            other => other,
+        })
+        .collect();
+    Some(words.join(" ")).filter(|k| !k.is_empty())
+}
+
+/// Hash the ID images of case `c` and store its matching keys.
+pub async fn fingerprint(pool: &PgPool, c: &Case) -> sqlx::Result<()> {
+    let front = hash_file(c.front_id_path.clone()).await;
+    let back = hash_file(c.back_id_path.clone()).await;
+    sqlx::query(
+        "UPDATE teacher_verifications SET front_phash = $2, back_phash = $3, name_key = $4, address_key = $5, fingerprinted_at = now()
+         WHERE id = $1",
+    )
+    .bind(c.id)
+    .bind(front)
+    .bind(back)
+    .bind(c.full_name.as_deref().and_then(name_key))
+    .bind(c.address.as_deref().and_then(address_key))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Another user's case that looks like the same person or document.
+pub struct Match {
+    pub id: i32,
+    pub status: String,
+    pub signals: Vec<String>,
+    pub distance: Option<i32>,
+}
+
+impl Match {
+    fn has(&self, signal: &str) -> bool {
+        self.signals.iter().any(|s| s == signal)
+    }
+}
+
+/// Compare fingerprinted case `id` with other users' cases and store the
+/// matches, replacing earlier ones.
+pub async fn record_matches(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Vec<Match>> {
+    let rows = sqlx::query(
+        "SELECT * FROM (
+             SELECT o.id, o.status,
+                 LEAST(phash_distance(o.front_phash, c.front_phash), phash_distance(o.front_phash, c.back_phash),
+                       phash_distance(o.back_phash, c.front_phash), phash_distance(o.back_phash, c.back_phash)) AS distance,
+                 COALESCE(o.doc_number_hash = c.doc_number_hash, FALSE) AS same_number,
+                 COALESCE(o.name_key = c.name_key AND o.dob = c.dob, FALSE) AS same_name_dob,
+                 COALESCE(o.address_key = c.address_key, FALSE) AS same_address
+             FROM teacher_verifications c
+             JOIN teacher_verifications o ON o.user_id <> c.user_id AND o.status <> 'draft' AND o.fingerprinted_at IS NOT NULL
+             WHERE c.id = $1
+         ) m
+         WHERE m.distance <= $2 OR m.same_number OR m.same_name_dob OR m.same_address
+         ORDER BY m.id",
+    )
+    .bind(id)
+    .bind(max_distance())
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let matches: Vec<Match> = rows
+        .iter()
+        .map(|r| {
+            let distance: Option<i32> = r.get("distance");
+            let mut signals = Vec::new();
+            if distance.map(|d| d <= max_distance()).unwrap_or(false) {
+                signals.push("same_document".to_string());
+            }
+            if r.get::<bool, _>("same_number") {
+                signals.push("same_document_number".to_string());
+            }
+            if r.get::<bool, _>("same_name_dob") {
+                signals.push("same_name_dob".to_string());
+            }
+            if r.get::<bool, _>("same_address") {
+                signals.push("same_address".to_string());
+            }
+            Match { id: r.get("id"), status: r.get("status"), signals, distance }
+        })
+        .collect();
+
+    sqlx::query("DELETE FROM kyc_duplicate_matches WHERE verification_id = $1 OR match_id = $1").bind(id).execute(&mut **tx).await?;
+    for m in &matches {
+        sqlx::query(
+            "INSERT INTO kyc_duplicate_matches (verification_id, match_id, signals, distance)
+             VALUES ($1, $2, $3, $4), ($2, $1, $3, $4)
+             ON CONFLICT (verification_id, match_id) DO UPDATE SET signals = EXCLUDED.signals, distance = EXCLUDED.distance",
+        )
+        .bind(id)
+        .bind(m.id)
+        .bind(&m.signals)
+        .bind(m.distance)
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(matches)
+}
+
+/// Risk flags from duplicate matches of case `id`, which is being
+/// submitted. Open cases on the other side are flagged as well.
+pub async fn risk_flags(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Vec<String>> {
+    let matches = record_matches(tx, id).await?;
+    let mut flags = Vec::new();
+    for m in &matches {
+        let mut theirs: Vec<String> = Vec::new();
+        if m.has("same_document") || m.has("same_document_number") {
+            theirs.push("duplicate_document".to_string());
+            if m.status == "rejected" {
+                flags.push("reused_rejected_document".to_string());
+            }
+        }
+        if m.has("same_name_dob") {
+            theirs.push("duplicate_identity".to_string());
+        }
+        if !theirs.is_empty() {
+            sqlx::query(
+                "UPDATE teacher_verifications SET risk_flags = ARRAY(SELECT DISTINCT unnest(risk_flags || $2::TEXT[]))
+                 WHERE id = $1 AND status IN ('submitted', 'under_review')",
+            )
+            .bind(m.id)
+            .bind(&theirs)
+            .execute(&mut **tx)
+            .await?;
+        }
+        flags.extend(theirs);
+    }
+    flags.sort();
+    flags.dedup();
+    Ok(flags)
+}
+
+/// Fingerprint and match submitted cases that predate duplicate detection.
+async fn backfill(pool: &PgPool) -> sqlx::Result<usize> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM teacher_verifications WHERE fingerprinted_at IS NULL AND status <> 'draft' ORDER BY id")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    for id in &ids {
+        if let Some(c) = kyc::case(pool, *id).await? {
+            fingerprint(pool, &c).await?;
+            let mut tx = pool.begin().await?;
+            record_matches(&mut tx, *id).await?;
+            tx.commit().await?;
+        }
+    }
+    Ok(ids.len())
+}
+
+pub fn spawn_background(pool: PgPool) {
+    tokio::spawn(async move {
+        match backfill(&pool).await {
+            Ok(0) => {}
+            Ok(n) => eprintln!("Fingerprinted {} KYC case(s) for duplicate detection", n),
+            Err(e) => eprintln!("KYC fingerprint backfill failed: {:?}", e),
+        }
+    });
+}
@@ -0,0 +1,186 @@
+// Expiry of approved teacher verifications.
+//
+// An approved case is due for re-verification on the expiry date of its ID
+// document, or KYC_REVERIFY_MONTHS after approval when that is set (off by
+// default), whichever comes first. Teachers get a `kyc` notification when the
+// due date is KYC_EXPIRY_WARN_DAYS away (default "30,7,1", one warning per
+// threshold). The day after it, the case moves to reverification_required,
+// which clears users.kyc_verified; anything that needs a verified teacher,
+// such as their service listings, is hidden until a new case is approved.
+// From the first warning on, teachers may open a renewal case; they stay
+// verified while it is reviewed, and once it is approved it replaces the old
+// case.
+// Runs every KYC_EXPIRY_INTERVAL_SECONDS (default 3600).
+
+use chrono::NaiveDate;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+
+use crate::services::kyc::{self, Change};
+use crate::services::notify_prefs;
+
+/// Warning thresholds in days before the due date, largest first.
+pub fn warn_days() -> Vec<i32> {
+    let mut days: Vec<i32> = std::env::var("KYC_EXPIRY_WARN_DAYS")
+        .unwrap_or_else(|_| "30,7,1".to_string())
+        .split(',')
+        .filter_map(|s| s.trim().parse().ok())
+        .filter(|d| *d > 0)
+        .collect();
+    days.sort_unstable_by(|a, b| b.cmp(a));
+    days.dedup();
+    days
+}
+
+pub fn reverify_months() -> i32 {
+    std::env::var("KYC_REVERIFY_MONTHS").ok().and_then(|s| s.parse().ok()).filter(|m: &i32| *m > 0).unwrap_or(0)
+}
+
+// due date of an approved case, $1 = KYC_REVERIFY_MONTHS
+const DUE_ON: &str = "LEAST(doc_expires_on, CASE WHEN $1 > 0 THEN (decided_at + $1 * interval '1 month')::DATE END)";
+
+// an approved case replaced by a renewal that was approved after it
+const SUPERSEDED: &str = "EXISTS (SELECT 1 FROM teacher_verifications n WHERE n.user_id = teacher_verifications.user_id AND n.id > teacher_verifications.id AND n.status = 'approved')";
+
+/// When the user's approved case is due for re-verification, if ever.
+pub async fn due_on(pool: &PgPool, user_id: i32) -> sqlx::Result<Option<NaiveDate>> {
+    let row = sqlx::query(&format!(
+        "SELECT {} AS due_on FROM teacher_verifications WHERE user_id = $2 AND status = 'approved' ORDER BY id DESC LIMIT 1",
+        DUE_ON
+    ))
+    .bind(reverify_months())
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|r| r.get("due_on")))
+}
+
+/// Whether the teacher may open a renewal case next to their approved one:
+/// its due date is within the first warning (KYC_EXPIRY_WARN_DAYS).
+pub async fn renewal_open(pool: &PgPool, user_id: i32) -> sqlx::Result<bool> {
+    let window = warn_days().first().copied().unwrap_or(30) as i64;
+    let today = chrono::Utc::now().date_naive();
+    Ok(due_on(pool, user_id).await?.is_some_and(|due| due <= today + chrono::Duration::days(window)))
+}
+
+/// Move approved cases past their due date to reverification_required.
+/// Returns how many were moved.
+pub async fn expire_due(pool: &PgPool) -> sqlx::Result<usize> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query(&format!(
+        "SELECT id, user_id, doc_expires_on, {} AS due_on FROM teacher_verifications
+         WHERE status = 'approved' AND {} < CURRENT_DATE AND NOT {}
+         FOR UPDATE SKIP LOCKED",
+        DUE_ON, DUE_ON, SUPERSEDED
+    ))
+    .bind(reverify_months())
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut moved: Vec<(i32, String)> = Vec::new();
+    for r in &rows {
+        let due: NaiveDate = r.get("due_on");
+        let note = if r.get::<Option<NaiveDate>, _>("doc_expires_on") == Some(due) {
+            format!("ID document expired on {}", due)
+        } else {
+            format!("periodic re-verification due on {}", due)
+        };
+        let change = Change { note: Some(&note), ..Default::default() };
+        if kyc::transition(&mut tx, r.get("id"), "approved", "reverification_required", None, &change).await? {
+            moved.push((r.get("user_id"), note));
+        }
+    }
+    tx.commit().await?;
+
+    for (user_id, note) in &moved {
+        let body = format!(
+            "Your teacher verification has lapsed ({}). Your listings are hidden until you verify again: open KYC on your dashboard and submit a current ID.",
+            note
+        );
+        if let Err(e) = notify_prefs::notify(pool, *user_id, None, "kyc", "high", "Please verify your identity again", &body).await {
+            eprintln!("KYC re-verification notification for user {} failed: {:?}", user_id, e);
+        }
+    }
+    Ok(moved.len())
+}
+
+/// Warn teachers whose due date is coming up. Returns how many were warned.
+pub async fn warn_upcoming(pool: &PgPool) -> sqlx::Result<usize> {
+    let thresholds = warn_days();
+    let furthest = match thresholds.first() {
+        Some(d) => *d,
+        None => return Ok(0),
+    };
+    let rows = sqlx::query(&format!(
+        "SELECT id, user_id, expiry_warned_days, due_on, due_on - CURRENT_DATE AS days_left FROM (
+             SELECT id, user_id, expiry_warned_days, {} AS due_on FROM teacher_verifications WHERE status = 'approved' AND NOT {}
+         ) c
+         WHERE due_on >= CURRENT_DATE AND due_on <= CURRENT_DATE + $2",
+        DUE_ON, SUPERSEDED
+    ))
+    .bind(reverify_months())
+    .bind(furthest)
+    .fetch_all(pool)
+    .await?;
+
+    let mut warned = 0;
+    for r in &rows {
+        let days_left: i32 = r.get("days_left");
+        // the tightest threshold reached, warned once
+        let threshold = match thresholds.iter().copied().filter(|t| days_left <= *t).min() {
+            Some(t) => t,
+            None => continue,
+        };
+        if r.get::<Option<i32>, _>("expiry_warned_days").map(|w| w <= threshold).unwrap_or(false) {
+            continue;
+        }
+        // claim the warning first so a second worker does not send it again
+        let claimed = sqlx::query(
+            "UPDATE teacher_verifications SET expiry_warned_days = $2
+             WHERE id = $1 AND status = 'approved' AND (expiry_warned_days IS NULL OR expiry_warned_days > $2)",
+        )
+        .bind(r.get::<i32, _>("id"))
+        .bind(threshold)
+        .execute(pool)
+        .await?;
+        if claimed.rows_affected() != 1 {
+            continue;
+        }
+        let due: NaiveDate = r.get("due_on");
+        let user_id: i32 = r.get("user_id");
+        let when = match days_left {
+            0 => "today".to_string(),
+            1 => "tomorrow".to_string(),
+            n => format!("in {} days", n),
+        };
+        let body = format!(
+            "Your teacher verification is due for renewal {} ({}). Submit a current ID before then to keep your listings visible.",
+            when, due
+        );
+        let priority = if days_left <= 7 { "high" } else { "normal" };
+        if let Err(e) = notify_prefs::notify(pool, user_id, None, "kyc", priority, "Your verification expires soon", &body).await {
+            eprintln!("KYC expiry warning for user {} failed: {:?}", user_id, e);
+        }
+        warned += 1;
+    }
+    Ok(warned)
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("KYC_EXPIRY_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(3600).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match warn_upcoming(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Sent {} KYC expiry warning(s)", n),
+                Err(e) => eprintln!("KYC expiry warnings failed: {:?}", e),
+            }
+            match expire_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("{} teacher verification(s) need re-verification", n),
+                Err(e) => eprintln!("KYC expiry failed: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,333 @@
+// KYC review queue: claims, timeouts and four-eyes approval.
+//
+// A reviewer claims a submitted case (the oldest one via `next`, or a given
+// one), which puts it under review and locks it to them for
+// KYC_CLAIM_MINUTES (default 30). Only the claim holder can decide it. Claims
+// that run out are handed back to the queue by a background job every
+// KYC_QUEUE_INTERVAL_SECONDS (default 60), or by the next reviewer who needs
+// the case.
+//
+// Cases with risk flags (set at submission, or by a reviewer) need two
+// approvals from different reviewers: the first approval puts the case back
+// in the queue, ahead of fresh submissions, for someone else. A rejection or
+// request for more information takes effect right away. KYC_FOUR_EYES=0 turns
+// the second approval off.
+
+use chrono::Utc;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use std::time::Duration;
+
+use crate::services::kyc::{self, Case, Change};
+use crate::services::kyc_duplicates;
+
+/// Flag a reviewer can set by hand.
+pub const MANUAL_FLAG: &str = "manual";
+
+pub fn claim_minutes() -> i64 {
+    std::env::var("KYC_CLAIM_MINUTES").ok().and_then(|s| s.parse().ok()).filter(|m: &i64| *m > 0).unwrap_or(30)
+}
+
+pub fn four_eyes() -> bool {
+    !matches!(std::env::var("KYC_FOUR_EYES").as_deref(), Ok("0") | Ok("false") | Ok("off"))
+}
+
+/// Why a queue action was refused.
+pub enum Refused {
+    NotFound,
+    Conflict(String),
+}
+
+pub enum Decided {
+    /// The decision is final
+    Applied,
+    /// First approval of a high-risk case, a second reviewer has to approve too
+    FirstApproval,
+}
+
+/// Risk flags for case `id`, looked at when it is submitted.
+pub async fn risk_flags(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Vec<String>> {
+    let row = sqlx::query(
+        "SELECT tv.submission_count, tv.full_name, u.full_name AS account_name, tv.dob, u.birthday,
+             (SELECT COUNT(*) FROM teacher_verifications o WHERE o.user_id = tv.user_id AND o.id <> tv.id AND o.status = 'rejected') AS rejected_before,
+             EXISTS (SELECT 1 FROM teacher_verifications o WHERE o.user_id = tv.user_id AND o.id <> tv.id
+                     AND 'suspected_fraud' = ANY(o.rejection_reasons)) AS fraud_before,
+             $2 = ANY(tv.risk_flags) AS manual
+         FROM teacher_verifications tv JOIN users u ON u.id = tv.user_id
+         WHERE tv.id = $1",
+    )
+    .bind(id)
+    .bind(MANUAL_FLAG)
+    .fetch_one(&mut **tx)
+    .await?;
+    let mut flags = Vec::new();
+    if row.get::<bool, _>("fraud_before") {
+        flags.push("previous_fraud".to_string());
+    }
+    if row.get::<i64, _>("rejected_before") >= 2 {
+        flags.push("repeated_rejections".to_string());
+    }
+    if row.get::<i32, _>("submission_count") >= 3 {
+        flags.push("many_resubmissions".to_string());
+    }
+    let case_name: Option<String> = row.get("full_name");
+    let account_name: String = row.get("account_name");
+    if case_name.map(|n| !same_name(&n, &account_name)).unwrap_or(false) {
+        flags.push("name_differs_from_account".to_string());
+    }
+    let dob: Option<chrono::NaiveDate> = row.get("dob");
+    let birthday: Option<chrono::NaiveDate> = row.get("birthday");
+    if let (Some(d), Some(b)) = (dob, birthday) {
+        if d != b {
+            flags.push("dob_differs_from_account".to_string());
+        }
+    }
+    flags.extend(kyc_duplicates::risk_flags(tx, id).await?);
+    if row.get::<bool, _>("manual") {
+        flags.push(MANUAL_FLAG.to_string());
+    }
+    Ok(flags)
+}
+
+/// Names match ignoring case, spacing and word order.
+fn same_name(a: &str, b: &str) -> bool {
+    let words = |s: &str| {
+        let mut w: Vec<String> = s.split_whitespace().map(|p| p.to_lowercase()).collect();
+        w.sort();
+        w
+    };
+    words(a) == words(b)
+}
+
+fn claim_live(c: &Case) -> bool {
+    c.claim_expires_at.map(|t| t > Utc::now()).unwrap_or(false)
+}
+
+/// Put an under-review case back in the queue.
+async fn unclaim(tx: &mut Transaction<'_, Postgres>, id: i32, actor_id: Option<i32>, note: &str) -> sqlx::Result<bool> {
+    kyc::transition(tx, id, "under_review", "submitted", actor_id, &Change { note: Some(note), ..Default::default() }).await
+}
+
+/// Claim the locked case `c` for `reviewer_id`, or extend their claim.
+async fn claim_locked(tx: &mut Transaction<'_, Postgres>, c: &Case, reviewer_id: i32) -> sqlx::Result<Result<(), Refused>> {
+    let mut status = c.status.as_str();
+    if status == "under_review" {
+        if c.claimed_by == Some(reviewer_id) {
+            sqlx::query("UPDATE teacher_verifications SET claim_expires_at = now() + $2 * interval '1 minute' WHERE id = $1")
+                .bind(c.id)
+                .bind(claim_minutes() as f64)
+                .execute(&mut **tx)
+                .await?;
+            return Ok(Ok(()));
+        }
+        if claim_live(c) {
+            return Ok(Err(Refused::Conflict("claimed by another reviewer".to_string())));
+        }
+        unclaim(tx, c.id, None, "claim expired").await?;
+        status = "submitted";
+    }
+    if status != "submitted" {
+        return Ok(Err(Refused::Conflict(format!("verification is {}", c.status))));
+    }
+    if c.first_approved_by == Some(reviewer_id) {
+        return Ok(Err(Refused::Conflict("you gave the first approval, another reviewer has to decide".to_string())));
+    }
+    if !kyc::transition(tx, c.id, "submitted", "under_review", Some(reviewer_id), &Change::default()).await? {
+        return Ok(Err(Refused::Conflict("verification changed, reload and try again".to_string())));
+    }
+    Ok(Ok(()))
+}
+
+/// Claim case `id` for `reviewer_id`.
+pub async fn claim(pool: &PgPool, id: i32, reviewer_id: i32) -> sqlx::Result<Result<(), Refused>> {
+    let mut tx = pool.begin().await?;
+    let c = match kyc::case_for_update(&mut tx, id).await? {
+        Some(c) => c,
+        None => return Ok(Err(Refused::NotFound)),
+    };
+    let res = claim_locked(&mut tx, &c, reviewer_id).await?;
+    if res.is_ok() {
+        tx.commit().await?;
+    }
+    Ok(res)
+}
+
+/// Claim the next case for `reviewer_id`: their live claim if they hold one,
+/// else a case waiting for a second approval, else the oldest submission.
+pub async fn next(pool: &PgPool, reviewer_id: i32) -> sqlx::Result<Option<i32>> {
+    release_expired(pool).await?;
+    let mine = sqlx::query(
+        "SELECT id FROM teacher_verifications WHERE status = 'under_review' AND claimed_by = $1 AND claim_expires_at > now() ORDER BY id LIMIT 1",
+    )
+    .bind(reviewer_id)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(r) = mine {
+        return Ok(Some(r.get("id")));
+    }
+    let mut tx = pool.begin().await?;
+    // SKIP LOCKED so reviewers asking at the same time get different cases
+    let row = sqlx::query(
+        "SELECT id FROM teacher_verifications
+         WHERE status = 'submitted' AND first_approved_by IS DISTINCT FROM $1
+         ORDER BY (first_approved_by IS NOT NULL) DESC, submitted_at NULLS LAST, id
+         LIMIT 1 FOR UPDATE SKIP LOCKED",
+    )
+    .bind(reviewer_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let id: i32 = match row {
+        Some(r) => r.get("id"),
+        None => return Ok(None),
+    };
+    if !kyc::transition(&mut tx, id, "submitted", "under_review", Some(reviewer_id), &Change::default()).await? {
+        return Ok(None);
+    }
+    tx.commit().await?;
+    Ok(Some(id))
+}
+
+/// Hand a claimed case back to the queue.
+pub async fn release(pool: &PgPool, id: i32, reviewer_id: i32) -> sqlx::Result<Result<(), Refused>> {
+    let mut tx = pool.begin().await?;
+    let c = match kyc::case_for_update(&mut tx, id).await? {
+        Some(c) => c,
+        None => return Ok(Err(Refused::NotFound)),
+    };
+    if c.status != "under_review" || c.claimed_by != Some(reviewer_id) {
+        return Ok(Err(Refused::Conflict("you do not hold a claim on this verification".to_string())));
+    }
+    unclaim(&mut tx, id, Some(reviewer_id), "released").await?;
+    tx.commit().await?;
+    Ok(Ok(()))
+}
+
+/// Return abandoned claims to the queue. Returns how many were released.
+pub async fn release_expired(pool: &PgPool) -> sqlx::Result<usize> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query(
+        "SELECT id FROM teacher_verifications
+         WHERE status = 'under_review' AND (claim_expires_at IS NULL OR claim_expires_at <= now())
+         FOR UPDATE SKIP LOCKED",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut released = 0;
+    for r in rows {
+        if unclaim(&mut tx, r.get("id"), None, "claim expired").await? {
+            released += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(released)
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("KYC_QUEUE_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(60).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match release_expired(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Returned {} expired KYC claim(s) to the queue", n),
+                Err(e) => eprintln!("KYC claim expiry failed: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Apply a checked decision (see `kyc::validate_decision`). A submitted case
+/// is claimed on the spot; one under review must be claimed by `reviewer_id`.
+pub async fn decide(pool: &PgPool, id: i32, reviewer_id: i32, status: &str, reasons: &[String], requested: &[String], note: Option<&str>) -> sqlx::Result<Result<Decided, Refused>> {
+    let mut tx = pool.begin().await?;
+    let c = match kyc::case_for_update(&mut tx, id).await? {
+        Some(c) => c,
+        None => return Ok(Err(Refused::NotFound)),
+    };
+    if let Err(refused) = claim_locked(&mut tx, &c, reviewer_id).await? {
+        return Ok(Err(refused));
+    }
+
+    if status == "approved" && four_eyes() && !c.risk_flags.is_empty() && c.first_approved_by.is_none() {
+        sqlx::query("UPDATE teacher_verifications SET first_approved_by = $2, first_approved_at = now() WHERE id = $1")
+            .bind(id)
+            .bind(reviewer_id)
+            .execute(&mut *tx)
+            .await?;
+        let note = match note {
+            Some(n) => format!("First approval, waiting for a second reviewer: {}", n),
+            None => "First approval, waiting for a second reviewer".to_string(),
+        };
+        unclaim(&mut tx, id, Some(reviewer_id), &note).await?;
+        tx.commit().await?;
+        return Ok(Ok(Decided::FirstApproval));
+    }
+
+    let change = Change { reasons, items: requested, note };
+    if !kyc::transition(&mut tx, id, "under_review", status, Some(reviewer_id), &change).await? {
+        return Ok(Err(Refused::Conflict("verification changed, reload and try again".to_string())));
+    }
+    tx.commit().await?;
+    kyc::notify_decision(pool, c.user_id, reviewer_id, status, reasons, requested, note).await;
+    Ok(Ok(Decided::Applied))
+}
+
+/// Mark a waiting or claimed case as high risk by hand, or clear that mark.
+pub async fn set_manual_flag(pool: &PgPool, id: i32, on: bool) -> sqlx::Result<bool> {
+    let res = sqlx::query(
+        "UPDATE teacher_verifications SET
+             risk_flags = CASE WHEN $2 THEN ARRAY(SELECT DISTINCT unnest(risk_flags || ARRAY[$3])) ELSE array_remove(risk_flags, $3) END,
+             updated_at = now()
+         WHERE id = $1 AND status IN ('submitted', 'under_review')",
+    )
+    .bind(id)
+    .bind(on)
+    .bind(MANUAL_FLAG)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Queue overview for the dashboard.
+pub async fn summary(pool: &PgPool, reviewer_id: i32) -> sqlx::Result<serde_json::Value> {
+    let counts = sqlx::query(
+        "SELECT
+             COUNT(*) FILTER (WHERE status = 'submitted') AS waiting,
+             COUNT(*) FILTER (WHERE status = 'submitted' AND first_approved_by IS NOT NULL) AS awaiting_second_approval,
+             COUNT(*) FILTER (WHERE status = 'submitted' AND first_approved_by IS DISTINCT FROM $1) AS available,
+             MIN(submitted_at) FILTER (WHERE status = 'submitted') AS oldest_submitted_at
+         FROM teacher_verifications",
+    )
+    .bind(reviewer_id)
+    .fetch_one(pool)
+    .await?;
+    let claims = sqlx::query(
+        "SELECT tv.id, tv.claimed_by, u.full_name AS reviewer_name, tv.claim_expires_at
+         FROM teacher_verifications tv LEFT JOIN users u ON u.id = tv.claimed_by
+         WHERE tv.status = 'under_review' ORDER BY tv.claim_expires_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    let in_review: Vec<serde_json::Value> = claims
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.get::<i32,_>("id"),
+                "claimed_by": r.get::<Option<i32>,_>("claimed_by"),
+                "reviewer_name": r.get::<Option<String>,_>("reviewer_name"),
+                "claim_expires_at": r.get::<Option<chrono::DateTime<Utc>>,_>("claim_expires_at").map(|d| d.to_rfc3339()),
+                "mine": r.get::<Option<i32>,_>("claimed_by") == Some(reviewer_id),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "waiting": counts.get::<i64,_>("waiting"),
+        "awaiting_second_approval": counts.get::<i64,_>("awaiting_second_approval"),
+        "available_to_me": counts.get::<i64,_>("available"),
+        "oldest_submitted_at": counts.get::<Option<chrono::DateTime<Utc>>,_>("oldest_submitted_at").map(|d| d.to_rfc3339()),
+        "in_review": in_review,
+        "claim_minutes": claim_minutes(),
+        "four_eyes": four_eyes(),
+    }))
+}
@@ -1,2 +1,30 @@
 pub mod email;
 pub mod token;
+pub mod upload_gc;
+pub mod signed_url;
+pub mod storage;
+pub mod resumable;
+pub mod quota;
+pub mod notify_hub;
+pub mod campaigns;
+pub mod notify_prefs;
+pub mod web_push;
+pub mod support;
+pub mod support_sla;
+pub mod support_mail;
+pub mod support_smtp;
+pub mod kyc;
+pub mod kyc_queue;
+pub mod kyc_duplicates;
+pub mod kyc_expiry;
+pub mod age;
+pub mod wallet_service;
+pub mod guardian;
+pub mod availability;
+pub mod bookings;
+pub mod ical;
+pub mod calendar;
+pub mod policy;
+pub mod groups;
+pub mod packages;
+pub mod series;
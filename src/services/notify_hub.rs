@@ -0,0 +1,282 @@
+// Real-time notification fan-out.
+//
+// A single hub actor keeps the live connections of each user and pushes an
+// event only to that user's sockets, as soon as it is published. Events are
+// also persisted in `notifications`, so a reconnecting client sends the last
+// id it saw and catches up from the DB (`events_since`).
+//
+// With several server instances behind a load balancer each one has its own
+// hub. `spawn_pg_bridge` LISTENs on a Postgres channel and `publish` NOTIFYs
+// it, so every instance delivers to the sockets it holds. Without a working
+// listener events are delivered to the local hub only.
+
+use actix::{Actor, Addr, Context, Handler, Message, Recipient};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgListener, PgRow};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::services::signed_url;
+use crate::POOL_DATA;
+
+/// Postgres channel shared by all instances.
+pub const PG_CHANNEL: &str = "notification_events";
+
+/// NOTIFY payloads are capped at 8000 bytes; larger events are sent by id and reloaded.
+const MAX_PAYLOAD: usize = 7000;
+
+/// Most events a reconnecting client is sent from the DB in one go.
+pub const CATCH_UP_LIMIT: i64 = 200;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationEvent {
+    pub id: i32,
+    pub user_id: i32,
+    pub title: String,
+    pub body: String,
+    pub attachment_url: Option<String>,
+    pub attachment_signed_url: Option<String>,
+    pub category: String,
+    pub priority: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub read: bool,
+}
+
+/// Delivered to a connection actor for each event addressed to its user.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Push(pub NotificationEvent);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Connect {
+    pub user_id: i32,
+    pub conn_id: usize,
+    pub addr: Recipient<Push>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub user_id: i32,
+    pub conn_id: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Deliver(pub NotificationEvent);
+
+#[derive(Default)]
+pub struct NotificationHub {
+    users: HashMap<i32, HashMap<usize, Recipient<Push>>>,
+}
+
+impl Actor for NotificationHub {
+    type Context = Context<Self>;
+}
+
+impl Handler<Connect> for NotificationHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) {
+        self.users.entry(msg.user_id).or_default().insert(msg.conn_id, msg.addr);
+    }
+}
+
+impl Handler<Disconnect> for NotificationHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) {
+        if let Some(conns) = self.users.get_mut(&msg.user_id) {
+            conns.remove(&msg.conn_id);
+            if conns.is_empty() {
+                self.users.remove(&msg.user_id);
+            }
+        }
+    }
+}
+
+impl Handler<Deliver> for NotificationHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Deliver, _ctx: &mut Self::Context) {
+        let user_id = msg.0.user_id;
+        if let Some(conns) = self.users.get_mut(&user_id) {
+            // drop connections whose actor is gone without a Disconnect
+            conns.retain(|_, r| r.connected());
+            for r in conns.values() {
+                r.do_send(Push(msg.0.clone()));
+            }
+            if conns.is_empty() {
+                self.users.remove(&user_id);
+            }
+        }
+    }
+}
+
+static HUB: OnceCell<Addr<NotificationHub>> = OnceCell::new();
+static NEXT_CONN_ID: AtomicUsize = AtomicUsize::new(1);
+static BRIDGE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Start the hub on the current arbiter. Must run inside the actix system
+/// (route init); later calls return the same address.
+pub fn start() -> Addr<NotificationHub> {
+    HUB.get_or_init(|| NotificationHub::default().start()).clone()
+}
+
+pub fn hub() -> Option<&'static Addr<NotificationHub>> {
+    HUB.get()
+}
+
+pub fn next_conn_id() -> usize {
+    NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn deliver_local(event: NotificationEvent) {
+    if let Some(hub) = hub() {
+        hub.do_send(Deliver(event));
+    }
+}
+
+/// Publish an already persisted event to the user's live connections on all instances.
+pub fn publish(event: NotificationEvent) {
+    let pool = match (BRIDGE_ACTIVE.load(Ordering::Relaxed), POOL_DATA.get()) {
+        (true, Some(p)) => p.get_ref().clone(),
+        _ => return deliver_local(event),
+    };
+    let payload = match serde_json::to_string(&event) {
+        Ok(s) if s.len() <= MAX_PAYLOAD => s,
+        _ => serde_json::json!({"id": event.id}).to_string(),
+    };
+    tokio::spawn(async move {
+        let res = sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(PG_CHANNEL)
+            .bind(&payload)
+            .execute(&pool)
+            .await;
+        if let Err(e) = res {
+            eprintln!("notify bridge: NOTIFY failed, delivering locally: {}", e);
+            deliver_local(event);
+        }
+    });
+}
+
+// Campaign notices share the campaign's attachment instead of their own.
+const EVENT_SELECT: &str = "SELECT n.id, n.user_id, n.title, n.body, COALESCE(n.attachment_path, c.attachment_path) AS attachment_path, n.category, n.priority, n.created_at, n.expires_at, n.read
+     FROM notifications n LEFT JOIN notification_campaigns c ON c.id = n.campaign_id";
+
+fn event_from_row(r: &PgRow) -> NotificationEvent {
+    let id: i32 = r.get("id");
+    let attachment_path: Option<String> = r.try_get("attachment_path").ok();
+    let created_at = r
+        .try_get::<DateTime<Utc>, _>("created_at")
+        .unwrap_or_else(|_| {
+            let naive: NaiveDateTime = r.get("created_at");
+            DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc)
+        });
+    NotificationEvent {
+        id,
+        user_id: r.get("user_id"),
+        title: r.get("title"),
+        body: r.get("body"),
+        attachment_url: attachment_path.as_ref().map(|_| format!("/api/notifications/{}/attachment", id)),
+        attachment_signed_url: attachment_path.as_ref().map(|p| signed_url::sign_default("notification", id, p)),
+        category: r.get("category"),
+        priority: r.get("priority"),
+        created_at: created_at.to_rfc3339(),
+        expires_at: r.try_get::<Option<DateTime<Utc>>, _>("expires_at").ok().flatten().map(|d| d.to_rfc3339()),
+        read: r.get("read"),
+    }
+}
+
+pub async fn event_by_id(pool: &PgPool, id: i32) -> sqlx::Result<Option<NotificationEvent>> {
+    let row = sqlx::query(&format!("{} WHERE n.id = $1", EVENT_SELECT))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(event_from_row))
+}
+
+pub async fn events_by_ids(pool: &PgPool, ids: &[i32]) -> sqlx::Result<Vec<NotificationEvent>> {
+    let rows = sqlx::query(&format!("{} WHERE n.id = ANY($1) ORDER BY n.id", EVENT_SELECT))
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+/// Events for `user_id` newer than `last_id`, oldest first.
+pub async fn events_since(pool: &PgPool, user_id: i32, last_id: i32) -> sqlx::Result<Vec<NotificationEvent>> {
+    let rows = sqlx::query(
+        &format!("{} WHERE n.user_id = $1 AND n.id > $2 AND n.deleted_at IS NULL AND (n.expires_at IS NULL OR n.expires_at > now()) ORDER BY n.id ASC LIMIT $3", EVENT_SELECT),
+    )
+    .bind(user_id)
+    .bind(last_id)
+    .bind(CATCH_UP_LIMIT)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+async fn handle_payload(pool: &PgPool, payload: &str) {
+    if let Ok(event) = serde_json::from_str::<NotificationEvent>(payload) {
+        return deliver_local(event);
+    }
+    let id = serde_json::from_str::<serde_json::Value>(payload)
+        .ok()
+        .and_then(|v| v.get("id").and_then(|i| i.as_i64()));
+    if let Some(id) = id {
+        match event_by_id(pool, id as i32).await {
+            Ok(Some(event)) => deliver_local(event),
+            Ok(None) => {}
+            Err(e) => eprintln!("notify bridge: load event {}: {}", id, e),
+        }
+    }
+}
+
+/// LISTEN for events published by any instance and hand them to the local hub.
+/// Reconnects with a delay if the connection drops; meanwhile `publish`
+/// falls back to local delivery. Disabled with NOTIFY_BRIDGE=0.
+pub fn spawn_pg_bridge(pool: PgPool) {
+    let enabled = std::env::var("NOTIFY_BRIDGE")
+        .map(|v| !matches!(v.trim().to_lowercase().as_str(), "0" | "false" | "no"))
+        .unwrap_or(true);
+    if !enabled {
+        eprintln!("Notification bridge disabled (NOTIFY_BRIDGE=0)");
+        return;
+    }
+    tokio::spawn(async move {
+        loop {
+            let mut listener = match PgListener::connect_with(&pool).await {
+                Ok(l) => l,
+                Err(e) => {
+                    eprintln!("notify bridge: connect failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            if let Err(e) = listener.listen(PG_CHANNEL).await {
+                eprintln!("notify bridge: LISTEN failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+            BRIDGE_ACTIVE.store(true, Ordering::Relaxed);
+            loop {
+                match listener.recv().await {
+                    Ok(n) => handle_payload(&pool, n.payload()).await,
+                    Err(e) => {
+                        eprintln!("notify bridge: connection lost: {}", e);
+                        break;
+                    }
+                }
+            }
+            BRIDGE_ACTIVE.store(false, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
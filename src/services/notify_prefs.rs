@@ -0,0 +1,440 @@
+// Per-user, per-category notification delivery preferences.
+//
+// Every notice is stored and listed in-app; the mode for its category decides
+// what else happens:
+//   in_app  - pushed to open sockets only
+//   email   - pushed, and emailed right away
+//   digest  - pushed, and included in the next daily digest email if still unread
+//   muted   - stored as already read, not pushed or emailed
+// Urgent notices skip the digest and are emailed right away.
+// Independently of the mode, each category has a Web Push switch (`push`):
+// unless muted, a notice is also sent to the user's registered browsers.
+// Users without a stored preference get `default_mode`. Every email carries a
+// signed one-click unsubscribe link, so no login is needed to opt out.
+
+use anyhow::Result;
+use chrono::{DateTime, Timelike, Utc};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::services::age;
+use crate::services::email;
+use crate::services::guardian;
+use crate::services::ical;
+use crate::services::notify_hub::{self, NotificationEvent};
+use crate::services::signed_url;
+use crate::services::web_push;
+
+pub const CATEGORIES: &[&str] = &["system", "booking", "payment", "kyc", "support", "announcement"];
+pub const MODES: &[&str] = &["in_app", "email", "digest", "muted"];
+pub const PRIORITIES: &[&str] = &["low", "normal", "high", "urgent"];
+
+const NOTIFICATION_TEMPLATE: &str = include_str!("../../templates/email/notification.html");
+const DIGEST_TEMPLATE: &str = include_str!("../../templates/email/digest.html");
+
+/// Most notices listed in one digest email; the rest are summarized by the count.
+const DIGEST_MAX_ITEMS: usize = 20;
+
+/// Mode used when the user hasn't chosen one. Announcements are batched;
+/// everything else is transactional and emailed right away.
+pub fn default_mode(category: &str) -> &'static str {
+    match category {
+        "announcement" => "digest",
+        _ => "email",
+    }
+}
+
+/// Whether notices of `category` go to the user's browsers when they haven't
+/// chosen. Announcements stay quiet.
+pub fn default_push(category: &str) -> bool {
+    category != "announcement"
+}
+
+// SQL expression for the effective mode of notifications row `n` joined with preference `p`.
+fn effective_mode_sql() -> String {
+    let cases: String = CATEGORIES
+        .iter()
+        .map(|c| format!(" WHEN '{}' THEN '{}'", c, default_mode(c)))
+        .collect();
+    format!("COALESCE(p.mode, CASE n.category{} ELSE 'email' END)", cases)
+}
+
+pub struct Preference {
+    pub category: &'static str,
+    pub mode: String,
+    pub push: bool,
+    pub is_default: bool,
+}
+
+pub async fn preferences(pool: &PgPool, user_id: i32) -> sqlx::Result<Vec<Preference>> {
+    let rows = sqlx::query("SELECT category, mode, push FROM notification_preferences WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    let stored: HashMap<String, (String, Option<bool>)> =
+        rows.iter().map(|r| (r.get("category"), (r.get("mode"), r.get("push")))).collect();
+    Ok(CATEGORIES
+        .iter()
+        .map(|c| match stored.get(*c) {
+            Some((m, push)) => Preference {
+                category: c,
+                mode: m.clone(),
+                push: push.unwrap_or_else(|| default_push(c)),
+                is_default: false,
+            },
+            None => Preference { category: c, mode: default_mode(c).to_string(), push: default_push(c), is_default: true },
+        })
+        .collect())
+}
+
+pub async fn set_mode(pool: &PgPool, user_id: i32, category: &str, mode: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO notification_preferences (user_id, category, mode) VALUES ($1, $2, $3)
+         ON CONFLICT (user_id, category) DO UPDATE SET mode = EXCLUDED.mode, updated_at = now()",
+    )
+    .bind(user_id)
+    .bind(category)
+    .bind(mode)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_push(pool: &PgPool, user_id: i32, category: &str, push: bool) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO notification_preferences (user_id, category, mode, push) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (user_id, category) DO UPDATE SET push = EXCLUDED.push, updated_at = now()",
+    )
+    .bind(user_id)
+    .bind(category)
+    .bind(default_mode(category))
+    .bind(push)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Stored (mode, push) per (user, category).
+async fn modes_for(pool: &PgPool, user_ids: &[i32]) -> sqlx::Result<HashMap<(i32, String), (String, Option<bool>)>> {
+    let rows = sqlx::query("SELECT user_id, category, mode, push FROM notification_preferences WHERE user_id = ANY($1)")
+        .bind(user_ids)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|r| ((r.get("user_id"), r.get("category")), (r.get("mode"), r.get("push"))))
+        .collect())
+}
+
+/// A system-generated notice for `deliver`.
+pub struct Notice<'a> {
+    pub user_id: i32,
+    pub sender_id: Option<i32>,
+    pub category: &'a str,
+    pub priority: &'a str,
+    pub title: &'a str,
+    pub body: &'a str,
+    /// Calendar object (see ical.rs) attached when the notice is emailed
+    pub ics: Option<&'a str>,
+}
+
+/// Store a system-generated notice for one user and deliver it per their
+/// preferences. Returns the notice id, or 0 when the sender may not write to
+/// the recipient (see age::may_message).
+pub async fn notify(pool: &PgPool, user_id: i32, sender_id: Option<i32>, category: &str, priority: &str, title: &str, body: &str) -> sqlx::Result<i32> {
+    deliver(pool, &Notice { user_id, sender_id, category, priority, title, body, ics: None }).await
+}
+
+/// `notify` for a notice that may carry a calendar attachment.
+pub async fn deliver(pool: &PgPool, n: &Notice<'_>) -> sqlx::Result<i32> {
+    let Notice { user_id, sender_id, category, priority, title, body, ics } = *n;
+    if let Some(sender) = sender_id {
+        if !age::may_message(pool, sender, user_id).await? {
+            eprintln!("Notice from user {} to minor account {} withheld", sender, user_id);
+            return Ok(0);
+        }
+    }
+    let row = sqlx::query(
+        "INSERT INTO notifications (user_id, sender_id, title, body, category, priority, ics) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(sender_id)
+    .bind(title)
+    .bind(body)
+    .bind(category)
+    .bind(priority)
+    .bind(ics)
+    .fetch_one(pool)
+    .await?;
+    let id: i32 = row.get("id");
+    let events = notify_hub::events_by_ids(pool, &[id]).await?;
+    dispatch(pool, events).await?;
+    if let Err(e) = guardian::copy_to_guardians(pool, user_id, sender_id, category, priority, title, body).await {
+        eprintln!("Guardian copies of notice {} failed: {:?}", id, e);
+    }
+    Ok(id)
+}
+
+/// Deliver freshly stored notices according to each recipient's preference for their category.
+pub async fn dispatch(pool: &PgPool, events: Vec<NotificationEvent>) -> sqlx::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let user_ids: Vec<i32> = events.iter().map(|e| e.user_id).collect();
+    let modes = modes_for(pool, &user_ids).await?;
+
+    let mut muted = Vec::new();
+    let mut email_now = Vec::new();
+    let mut push = Vec::new();
+    for evt in events {
+        let stored = modes.get(&(evt.user_id, evt.category.clone()));
+        let mode = stored.map(|(m, _)| m.as_str()).unwrap_or_else(|| default_mode(&evt.category));
+        let wants_push = stored.and_then(|(_, p)| *p).unwrap_or_else(|| default_push(&evt.category));
+        if wants_push && mode != "muted" && web_push::enabled() {
+            push.push(evt.clone());
+        }
+        match mode {
+            "muted" => muted.push(evt.id),
+            "email" => {
+                email_now.push(evt.id);
+                notify_hub::publish(evt);
+            }
+            "digest" if evt.priority == "urgent" => {
+                email_now.push(evt.id);
+                notify_hub::publish(evt);
+            }
+            _ => notify_hub::publish(evt),
+        }
+    }
+
+    if !muted.is_empty() {
+        sqlx::query("UPDATE notifications SET read = TRUE WHERE id = ANY($1)")
+            .bind(&muted)
+            .execute(pool)
+            .await?;
+    }
+    if !email_now.is_empty() {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_immediate(&pool, &email_now).await {
+                eprintln!("Notification email failed: {:?}", e);
+            }
+        });
+    }
+    if !push.is_empty() {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = web_push::send_events(&pool, push).await {
+                eprintln!("Web push failed: {:?}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Signed link that switches `category` (or "all") from email to in-app only for `user_id`.
+pub fn unsubscribe_url(user_id: i32, category: &str) -> String {
+    let sig = signed_url::token("unsubscribe", &format!("{}:{}", user_id, category));
+    format!("{}/unsubscribe?u={}&c={}&t={}", email::base_url(), user_id, category, sig)
+}
+
+pub fn verify_unsubscribe(user_id: i32, category: &str, token: &str) -> bool {
+    signed_url::verify_token("unsubscribe", &format!("{}:{}", user_id, category), token)
+}
+
+/// Stop emails for `category` ("all" for every category). Notices stay in-app;
+/// muted categories remain muted.
+pub async fn unsubscribe(pool: &PgPool, user_id: i32, category: &str) -> sqlx::Result<Vec<&'static str>> {
+    let current = preferences(pool, user_id).await?;
+    let mut changed = Vec::new();
+    for p in current {
+        if (category == "all" || category == p.category) && (p.mode == "email" || p.mode == "digest") {
+            set_mode(pool, user_id, p.category, "in_app").await?;
+            changed.push(p.category);
+        }
+    }
+    Ok(changed)
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render(template: &str, vars: &[(&str, String)]) -> String {
+    let mut out = template.to_string();
+    for (k, v) in vars {
+        out = out.replace(&format!("{{{{{}}}}}", k), v);
+    }
+    out
+}
+
+async fn send_email(to: String, subject: String, html: String, unsubscribe: String, reply_to: Option<String>, ics: Option<String>) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let attachments: Vec<email::Attachment> = ics.iter().map(|c| ical::attachment(c)).collect();
+        email::send_html_with(&to, &subject, html, Some(&unsubscribe), reply_to.as_deref(), &attachments)
+    })
+    .await?
+}
+
+/// Support notices are answered through the inbound mailbox when one is configured.
+fn reply_to_for(category: &str) -> Option<String> {
+    if category != "support" {
+        return None;
+    }
+    std::env::var("SUPPORT_INBOUND_ADDRESS").ok().map(|a| a.trim().to_string()).filter(|a| !a.is_empty())
+}
+
+async fn send_immediate(pool: &PgPool, ids: &[i32]) -> Result<()> {
+    let rows = sqlx::query(
+        "SELECT n.id, n.user_id, n.title, n.body, n.category, n.ics, u.email, u.full_name
+         FROM notifications n JOIN users u ON u.id = n.user_id
+         WHERE n.id = ANY($1) AND n.emailed_at IS NULL AND COALESCE(u.active, TRUE)",
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+
+    for r in rows {
+        let id: i32 = r.get("id");
+        let user_id: i32 = r.get("user_id");
+        let category: String = r.get("category");
+        let title: String = r.get("title");
+        let unsubscribe = unsubscribe_url(user_id, &category);
+        let html = render(
+            NOTIFICATION_TEMPLATE,
+            &[
+                ("category", escape_html(&category)),
+                ("title", escape_html(&title)),
+                ("body", escape_html(&r.get::<String, _>("body"))),
+                ("name", escape_html(&r.get::<String, _>("full_name"))),
+                ("open_url", email::base_url()),
+                ("unsubscribe_url", unsubscribe.clone()),
+                ("preferences_url", format!("{}/settings/notifications", email::base_url())),
+            ],
+        );
+        match send_email(r.get("email"), title, html, unsubscribe, reply_to_for(&category), r.get("ics")).await {
+            Ok(()) => {
+                sqlx::query("UPDATE notifications SET emailed_at = now() WHERE id = $1")
+                    .bind(id)
+                    .execute(pool)
+                    .await?;
+            }
+            Err(e) => eprintln!("Notification email {} failed: {:?}", id, e),
+        }
+    }
+    Ok(())
+}
+
+/// Email each user with digest-mode notices still unread, at most once per
+/// ~day. Returns the number of digests sent.
+pub async fn send_digests(pool: &PgPool) -> Result<usize> {
+    let rows = sqlx::query(&format!(
+        "SELECT n.id, n.user_id, n.title, n.body, n.category, n.created_at, u.email, u.full_name
+         FROM notifications n
+         JOIN users u ON u.id = n.user_id
+         LEFT JOIN notification_preferences p ON p.user_id = n.user_id AND p.category = n.category
+         WHERE NOT n.read AND n.digested_at IS NULL AND n.emailed_at IS NULL
+           AND n.deleted_at IS NULL AND n.archived_at IS NULL
+           AND (n.expires_at IS NULL OR n.expires_at > now())
+           AND COALESCE(u.active, TRUE)
+           AND {} = 'digest'
+           AND NOT EXISTS (
+               SELECT 1 FROM notification_digests d WHERE d.user_id = n.user_id AND d.sent_at > now() - interval '20 hours'
+           )
+         ORDER BY n.user_id, (n.priority IN ('high', 'urgent')) DESC, n.id DESC",
+        effective_mode_sql()
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_user: Vec<(i32, Vec<sqlx::postgres::PgRow>)> = Vec::new();
+    for r in rows {
+        let uid: i32 = r.get("user_id");
+        match by_user.last_mut() {
+            Some((last, items)) if *last == uid => items.push(r),
+            _ => by_user.push((uid, vec![r])),
+        }
+    }
+
+    let mut sent = 0;
+    for (user_id, items) in by_user {
+        let ids: Vec<i32> = items.iter().map(|r| r.get("id")).collect();
+        let items_html: String = items
+            .iter()
+            .take(DIGEST_MAX_ITEMS)
+            .map(|r| {
+                let created: DateTime<Utc> = r.get("created_at");
+                let body: String = r.get("body");
+                let snippet: String = body.chars().take(200).collect();
+                format!(
+                    r#"<div style="border-top:1px solid #e5e7eb; padding:10px 0;"><div style="color:#6b7280; font-size:12px;">{} · {}</div><strong>{}</strong><p style="margin:4px 0 0 0;">{}</p></div>"#,
+                    escape_html(&r.get::<String, _>("category")),
+                    created.format("%b %d, %H:%M UTC"),
+                    escape_html(&r.get::<String, _>("title")),
+                    escape_html(&snippet),
+                )
+            })
+            .collect();
+        let more = items.len().saturating_sub(DIGEST_MAX_ITEMS);
+        let items_html = if more > 0 {
+            format!(r#"{}<p style="color:#6b7280;">…and {} more.</p>"#, items_html, more)
+        } else {
+            items_html
+        };
+
+        let unsubscribe = unsubscribe_url(user_id, "all");
+        let html = render(
+            DIGEST_TEMPLATE,
+            &[
+                ("name", escape_html(&items[0].get::<String, _>("full_name"))),
+                ("count", items.len().to_string()),
+                ("items", items_html),
+                ("open_url", email::base_url()),
+                ("unsubscribe_url", unsubscribe.clone()),
+                ("preferences_url", format!("{}/settings/notifications", email::base_url())),
+            ],
+        );
+        let subject = format!("You have {} unread notice(s)", items.len());
+        if let Err(e) = send_email(items[0].get("email"), subject, html, unsubscribe, None, None).await {
+            eprintln!("Digest for user {} failed: {:?}", user_id, e);
+            continue;
+        }
+
+        sqlx::query("UPDATE notifications SET digested_at = now() WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(pool)
+            .await?;
+        sqlx::query("INSERT INTO notification_digests (user_id, notification_count) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(ids.len() as i32)
+            .execute(pool)
+            .await?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Send digests once a day, from DIGEST_HOUR_UTC (default 8) onwards. Checked
+/// every 15 minutes so a restart just picks up where it left off.
+pub fn spawn_digest_job(pool: PgPool) {
+    let hour: u32 = std::env::var("DIGEST_HOUR_UTC").ok().and_then(|s| s.parse().ok()).unwrap_or(8).min(23);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(15 * 60));
+        loop {
+            ticker.tick().await;
+            if Utc::now().hour() < hour {
+                continue;
+            }
+            match send_digests(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Sent {} notification digest(s)", n),
+                Err(e) => eprintln!("Digest job failed: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,519 @@
+// Prepaid session packages.
+//
+// A teacher offers packages of `sessions` sessions at discount_percent off
+// their session price, valid for valid_days. Buying one takes the whole
+// price from the student's wallet (`package_purchase`) and holds it on the
+// package, or waits for a guardian's approval above the spending limit.
+// Every booking paid from the package moves one session's price from the
+// package into the booking's escrow (see bookings.rs), so each session is
+// settled on its own: paid out when it ends, refunded under the policy
+// when cancelled. A full refund gives the session back to the package.
+//
+// At expiry, unused sessions are refunded at unused_refund_percent of their
+// value and the rest goes to the teacher. Students are warned
+// PACKAGE_EXPIRY_WARN_DAYS (default 7) before. Runs every
+// PACKAGES_INTERVAL_SECONDS (default 3600).
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+use std::time::Duration;
+
+use crate::services::age;
+use crate::services::availability;
+use crate::services::bookings::{self, when};
+use crate::services::guardian::{self, Settlement};
+use crate::services::notify_prefs;
+use crate::services::wallet_service::{self, Entry};
+
+pub fn expiry_warn_days() -> i64 {
+    std::env::var("PACKAGE_EXPIRY_WARN_DAYS").ok().and_then(|s| s.parse().ok()).filter(|d: &i64| *d >= 0).unwrap_or(7)
+}
+
+/// A package offer as a teacher defines it.
+pub struct Offer {
+    pub id: i32,
+    pub teacher_id: i32,
+    pub name: String,
+    pub sessions: i32,
+    pub discount_percent: i32,
+    pub valid_days: i32,
+    pub unused_refund_percent: i32,
+    pub active: bool,
+}
+
+impl Offer {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Offer {
+        Offer {
+            id: r.get("id"),
+            teacher_id: r.get("teacher_id"),
+            name: r.get("name"),
+            sessions: r.get("sessions"),
+            discount_percent: r.get("discount_percent"),
+            valid_days: r.get("valid_days"),
+            unused_refund_percent: r.get("unused_refund_percent"),
+            active: r.get("active"),
+        }
+    }
+
+    fn session_price(&self, full_price: i64) -> i64 {
+        full_price * (100 - self.discount_percent as i64) / 100
+    }
+
+    /// The offer with prices at the teacher's current session price.
+    pub fn to_json(&self, full_price: i64) -> serde_json::Value {
+        let each = self.session_price(full_price);
+        json!({
+            "id": self.id,
+            "teacher_id": self.teacher_id,
+            "name": self.name,
+            "sessions": self.sessions,
+            "discount_percent": self.discount_percent,
+            "valid_days": self.valid_days,
+            "unused_refund_percent": self.unused_refund_percent,
+            "active": self.active,
+            "session_price_coins": each,
+            "price_coins": each * self.sessions as i64,
+            "saving_coins": (full_price - each) * self.sessions as i64,
+        })
+    }
+}
+
+pub struct Package {
+    pub id: i32,
+    pub teacher_id: i32,
+    pub student_id: i32,
+    pub name: String,
+    pub sessions_total: i32,
+    pub sessions_left: i32,
+    pub session_price_coins: i64,
+    pub valid_days: i32,
+    pub unused_refund_percent: i32,
+    pub status: String,
+    pub approval_id: Option<i32>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Package {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Package {
+        Package {
+            id: r.get("id"),
+            teacher_id: r.get("teacher_id"),
+            student_id: r.get("student_id"),
+            name: r.get("name"),
+            sessions_total: r.get("sessions_total"),
+            sessions_left: r.get("sessions_left"),
+            session_price_coins: r.get("session_price_coins"),
+            valid_days: r.get("valid_days"),
+            unused_refund_percent: r.get("unused_refund_percent"),
+            status: r.get("status"),
+            approval_id: r.get("approval_id"),
+            expires_at: r.get("expires_at"),
+        }
+    }
+
+    fn reference(&self) -> String {
+        format!("package:{}", self.id)
+    }
+
+    fn price(&self) -> i64 {
+        self.session_price_coins * self.sessions_total as i64
+    }
+
+    /// Whether sessions can still be booked from it.
+    pub fn is_usable(&self) -> bool {
+        self.status == "active" && self.sessions_left > 0 && self.expires_at.is_some_and(|t| t > Utc::now())
+    }
+}
+
+async fn lock(tx: &mut Transaction<'_, Postgres>, id: i32) -> sqlx::Result<Option<Package>> {
+    let row = sqlx::query("SELECT * FROM packages WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut **tx).await?;
+    Ok(row.as_ref().map(Package::from_row))
+}
+
+pub async fn get(pool: &PgPool, id: i32) -> sqlx::Result<Option<Package>> {
+    let row = sqlx::query("SELECT * FROM packages WHERE id = $1").bind(id).fetch_optional(pool).await?;
+    Ok(row.as_ref().map(Package::from_row))
+}
+
+async fn tell(pool: &PgPool, user_id: i32, category: &str, priority: &str, title: &str, body: &str) {
+    if let Err(e) = notify_prefs::notify(pool, user_id, None, category, priority, title, body).await {
+        eprintln!("Package notification for user {} failed: {:?}", user_id, e);
+    }
+}
+
+/// `user_id`'s name as `viewer` may see it (see age.rs).
+async fn name(pool: &PgPool, user_id: i32, viewer: i32) -> sqlx::Result<String> {
+    let row = sqlx::query("SELECT shown_name(u, $2) AS name FROM users u WHERE u.id = $1").bind(user_id).bind(viewer).fetch_optional(pool).await?;
+    Ok(row.map(|r| r.get("name")).unwrap_or_default())
+}
+
+/// The terms of a new offer.
+pub struct NewOffer {
+    pub name: String,
+    pub sessions: i32,
+    pub discount_percent: i32,
+    pub valid_days: i32,
+    pub unused_refund_percent: i32,
+}
+
+pub async fn create_offer(pool: &PgPool, teacher_id: i32, n: &NewOffer) -> anyhow::Result<Offer> {
+    let title = n.name.trim();
+    if title.is_empty() || title.chars().count() > 120 {
+        return Err(anyhow!("name must be 1-120 characters"));
+    }
+    if !(2..=100).contains(&n.sessions) {
+        return Err(anyhow!("sessions must be between 2 and 100"));
+    }
+    if !(0..=90).contains(&n.discount_percent) || !(0..=100).contains(&n.unused_refund_percent) {
+        return Err(anyhow!("discount_percent must be 0-90 and unused_refund_percent 0-100"));
+    }
+    if !(7..=730).contains(&n.valid_days) {
+        return Err(anyhow!("valid_days must be between 7 and 730"));
+    }
+    let row = sqlx::query(
+        "INSERT INTO package_offers (teacher_id, name, sessions, discount_percent, valid_days, unused_refund_percent)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+    )
+    .bind(teacher_id)
+    .bind(title)
+    .bind(n.sessions)
+    .bind(n.discount_percent)
+    .bind(n.valid_days)
+    .bind(n.unused_refund_percent)
+    .fetch_one(pool)
+    .await?;
+    Ok(Offer::from_row(&row))
+}
+
+/// Stop selling an offer; packages already bought keep their terms.
+pub async fn retire_offer(pool: &PgPool, teacher_id: i32, id: i32) -> sqlx::Result<bool> {
+    let res = sqlx::query("UPDATE package_offers SET active = FALSE WHERE id = $1 AND teacher_id = $2").bind(id).bind(teacher_id).execute(pool).await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// The teacher's offers at their current session price.
+pub async fn offers(pool: &PgPool, teacher_id: i32, include_retired: bool) -> sqlx::Result<Vec<serde_json::Value>> {
+    let price = availability::schedule(pool, teacher_id).await?.price_coins;
+    let rows = sqlx::query("SELECT * FROM package_offers WHERE teacher_id = $1 AND (active OR $2) ORDER BY sessions, id")
+        .bind(teacher_id)
+        .bind(include_retired)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|r| Offer::from_row(r).to_json(price)).collect())
+}
+
+/// Take the package price from the student and start its validity.
+async fn charge(tx: &mut Transaction<'_, Postgres>, p: &mut Package) -> anyhow::Result<()> {
+    let reference = p.reference();
+    let entry = Entry { kind: "package_purchase", reference: Some(&reference), actor_id: Some(p.student_id), note: None };
+    wallet_service::post(tx, p.student_id, -p.price(), &entry).await?;
+    let expires_at = Utc::now() + ChronoDuration::days(p.valid_days as i64);
+    sqlx::query("UPDATE packages SET status = 'active', expires_at = $2, updated_at = now() WHERE id = $1")
+        .bind(p.id)
+        .bind(expires_at)
+        .execute(&mut **tx)
+        .await?;
+    p.status = "active".to_string();
+    p.expires_at = Some(expires_at);
+    Ok(())
+}
+
+async fn announce_bought(pool: &PgPool, p: &Package) -> sqlx::Result<()> {
+    let tz = availability::user_tz(pool, p.student_id).await?;
+    let until = p.expires_at.map(|t| when(t, tz)).unwrap_or_default();
+    let teacher = name(pool, p.teacher_id, p.student_id).await?;
+    let body = format!(
+        "You bought {} with {}: {} sessions for {} coins, to be booked by {}.",
+        p.name,
+        teacher,
+        p.sessions_total,
+        p.price(),
+        until
+    );
+    tell(pool, p.student_id, "payment", "normal", "Package bought", &body).await;
+    let student = name(pool, p.student_id, p.teacher_id).await?;
+    tell(pool, p.teacher_id, "booking", "normal", "Package sold", &format!("{} bought {} ({} sessions).", student, p.name, p.sessions_total)).await;
+    Ok(())
+}
+
+/// Buy offer `offer_id` for `student_id` at the teacher's current price.
+pub async fn buy(pool: &PgPool, student_id: i32, offer_id: i32) -> anyhow::Result<Package> {
+    let standing = age::standing(pool, student_id).await?.ok_or_else(|| anyhow!("account not found"))?;
+    standing.may_transact().map_err(|e| anyhow!(e))?;
+    let row = sqlx::query(
+        "SELECT o.* FROM package_offers o JOIN users u ON u.id = o.teacher_id
+         WHERE o.id = $1 AND o.active AND u.role = 'teacher' AND u.kyc_verified",
+    )
+    .bind(offer_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow!("package not found"))?;
+    let offer = Offer::from_row(&row);
+    if offer.teacher_id == student_id {
+        return Err(anyhow!("you cannot buy your own package"));
+    }
+    let each = offer.session_price(availability::schedule(pool, offer.teacher_id).await?.price_coins);
+    if each <= 0 {
+        return Err(anyhow!("this teacher's sessions are free"));
+    }
+    let total = each * offer.sessions as i64;
+    let needs_approval = guardian::approval_limit(pool, student_id).await?.map(|l| total > l).unwrap_or(false);
+
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query(
+        "INSERT INTO packages (offer_id, teacher_id, student_id, name, sessions_total, sessions_left, session_price_coins, valid_days, unused_refund_percent, status)
+         VALUES ($1, $2, $3, $4, $5, $5, $6, $7, $8, 'awaiting_approval') RETURNING *",
+    )
+    .bind(offer.id)
+    .bind(offer.teacher_id)
+    .bind(student_id)
+    .bind(&offer.name)
+    .bind(offer.sessions)
+    .bind(each)
+    .bind(offer.valid_days)
+    .bind(offer.unused_refund_percent)
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut package = Package::from_row(&row);
+    if needs_approval {
+        let teacher = name(pool, offer.teacher_id, student_id).await?;
+        let description = format!("the package \"{}\" with {} ({} sessions)", offer.name, teacher, offer.sessions);
+        let approval_id = guardian::request_approval(&mut tx, student_id, total, "package", Some(package.id), &description).await?;
+        sqlx::query("UPDATE packages SET approval_id = $2 WHERE id = $1").bind(package.id).bind(approval_id).execute(&mut *tx).await?;
+        package.approval_id = Some(approval_id);
+    } else {
+        charge(&mut tx, &mut package).await?;
+    }
+    tx.commit().await?;
+
+    if let Some(approval_id) = package.approval_id {
+        if let Err(e) = guardian::notify_approval_request(pool, approval_id).await {
+            eprintln!("Approval request {} notification failed: {:?}", approval_id, e);
+        }
+        let body = format!("{} is held until a parent or guardian approves it.", package.name);
+        tell(pool, student_id, "payment", "normal", "Package waiting for approval", &body).await;
+    } else {
+        announce_bought(pool, &package).await?;
+    }
+    Ok(package)
+}
+
+/// Settle a package held for approval: charge and activate it, or decline it.
+pub async fn apply_approval(pool: &PgPool, package_id: i32, approved: bool) -> anyhow::Result<Settlement> {
+    settle_approval(pool, package_id, if approved { None } else { Some("a guardian declined it".to_string()) }).await
+}
+
+/// Charge a package waiting for approval, or decline it with `refusal`.
+async fn settle_approval(pool: &PgPool, package_id: i32, refusal: Option<String>) -> anyhow::Result<Settlement> {
+    let mut tx = pool.begin().await?;
+    let mut p = match lock(&mut tx, package_id).await? {
+        Some(p) if p.status == "awaiting_approval" => p,
+        _ => return Ok(Settlement::Stale),
+    };
+    let mut reason = refusal;
+    if reason.is_none() {
+        // a failed charge leaves the transaction usable: post checks before writing
+        if let Err(e) = charge(&mut tx, &mut p).await {
+            reason = Some(e.to_string());
+        }
+    }
+    if let Some(r) = &reason {
+        if let Some(approval_id) = p.approval_id {
+            guardian::cancel_approval(&mut tx, approval_id).await?;
+        }
+        sqlx::query("UPDATE packages SET status = 'declined', updated_at = now() WHERE id = $1").bind(p.id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        tell(pool, p.student_id, "payment", "normal", "Package declined", &format!("{} was not bought: {}.", p.name, r)).await;
+        return Ok(Settlement::Refused(r.clone()));
+    }
+    tx.commit().await?;
+    announce_bought(pool, &p).await?;
+    Ok(Settlement::Done)
+}
+
+/// Move one session's price from the package into a booking's escrow.
+/// Returns the session price.
+pub async fn draw(tx: &mut Transaction<'_, Postgres>, package_id: i32, student_id: i32, teacher_id: i32, ends_at: DateTime<Utc>) -> anyhow::Result<i64> {
+    let p = lock(tx, package_id).await?.filter(|p| p.student_id == student_id).ok_or_else(|| anyhow!("package not found"))?;
+    if p.teacher_id != teacher_id {
+        return Err(anyhow!("that package is for sessions with another teacher"));
+    }
+    if !p.is_usable() {
+        return Err(anyhow!("the package has no sessions left or has expired"));
+    }
+    if p.expires_at.is_some_and(|t| ends_at > t) {
+        return Err(anyhow!("the package expires before that session"));
+    }
+    sqlx::query("UPDATE packages SET sessions_left = sessions_left - 1, updated_at = now() WHERE id = $1").bind(p.id).execute(&mut **tx).await?;
+    Ok(p.session_price_coins)
+}
+
+/// Give a fully refunded session back to its package. Returns false if the
+/// package can no longer take it (expired), so the caller refunds the coins.
+pub async fn restore(tx: &mut Transaction<'_, Postgres>, package_id: i32) -> sqlx::Result<bool> {
+    let res = sqlx::query(
+        "UPDATE packages SET sessions_left = sessions_left + 1, updated_at = now()
+         WHERE id = $1 AND status = 'active' AND expires_at > now() AND sessions_left < sessions_total",
+    )
+    .bind(package_id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Packages bought by or from `user_id`, newest first.
+pub async fn list_for(pool: &PgPool, user_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let tz = availability::user_tz(pool, user_id).await?;
+    let rows = sqlx::query(
+        "SELECT p.*, shown_name(t, $1) AS teacher_name, shown_name(s, $1) AS student_name,
+                (SELECT COUNT(*) FROM bookings b WHERE b.package_id = p.id AND b.status IN ('confirmed', 'awaiting_approval')) AS upcoming,
+                (SELECT COUNT(*) FROM bookings b WHERE b.package_id = p.id AND b.status = 'completed') AS completed
+         FROM packages p JOIN users t ON t.id = p.teacher_id JOIN users s ON s.id = p.student_id
+         WHERE p.student_id = $1 OR p.teacher_id = $1
+         ORDER BY p.id DESC LIMIT 100",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let p = Package::from_row(r);
+            json!({
+                "id": p.id,
+                "name": p.name,
+                "teacher_id": p.teacher_id,
+                "teacher_name": r.get::<String,_>("teacher_name"),
+                "student_id": p.student_id,
+                "student_name": r.get::<String,_>("student_name"),
+                "status": p.status,
+                "sessions_total": p.sessions_total,
+                "sessions_left": p.sessions_left,
+                "sessions_upcoming": r.get::<i64,_>("upcoming"),
+                "sessions_completed": r.get::<i64,_>("completed"),
+                "sessions_expired": r.get::<i32,_>("sessions_expired"),
+                "session_price_coins": p.session_price_coins,
+                "unused_refund_percent": p.unused_refund_percent,
+                "expires_at": p.expires_at.map(|t| t.with_timezone(&tz).to_rfc3339()),
+            })
+        })
+        .collect())
+}
+
+/// Expire packages past their date; unused sessions are refunded at the
+/// package's unused_refund_percent and the rest goes to the teacher.
+pub async fn expire_due(pool: &PgPool) -> anyhow::Result<usize> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM packages WHERE status = 'active' AND expires_at <= now() ORDER BY expires_at LIMIT 500")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    let mut expired = 0;
+    for id in ids {
+        let mut tx = pool.begin().await?;
+        let p = match lock(&mut tx, id).await? {
+            Some(p) if p.status == "active" => p,
+            _ => continue,
+        };
+        let unused = p.session_price_coins * p.sessions_left as i64;
+        let refund = unused * p.unused_refund_percent as i64 / 100;
+        let note = format!(
+            "{} unused session{} of {} expired: {}% ({} coins) back to the student, {} coins to the teacher.",
+            p.sessions_left,
+            if p.sessions_left == 1 { "" } else { "s" },
+            p.name,
+            p.unused_refund_percent,
+            refund,
+            unused - refund
+        );
+        let reference = p.reference();
+        let entry = |kind| Entry { kind, reference: Some(&reference), actor_id: None, note: Some(&note) };
+        if refund > 0 {
+            wallet_service::post(&mut tx, p.student_id, refund, &entry("package_expiry_refund")).await?;
+        }
+        if unused - refund > 0 {
+            wallet_service::post(&mut tx, p.teacher_id, unused - refund, &entry("package_expiry_fee")).await?;
+        }
+        sqlx::query("UPDATE packages SET status = 'expired', sessions_expired = sessions_left, sessions_left = 0, updated_at = now() WHERE id = $1")
+            .bind(p.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        if p.sessions_left > 0 {
+            tell(pool, p.student_id, "payment", "normal", "Package expired", &note).await;
+            tell(pool, p.teacher_id, "payment", "low", "Package expired", &note).await;
+        }
+        expired += 1;
+    }
+    Ok(expired)
+}
+
+/// Remind students of packages expiring within PACKAGE_EXPIRY_WARN_DAYS
+/// with sessions left, once per package.
+pub async fn warn_expiring(pool: &PgPool) -> anyhow::Result<usize> {
+    let rows = sqlx::query(
+        "UPDATE packages SET expiry_warned_at = now()
+         WHERE status = 'active' AND sessions_left > 0 AND expiry_warned_at IS NULL AND expires_at <= $1 AND expires_at > now()
+         RETURNING *",
+    )
+    .bind(Utc::now() + ChronoDuration::days(expiry_warn_days()))
+    .fetch_all(pool)
+    .await?;
+    for p in rows.iter().map(Package::from_row) {
+        let tz = availability::user_tz(pool, p.student_id).await.unwrap_or(Tz::UTC);
+        let body = format!(
+            "{} has {} session{} left, which expire on {}. Unused sessions are refunded at {}%.",
+            p.name,
+            p.sessions_left,
+            if p.sessions_left == 1 { "" } else { "s" },
+            p.expires_at.map(|t| when(t, tz)).unwrap_or_default(),
+            p.unused_refund_percent
+        );
+        tell(pool, p.student_id, "payment", "high", "Package expiring soon", &body).await;
+    }
+    Ok(rows.len())
+}
+
+/// Decline packages whose approval took too long (BOOKING_APPROVAL_HOURS).
+pub async fn expire_approvals(pool: &PgPool) -> anyhow::Result<usize> {
+    let rows = sqlx::query("SELECT id FROM packages WHERE status = 'awaiting_approval' AND created_at < $1")
+        .bind(Utc::now() - ChronoDuration::hours(bookings::approval_hours()))
+        .fetch_all(pool)
+        .await?;
+    let mut expired = 0;
+    for r in &rows {
+        if settle_approval(pool, r.get("id"), Some("no guardian approved it in time".to_string())).await? != Settlement::Stale {
+            expired += 1;
+        }
+    }
+    Ok(expired)
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("PACKAGES_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(3600).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match expire_approvals(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Declined {} package(s) not approved in time", n),
+                Err(e) => eprintln!("Package approval expiry failed: {:?}", e),
+            }
+            match warn_expiring(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Warned about {} expiring package(s)", n),
+                Err(e) => eprintln!("Package expiry warnings failed: {:?}", e),
+            }
+            match expire_due(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Expired {} package(s)", n),
+                Err(e) => eprintln!("Package expiry failed: {:?}", e),
+            }
+        }
+    });
+}
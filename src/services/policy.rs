@@ -0,0 +1,444 @@
+// Cancellation and reschedule policies.
+//
+// The platform defines templates (booking_policies, edited by admins); each
+// teacher picks one (teacher_schedules.policy_key) and every new booking
+// keeps a copy of it (bookings.policy), so later edits don't change the
+// terms of sessions already booked.
+//
+// A student (or their guardian) cancelling gets the escrow back in full at
+// least full_refund_hours ahead, partial_refund_percent of it at least
+// partial_refund_hours ahead, and nothing later; what is not refunded goes to
+// the teacher as a late cancellation fee. A teacher cancelling always
+// refunds the student in full and, with less than teacher_notice_hours
+// notice, pays teacher_penalty_percent of the escrow to the student on top,
+// as far as their wallet covers it.
+//
+// Students may move a booking max_reschedules times, no later than
+// reschedule_notice_hours ahead. Teachers may move it no later than
+// teacher_notice_hours ahead; after that they can only cancel.
+//
+// Every evaluated request is stored in booking_policy_decisions, with the
+// coins moved and the reason, whether it was allowed or not.
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+
+/// Template used when a teacher has not picked one.
+pub const DEFAULT_KEY: &str = "flexible";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Side {
+    Student,
+    Teacher,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::Student => "student",
+            Side::Teacher => "teacher",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Policy {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub full_refund_hours: i32,
+    pub partial_refund_hours: i32,
+    pub partial_refund_percent: i32,
+    pub teacher_notice_hours: i32,
+    pub teacher_penalty_percent: i32,
+    pub max_reschedules: i32,
+    pub reschedule_notice_hours: i32,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+impl Policy {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Policy {
+        Policy {
+            key: r.get("key"),
+            name: r.get("name"),
+            description: r.get("description"),
+            full_refund_hours: r.get("full_refund_hours"),
+            partial_refund_hours: r.get("partial_refund_hours"),
+            partial_refund_percent: r.get("partial_refund_percent"),
+            teacher_notice_hours: r.get("teacher_notice_hours"),
+            teacher_penalty_percent: r.get("teacher_penalty_percent"),
+            max_reschedules: r.get("max_reschedules"),
+            reschedule_notice_hours: r.get("reschedule_notice_hours"),
+            active: r.get("active"),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// The copy kept on a booking (bookings.policy).
+    pub fn from_snapshot(v: Option<&serde_json::Value>) -> Option<Policy> {
+        v.and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let valid_key = !self.key.is_empty() && self.key.len() <= 40 && self.key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_key {
+            return Err(anyhow!("key must be 1-40 lowercase letters, digits or underscores"));
+        }
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("name is required"));
+        }
+        let hours = [self.full_refund_hours, self.partial_refund_hours, self.teacher_notice_hours, self.reschedule_notice_hours];
+        if hours.iter().any(|h| *h < 0) || self.max_reschedules < 0 {
+            return Err(anyhow!("hours and reschedule limits cannot be negative"));
+        }
+        if self.partial_refund_hours > self.full_refund_hours {
+            return Err(anyhow!("partial_refund_hours cannot exceed full_refund_hours"));
+        }
+        if !(0..=100).contains(&self.partial_refund_percent) || !(0..=100).contains(&self.teacher_penalty_percent) {
+            return Err(anyhow!("percentages must be between 0 and 100"));
+        }
+        Ok(())
+    }
+}
+
+pub async fn templates(pool: &PgPool, include_retired: bool) -> sqlx::Result<Vec<Policy>> {
+    let rows = sqlx::query("SELECT * FROM booking_policies WHERE active OR $1 ORDER BY full_refund_hours, key")
+        .bind(include_retired)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(Policy::from_row).collect())
+}
+
+pub async fn get(pool: &PgPool, key: &str) -> sqlx::Result<Option<Policy>> {
+    let row = sqlx::query("SELECT * FROM booking_policies WHERE key = $1").bind(key).fetch_optional(pool).await?;
+    Ok(row.as_ref().map(Policy::from_row))
+}
+
+/// Create or update a template (admins).
+pub async fn save(pool: &PgPool, p: &Policy) -> anyhow::Result<()> {
+    p.validate()?;
+    sqlx::query(
+        "INSERT INTO booking_policies (key, name, description, full_refund_hours, partial_refund_hours, partial_refund_percent,
+                                       teacher_notice_hours, teacher_penalty_percent, max_reschedules, reschedule_notice_hours, active)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+         ON CONFLICT (key) DO UPDATE SET name = $2, description = $3, full_refund_hours = $4, partial_refund_hours = $5,
+             partial_refund_percent = $6, teacher_notice_hours = $7, teacher_penalty_percent = $8, max_reschedules = $9,
+             reschedule_notice_hours = $10, active = $11, updated_at = now()",
+    )
+    .bind(&p.key)
+    .bind(p.name.trim())
+    .bind(p.description.trim())
+    .bind(p.full_refund_hours)
+    .bind(p.partial_refund_hours)
+    .bind(p.partial_refund_percent)
+    .bind(p.teacher_notice_hours)
+    .bind(p.teacher_penalty_percent)
+    .bind(p.max_reschedules)
+    .bind(p.reschedule_notice_hours)
+    .bind(p.active)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The template the teacher currently uses.
+pub async fn for_teacher(pool: &PgPool, teacher_id: i32) -> sqlx::Result<Policy> {
+    let row = sqlx::query(
+        "SELECT * FROM booking_policies
+         WHERE key = COALESCE((SELECT policy_key FROM teacher_schedules WHERE teacher_id = $1), $2)",
+    )
+    .bind(teacher_id)
+    .bind(DEFAULT_KEY)
+    .fetch_one(pool)
+    .await?;
+    Ok(Policy::from_row(&row))
+}
+
+/// The outcome of one cancel or reschedule request.
+pub struct Decision {
+    pub action: &'static str,
+    pub side: Side,
+    pub minutes_before: i64,
+    pub allowed: bool,
+    /// coins back to the student from escrow
+    pub refund_coins: i64,
+    /// coins from escrow to the teacher
+    pub teacher_fee_coins: i64,
+    /// coins from the teacher's wallet to the student
+    pub penalty_coins: i64,
+    pub reason: String,
+}
+
+impl Decision {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "action": self.action,
+            "side": self.side.as_str(),
+            "minutes_before": self.minutes_before,
+            "allowed": self.allowed,
+            "refund_coins": self.refund_coins,
+            "teacher_fee_coins": self.teacher_fee_coins,
+            "penalty_coins": self.penalty_coins,
+            "reason": self.reason,
+        })
+    }
+
+    /// Lower the penalty to what the teacher's wallet holds.
+    pub fn cap_penalty(&mut self, available: i64) {
+        if self.penalty_coins > available {
+            self.reason.push_str(&format!(" The teacher's wallet covered {} of the {} coins.", available.max(0), self.penalty_coins));
+            self.penalty_coins = available.max(0);
+        }
+    }
+}
+
+/// "3 days 2 h" style notice.
+fn notice(minutes: i64) -> String {
+    let minutes = minutes.max(0);
+    let (days, hours, mins) = (minutes / 1440, minutes % 1440 / 60, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{} min", mins),
+        (0, h) => format!("{} h {} min", h, mins),
+        (d, h) => format!("{} day{} {} h", d, if d == 1 { "" } else { "s" }, h),
+    }
+}
+
+/// What cancelling `minutes_before` the start means with `escrow` coins held.
+pub fn evaluate_cancel(p: &Policy, side: Side, escrow: i64, minutes_before: i64) -> Decision {
+    let mut d = Decision {
+        action: "cancel",
+        side,
+        minutes_before,
+        allowed: true,
+        refund_coins: escrow,
+        teacher_fee_coins: 0,
+        penalty_coins: 0,
+        reason: String::new(),
+    };
+    let ahead = notice(minutes_before);
+    if side == Side::Teacher {
+        if minutes_before < p.teacher_notice_hours as i64 * 60 && escrow > 0 {
+            d.penalty_coins = escrow * p.teacher_penalty_percent as i64 / 100;
+            d.reason = format!(
+                "The teacher cancelled {} ahead, with less than the {} h notice the {} policy asks for: full refund plus a {}% penalty ({} coins) to the student.",
+                ahead, p.teacher_notice_hours, p.name, p.teacher_penalty_percent, d.penalty_coins
+            );
+        } else {
+            d.reason = format!("The teacher cancelled {} ahead: full refund.", ahead);
+        }
+        return d;
+    }
+    if escrow == 0 {
+        d.reason = format!("Cancelled {} ahead; nothing had been charged.", ahead);
+        return d;
+    }
+    if minutes_before >= p.full_refund_hours as i64 * 60 {
+        d.reason = format!("Cancelled {} ahead, at least {} h before: full refund under the {} policy.", ahead, p.full_refund_hours, p.name);
+    } else if minutes_before >= p.partial_refund_hours as i64 * 60 {
+        d.refund_coins = escrow * p.partial_refund_percent as i64 / 100;
+        d.teacher_fee_coins = escrow - d.refund_coins;
+        d.reason = format!(
+            "Cancelled {} ahead, less than {} h but at least {} h before: {}% refund under the {} policy; {} coins go to the teacher.",
+            ahead, p.full_refund_hours, p.partial_refund_hours, p.partial_refund_percent, p.name, d.teacher_fee_coins
+        );
+    } else {
+        d.refund_coins = 0;
+        d.teacher_fee_coins = escrow;
+        d.reason = format!(
+            "Cancelled {} ahead, less than {} h before: no refund under the {} policy; {} coins go to the teacher.",
+            ahead, p.partial_refund_hours, p.name, escrow
+        );
+    }
+    d
+}
+
+/// Whether a booking already moved `done` times may be moved `minutes_before` the start.
+pub fn evaluate_reschedule(p: &Policy, side: Side, done: i32, minutes_before: i64) -> Decision {
+    let mut d = Decision {
+        action: "reschedule",
+        side,
+        minutes_before,
+        allowed: false,
+        refund_coins: 0,
+        teacher_fee_coins: 0,
+        penalty_coins: 0,
+        reason: String::new(),
+    };
+    let ahead = notice(minutes_before);
+    d.reason = match side {
+        Side::Teacher if minutes_before < p.teacher_notice_hours as i64 * 60 => format!(
+            "Teachers can move a session up to {} h before it under the {} policy ({} left); cancel it instead.",
+            p.teacher_notice_hours, p.name, ahead
+        ),
+        Side::Teacher => {
+            d.allowed = true;
+            format!("The teacher moved the session {} ahead.", ahead)
+        }
+        Side::Student if done >= p.max_reschedules => format!(
+            "The {} policy allows {} reschedule{} per booking and this one has been moved {} time{}.",
+            p.name,
+            p.max_reschedules,
+            if p.max_reschedules == 1 { "" } else { "s" },
+            done,
+            if done == 1 { "" } else { "s" }
+        ),
+        Side::Student if minutes_before < p.reschedule_notice_hours as i64 * 60 => format!(
+            "The {} policy allows reschedules up to {} h before the session ({} left).",
+            p.name, p.reschedule_notice_hours, ahead
+        ),
+        Side::Student => {
+            d.allowed = true;
+            format!("Moved {} ahead; reschedule {} of {} under the {} policy.", ahead, done + 1, p.max_reschedules, p.name)
+        }
+    };
+    d
+}
+
+pub async fn record(tx: &mut Transaction<'_, Postgres>, booking_id: i32, actor_id: i32, policy_key: &str, d: &Decision) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO booking_policy_decisions (booking_id, action, actor_id, side, policy_key, minutes_before, allowed,
+                                               refund_coins, teacher_fee_coins, penalty_coins, reason)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+    )
+    .bind(booking_id)
+    .bind(d.action)
+    .bind(actor_id)
+    .bind(d.side.as_str())
+    .bind(policy_key)
+    .bind(d.minutes_before.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    .bind(d.allowed)
+    .bind(d.refund_coins)
+    .bind(d.teacher_fee_coins)
+    .bind(d.penalty_coins)
+    .bind(&d.reason)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Recorded decisions for a booking, oldest first.
+pub async fn decisions(pool: &PgPool, booking_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query("SELECT * FROM booking_policy_decisions WHERE booking_id = $1 ORDER BY id").bind(booking_id).fetch_all(pool).await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.get::<i32,_>("id"),
+                "action": r.get::<String,_>("action"),
+                "actor_id": r.get::<Option<i32>,_>("actor_id"),
+                "side": r.get::<String,_>("side"),
+                "policy_key": r.get::<Option<String>,_>("policy_key"),
+                "minutes_before": r.get::<i32,_>("minutes_before"),
+                "allowed": r.get::<bool,_>("allowed"),
+                "refund_coins": r.get::<i64,_>("refund_coins"),
+                "teacher_fee_coins": r.get::<i64,_>("teacher_fee_coins"),
+                "penalty_coins": r.get::<i64,_>("penalty_coins"),
+                "reason": r.get::<String,_>("reason"),
+                "created_at": r.get::<chrono::DateTime<chrono::Utc>,_>("created_at").to_rfc3339(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the seeded "moderate" template
+    fn moderate() -> Policy {
+        Policy {
+            key: "moderate".to_string(),
+            name: "Moderate".to_string(),
+            description: String::new(),
+            full_refund_hours: 48,
+            partial_refund_hours: 24,
+            partial_refund_percent: 50,
+            teacher_notice_hours: 24,
+            teacher_penalty_percent: 20,
+            max_reschedules: 2,
+            reschedule_notice_hours: 24,
+            active: true,
+        }
+    }
+
+    const HOUR: i64 = 60;
+
+    #[test]
+    fn student_refund_thresholds() {
+        let p = moderate();
+        let full = evaluate_cancel(&p, Side::Student, 100, 48 * HOUR);
+        assert_eq!((full.refund_coins, full.teacher_fee_coins, full.penalty_coins), (100, 0, 0));
+        let partial = evaluate_cancel(&p, Side::Student, 100, 48 * HOUR - 1);
+        assert_eq!((partial.refund_coins, partial.teacher_fee_coins), (50, 50));
+        let partial = evaluate_cancel(&p, Side::Student, 100, 24 * HOUR);
+        assert_eq!((partial.refund_coins, partial.teacher_fee_coins), (50, 50));
+        let none = evaluate_cancel(&p, Side::Student, 100, 24 * HOUR - 1);
+        assert_eq!((none.refund_coins, none.teacher_fee_coins), (0, 100));
+        let free = evaluate_cancel(&p, Side::Student, 0, 0);
+        assert_eq!((free.refund_coins, free.teacher_fee_coins), (0, 0));
+        assert!([full, partial, none, free].iter().all(|d| d.allowed && d.penalty_coins == 0));
+    }
+
+    #[test]
+    fn refund_and_fee_add_up_to_escrow() {
+        let p = moderate();
+        for escrow in [0, 1, 7, 99, 100, 12345] {
+            for minutes in [0, 23 * HOUR, 24 * HOUR, 47 * HOUR, 48 * HOUR, 200 * HOUR] {
+                for side in [Side::Student, Side::Teacher] {
+                    let d = evaluate_cancel(&p, side, escrow, minutes);
+                    assert_eq!(d.refund_coins + d.teacher_fee_coins, escrow, "{} coins, {} min", escrow, minutes);
+                    assert!(d.refund_coins >= 0 && d.teacher_fee_coins >= 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn teacher_penalty_is_capped_by_wallet() {
+        let p = moderate();
+        let early = evaluate_cancel(&p, Side::Teacher, 100, 24 * HOUR);
+        assert_eq!((early.refund_coins, early.penalty_coins), (100, 0));
+        let late = evaluate_cancel(&p, Side::Teacher, 100, 24 * HOUR - 1);
+        assert_eq!((late.refund_coins, late.teacher_fee_coins, late.penalty_coins), (100, 0, 20));
+        assert_eq!(evaluate_cancel(&p, Side::Teacher, 0, 0).penalty_coins, 0);
+
+        let mut covered = evaluate_cancel(&p, Side::Teacher, 100, 0);
+        let reason = covered.reason.clone();
+        covered.cap_penalty(20);
+        assert_eq!((covered.penalty_coins, covered.reason), (20, reason));
+        let mut short = evaluate_cancel(&p, Side::Teacher, 100, 0);
+        short.cap_penalty(5);
+        assert_eq!(short.penalty_coins, 5);
+        assert!(short.reason.contains("covered 5 of the 20 coins"));
+        let mut overdrawn = evaluate_cancel(&p, Side::Teacher, 100, 0);
+        overdrawn.cap_penalty(-3);
+        assert_eq!(overdrawn.penalty_coins, 0);
+    }
+
+    #[test]
+    fn reschedule_limits_per_side() {
+        let p = moderate();
+        assert!(evaluate_reschedule(&p, Side::Student, 0, 24 * HOUR).allowed);
+        assert!(evaluate_reschedule(&p, Side::Student, 1, 24 * HOUR).allowed);
+        assert!(!evaluate_reschedule(&p, Side::Student, 2, 200 * HOUR).allowed);
+        assert!(!evaluate_reschedule(&p, Side::Student, 0, 24 * HOUR - 1).allowed);
+
+        // teachers have no count limit, only their notice
+        assert!(evaluate_reschedule(&p, Side::Teacher, 10, 24 * HOUR).allowed);
+        assert!(!evaluate_reschedule(&p, Side::Teacher, 0, 24 * HOUR - 1).allowed);
+
+        let d = evaluate_reschedule(&p, Side::Student, 0, 30 * HOUR);
+        assert_eq!((d.refund_coins, d.teacher_fee_coins, d.penalty_coins), (0, 0, 0));
+    }
+}
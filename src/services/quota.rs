@@ -0,0 +1,121 @@
+// Per-user storage accounting and quota enforcement.
+//
+// Avatars, support attachments and notification attachments (charged to the
+// sending admin) count. Usage is kept as running counters in `storage_usage`,
+// bumped when a file is attached to a row and decremented when it is removed.
+// `recalculate` rebuilds the counters from the referenced files on disk if
+// they ever drift.
+//
+// Quotas: total per user = users.storage_quota_bytes or STORAGE_QUOTA_BYTES
+// (default 2 GiB). Optional per-category caps via STORAGE_QUOTA_<CATEGORY>_BYTES,
+// e.g. STORAGE_QUOTA_NOTIFICATION_BYTES.
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+
+const DEFAULT_QUOTA_BYTES: i64 = 2 * 1024 * 1024 * 1024;
+
+pub fn default_quota_bytes() -> i64 {
+    std::env::var("STORAGE_QUOTA_BYTES").ok().and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_QUOTA_BYTES)
+}
+
+fn category_cap(category: &str) -> Option<i64> {
+    std::env::var(format!("STORAGE_QUOTA_{}_BYTES", category.to_uppercase())).ok().and_then(|s| s.parse().ok())
+}
+
+/// Size of a stored file on disk, 0 if it is gone.
+pub fn file_size(path: &str) -> i64 {
+    std::fs::metadata(path.replace('\\', "/")).map(|m| m.len() as i64).unwrap_or(0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub used_bytes: i64,
+    pub quota_bytes: i64,
+    pub by_category: HashMap<String, i64>,
+}
+
+pub async fn usage(pool: &PgPool, user_id: i32) -> sqlx::Result<Usage> {
+    let quota_bytes = sqlx::query("SELECT storage_quota_bytes FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|r| r.try_get::<Option<i64>, _>("storage_quota_bytes").ok().flatten())
+        .unwrap_or_else(default_quota_bytes);
+
+    let rows = sqlx::query("SELECT category, bytes FROM storage_usage WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    let mut by_category = HashMap::new();
+    let mut used_bytes = 0;
+    for r in rows {
+        let bytes: i64 = r.get("bytes");
+        used_bytes += bytes;
+        by_category.insert(r.get::<String, _>("category"), bytes);
+    }
+    Ok(Usage { used_bytes, quota_bytes, by_category })
+}
+
+/// Bytes `user_id` may still store in `category` (the smaller of the total and category headroom).
+pub async fn remaining(pool: &PgPool, user_id: i32, category: &str) -> sqlx::Result<u64> {
+    let u = usage(pool, user_id).await?;
+    let mut left = u.quota_bytes - u.used_bytes;
+    if let Some(cap) = category_cap(category) {
+        let used_in_cat = u.by_category.get(category).copied().unwrap_or(0);
+        left = left.min(cap - used_in_cat);
+    }
+    Ok(left.max(0) as u64)
+}
+
+/// Adjust the counters for `user_id`/`category`. Never goes below zero.
+pub async fn record(pool: &PgPool, user_id: i32, category: &str, delta_bytes: i64, delta_files: i32) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO storage_usage (user_id, category, bytes, files) VALUES ($1, $2, GREATEST($3, 0), GREATEST($4, 0))
+         ON CONFLICT (user_id, category) DO UPDATE
+         SET bytes = GREATEST(storage_usage.bytes + $3, 0), files = GREATEST(storage_usage.files + $4, 0), updated_at = now()",
+    )
+    .bind(user_id)
+    .bind(category)
+    .bind(delta_bytes)
+    .bind(delta_files)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Rebuild all counters from the files currently referenced in the DB.
+pub async fn recalculate(pool: &PgPool) -> Result<usize> {
+    let rows = sqlx::query(
+        "SELECT id AS user_id, 'avatar' AS category, avatar_path AS p FROM users WHERE avatar_path IS NOT NULL
+         UNION ALL SELECT author_id, 'support', attachment_path FROM support_messages WHERE attachment_path IS NOT NULL AND author_id IS NOT NULL
+         UNION ALL SELECT sender_id, 'notification', attachment_path FROM notifications WHERE attachment_path IS NOT NULL AND sender_id IS NOT NULL
+         UNION ALL SELECT sender_id, 'notification', attachment_path FROM notification_campaigns WHERE attachment_path IS NOT NULL AND sender_id IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals: HashMap<(i32, String), (i64, i32)> = HashMap::new();
+    for r in rows {
+        let path: String = r.get("p");
+        let entry = totals.entry((r.get("user_id"), r.get("category"))).or_insert((0, 0));
+        entry.0 += file_size(&path);
+        entry.1 += 1;
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM storage_usage").execute(&mut *tx).await?;
+    for ((user_id, category), (bytes, files)) in &totals {
+        sqlx::query("INSERT INTO storage_usage (user_id, category, bytes, files) VALUES ($1, $2, $3, $4)")
+            .bind(user_id)
+            .bind(category)
+            .bind(bytes)
+            .bind(files)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(totals.len())
+}
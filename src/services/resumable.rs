@@ -0,0 +1,97 @@
+// Resumable upload bookkeeping: partial file layout, consuming finished
+// uploads from the regular handlers, and expiry of abandoned sessions.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+pub const PARTIAL_DIR: &str = "uploads/.partial";
+
+/// Categories that accept resumable uploads (and have a handler that consumes them).
+pub const RESUMABLE_CATEGORIES: &[&str] = &["notification", "support"];
+
+/// Largest single chunk accepted by the append endpoint.
+pub const MAX_CHUNK_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Sliding lifetime of a session since its last activity (RESUMABLE_UPLOAD_TTL_SECONDS, default 24h).
+pub fn ttl_secs() -> i64 {
+    std::env::var("RESUMABLE_UPLOAD_TTL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(24 * 3600)
+}
+
+pub fn partial_path(id: &str) -> PathBuf {
+    let dir = Path::new(PARTIAL_DIR);
+    if !dir.exists() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    dir.join(format!("{}.part", id))
+}
+
+/// Hex SHA-256 of a file, read in blocks.
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut f = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hand a completed upload over to a regular handler. Returns the stored path
+/// if `id` is a completed upload owned by `user_id` in `category`; the session
+/// is marked consumed so it can only be attached once.
+pub async fn take_completed(pool: &PgPool, id: &str, user_id: i32, category: &str) -> sqlx::Result<Option<String>> {
+    let row = sqlx::query(
+        "UPDATE upload_sessions SET status = 'consumed', updated_at = now()
+         WHERE id = $1 AND user_id = $2 AND category = $3 AND status = 'completed'
+         RETURNING stored_path",
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(category)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|r| r.try_get::<String, _>("stored_path").ok()))
+}
+
+/// Expire sessions past their deadline and remove their files. Completed but
+/// never attached uploads are dropped too.
+pub async fn expire_abandoned(pool: &PgPool) -> Result<usize> {
+    let rows = sqlx::query(
+        "UPDATE upload_sessions SET status = 'expired', updated_at = now()
+         WHERE status IN ('pending', 'completed') AND expires_at < now()
+         RETURNING id, stored_path",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for r in &rows {
+        let id: String = r.get("id");
+        let _ = tokio::fs::remove_file(partial_path(&id)).await;
+        if let Ok(p) = r.try_get::<String, _>("stored_path") {
+            let _ = tokio::fs::remove_file(p).await;
+        }
+    }
+    Ok(rows.len())
+}
+
+pub fn spawn_background(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(15 * 60));
+        loop {
+            ticker.tick().await;
+            match expire_abandoned(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Expired {} abandoned upload session(s)", n),
+                Err(e) => eprintln!("Upload session expiry failed: {:?}", e),
+            }
+        }
+    });
+}
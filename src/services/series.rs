@@ -0,0 +1,338 @@
+// Recurring bookings.
+//
+// A series books the same teacher at the same local time every
+// interval_weeks weeks, `occurrences` times. Times are kept in the teacher's
+// timezone, so a Tuesday 17:00 lesson stays at 17:00 across DST changes.
+// Every occurrence is an ordinary booking (bookings.rs) with its own escrow,
+// paid from the series' package or, without one, from the wallet when it is
+// booked.
+//
+// The first occurrence is booked when the series is created; the others are
+// booked by the bookings job once they come within the teacher's booking
+// horizon. An occurrence that cannot be booked (the slot is taken, the
+// wallet is short, the package is used up) is passed over and the student
+// is told. Students skip single occurrences (cancelled under the booking's
+// policy when already booked) and move them like any booking; cancelling
+// the series cancels its upcoming bookings and books no more.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+
+use crate::services::availability;
+use crate::services::bookings::{self, when, Booking, Origin};
+use crate::services::guardian;
+use crate::services::notify_prefs;
+use crate::services::packages;
+
+pub struct Series {
+    pub id: i32,
+    pub student_id: i32,
+    pub teacher_id: i32,
+    pub package_id: Option<i32>,
+    pub first_starts_at: DateTime<Utc>,
+    pub interval_weeks: i32,
+    pub occurrences: i32,
+    pub next_index: i32,
+    pub skipped: Vec<i32>,
+    pub status: String,
+}
+
+impl Series {
+    fn from_row(r: &sqlx::postgres::PgRow) -> Series {
+        Series {
+            id: r.get("id"),
+            student_id: r.get("student_id"),
+            teacher_id: r.get("teacher_id"),
+            package_id: r.get("package_id"),
+            first_starts_at: r.get("first_starts_at"),
+            interval_weeks: r.get("interval_weeks"),
+            occurrences: r.get("occurrences"),
+            next_index: r.get("next_index"),
+            skipped: r.get("skipped"),
+            status: r.get("status"),
+        }
+    }
+
+    /// Start of occurrence `index`, at the first one's wall time in `tz`.
+    fn start_of(&self, index: i32, tz: Tz) -> DateTime<Utc> {
+        let local = self.first_starts_at.with_timezone(&tz).naive_local() + ChronoDuration::weeks((index * self.interval_weeks) as i64);
+        availability::resolve(&tz, local, false)
+    }
+}
+
+async fn load(pool: &PgPool, id: i32) -> sqlx::Result<Option<Series>> {
+    let row = sqlx::query("SELECT * FROM booking_series WHERE id = $1").bind(id).fetch_optional(pool).await?;
+    Ok(row.as_ref().map(Series::from_row))
+}
+
+/// The series if `user_id` is its student, teacher or one of the student's
+/// guardians.
+async fn visible(pool: &PgPool, id: i32, user_id: i32) -> anyhow::Result<Series> {
+    let s = load(pool, id).await?.filter(|s| s.status != "pending").ok_or_else(|| anyhow!("series not found"))?;
+    if s.student_id == user_id || s.teacher_id == user_id || guardian::is_guardian_of(pool, user_id, s.student_id).await? {
+        Ok(s)
+    } else {
+        Err(anyhow!("series not found"))
+    }
+}
+
+/// Details of a new series.
+pub struct NewSeries {
+    pub teacher_id: i32,
+    /// the first occurrence, an open slot of the teacher
+    pub starts_at: DateTime<Utc>,
+    pub interval_weeks: i32,
+    /// defaults to the sessions left in the package
+    pub occurrences: Option<i32>,
+    pub package_id: Option<i32>,
+}
+
+/// Start a series for `student_id` and book its first occurrence.
+pub async fn create(pool: &PgPool, student_id: i32, n: &NewSeries) -> anyhow::Result<(Series, Booking)> {
+    if !(1..=4).contains(&n.interval_weeks) {
+        return Err(anyhow!("interval_weeks must be between 1 and 4"));
+    }
+    let occurrences = match (n.occurrences, n.package_id) {
+        (Some(count), _) => count,
+        (None, Some(package_id)) => {
+            let p = packages::get(pool, package_id).await?.filter(|p| p.student_id == student_id).ok_or_else(|| anyhow!("package not found"))?;
+            p.sessions_left
+        }
+        (None, None) => return Err(anyhow!("give the number of occurrences")),
+    };
+    if !(2..=104).contains(&occurrences) {
+        return Err(anyhow!("a series has 2 to 104 occurrences"));
+    }
+    // pending until the first booking succeeds, so a failed one is never extended
+    let row = sqlx::query(
+        "INSERT INTO booking_series (student_id, teacher_id, package_id, first_starts_at, interval_weeks, occurrences, next_index, status)
+         VALUES ($1, $2, $3, $4, $5, $6, 1, 'pending') RETURNING *",
+    )
+    .bind(student_id)
+    .bind(n.teacher_id)
+    .bind(n.package_id)
+    .bind(n.starts_at)
+    .bind(n.interval_weeks)
+    .bind(occurrences)
+    .fetch_one(pool)
+    .await?;
+    let mut series = Series::from_row(&row);
+    let origin = Origin { package_id: series.package_id, series: Some((series.id, 0)) };
+    let first = match bookings::create(pool, student_id, n.teacher_id, n.starts_at, origin).await {
+        Ok(b) => b,
+        Err(e) => {
+            // only tidying up: a pending series is never booked or listed
+            if let Err(cleanup) = sqlx::query("DELETE FROM booking_series WHERE id = $1").bind(series.id).execute(pool).await {
+                eprintln!("Series {} cleanup failed: {:?}", series.id, cleanup);
+            }
+            return Err(e);
+        }
+    };
+    sqlx::query("UPDATE booking_series SET status = 'active', updated_at = now() WHERE id = $1").bind(series.id).execute(pool).await?;
+    series.status = "active".to_string();
+    if let Err(e) = extend(pool, series.id).await {
+        eprintln!("Series {} booking failed: {:?}", series.id, e);
+    }
+    Ok((series, first))
+}
+
+/// Book the series' occurrences that have come within the teacher's horizon.
+/// Returns how many were booked.
+pub async fn extend(pool: &PgPool, id: i32) -> anyhow::Result<usize> {
+    let mut booked = 0;
+    loop {
+        let Some(s) = load(pool, id).await? else { break };
+        if s.status != "active" {
+            break;
+        }
+        if s.next_index >= s.occurrences {
+            sqlx::query("UPDATE booking_series SET status = 'finished', updated_at = now() WHERE id = $1 AND status = 'active'").bind(s.id).execute(pool).await?;
+            break;
+        }
+        let schedule = availability::schedule(pool, s.teacher_id).await?;
+        let index = s.next_index;
+        let at = s.start_of(index, schedule.timezone);
+        if at > Utc::now() + ChronoDuration::days(schedule.horizon_days as i64) {
+            break;
+        }
+        // claim the occurrence first, so no other run books it and a skip
+        // can no longer race with booking it
+        let claimed = sqlx::query(
+            "UPDATE booking_series SET next_index = $2 + 1, updated_at = now() WHERE id = $1 AND next_index = $2 AND status = 'active'
+             RETURNING $2 = ANY(skipped) AS skipped",
+        )
+        .bind(s.id)
+        .bind(index)
+        .fetch_optional(pool)
+        .await?;
+        if claimed.map(|r| r.get::<bool, _>("skipped")).unwrap_or(true) || at <= Utc::now() {
+            continue;
+        }
+        let origin = Origin { package_id: s.package_id, series: Some((s.id, index)) };
+        match bookings::create(pool, s.student_id, s.teacher_id, at, origin).await {
+            Ok(_) => booked += 1,
+            Err(e) => {
+                let tz = availability::user_tz(pool, s.student_id).await.unwrap_or(Tz::UTC);
+                let body = format!(
+                    "Session {} of your recurring series on {} could not be booked: {}. The rest of the series goes on.",
+                    index + 1,
+                    when(at, tz),
+                    e
+                );
+                if let Err(e) = notify_prefs::notify(pool, s.student_id, None, "booking", "high", "Recurring session not booked", &body).await {
+                    eprintln!("Series {} notification failed: {:?}", s.id, e);
+                }
+            }
+        }
+    }
+    Ok(booked)
+}
+
+/// Extend every active series; called by the bookings job.
+pub async fn extend_due(pool: &PgPool) -> anyhow::Result<usize> {
+    let ids: Vec<i32> = sqlx::query("SELECT id FROM booking_series WHERE status = 'active' ORDER BY id")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+    let mut booked = 0;
+    for id in ids {
+        match extend(pool, id).await {
+            Ok(n) => booked += n,
+            Err(e) => eprintln!("Series {} booking failed: {:?}", id, e),
+        }
+    }
+    Ok(booked)
+}
+
+/// Skip occurrence `index`: cancel its booking under the booking's policy,
+/// or make sure it is never booked.
+pub async fn skip(pool: &PgPool, id: i32, actor_id: i32, index: i32) -> anyhow::Result<serde_json::Value> {
+    let s = visible(pool, id, actor_id).await?;
+    if actor_id == s.teacher_id {
+        return Err(anyhow!("teachers cancel single sessions from their bookings"));
+    }
+    if s.status == "cancelled" || !(0..s.occurrences).contains(&index) {
+        return Err(anyhow!("no such upcoming occurrence"));
+    }
+    let not_yet_booked = sqlx::query(
+        "UPDATE booking_series SET skipped = array_append(skipped, $2), updated_at = now()
+         WHERE id = $1 AND status = 'active' AND next_index <= $2 AND NOT ($2 = ANY(skipped))",
+    )
+    .bind(s.id)
+    .bind(index)
+    .execute(pool)
+    .await?;
+    if not_yet_booked.rows_affected() == 1 || (index >= s.next_index && s.skipped.contains(&index)) {
+        return Ok(json!({"index": index, "booking_id": null}));
+    }
+    let row = sqlx::query("SELECT id FROM bookings WHERE series_id = $1 AND series_index = $2 AND status IN ('awaiting_approval', 'confirmed')")
+        .bind(s.id)
+        .bind(index)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow!("that occurrence has passed or was not booked"))?;
+    let (b, decision) = bookings::cancel(pool, row.get("id"), actor_id, Some("skipped this occurrence")).await?;
+    Ok(json!({"index": index, "booking_id": b.id, "decision": decision.to_json()}))
+}
+
+/// Stop the series and cancel its upcoming bookings, each under its policy.
+pub async fn cancel(pool: &PgPool, id: i32, actor_id: i32, reason: Option<&str>) -> anyhow::Result<serde_json::Value> {
+    let s = visible(pool, id, actor_id).await?;
+    let res = sqlx::query("UPDATE booking_series SET status = 'cancelled', updated_at = now() WHERE id = $1 AND status IN ('active', 'finished')")
+        .bind(s.id)
+        .execute(pool)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Err(anyhow!("the series is already cancelled"));
+    }
+    let rows = sqlx::query(
+        "SELECT id FROM bookings WHERE series_id = $1 AND status IN ('awaiting_approval', 'confirmed') AND starts_at > now() ORDER BY starts_at",
+    )
+    .bind(s.id)
+    .fetch_all(pool)
+    .await?;
+    let mut results = Vec::new();
+    for r in &rows {
+        let booking_id: i32 = r.get("id");
+        results.push(match bookings::cancel(pool, booking_id, actor_id, reason).await {
+            Ok((_, decision)) => json!({"booking_id": booking_id, "decision": decision.to_json()}),
+            Err(e) => json!({"booking_id": booking_id, "error": e.to_string()}),
+        });
+    }
+    Ok(json!({"cancelled": results}))
+}
+
+fn summary(r: &sqlx::postgres::PgRow, tz: Tz) -> serde_json::Value {
+    let s = Series::from_row(r);
+    json!({
+        "id": s.id,
+        "student_id": s.student_id,
+        "student_name": r.get::<String, _>("student_name"),
+        "teacher_id": s.teacher_id,
+        "teacher_name": r.get::<String, _>("teacher_name"),
+        "package_id": s.package_id,
+        "first_starts_at": s.first_starts_at.with_timezone(&tz).to_rfc3339(),
+        "interval_weeks": s.interval_weeks,
+        "occurrences": s.occurrences,
+        "status": s.status,
+    })
+}
+
+// $1 = the viewer
+const SUMMARY: &str = "SELECT r.*, shown_name(s, $1) AS student_name, shown_name(t, $1) AS teacher_name
+    FROM booking_series r JOIN users s ON s.id = r.student_id JOIN users t ON t.id = r.teacher_id";
+
+/// Series of `user_id` as student or teacher, newest first.
+pub async fn list_for(pool: &PgPool, user_id: i32) -> sqlx::Result<Vec<serde_json::Value>> {
+    let tz = availability::user_tz(pool, user_id).await?;
+    let rows = sqlx::query(&format!("{} WHERE (r.student_id = $1 OR r.teacher_id = $1) AND r.status <> 'pending' ORDER BY r.id DESC LIMIT 100", SUMMARY))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(|r| summary(r, tz)).collect())
+}
+
+/// The series with every occurrence: its booking, or whether it was
+/// skipped, passed over or is still to be booked.
+pub async fn detail(pool: &PgPool, id: i32, viewer: i32) -> anyhow::Result<serde_json::Value> {
+    let s = visible(pool, id, viewer).await?;
+    let tz = availability::user_tz(pool, viewer).await?;
+    let teacher_tz = availability::schedule(pool, s.teacher_id).await?.timezone;
+    let row = sqlx::query(&format!("{} WHERE r.id = $2", SUMMARY)).bind(viewer).bind(s.id).fetch_one(pool).await?;
+    let booked = sqlx::query("SELECT id, series_index, starts_at, status, escrow_coins FROM bookings WHERE series_id = $1 ORDER BY id")
+        .bind(s.id)
+        .fetch_all(pool)
+        .await?;
+    let occurrences: Vec<serde_json::Value> = (0..s.occurrences)
+        .map(|index| {
+            // the latest booking of the occurrence, e.g. after a cancellation
+            match booked.iter().rev().find(|b| b.get::<Option<i32>, _>("series_index") == Some(index)) {
+                Some(b) => json!({
+                    "index": index,
+                    "starts_at": b.get::<DateTime<Utc>, _>("starts_at").with_timezone(&tz).to_rfc3339(),
+                    "status": b.get::<String, _>("status"),
+                    "booking_id": b.get::<i32, _>("id"),
+                    "escrow_coins": b.get::<i64, _>("escrow_coins"),
+                }),
+                None => {
+                    let status = if s.skipped.contains(&index) {
+                        "skipped"
+                    } else if index < s.next_index || s.status == "cancelled" {
+                        "not_booked"
+                    } else {
+                        "scheduled"
+                    };
+                    json!({"index": index, "starts_at": s.start_of(index, teacher_tz).with_timezone(&tz).to_rfc3339(), "status": status, "booking_id": null})
+                }
+            }
+        })
+        .collect();
+    let mut v = summary(&row, tz);
+    v["occurrence_list"] = json!(occurrences);
+    Ok(v)
+}
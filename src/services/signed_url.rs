@@ -0,0 +1,111 @@
+// HMAC-signed, time-limited download URLs for stored files.
+//
+// A signed URL looks like `/files/{kind}/{id}/{name}?exp={unix}&sig={hex}`.
+// The signature covers kind, id and expiry; `name` is only there so clients
+// can sniff the type from the extension. Works without the session cookie,
+// so it can be embedded in emails and fetched with HEAD/Range.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use sha2::Sha256;
+use std::path::Path;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Kinds of stored file that can be served through a signed URL.
+pub const KINDS: &[&str] = &["notification", "support", "support-message", "avatar", "kyc-front", "kyc-back"];
+
+// Fallback key when neither SIGNED_URL_SECRET nor SECRET_KEY is available
+// (URLs then only live as long as the process).
+static EPHEMERAL_KEY: Lazy<Vec<u8>> = Lazy::new(|| {
+    let mut k = vec![0u8; 32];
+    rand::thread_rng().fill_bytes(&mut k);
+    k
+});
+
+fn signing_key() -> Vec<u8> {
+    if let Ok(hex_key) = std::env::var("SIGNED_URL_SECRET") {
+        if let Ok(bytes) = hex::decode(hex_key.trim()) {
+            if !bytes.is_empty() {
+                return bytes;
+            }
+        }
+    }
+    match crate::SECRET_KEY_CELL.get() {
+        Some(k) => k.signing().to_vec(),
+        None => EPHEMERAL_KEY.clone(),
+    }
+}
+
+/// Default lifetime for issued URLs (SIGNED_URL_TTL_SECONDS, default 1h).
+pub fn default_ttl_secs() -> i64 {
+    std::env::var("SIGNED_URL_TTL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(3600)
+}
+
+fn mac_for(kind: &str, id: i32, exp: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&signing_key()).expect("HMAC accepts any key length");
+    mac.update(format!("{}:{}:{}", kind, id, exp).as_bytes());
+    mac
+}
+
+/// Build a signed URL for `kind`/`id` valid for `ttl_secs`. `stored_path` is
+/// used only to derive a display file name.
+pub fn sign(kind: &str, id: i32, stored_path: &str, ttl_secs: i64) -> String {
+    let exp = Utc::now().timestamp() + ttl_secs;
+    let sig = hex::encode(mac_for(kind, id, exp).finalize().into_bytes());
+    let normalized = stored_path.replace('\\', "/");
+    let name = Path::new(&normalized)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "file".to_string());
+    format!("/files/{}/{}/{}?exp={}&sig={}", kind, id, urlencode(&name), exp, sig)
+}
+
+/// Shorthand for `sign` with the configured default TTL.
+pub fn sign_default(kind: &str, id: i32, stored_path: &str) -> String {
+    sign(kind, id, stored_path, default_ttl_secs())
+}
+
+/// Check a signature and expiry. Comparison is constant-time.
+pub fn verify(kind: &str, id: i32, exp: i64, sig_hex: &str) -> bool {
+    if exp < Utc::now().timestamp() {
+        return false;
+    }
+    let sig = match hex::decode(sig_hex) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    mac_for(kind, id, exp).verify_slice(&sig).is_ok()
+}
+
+/// Non-expiring token binding `purpose` to `subject`, for links that must
+/// keep working without a session (e.g. email unsubscribe).
+pub fn token(purpose: &str, subject: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(&signing_key()).expect("HMAC accepts any key length");
+    mac.update(format!("token:{}:{}", purpose, subject).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_token(purpose: &str, subject: &str, sig_hex: &str) -> bool {
+    let sig = match hex::decode(sig_hex) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(&signing_key()).expect("HMAC accepts any key length");
+    mac.update(format!("token:{}:{}", purpose, subject).as_bytes());
+    mac.verify_slice(&sig).is_ok()
+}
+
+pub(crate) fn urlencode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
@@ -0,0 +1,139 @@
+// Shared storage and validation rules for uploaded files.
+//
+// Multipart handlers and the resumable upload endpoints both go through the
+// category table below so size/type limits and on-disk layout stay in one place.
+
+use actix_multipart::Field;
+use actix_web::HttpResponse;
+use futures_util::StreamExt;
+use sanitize_filename::sanitize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tokio::fs::File as TokioFile;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+pub struct Category {
+    pub name: &'static str,
+    pub dir: &'static str,
+    pub max_bytes: u64,
+    /// Restrict to image/* content types
+    pub image_only: bool,
+}
+
+pub const NOTIFICATION: Category = Category { name: "notification", dir: "uploads/notifications", max_bytes: 500 * 1024 * 1024, image_only: false };
+pub const SUPPORT: Category = Category { name: "support", dir: "uploads/support_requests", max_bytes: 25 * 1024 * 1024, image_only: false };
+pub const AVATAR: Category = Category { name: "avatar", dir: "uploads/profile_pics", max_bytes: 5 * 1024 * 1024, image_only: true };
+pub const KYC: Category = Category { name: "kyc", dir: "uploads/teacher_ids", max_bytes: 25 * 1024 * 1024, image_only: true };
+
+pub fn category(name: &str) -> Option<&'static Category> {
+    match name {
+        "notification" => Some(&NOTIFICATION),
+        "support" => Some(&SUPPORT),
+        "avatar" => Some(&AVATAR),
+        "kyc" => Some(&KYC),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    TooLarge(u64),
+    QuotaExceeded(u64),
+    BadType,
+    Multipart(String),
+    Io(String),
+}
+
+impl UploadError {
+    pub fn to_response(&self) -> HttpResponse {
+        match self {
+            UploadError::TooLarge(max) => HttpResponse::BadRequest().json(json!({"error": format!("file too large (max {}MB)", max / (1024 * 1024))})),
+            UploadError::QuotaExceeded(left) => HttpResponse::PayloadTooLarge().json(json!({"error": "storage quota exceeded", "remaining_bytes": left})),
+            UploadError::BadType => HttpResponse::BadRequest().json(json!({"error": "only image uploads allowed"})),
+            UploadError::Multipart(e) => HttpResponse::BadRequest().json(json!({"error": format!("upload chunk: {}", e)})),
+            UploadError::Io(e) => HttpResponse::InternalServerError().json(json!({"error": format!("write failed: {}", e)})),
+        }
+    }
+}
+
+/// Validate declared metadata before any bytes are accepted.
+pub fn validate(cat: &Category, content_type: &str, size: u64) -> Result<(), UploadError> {
+    if size > cat.max_bytes {
+        return Err(UploadError::TooLarge(cat.max_bytes));
+    }
+    if cat.image_only && !content_type.to_lowercase().starts_with("image/") {
+        return Err(UploadError::BadType);
+    }
+    Ok(())
+}
+
+/// Destination for a new file in `cat`, keeping the sanitized original name.
+pub fn destination(cat: &Category, original_name: Option<&str>) -> PathBuf {
+    let dir = Path::new(cat.dir);
+    if !dir.exists() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let safe_name = original_name
+        .map(sanitize)
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| format!("file-{}.bin", Uuid::new_v4()));
+    dir.join(format!("{}-{}", Uuid::new_v4(), safe_name))
+}
+
+pub struct StoredFile {
+    pub path: String,
+    pub bytes: u64,
+}
+
+/// Stream a multipart file field to disk under `cat`, enforcing its limits
+/// and the uploader's remaining quota. Partial files are removed on failure.
+pub async fn save_field(field: &mut Field, cat: &Category, quota_left: u64) -> Result<StoredFile, UploadError> {
+    let content_type = field.content_type().to_string();
+    validate(cat, &content_type, 0)?;
+    let original = field.content_disposition().get_filename().map(|s| s.to_string());
+    let full_path = destination(cat, original.as_deref());
+
+    let mut f = TokioFile::create(&full_path).await.map_err(|e| UploadError::Io(e.to_string()))?;
+    let mut total: u64 = 0;
+    while let Some(chunk) = field.next().await {
+        let data = match chunk {
+            Ok(d) => d,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&full_path).await;
+                return Err(UploadError::Multipart(e.to_string()));
+            }
+        };
+        total += data.len() as u64;
+        if total > cat.max_bytes {
+            let _ = tokio::fs::remove_file(&full_path).await;
+            return Err(UploadError::TooLarge(cat.max_bytes));
+        }
+        if total > quota_left {
+            let _ = tokio::fs::remove_file(&full_path).await;
+            return Err(UploadError::QuotaExceeded(quota_left));
+        }
+        if let Err(e) = f.write_all(&data).await {
+            let _ = tokio::fs::remove_file(&full_path).await;
+            return Err(UploadError::Io(e.to_string()));
+        }
+    }
+
+    Ok(StoredFile { path: full_path.to_string_lossy().to_string(), bytes: total })
+}
+
+/// Write an in-memory file (e.g. an email attachment) under `cat` with the
+/// same limits as `save_field`.
+pub async fn save_bytes(cat: &Category, original_name: Option<&str>, content_type: &str, data: &[u8], quota_left: u64) -> Result<StoredFile, UploadError> {
+    let size = data.len() as u64;
+    validate(cat, content_type, size)?;
+    if size > quota_left {
+        return Err(UploadError::QuotaExceeded(quota_left));
+    }
+    let full_path = destination(cat, original_name);
+    if let Err(e) = tokio::fs::write(&full_path, data).await {
+        let _ = tokio::fs::remove_file(&full_path).await;
+        return Err(UploadError::Io(e.to_string()));
+    }
+    Ok(StoredFile { path: full_path.to_string_lossy().to_string(), bytes: size })
+}
@@ -0,0 +1,281 @@
+// Support ticket threads and their status workflow.
+//
+//   new -> open -> pending_user -> resolved -> closed
+//
+// `open` means staff is working on it, `pending_user` that staff answered and
+// waits for the user. A user reply moves pending_user/resolved back to open.
+// Closed tickets are final; the user opens a new one.
+//
+// SLA timers (services/support_sla.rs) run from creation; the resolution clock
+// is paused while the ticket is pending_user or resolved.
+
+use anyhow::{anyhow, Result};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+
+use crate::services::{notify_prefs, support_mail, support_sla};
+
+pub const STATUSES: &[&str] = &["new", "open", "pending_user", "resolved", "closed"];
+
+/// Ticket priorities, lowest first. Users may pick up to `high`.
+pub const PRIORITIES: &[&str] = &["low", "normal", "high", "urgent"];
+pub const USER_PRIORITIES: &[&str] = &["low", "normal", "high"];
+
+/// Allowed single-step status changes.
+pub fn can_transition(from: &str, to: &str) -> bool {
+    matches!(
+        (from, to),
+        ("new", "open")
+            | ("open", "pending_user")
+            | ("open", "resolved")
+            | ("pending_user", "open")
+            | ("pending_user", "resolved")
+            | ("resolved", "open")
+            | ("resolved", "closed")
+    )
+}
+
+/// Status after a staff reply. Answering a new ticket implicitly opens it;
+/// `requested` lets staff reply and resolve in one go.
+pub fn status_after_staff_reply(current: &str, requested: Option<&str>) -> Result<&'static str> {
+    if current == "closed" {
+        return Err(anyhow!("ticket is closed"));
+    }
+    let from = if current == "new" { "open" } else { current };
+    let to = match requested {
+        Some(r) => r,
+        None if current == "resolved" => "resolved",
+        None => "pending_user",
+    };
+    let to = STATUSES.iter().find(|s| **s == to).copied().ok_or_else(|| anyhow!("invalid status"))?;
+    if from == to || can_transition(from, to) {
+        Ok(to)
+    } else {
+        Err(anyhow!("cannot move ticket from {} to {}", current, to))
+    }
+}
+
+/// Status after the ticket owner replies.
+pub fn status_after_user_reply(current: &str) -> Result<&'static str> {
+    match current {
+        "new" => Ok("new"),
+        "open" | "pending_user" | "resolved" => Ok("open"),
+        _ => Err(anyhow!("ticket is closed, please open a new one")),
+    }
+}
+
+pub struct Ticket {
+    pub id: i32,
+    pub user_id: i32,
+    pub status: String,
+    pub subject: Option<String>,
+    pub assignee_id: Option<i32>,
+}
+
+pub async fn ticket(pool: &PgPool, id: i32) -> sqlx::Result<Option<Ticket>> {
+    let row = sqlx::query("SELECT id, user_id, status, subject, assignee_id FROM support_requests WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|r| Ticket {
+        id: r.get("id"),
+        user_id: r.get("user_id"),
+        status: r.get("status"),
+        subject: r.get("subject"),
+        assignee_id: r.get("assignee_id"),
+    }))
+}
+
+/// Move `id` from `from` to `to`, stamping resolved/closed times and pausing
+/// or resuming the resolution clock. False if the ticket changed status in
+/// the meantime.
+pub async fn set_status(pool: &PgPool, id: i32, from: &str, to: &str) -> sqlx::Result<bool> {
+    let res = sqlx::query(
+        "UPDATE support_requests SET status = $3, updated_at = now(),
+             resolved_at = CASE WHEN $3 = 'resolved' THEN now() WHEN $3 = 'closed' THEN resolved_at ELSE NULL END,
+             closed_at = CASE WHEN $3 = 'closed' THEN now() ELSE NULL END,
+             sla_paused_seconds = sla_paused_seconds + CASE WHEN sla_paused_at IS NOT NULL AND $3 IN ('new', 'open')
+                 THEN EXTRACT(EPOCH FROM now() - sla_paused_at)::BIGINT ELSE 0 END,
+             resolution_due_at = resolution_due_at + CASE WHEN sla_paused_at IS NOT NULL AND $3 IN ('new', 'open')
+                 THEN now() - sla_paused_at ELSE interval '0' END,
+             sla_paused_at = CASE WHEN $3 IN ('new', 'open') THEN NULL ELSE COALESCE(sla_paused_at, now()) END
+         WHERE id = $1 AND status = $2",
+    )
+    .bind(id)
+    .bind(from)
+    .bind(to)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Append a message to the thread.
+pub async fn add_message(pool: &PgPool, request_id: i32, author_id: Option<i32>, author_kind: &str, body: &str, attachment_path: Option<&str>) -> sqlx::Result<i32> {
+    let row = sqlx::query(
+        "INSERT INTO support_messages (request_id, author_id, author_kind, body, attachment_path) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+    )
+    .bind(request_id)
+    .bind(author_id)
+    .bind(author_kind)
+    .bind(body)
+    .bind(attachment_path)
+    .fetch_one(pool)
+    .await?;
+    sqlx::query(
+        "UPDATE support_requests SET last_message_at = now(), updated_at = now(),
+             first_response_at = CASE WHEN $2 = 'staff' THEN COALESCE(first_response_at, now()) ELSE first_response_at END
+         WHERE id = $1",
+    )
+    .bind(request_id)
+    .bind(author_kind)
+    .execute(pool)
+    .await?;
+    Ok(row.get("id"))
+}
+
+/// A new ticket and its first message.
+pub struct NewTicket<'a> {
+    pub user_id: i32,
+    pub role: &'a str,
+    pub kyc_status: &'a str,
+    pub priority: &'a str,
+    pub subject: Option<&'a str>,
+    pub body: &'a str,
+    pub attachment_path: Option<&'a str>,
+}
+
+/// Open a ticket with its first message. Returns the ticket id.
+pub async fn create_ticket(pool: &PgPool, t: &NewTicket<'_>) -> sqlx::Result<i32> {
+    let policy = support_sla::policy(t.priority);
+    let row = sqlx::query(
+        "INSERT INTO support_requests (user_id, role, kyc_status, priority, subject, body, last_message_at, first_response_due_at, resolution_due_at)
+         VALUES ($1, $2, $3, $4, $5, $6, now(), now() + $7 * interval '1 minute', now() + $8 * interval '1 minute') RETURNING id",
+    )
+    .bind(t.user_id)
+    .bind(t.role)
+    .bind(t.kyc_status)
+    .bind(t.priority)
+    .bind(t.subject)
+    .bind(t.body)
+    .bind(policy.first_response_minutes as f64)
+    .bind(policy.resolution_minutes as f64)
+    .fetch_one(pool)
+    .await?;
+    let id: i32 = row.get("id");
+    add_message(pool, id, Some(t.user_id), "user", t.body, t.attachment_path).await?;
+    Ok(id)
+}
+
+/// The user's latest KYC status, stored on tickets for staff context.
+pub async fn latest_kyc_status(pool: &PgPool, user_id: i32) -> String {
+    match sqlx::query("SELECT status FROM teacher_verifications WHERE user_id = $1 ORDER BY updated_at DESC NULLS LAST, created_at DESC LIMIT 1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(Some(r)) => r.try_get::<String, _>("status").unwrap_or_else(|_| "unverified".to_string()),
+        _ => "unverified".to_string(),
+    }
+}
+
+/// Users who can be assigned tickets.
+pub async fn is_staff(pool: &PgPool, user_id: i32) -> sqlx::Result<bool> {
+    let row = sqlx::query("SELECT 1 FROM users WHERE id = $1 AND role IN ('admin', 'agent')")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Tell the ticket owner that staff answered.
+pub async fn notify_reply(pool: &PgPool, ticket: &Ticket, staff_id: i32, body: &str) -> sqlx::Result<()> {
+    let excerpt: String = body.chars().take(280).collect();
+    // the tag lets an emailed copy be answered by mail (services/support_mail.rs)
+    let tag = support_mail::subject_tag(ticket.id);
+    let title = match &ticket.subject {
+        Some(s) => format!("New reply on ticket #{}: {} {}", ticket.id, s, tag),
+        None => format!("New reply on your support ticket #{} {}", ticket.id, tag),
+    };
+    notify_prefs::notify(pool, ticket.user_id, Some(staff_id), "support", "normal", &title, &excerpt).await?;
+    Ok(())
+}
+
+/// Change the priority and move both due times to the new policy, counted
+/// from creation. A breach that the new policy no longer has is cleared.
+pub async fn set_priority(pool: &PgPool, id: i32, priority: &str) -> sqlx::Result<bool> {
+    let policy = support_sla::policy(priority);
+    let res = sqlx::query(
+        "UPDATE support_requests SET priority = $2, updated_at = now(),
+             first_response_due_at = created_at + $3 * interval '1 minute',
+             resolution_due_at = created_at + $4 * interval '1 minute' + sla_paused_seconds * interval '1 second',
+             first_response_breached_at = CASE WHEN COALESCE(first_response_at, now()) <= created_at + $3 * interval '1 minute'
+                 THEN NULL ELSE first_response_breached_at END,
+             resolution_breached_at = CASE WHEN now() <= created_at + $4 * interval '1 minute' + sla_paused_seconds * interval '1 second'
+                 THEN NULL ELSE resolution_breached_at END
+         WHERE id = $1 AND status <> 'closed'",
+    )
+    .bind(id)
+    .bind(priority)
+    .bind(policy.first_response_minutes as f64)
+    .bind(policy.resolution_minutes as f64)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() == 1)
+}
+
+/// Variables available to canned replies for one ticket.
+pub async fn canned_vars(pool: &PgPool, request_id: i32, staff_id: i32) -> sqlx::Result<Option<HashMap<&'static str, String>>> {
+    let row = sqlx::query(
+        "SELECT sr.id, sr.subject, sr.kyc_status, sr.role, u.full_name, u.email,
+             (SELECT s.full_name FROM users s WHERE s.id = $2) AS agent_name,
+             (SELECT tv.status FROM teacher_verifications tv WHERE tv.user_id = sr.user_id
+              ORDER BY tv.updated_at DESC NULLS LAST, tv.created_at DESC LIMIT 1) AS current_kyc
+         FROM support_requests sr JOIN users u ON u.id = sr.user_id WHERE sr.id = $1",
+    )
+    .bind(request_id)
+    .bind(staff_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| {
+        let full_name: String = r.get("full_name");
+        let first_name = full_name.split_whitespace().next().unwrap_or("").to_string();
+        let kyc: String = r.get::<Option<String>, _>("current_kyc").unwrap_or_else(|| r.get("kyc_status"));
+        let mut vars = HashMap::new();
+        vars.insert("ticket_id", r.get::<i32, _>("id").to_string());
+        vars.insert("subject", r.get::<Option<String>, _>("subject").unwrap_or_default());
+        vars.insert("user_name", full_name);
+        vars.insert("first_name", first_name);
+        vars.insert("user_email", r.get("email"));
+        vars.insert("role", r.get("role"));
+        vars.insert("kyc_status", kyc);
+        vars.insert("agent_name", r.get::<Option<String>, _>("agent_name").unwrap_or_default());
+        vars
+    }))
+}
+
+/// Fill `{{name}}` placeholders. Unknown names are left as written so a typo
+/// shows up in the draft instead of silently vanishing.
+pub fn render_canned(template: &str, vars: &HashMap<&'static str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match vars.get(name) {
+                    Some(v) => out.push_str(v),
+                    None => out.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,561 @@
+// Email-to-ticket ingestion.
+//
+// Raw RFC 5322/MIME messages come from a maildir (SUPPORT_MAILDIR), the SMTP
+// listener in services/support_smtp.rs, the `ingest-mail` CLI subcommand or
+// the admin inbound endpoint. Each message is matched to a user by its From
+// address; a reply carrying a ticket token ("[#T42-1a2b3c4d5e]" in the subject
+// or an X-Support-Ticket header) is added to that ticket's thread, anything
+// else opens a new ticket. Attachments go through the storage layer and count
+// against the sender's quota.
+//
+// Every message is recorded in support_inbound_emails, keyed by Message-ID
+// (or a hash of the raw bytes), so re-delivered mail is only processed once.
+
+use anyhow::{anyhow, Result};
+use mail_parser::{MessageParser, MimeHeaders};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::services::{quota, signed_url, storage, support};
+
+/// Bytes of the HMAC kept in a ticket token (hex).
+const TOKEN_SIG_LEN: usize = 10;
+
+/// Largest message accepted from any source.
+pub fn max_message_bytes() -> usize {
+    std::env::var("SUPPORT_MAIL_MAX_BYTES").ok().and_then(|s| s.parse().ok()).unwrap_or(30 * 1024 * 1024)
+}
+
+/// Token identifying a ticket in mail, e.g. `T42-1a2b3c4d5e`.
+pub fn ticket_token(ticket_id: i32) -> String {
+    let sig = signed_url::token("support-ticket", &ticket_id.to_string());
+    format!("T{}-{}", ticket_id, &sig[..TOKEN_SIG_LEN])
+}
+
+/// The subject tag staff replies carry so that answers thread back.
+pub fn subject_tag(ticket_id: i32) -> String {
+    format!("[#{}]", ticket_token(ticket_id))
+}
+
+/// Ticket id of a well-formed token with a valid signature.
+fn verify_ticket_token(token: &str) -> Option<i32> {
+    let rest = token.trim().strip_prefix('T').or_else(|| token.trim().strip_prefix('t'))?;
+    let (id, sig) = rest.split_once('-')?;
+    let id: i32 = id.parse().ok()?;
+    let expected = ticket_token(id);
+    let expected_sig = expected.rsplit('-').next()?.as_bytes();
+    let sig = sig.to_ascii_lowercase();
+    if sig.len() != expected_sig.len() {
+        return None;
+    }
+    let diff = sig.bytes().zip(expected_sig.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if diff == 0 {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// First valid `[#T..]` tag in a subject.
+fn token_in_subject(subject: &str) -> Option<i32> {
+    let mut rest = subject;
+    while let Some(start) = rest.find("[#") {
+        let after = &rest[start + 2..];
+        if let Some(end) = after.find(']') {
+            if let Some(id) = verify_ticket_token(&after[..end]) {
+                return Some(id);
+            }
+            rest = &after[end + 1..];
+        } else {
+            break;
+        }
+    }
+    None
+}
+
+/// Subject for a new ticket: reply/forward prefixes and ticket tags removed.
+fn clean_subject(subject: &str) -> String {
+    let mut s = subject.to_string();
+    while let Some(start) = s.find("[#") {
+        match s[start..].find(']') {
+            Some(len) => s.replace_range(start..=start + len, ""),
+            None => break,
+        }
+    }
+    let mut s = s.trim();
+    loop {
+        let lower = s.to_ascii_lowercase();
+        let stripped = ["re:", "fw:", "fwd:", "aw:", "sv:"].iter().find(|p| lower.starts_with(*p)).map(|p| s[p.len()..].trim_start());
+        match stripped {
+            Some(rest) => s = rest,
+            None => break,
+        }
+    }
+    s.chars().take(200).collect()
+}
+
+/// Drop the quoted history mail clients append below a reply.
+pub fn strip_quoted(text: &str) -> String {
+    let mut kept: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let t = line.trim();
+        if t == "-----Original Message-----"
+            || t == "-- "
+            || line == "-- "
+            || (t.starts_with("On ") && t.ends_with("wrote:"))
+            || (t.starts_with("Am ") && t.ends_with("schrieb:"))
+            || (t.starts_with("Le ") && t.ends_with("a écrit :"))
+            || t.starts_with("________________________________")
+        {
+            break;
+        }
+        kept.push(line);
+    }
+    // trailing ">" quotes and blank lines
+    while let Some(last) = kept.last() {
+        let t = last.trim();
+        if t.is_empty() || t.starts_with('>') {
+            kept.pop();
+        } else {
+            break;
+        }
+    }
+    kept.join("\n").trim().to_string()
+}
+
+#[derive(Serialize)]
+pub struct InboundAttachment {
+    pub name: Option<String>,
+    pub content_type: String,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+    pub size: usize,
+}
+
+#[derive(Serialize)]
+pub struct InboundMail {
+    /// Message-ID, or a hash of the raw message when there is none
+    pub dedupe_key: String,
+    pub message_id: Option<String>,
+    pub from_address: String,
+    pub from_name: Option<String>,
+    pub subject: Option<String>,
+    pub body: String,
+    /// Ticket named by a valid token in the subject or X-Support-Ticket header
+    pub ticket_id: Option<i32>,
+    /// Bounce, vacation reply or bulk mail; never turned into a ticket
+    pub auto_generated: bool,
+    /// SPF or DKIM passed according to our own MTA's Authentication-Results
+    pub authenticated: bool,
+    pub attachments: Vec<InboundAttachment>,
+}
+
+fn header_text(msg: &mail_parser::Message, name: &str) -> Option<String> {
+    msg.header_raw(name).map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// The authserv-id our own MTA writes in Authentication-Results
+/// (SUPPORT_MAIL_AUTHSERV_ID, e.g. mx.example.com).
+fn authserv_id() -> Option<String> {
+    std::env::var("SUPPORT_MAIL_AUTHSERV_ID").ok().map(|v| v.trim().to_ascii_lowercase()).filter(|v| !v.is_empty())
+}
+
+/// Whether SPF or DKIM passed according to Authentication-Results (RFC 8601).
+/// Senders can add that header themselves, so only the one from our own MTA
+/// counts: the one with the configured authserv-id, else the topmost one,
+/// which the receiving MTA adds last.
+fn authenticated(msg: &mail_parser::Message) -> bool {
+    let mut results = msg.headers_raw().filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results")).map(|(_, v)| v.to_ascii_lowercase());
+    let trusted = match authserv_id() {
+        Some(id) => results.find(|v| v.split(';').next().and_then(|s| s.split_whitespace().next()) == Some(id.as_str())),
+        None => results.next(),
+    };
+    let Some(trusted) = trusted else { return false };
+    // "authserv-id; dkim=pass header.d=...; spf=pass smtp.mailfrom=..."
+    trusted.split(';').skip(1).any(|info| {
+        let Some((method, result)) = info.split_once('=') else { return false };
+        matches!(method.trim(), "dkim" | "spf") && result.split_whitespace().next() == Some("pass")
+    })
+}
+
+/// Parse a raw message. Works without a database, so fixtures can be checked
+/// with `ingest-mail --dry-run`.
+pub fn parse(raw: &[u8]) -> Result<InboundMail> {
+    let msg = MessageParser::default().parse(raw).ok_or_else(|| anyhow!("not an RFC 5322 message"))?;
+
+    let from = msg.from().and_then(|a| a.first()).ok_or_else(|| anyhow!("missing From"))?;
+    let from_address = from
+        .address()
+        .map(|a| a.trim().to_lowercase())
+        .filter(|a| a.contains('@'))
+        .ok_or_else(|| anyhow!("From has no address"))?;
+    let from_name = from.name().map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+
+    let subject = msg.subject().map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    let ticket_id = header_text(&msg, "X-Support-Ticket")
+        .and_then(|t| verify_ticket_token(&t))
+        .or_else(|| subject.as_deref().and_then(token_in_subject));
+
+    let local_part = from_address.split('@').next().unwrap_or("");
+    let auto_submitted = header_text(&msg, "Auto-Submitted").map(|v| !v.eq_ignore_ascii_case("no")).unwrap_or(false);
+    let precedence = header_text(&msg, "Precedence")
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "bulk" | "junk" | "list" | "auto_reply"))
+        .unwrap_or(false);
+    let is_report = msg.content_type().map(|ct| ct.ctype().eq_ignore_ascii_case("multipart") && ct.subtype().map(|s| s.eq_ignore_ascii_case("report")).unwrap_or(false)).unwrap_or(false);
+    let auto_generated = auto_submitted
+        || precedence
+        || is_report
+        || header_text(&msg, "X-Autoreply").is_some()
+        || header_text(&msg, "X-Autorespond").is_some()
+        || matches!(local_part, "mailer-daemon" | "postmaster" | "noreply" | "no-reply");
+
+    let authenticated = authenticated(&msg);
+
+    let text = msg.body_text(0).map(|t| t.into_owned()).unwrap_or_default();
+    let body = strip_quoted(&text.replace("\r\n", "\n"));
+
+    let attachments = msg
+        .attachments()
+        .filter(|part| !part.is_message())
+        .map(|part| {
+            let content_type = part
+                .content_type()
+                .map(|ct| match ct.subtype() {
+                    Some(sub) => format!("{}/{}", ct.ctype(), sub),
+                    None => ct.ctype().to_string(),
+                })
+                .unwrap_or_else(|| "application/octet-stream".to_string())
+                .to_lowercase();
+            let data = part.contents().to_vec();
+            InboundAttachment { name: part.attachment_name().map(|n| n.to_string()), content_type, size: data.len(), data }
+        })
+        .filter(|a| a.size > 0)
+        .collect();
+
+    let message_id = msg.message_id().map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+    let dedupe_key = match &message_id {
+        Some(id) => format!("mid:{}", id),
+        None => format!("sha256:{}", hex::encode(Sha256::digest(raw))),
+    };
+
+    Ok(InboundMail { dedupe_key, message_id, from_address, from_name, subject, body, ticket_id, auto_generated, authenticated, attachments })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Created { ticket_id: i32 },
+    Replied { ticket_id: i32 },
+    Duplicate,
+    Rejected { reason: String },
+}
+
+impl Outcome {
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Created { .. } => "created",
+            Outcome::Replied { .. } => "replied",
+            Outcome::Duplicate => "duplicate",
+            Outcome::Rejected { .. } => "rejected",
+        }
+    }
+}
+
+/// Mail must pass SPF or DKIM, since From alone is easy to forge and would
+/// let anyone write as any user. SUPPORT_MAIL_REQUIRE_AUTH=0 turns this off,
+/// e.g. for local testing.
+fn require_auth() -> bool {
+    std::env::var("SUPPORT_MAIL_REQUIRE_AUTH").map(|v| !(v == "0" || v.eq_ignore_ascii_case("false"))).unwrap_or(true)
+}
+
+/// Parse and file one raw message. `source` is recorded for the audit trail
+/// (maildir, smtp, cli, api). Errors are database failures worth retrying;
+/// anything wrong with the message itself is a `Rejected` outcome.
+pub async fn ingest(pool: &PgPool, raw: &[u8], source: &str) -> Result<Outcome> {
+    if raw.len() > max_message_bytes() {
+        return Ok(Outcome::Rejected { reason: "message too large".to_string() });
+    }
+    let mail = match parse(raw) {
+        Ok(m) => m,
+        Err(e) => {
+            let key = format!("sha256:{}", hex::encode(Sha256::digest(raw)));
+            let reason = format!("unparseable: {}", e);
+            sqlx::query(
+                "INSERT INTO support_inbound_emails (dedupe_key, source, outcome, detail) VALUES ($1, $2, 'rejected', $3)
+                 ON CONFLICT (dedupe_key) DO NOTHING",
+            )
+            .bind(&key)
+            .bind(source)
+            .bind(&reason)
+            .execute(pool)
+            .await?;
+            return Ok(Outcome::Rejected { reason });
+        }
+    };
+
+    let claimed = sqlx::query(
+        "INSERT INTO support_inbound_emails (dedupe_key, message_id, source, from_address, subject, outcome)
+         VALUES ($1, $2, $3, $4, $5, 'processing')
+         ON CONFLICT (dedupe_key) DO NOTHING RETURNING id",
+    )
+    .bind(&mail.dedupe_key)
+    .bind(&mail.message_id)
+    .bind(source)
+    .bind(&mail.from_address)
+    .bind(&mail.subject)
+    .fetch_optional(pool)
+    .await?;
+    let record_id: i32 = match claimed {
+        Some(r) => r.get("id"),
+        None => return Ok(Outcome::Duplicate),
+    };
+
+    let outcome = match file_mail(pool, &mail).await {
+        Ok(o) => o,
+        Err(e) => {
+            // let a later delivery retry it
+            let _ = sqlx::query("DELETE FROM support_inbound_emails WHERE id = $1").bind(record_id).execute(pool).await;
+            return Err(e);
+        }
+    };
+    let (ticket_id, detail) = match &outcome {
+        Outcome::Created { ticket_id } | Outcome::Replied { ticket_id } => (Some(*ticket_id), None),
+        Outcome::Rejected { reason } => (None, Some(reason.clone())),
+        Outcome::Duplicate => (None, None),
+    };
+    sqlx::query("UPDATE support_inbound_emails SET outcome = $2, ticket_id = $3, detail = $4 WHERE id = $1")
+        .bind(record_id)
+        .bind(outcome.label())
+        .bind(ticket_id)
+        .bind(detail)
+        .execute(pool)
+        .await?;
+    Ok(outcome)
+}
+
+async fn file_mail(pool: &PgPool, mail: &InboundMail) -> Result<Outcome> {
+    if mail.auto_generated {
+        return Ok(Outcome::Rejected { reason: "auto-generated message".to_string() });
+    }
+    if require_auth() && !mail.authenticated {
+        return Ok(Outcome::Rejected { reason: "sender not authenticated (no SPF/DKIM pass)".to_string() });
+    }
+
+    let user = sqlx::query("SELECT id, role, COALESCE(active, TRUE) AS active FROM users WHERE lower(email) = $1")
+        .bind(&mail.from_address)
+        .fetch_optional(pool)
+        .await?;
+    let (user_id, role) = match user {
+        Some(r) if r.get::<bool, _>("active") => (r.get::<i32, _>("id"), r.get::<Option<String>, _>("role").unwrap_or_else(|| "student".to_string())),
+        Some(_) => return Ok(Outcome::Rejected { reason: "account disabled".to_string() }),
+        None => return Ok(Outcome::Rejected { reason: "unknown sender".to_string() }),
+    };
+
+    // Attachments first, so size/quota problems can be noted in the message
+    let mut quota_left = quota::remaining(pool, user_id, storage::SUPPORT.name).await?;
+    let mut stored: Vec<(String, String)> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    let mut stored_bytes: i64 = 0;
+    for a in &mail.attachments {
+        let name = a.name.clone().unwrap_or_else(|| "attachment".to_string());
+        match storage::save_bytes(&storage::SUPPORT, a.name.as_deref(), &a.content_type, &a.data, quota_left).await {
+            Ok(file) => {
+                quota_left = quota_left.saturating_sub(file.bytes);
+                stored_bytes += file.bytes as i64;
+                stored.push((name, file.path));
+            }
+            Err(storage::UploadError::TooLarge(_)) => skipped.push(format!("{} (too large)", name)),
+            Err(storage::UploadError::QuotaExceeded(_)) => skipped.push(format!("{} (storage quota exceeded)", name)),
+            Err(e) => skipped.push(format!("{} ({:?})", name, e)),
+        }
+    }
+
+    let mut body = mail.body.clone();
+    if !skipped.is_empty() {
+        body.push_str(&format!("\n\n[attachments not stored: {}]", skipped.join(", ")));
+    }
+    if body.trim().is_empty() {
+        if stored.is_empty() {
+            return Ok(Outcome::Rejected { reason: "empty message".to_string() });
+        }
+        body = "(attachment)".to_string();
+    }
+    let first_attachment = stored.first().map(|(_, p)| p.as_str());
+
+    // Reply to an existing ticket of this user
+    let mut existing = None;
+    if let Some(id) = mail.ticket_id {
+        if let Some(t) = support::ticket(pool, id).await? {
+            if t.user_id == user_id {
+                existing = Some(t);
+            }
+        }
+    }
+    let (outcome, request_id) = match existing {
+        Some(t) if t.status != "closed" => {
+            let next = support::status_after_user_reply(&t.status).map_err(|e| anyhow!(e.to_string()))?;
+            support::add_message(pool, t.id, Some(user_id), "user", &body, first_attachment).await?;
+            if next != t.status {
+                support::set_status(pool, t.id, &t.status, next).await?;
+            }
+            (Outcome::Replied { ticket_id: t.id }, t.id)
+        }
+        previous => {
+            // a reply to a closed ticket starts a follow-up
+            let subject = match (&previous, mail.subject.as_deref().map(clean_subject).filter(|s| !s.is_empty())) {
+                (Some(t), Some(s)) => Some(format!("{} (follow-up to #{})", s, t.id)),
+                (Some(t), None) => Some(format!("Follow-up to #{}", t.id)),
+                (None, s) => s,
+            };
+            let kyc_status = support::latest_kyc_status(pool, user_id).await;
+            let new = support::NewTicket {
+                user_id,
+                role: &role,
+                kyc_status: &kyc_status,
+                priority: "normal",
+                subject: subject.as_deref(),
+                body: &body,
+                attachment_path: first_attachment,
+            };
+            let id = support::create_ticket(pool, &new).await?;
+            (Outcome::Created { ticket_id: id }, id)
+        }
+    };
+
+    // Further attachments each get their own message in the thread
+    let total = stored.len();
+    for (i, (name, path)) in stored.iter().enumerate().skip(1) {
+        let note = format!("Attachment {} of {}: {}", i + 1, total, name);
+        support::add_message(pool, request_id, Some(user_id), "user", &note, Some(path)).await?;
+    }
+    if !stored.is_empty() {
+        let _ = quota::record(pool, user_id, storage::SUPPORT.name, stored_bytes, stored.len() as i32).await;
+    }
+    Ok(outcome)
+}
+
+/// Process every message waiting in `maildir/new`, moving each to `cur`
+/// once handled. Messages hitting a database error stay in `new` for the
+/// next run. Returns (processed, failed).
+pub async fn poll_maildir(pool: &PgPool, maildir: &Path) -> Result<(usize, usize)> {
+    let new_dir = maildir.join("new");
+    let cur_dir = maildir.join("cur");
+    tokio::fs::create_dir_all(&cur_dir).await?;
+    let mut entries = tokio::fs::read_dir(&new_dir).await?;
+    let mut files: Vec<PathBuf> = Vec::new();
+    while let Some(e) = entries.next_entry().await? {
+        if e.file_type().await.map(|t| t.is_file()).unwrap_or(false) {
+            files.push(e.path());
+        }
+    }
+    files.sort();
+
+    let (mut processed, mut failed) = (0, 0);
+    for path in files {
+        let raw = match tokio::fs::read(&path).await {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Support maildir: cannot read {:?}: {:?}", path, e);
+                failed += 1;
+                continue;
+            }
+        };
+        match ingest(pool, &raw, "maildir").await {
+            Ok(outcome) => {
+                if let Outcome::Rejected { reason } = &outcome {
+                    eprintln!("Support maildir: {:?} rejected: {}", path.file_name().unwrap_or_default(), reason);
+                }
+                let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                // maildir "seen" flag
+                let target = cur_dir.join(format!("{}:2,S", name.split(":2,").next().unwrap_or(&name)));
+                if let Err(e) = tokio::fs::rename(&path, &target).await {
+                    eprintln!("Support maildir: cannot move {:?}: {:?}", path, e);
+                }
+                processed += 1;
+            }
+            Err(e) => {
+                eprintln!("Support maildir: {:?} failed: {:?}", path, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((processed, failed))
+}
+
+/// Poll SUPPORT_MAILDIR every SUPPORT_MAIL_POLL_SECONDS (default 30) when set.
+pub fn spawn_maildir(pool: PgPool) {
+    let maildir = match std::env::var("SUPPORT_MAILDIR") {
+        Ok(d) if !d.trim().is_empty() => PathBuf::from(d.trim()),
+        _ => return,
+    };
+    let interval_secs: u64 = std::env::var("SUPPORT_MAIL_POLL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(30).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match poll_maildir(&pool, &maildir).await {
+                Ok((0, 0)) => {}
+                Ok((n, failed)) => eprintln!("Support maildir: {} message(s) processed, {} failed", n, failed),
+                Err(e) => eprintln!("Support maildir poll failed: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a file from fixtures/support_mail, with the secret its tokens
+    /// are signed with (see the README there).
+    fn fixture(name: &str) -> InboundMail {
+        std::env::set_var("SIGNED_URL_SECRET", "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff");
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/support_mail").join(name);
+        parse(&std::fs::read(&path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn new_ticket() {
+        let mail = fixture("new_ticket.eml");
+        assert_eq!(mail.from_address, "student@example.com");
+        assert_eq!(mail.ticket_id, None);
+        assert!(!mail.auto_generated);
+        assert!(!mail.body.is_empty());
+    }
+
+    #[test]
+    fn reply_with_token() {
+        let mail = fixture("reply_with_token.eml");
+        assert_eq!(mail.ticket_id, Some(1));
+        assert!(!mail.auto_generated);
+        assert_eq!(mail.body, "That worked, merci beaucoup! Café time.");
+    }
+
+    #[test]
+    fn html_with_attachments() {
+        let mail = fixture("html_with_attachments.eml");
+        assert_eq!(mail.from_address, "teacher@example.com");
+        assert_eq!(mail.ticket_id, None);
+        assert_eq!(mail.attachments.len(), 2);
+    }
+
+    #[test]
+    fn auto_generated() {
+        assert!(fixture("auto_reply.eml").auto_generated);
+        assert!(fixture("bounce.eml").auto_generated);
+    }
+
+    #[test]
+    fn only_topmost_authentication_results() {
+        let forged = b"Authentication-Results: mx.example.com; spf=fail smtp.mailfrom=example.com\r\n\
+Authentication-Results: mx.example.com; dkim=pass header.d=example.com\r\n\
+From: a@example.com\r\nSubject: hi\r\n\r\nhello\r\n";
+        assert!(!parse(forged).unwrap().authenticated);
+        let ours = b"Authentication-Results: mx.example.com; dkim=pass (good signature) header.d=example.com; spf=none\r\n\
+From: a@example.com\r\nSubject: hi\r\n\r\nhello\r\n";
+        assert!(parse(ours).unwrap().authenticated);
+    }
+}
@@ -0,0 +1,216 @@
+// Support SLA policy, breach detection and desk metrics.
+//
+// Every ticket gets a first-response and a resolution due time from its
+// priority. A background job stamps breaches once and escalates them to the
+// assignee and all admins. Targets can be changed per priority with
+// SUPPORT_SLA_<PRIORITY>_RESPONSE_MINUTES / SUPPORT_SLA_<PRIORITY>_RESOLUTION_MINUTES.
+
+use anyhow::Result;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+
+use crate::services::notify_prefs;
+
+pub struct Policy {
+    pub first_response_minutes: i64,
+    pub resolution_minutes: i64,
+}
+
+fn env_minutes(priority: &str, kind: &str, default: i64) -> i64 {
+    std::env::var(format!("SUPPORT_SLA_{}_{}_MINUTES", priority.to_uppercase(), kind))
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|m: &i64| *m > 0)
+        .unwrap_or(default)
+}
+
+/// Targets for a priority (unknown priorities get `normal`).
+pub fn policy(priority: &str) -> Policy {
+    let (priority, response, resolution) = match priority {
+        "urgent" => ("urgent", 60, 8 * 60),
+        "high" => ("high", 4 * 60, 24 * 60),
+        "low" => ("low", 48 * 60, 7 * 24 * 60),
+        _ => ("normal", 24 * 60, 72 * 60),
+    };
+    Policy {
+        first_response_minutes: env_minutes(priority, "RESPONSE", response),
+        resolution_minutes: env_minutes(priority, "RESOLUTION", resolution),
+    }
+}
+
+struct Breach {
+    id: i32,
+    subject: Option<String>,
+    priority: String,
+    assignee_id: Option<i32>,
+}
+
+fn breaches(rows: Vec<sqlx::postgres::PgRow>) -> Vec<Breach> {
+    rows.iter()
+        .map(|r| Breach {
+            id: r.get("id"),
+            subject: r.get("subject"),
+            priority: r.get("priority"),
+            assignee_id: r.get("assignee_id"),
+        })
+        .collect()
+}
+
+/// Stamp tickets that just went past a due time and escalate them. Returns
+/// how many breaches were found.
+pub async fn check_breaches(pool: &PgPool) -> Result<usize> {
+    // UPDATE .. RETURNING claims each breach once, even with several instances running
+    let first_response = breaches(
+        sqlx::query(
+            "UPDATE support_requests SET first_response_breached_at = now()
+             WHERE first_response_at IS NULL AND first_response_breached_at IS NULL
+               AND first_response_due_at <= now() AND status IN ('new', 'open')
+             RETURNING id, subject, priority, assignee_id",
+        )
+        .fetch_all(pool)
+        .await?,
+    );
+    let resolution = breaches(
+        sqlx::query(
+            "UPDATE support_requests SET resolution_breached_at = now()
+             WHERE resolution_breached_at IS NULL AND sla_paused_at IS NULL
+               AND resolution_due_at <= now() AND status IN ('new', 'open')
+             RETURNING id, subject, priority, assignee_id",
+        )
+        .fetch_all(pool)
+        .await?,
+    );
+    if first_response.is_empty() && resolution.is_empty() {
+        return Ok(0);
+    }
+
+    let admins: Vec<i32> = sqlx::query("SELECT id FROM users WHERE role = 'admin' AND active IS NOT FALSE")
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|r| r.get("id"))
+        .collect();
+
+    let count = first_response.len() + resolution.len();
+    for (kind, list) in [("first response", first_response), ("resolution", resolution)] {
+        for b in list {
+            let title = format!("SLA breach: {} overdue on ticket #{}", kind, b.id);
+            let body = format!(
+                "{} priority ticket #{}{} missed its {} target.",
+                b.priority,
+                b.id,
+                b.subject.as_ref().map(|s| format!(" ({})", s)).unwrap_or_default(),
+                kind
+            );
+            let mut recipients = admins.clone();
+            if let Some(a) = b.assignee_id {
+                if !recipients.contains(&a) {
+                    recipients.push(a);
+                }
+            }
+            for user_id in recipients {
+                if let Err(e) = notify_prefs::notify(pool, user_id, None, "support", "urgent", &title, &body).await {
+                    eprintln!("SLA escalation for ticket {} failed: {:?}", b.id, e);
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("SUPPORT_SLA_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(60).max(1);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match check_breaches(&pool).await {
+                Ok(0) => {}
+                Ok(n) => eprintln!("Escalated {} support SLA breach(es)", n),
+                Err(e) => eprintln!("Support SLA check failed: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Desk metrics: current backlog, and response times and breaches for
+/// tickets opened in the last `days` days.
+pub async fn metrics(pool: &PgPool, days: i64) -> sqlx::Result<serde_json::Value> {
+    let backlog_rows = sqlx::query(
+        "SELECT status, priority, COUNT(*) AS n FROM support_requests WHERE status <> 'closed' GROUP BY status, priority",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut by_status = serde_json::Map::new();
+    let mut by_priority = serde_json::Map::new();
+    let mut total: i64 = 0;
+    for r in &backlog_rows {
+        let n: i64 = r.get("n");
+        total += n;
+        let s: String = r.get("status");
+        let p: String = r.get("priority");
+        let e = by_status.entry(s).or_insert(json!(0));
+        *e = json!(e.as_i64().unwrap_or(0) + n);
+        let e = by_priority.entry(p).or_insert(json!(0));
+        *e = json!(e.as_i64().unwrap_or(0) + n);
+    }
+
+    let live = sqlx::query(
+        "SELECT
+             COUNT(*) FILTER (WHERE assignee_id IS NULL) AS unassigned,
+             COUNT(*) FILTER (WHERE first_response_at IS NULL AND first_response_due_at < now()) AS first_response_overdue,
+             COUNT(*) FILTER (WHERE sla_paused_at IS NULL AND resolution_due_at < now() AND status IN ('new', 'open')) AS resolution_overdue
+         FROM support_requests WHERE status <> 'closed'",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let window = sqlx::query(
+        "SELECT
+             COUNT(*) AS opened,
+             COUNT(*) FILTER (WHERE first_response_at IS NOT NULL) AS responded,
+             COUNT(*) FILTER (WHERE first_response_at <= first_response_due_at) AS responded_on_time,
+             COUNT(*) FILTER (WHERE resolved_at IS NOT NULL OR closed_at IS NOT NULL) AS resolved,
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM first_response_at - created_at)::FLOAT8)
+                 FILTER (WHERE first_response_at IS NOT NULL) AS median_first_response_seconds,
+             percentile_cont(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM COALESCE(resolved_at, closed_at) - created_at)::FLOAT8)
+                 FILTER (WHERE resolved_at IS NOT NULL OR closed_at IS NOT NULL) AS median_resolution_seconds,
+             COUNT(*) FILTER (WHERE first_response_breached_at IS NOT NULL OR first_response_at > first_response_due_at) AS first_response_breaches,
+             COUNT(*) FILTER (WHERE resolution_breached_at IS NOT NULL) AS resolution_breaches
+         FROM support_requests WHERE created_at >= now() - $1 * interval '1 day'",
+    )
+    .bind(days as f64)
+    .fetch_one(pool)
+    .await?;
+
+    let responded: i64 = window.get("responded");
+    let first_response_breaches: i64 = window.get("first_response_breaches");
+    let on_time = if responded > 0 {
+        Some(window.get::<i64, _>("responded_on_time") as f64 / responded as f64)
+    } else {
+        None
+    };
+
+    Ok(json!({
+        "backlog": {
+            "total": total,
+            "by_status": by_status,
+            "by_priority": by_priority,
+            "unassigned": live.get::<i64,_>("unassigned"),
+            "first_response_overdue": live.get::<i64,_>("first_response_overdue"),
+            "resolution_overdue": live.get::<i64,_>("resolution_overdue"),
+        },
+        "window_days": days,
+        "opened": window.get::<i64,_>("opened"),
+        "responded": responded,
+        "resolved": window.get::<i64,_>("resolved"),
+        "median_first_response_seconds": window.get::<Option<f64>,_>("median_first_response_seconds"),
+        "median_resolution_seconds": window.get::<Option<f64>,_>("median_resolution_seconds"),
+        "first_response_on_time_rate": on_time,
+        "sla_breaches": {
+            "first_response": first_response_breaches,
+            "resolution": window.get::<i64,_>("resolution_breaches"),
+        },
+    }))
+}
@@ -0,0 +1,239 @@
+// Minimal SMTP receiver feeding services/support_mail.rs.
+//
+// Enabled with SUPPORT_SMTP_LISTEN (e.g. 127.0.0.1:2525). It speaks just
+// enough RFC 5321 for an MTA to hand messages over (HELO/EHLO, MAIL, RCPT,
+// DATA, RSET, NOOP, QUIT) and has no TLS or AUTH, so it is meant to sit
+// behind the real mail server rather than face the internet. With
+// SUPPORT_INBOUND_ADDRESS set, only that recipient is accepted.
+
+use anyhow::Result;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+use crate::services::support_mail::{self, Outcome};
+
+const MAX_LINE: usize = 4096;
+const MAX_RECIPIENTS: usize = 100;
+const MAX_CONNECTIONS: usize = 32;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+fn hostname() -> String {
+    std::env::var("SUPPORT_SMTP_HOSTNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+fn inbound_address() -> Option<String> {
+    std::env::var("SUPPORT_INBOUND_ADDRESS").ok().map(|a| a.trim().to_lowercase()).filter(|a| !a.is_empty())
+}
+
+enum Line {
+    Text(Vec<u8>),
+    TooLong,
+    Closed,
+}
+
+async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, limit: usize) -> Result<Line> {
+    let mut buf = Vec::new();
+    loop {
+        let available = match tokio::time::timeout(IDLE_TIMEOUT, reader.fill_buf()).await {
+            Ok(r) => r?,
+            Err(_) => return Ok(Line::Closed),
+        };
+        if available.is_empty() {
+            return Ok(if buf.is_empty() { Line::Closed } else { Line::Text(buf) });
+        }
+        match available.iter().position(|b| *b == b'\n') {
+            Some(i) => {
+                buf.extend_from_slice(&available[..=i]);
+                reader.consume(i + 1);
+                return Ok(if buf.len() > limit { Line::TooLong } else { Line::Text(buf) });
+            }
+            None => {
+                let n = available.len();
+                buf.extend_from_slice(available);
+                reader.consume(n);
+                if buf.len() > limit {
+                    // skip the rest of the line
+                    buf.clear();
+                    buf.resize(limit + 1, 0);
+                }
+            }
+        }
+    }
+}
+
+/// Address inside `FROM:<...>` / `TO:<...>`, lowercased.
+fn path_arg(arg: &str) -> Option<String> {
+    let start = arg.find('<')?;
+    let end = arg[start..].find('>')? + start;
+    Some(arg[start + 1..end].trim().to_lowercase())
+}
+
+async fn handle(pool: PgPool, stream: TcpStream) -> Result<()> {
+    let (read_half, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+    let host = hostname();
+    let max_bytes = support_mail::max_message_bytes();
+    let accept_only = inbound_address();
+
+    writer.write_all(format!("220 {} ESMTP support inbox\r\n", host).as_bytes()).await?;
+    let mut have_sender = false;
+    let mut recipients = 0usize;
+
+    loop {
+        let line = match read_line(&mut reader, MAX_LINE).await? {
+            Line::Text(l) => l,
+            Line::TooLong => {
+                writer.write_all(b"500 line too long\r\n").await?;
+                continue;
+            }
+            Line::Closed => return Ok(()),
+        };
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        let (verb, arg) = match line.split_once(' ') {
+            Some((v, a)) => (v.to_ascii_uppercase(), a.trim().to_string()),
+            None => (line.to_ascii_uppercase(), String::new()),
+        };
+
+        let reply: String = match verb.as_str() {
+            "EHLO" => format!("250-{}\r\n250-SIZE {}\r\n250 8BITMIME\r\n", host, max_bytes),
+            "HELO" => format!("250 {}\r\n", host),
+            "MAIL" => {
+                let size = arg
+                    .split_whitespace()
+                    .find_map(|p| p.to_ascii_uppercase().strip_prefix("SIZE=").and_then(|n| n.parse::<usize>().ok()));
+                if !arg.to_ascii_uppercase().starts_with("FROM:") || path_arg(&arg).is_none() {
+                    "501 syntax: MAIL FROM:<address>\r\n".to_string()
+                } else if size.map(|s| s > max_bytes).unwrap_or(false) {
+                    "552 message too large\r\n".to_string()
+                } else {
+                    have_sender = true;
+                    recipients = 0;
+                    "250 OK\r\n".to_string()
+                }
+            }
+            "RCPT" => {
+                if !have_sender {
+                    "503 MAIL first\r\n".to_string()
+                } else {
+                    match path_arg(&arg) {
+                        None => "501 syntax: RCPT TO:<address>\r\n".to_string(),
+                        Some(to) if accept_only.as_ref().map(|a| *a != to).unwrap_or(false) => "550 no such mailbox\r\n".to_string(),
+                        Some(_) if recipients >= MAX_RECIPIENTS => "452 too many recipients\r\n".to_string(),
+                        Some(_) => {
+                            recipients += 1;
+                            "250 OK\r\n".to_string()
+                        }
+                    }
+                }
+            }
+            "DATA" => {
+                if recipients == 0 {
+                    "503 RCPT first\r\n".to_string()
+                } else {
+                    writer.write_all(b"354 end data with <CR><LF>.<CR><LF>\r\n").await?;
+                    let mut data: Vec<u8> = Vec::new();
+                    let mut too_large = false;
+                    loop {
+                        let chunk = match read_line(&mut reader, max_bytes).await? {
+                            Line::Text(l) => l,
+                            Line::TooLong => {
+                                too_large = true;
+                                continue;
+                            }
+                            Line::Closed => return Ok(()),
+                        };
+                        if chunk == b".\r\n" || chunk == b".\n" {
+                            break;
+                        }
+                        // dot-unstuffing
+                        let content = if chunk.first() == Some(&b'.') { &chunk[1..] } else { &chunk[..] };
+                        if data.len() + content.len() > max_bytes {
+                            too_large = true;
+                        } else if !too_large {
+                            data.extend_from_slice(content);
+                        }
+                    }
+                    have_sender = false;
+                    recipients = 0;
+                    if too_large {
+                        "552 message too large\r\n".to_string()
+                    } else {
+                        match support_mail::ingest(&pool, &data, "smtp").await {
+                            // Rejected mail is still accepted: bouncing it would only create backscatter
+                            Ok(Outcome::Rejected { reason }) => {
+                                eprintln!("Support SMTP: message rejected: {}", reason);
+                                "250 OK\r\n".to_string()
+                            }
+                            Ok(_) => "250 OK\r\n".to_string(),
+                            Err(e) => {
+                                eprintln!("Support SMTP: ingest failed: {:?}", e);
+                                "451 temporary failure, try again later\r\n".to_string()
+                            }
+                        }
+                    }
+                }
+            }
+            "RSET" => {
+                have_sender = false;
+                recipients = 0;
+                "250 OK\r\n".to_string()
+            }
+            "NOOP" => "250 OK\r\n".to_string(),
+            "VRFY" => "252 cannot verify\r\n".to_string(),
+            "QUIT" => {
+                writer.write_all(format!("221 {} closing\r\n", host).as_bytes()).await?;
+                return Ok(());
+            }
+            "" => "500 empty command\r\n".to_string(),
+            _ => "502 command not implemented\r\n".to_string(),
+        };
+        writer.write_all(reply.as_bytes()).await?;
+    }
+}
+
+/// Start the listener when SUPPORT_SMTP_LISTEN is set.
+pub fn spawn_listener(pool: PgPool) {
+    let addr = match std::env::var("SUPPORT_SMTP_LISTEN") {
+        Ok(a) if !a.trim().is_empty() => a.trim().to_string(),
+        _ => return,
+    };
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(&addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Support SMTP: cannot listen on {}: {:?}", addr, e);
+                return;
+            }
+        };
+        eprintln!("Support SMTP listening on {}", addr);
+        let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Support SMTP: accept failed: {:?}", e);
+                    continue;
+                }
+            };
+            let permit = match slots.clone().try_acquire_owned() {
+                Ok(p) => p,
+                Err(_) => {
+                    let mut stream = stream;
+                    let _ = stream.write_all(b"421 too many connections, try again later\r\n").await;
+                    continue;
+                }
+            };
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle(pool, stream).await {
+                    eprintln!("Support SMTP: session with {} failed: {:?}", peer, e);
+                }
+                drop(permit);
+            });
+        }
+    });
+}
@@ -0,0 +1,199 @@
+// Reconcile upload directories against the paths stored in the database.
+//
+// Files can be orphaned when a best-effort delete fails (notice delete/update,
+// avatar replacement) or when a DB insert fails after the upload was written.
+// This sweeps the known upload roots and reports (or removes) any file that no
+// row references and that is older than a grace period, so in-flight uploads
+// whose row hasn't been written yet are left alone.
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Directories that handlers write uploads into.
+pub const UPLOAD_DIRS: &[&str] = &[
+    "uploads/notifications",
+    "uploads/support_requests",
+    "uploads/profile_pics",
+    "uploads/teacher_ids",
+    "private/teacher_ids",
+];
+
+#[derive(Clone, Debug)]
+pub struct GcOptions {
+    pub dry_run: bool,
+    pub grace: Duration,
+}
+
+impl GcOptions {
+    /// Read options for the background job from the environment.
+    /// Defaults to a dry run with a 24h grace period.
+    pub fn from_env() -> Self {
+        let dry_run = std::env::var("UPLOAD_GC_DRY_RUN")
+            .map(|v| !matches!(v.trim().to_lowercase().as_str(), "0" | "false" | "no"))
+            .unwrap_or(true);
+        let grace_secs: u64 = std::env::var("UPLOAD_GC_GRACE_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(24 * 3600);
+        GcOptions { dry_run, grace: Duration::from_secs(grace_secs) }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct OrphanFile {
+    pub path: String,
+    pub bytes: u64,
+    pub age_seconds: u64,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub scanned: usize,
+    pub referenced: usize,
+    pub within_grace: usize,
+    pub orphans: Vec<OrphanFile>,
+    pub orphan_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+/// Normalize a stored or on-disk path so both sides compare equal
+/// (Windows separators, leading "./").
+fn normalize(p: &str) -> String {
+    let s = p.replace('\\', "/");
+    s.trim_start_matches("./").to_string()
+}
+
+async fn referenced_paths(pool: &PgPool) -> Result<HashSet<String>> {
+    let rows = sqlx::query(
+        "SELECT attachment_path AS p FROM notifications WHERE attachment_path IS NOT NULL
+         UNION SELECT attachment_path FROM notification_campaigns WHERE attachment_path IS NOT NULL
+         UNION SELECT attachment_path FROM support_requests WHERE attachment_path IS NOT NULL
+         UNION SELECT attachment_path FROM support_messages WHERE attachment_path IS NOT NULL
+         UNION SELECT avatar_path FROM users WHERE avatar_path IS NOT NULL
+         UNION SELECT id_path FROM teacher_verifications WHERE id_path IS NOT NULL
+         UNION SELECT front_id_path FROM teacher_verifications WHERE front_id_path IS NOT NULL
+         UNION SELECT back_id_path FROM teacher_verifications WHERE back_id_path IS NOT NULL
+         UNION SELECT stored_path FROM upload_sessions WHERE stored_path IS NOT NULL AND status = 'completed'",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|r| r.try_get::<String, _>("p").ok())
+        .map(|p| normalize(&p))
+        .collect())
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>, errors: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            // a missing root just means nothing was ever uploaded there
+            if e.kind() != std::io::ErrorKind::NotFound {
+                errors.push(format!("read_dir {}: {}", dir.display(), e));
+            }
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(ft) if ft.is_dir() => collect_files(&path, out, errors),
+            Ok(ft) if ft.is_file() => out.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Scan all upload roots and report (or delete, unless `dry_run`) unreferenced files.
+pub async fn run(pool: &PgPool, opts: &GcOptions) -> Result<GcReport> {
+    // Load references first: a file written after this point is younger than
+    // the grace period and therefore never considered.
+    let referenced = referenced_paths(pool).await?;
+
+    let mut report = GcReport { dry_run: opts.dry_run, ..Default::default() };
+    let mut files = Vec::new();
+    for dir in UPLOAD_DIRS {
+        collect_files(Path::new(dir), &mut files, &mut report.errors);
+    }
+    report.scanned = files.len();
+
+    let now = SystemTime::now();
+    for path in files {
+        let key = normalize(&path.to_string_lossy());
+        if referenced.contains(&key) {
+            report.referenced += 1;
+            continue;
+        }
+        let meta = match std::fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) => {
+                report.errors.push(format!("stat {}: {}", key, e));
+                continue;
+            }
+        };
+        let age = meta
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok())
+            .unwrap_or_default();
+        if age < opts.grace {
+            report.within_grace += 1;
+            continue;
+        }
+
+        let mut orphan = OrphanFile { path: key, bytes: meta.len(), age_seconds: age.as_secs(), deleted: false };
+        if !opts.dry_run {
+            match tokio::fs::remove_file(&path).await {
+                Ok(_) => orphan.deleted = true,
+                Err(e) => report.errors.push(format!("remove {}: {}", orphan.path, e)),
+            }
+        }
+        report.orphan_bytes += orphan.bytes;
+        report.orphans.push(orphan);
+    }
+
+    Ok(report)
+}
+
+/// Spawn the periodic collector. Interval comes from UPLOAD_GC_INTERVAL_SECONDS
+/// (default 6h, 0 disables).
+pub fn spawn_background(pool: PgPool) {
+    let interval_secs: u64 = std::env::var("UPLOAD_GC_INTERVAL_SECONDS").ok().and_then(|s| s.parse().ok()).unwrap_or(6 * 3600);
+    if interval_secs == 0 {
+        eprintln!("Upload GC disabled (UPLOAD_GC_INTERVAL_SECONDS=0)");
+        return;
+    }
+    let opts = GcOptions::from_env();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            ticker.tick().await;
+            match run(&pool, &opts).await {
+                Ok(report) => {
+                    if !report.orphans.is_empty() || !report.errors.is_empty() {
+                        eprintln!(
+                            "Upload GC: {} orphan(s), {} bytes{} ({} scanned, {} error(s))",
+                            report.orphans.len(),
+                            report.orphan_bytes,
+                            if report.dry_run { " found (dry run)" } else { " removed" },
+                            report.scanned,
+                            report.errors.len()
+                        );
+                        for o in &report.orphans {
+                            eprintln!("  orphan: {} ({} bytes)", o.path, o.bytes);
+                        }
+                        for e in &report.errors {
+                            eprintln!("  error: {}", e);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Upload GC failed: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,93 @@
+// Coin wallets. Every change to wallets.balance goes through `post`, which
+// locks the wallet row, refuses to go below zero and writes a wallet_ledger
+// entry with the new balance, all inside the caller's transaction.
+
+use anyhow::anyhow;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Row, Transaction};
+
+/// Why a balance changes.
+#[derive(Default)]
+pub struct Entry<'a> {
+    pub kind: &'a str,
+    pub reference: Option<&'a str>,
+    pub actor_id: Option<i32>,
+    pub note: Option<&'a str>,
+}
+
+pub async fn balance(pool: &PgPool, user_id: i32) -> sqlx::Result<i64> {
+    let row = sqlx::query("SELECT balance FROM wallets WHERE user_id = $1").bind(user_id).fetch_optional(pool).await?;
+    Ok(row.map(|r| r.get("balance")).unwrap_or(0))
+}
+
+/// Balance of `user_id`, locking the wallet row until the transaction ends.
+pub async fn lock_balance(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> sqlx::Result<i64> {
+    let row = sqlx::query("SELECT balance FROM wallets WHERE user_id = $1 FOR UPDATE").bind(user_id).fetch_optional(&mut **tx).await?;
+    Ok(row.map(|r| r.get("balance")).unwrap_or(0))
+}
+
+/// Add `amount` (negative to take) to the wallet of `user_id`. Returns the
+/// new balance; fails without changes if the balance would go below zero.
+pub async fn post(tx: &mut Transaction<'_, Postgres>, user_id: i32, amount: i64, entry: &Entry<'_>) -> anyhow::Result<i64> {
+    if amount == 0 {
+        return Err(anyhow!("amount must not be zero"));
+    }
+    sqlx::query("INSERT INTO wallets (user_id) VALUES ($1) ON CONFLICT (user_id) DO NOTHING")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+    let current: i64 = sqlx::query("SELECT balance FROM wallets WHERE user_id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_one(&mut **tx)
+        .await?
+        .get("balance");
+    let after = current + amount;
+    if after < 0 {
+        return Err(anyhow!("insufficient balance: {} coins available, {} needed", current, -amount));
+    }
+    sqlx::query("UPDATE wallets SET balance = $2, updated_at = now() WHERE user_id = $1")
+        .bind(user_id)
+        .bind(after)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO wallet_ledger (user_id, amount, balance_after, kind, reference, actor_id, note) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(user_id)
+    .bind(amount)
+    .bind(after)
+    .bind(entry.kind)
+    .bind(entry.reference)
+    .bind(entry.actor_id)
+    .bind(entry.note)
+    .execute(&mut **tx)
+    .await?;
+    Ok(after)
+}
+
+/// Latest ledger entries of `user_id`, newest first.
+pub async fn history(pool: &PgPool, user_id: i32, limit: i64) -> sqlx::Result<Vec<serde_json::Value>> {
+    let rows = sqlx::query(
+        "SELECT id, amount, balance_after, kind, reference, actor_id, note, created_at FROM wallet_ledger
+         WHERE user_id = $1 ORDER BY id DESC LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| {
+            json!({
+                "id": r.get::<i64,_>("id"),
+                "amount": r.get::<i64,_>("amount"),
+                "balance_after": r.get::<i64,_>("balance_after"),
+                "kind": r.get::<String,_>("kind"),
+                "reference": r.get::<Option<String>,_>("reference"),
+                "actor_id": r.get::<Option<i32>,_>("actor_id"),
+                "note": r.get::<Option<String>,_>("note"),
+                "created_at": r.get::<chrono::DateTime<chrono::Utc>,_>("created_at").to_rfc3339(),
+            })
+        })
+        .collect())
+}
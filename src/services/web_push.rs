@@ -0,0 +1,522 @@
+// Web Push delivery (RFC 8030) with VAPID authentication (RFC 8292) and
+// aes128gcm payload encryption (RFC 8291).
+//
+// Browsers register a subscription (push service endpoint + client keys) per
+// device; `send_events` encrypts a small JSON summary of each notice for each
+// of the recipient's subscriptions and POSTs it to the push service. Endpoints
+// the push service reports as gone (404/410) are deleted right away; others
+// are dropped after MAX_FAILURES consecutive errors.
+//
+// Config: VAPID_PRIVATE_KEY (base64url P-256 scalar, see `skillvine vapid-keys`),
+// VAPID_SUBJECT (mailto: or https: contact). Push is off without a key.
+// Endpoints must be https on a known push service (PUSH_SERVICE_HOSTS), so
+// users can't make the server POST to internal hosts; WEB_PUSH_HOSTS adds
+// hosts. WEB_PUSH_MOCK=1 also allows this server's own mock push service at
+// APP_BASE_URL/push-mock/ (see routes/push.rs).
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use hkdf::Hkdf;
+use once_cell::sync::Lazy;
+use p256::ecdh::EphemeralSecret;
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use rand_core::{OsRng, RngCore};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::services::email;
+use crate::services::notify_hub::NotificationEvent;
+
+/// Record size advertised in the aes128gcm header; the whole payload is one record.
+const RECORD_SIZE: u32 = 4096;
+
+/// Largest plaintext that fits one record (tag, padding delimiter and header included).
+pub const MAX_PAYLOAD: usize = 3800;
+
+/// How long the push service keeps an undelivered message (seconds).
+const TTL_SECS: u32 = 24 * 3600;
+
+/// Consecutive non-404/410 failures after which a subscription is dropped.
+const MAX_FAILURES: i32 = 20;
+
+/// Push services of the major browsers: FCM (Chrome, Edge on Android), Mozilla
+/// autopush, Apple and WNS. A leading dot matches any subdomain.
+const PUSH_SERVICE_HOSTS: [&str; 4] = ["fcm.googleapis.com", ".push.services.mozilla.com", ".push.apple.com", ".notify.windows.com"];
+
+struct Vapid {
+    key: SigningKey,
+    public_key: String,
+    subject: String,
+}
+
+static VAPID: Lazy<Option<Vapid>> = Lazy::new(|| {
+    let raw = std::env::var("VAPID_PRIVATE_KEY").ok()?;
+    let key = match b64_decode(raw.trim()).ok().and_then(|b| SigningKey::from_slice(&b).ok()) {
+        Some(k) => k,
+        None => {
+            eprintln!("Web Push disabled: VAPID_PRIVATE_KEY is not a base64url P-256 private key");
+            return None;
+        }
+    };
+    let public_key = b64_encode(key.verifying_key().to_encoded_point(false).as_bytes());
+    let subject = std::env::var("VAPID_SUBJECT")
+        .ok()
+        .or_else(|| std::env::var("GMAIL_USERNAME").ok().map(|m| format!("mailto:{}", m)))
+        .unwrap_or_else(email::base_url);
+    Some(Vapid { key, public_key, subject })
+});
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    // no redirects: they could lead off the allowed push services
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("reqwest client")
+});
+
+pub fn enabled() -> bool {
+    VAPID.is_some()
+}
+
+/// Application server key for `pushManager.subscribe`, base64url.
+pub fn public_key() -> Option<&'static str> {
+    VAPID.as_ref().map(|v| v.public_key.as_str())
+}
+
+/// A new (private, public) VAPID key pair, base64url.
+pub fn generate_vapid_keys() -> (String, String) {
+    let secret = SecretKey::random(&mut OsRng);
+    let public = secret.public_key().to_encoded_point(false);
+    (b64_encode(&secret.to_bytes()), b64_encode(public.as_bytes()))
+}
+
+pub fn mock_enabled() -> bool {
+    std::env::var("WEB_PUSH_MOCK").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+pub fn b64_encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decode base64url, tolerating padding and the standard alphabet some clients send.
+pub fn b64_decode(s: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    URL_SAFE_NO_PAD.decode(s.trim_end_matches('=').replace('+', "-").replace('/', "_"))
+}
+
+/// scheme://host[:port] of a push endpoint, the JWT audience.
+fn origin(endpoint: &str) -> Option<String> {
+    let (scheme, rest) = endpoint.split_once("://")?;
+    let host = rest.split('/').next().filter(|h| !h.is_empty())?;
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// Extra push service hosts from WEB_PUSH_HOSTS (comma-separated, same form
+/// as PUSH_SERVICE_HOSTS).
+fn extra_hosts() -> Vec<String> {
+    std::env::var("WEB_PUSH_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|h| h.trim().trim_start_matches('*').to_ascii_lowercase())
+        .filter(|h| !h.is_empty() && h != ".")
+        .collect()
+}
+
+fn host_allowed(host: &str, pattern: &str) -> bool {
+    if pattern.starts_with('.') {
+        host.strip_suffix(pattern).is_some_and(|sub| !sub.is_empty())
+    } else {
+        host == pattern
+    }
+}
+
+/// Push endpoints must be https on a known push service host, by name and on
+/// the default port, so the server can't be pointed at internal services. In
+/// mock mode the mock endpoint on APP_BASE_URL is allowed too.
+pub fn validate_endpoint(endpoint: &str) -> Result<()> {
+    if endpoint.len() > 2048 || origin(endpoint).is_none() {
+        return Err(anyhow!("invalid endpoint"));
+    }
+    if mock_enabled() && endpoint.starts_with(&format!("{}/push-mock/", email::base_url())) {
+        return Ok(());
+    }
+    let url = reqwest::Url::parse(endpoint).map_err(|_| anyhow!("invalid endpoint"))?;
+    if url.scheme() != "https" {
+        return Err(anyhow!("endpoint must be https"));
+    }
+    if !url.username().is_empty() || url.password().is_some() || url.port().is_some() {
+        return Err(anyhow!("invalid endpoint"));
+    }
+    // no domain means an IP literal, never a push service
+    let host = url.domain().map(|h| h.trim_end_matches('.').to_ascii_lowercase()).ok_or_else(|| anyhow!("endpoint is not a known push service"))?;
+    let extra = extra_hosts();
+    if PUSH_SERVICE_HOSTS.iter().copied().chain(extra.iter().map(String::as_str)).any(|p| host_allowed(&host, p)) {
+        Ok(())
+    } else {
+        Err(anyhow!("endpoint is not a known push service"))
+    }
+}
+
+/// Check client keys from `PushSubscription.toJSON().keys`.
+pub fn validate_keys(p256dh: &str, auth: &str) -> Result<()> {
+    let key = b64_decode(p256dh).map_err(|_| anyhow!("p256dh is not base64url"))?;
+    PublicKey::from_sec1_bytes(&key).map_err(|_| anyhow!("p256dh is not a P-256 public key"))?;
+    let auth = b64_decode(auth).map_err(|_| anyhow!("auth is not base64url"))?;
+    if auth.len() != 16 {
+        return Err(anyhow!("auth must be 16 bytes"));
+    }
+    Ok(())
+}
+
+fn vapid_authorization(vapid: &Vapid, endpoint: &str) -> Result<String> {
+    let aud = origin(endpoint).ok_or_else(|| anyhow!("invalid endpoint"))?;
+    let header = b64_encode(br#"{"typ":"JWT","alg":"ES256"}"#);
+    let claims = b64_encode(
+        json!({"aud": aud, "exp": Utc::now().timestamp() + 12 * 3600, "sub": vapid.subject})
+            .to_string()
+            .as_bytes(),
+    );
+    let signing_input = format!("{}.{}", header, claims);
+    let sig: Signature = vapid.key.sign(signing_input.as_bytes());
+    Ok(format!("vapid t={}.{}, k={}", signing_input, b64_encode(&sig.to_bytes()), vapid.public_key))
+}
+
+/// Verify an `Authorization: vapid t=..., k=...` header against our own key,
+/// as a push service would. Used by the mock endpoint.
+pub fn verify_vapid(authorization: &str, endpoint: &str) -> Result<()> {
+    let vapid = VAPID.as_ref().ok_or_else(|| anyhow!("web push disabled"))?;
+    let params = authorization.strip_prefix("vapid ").ok_or_else(|| anyhow!("not a vapid header"))?;
+    let mut token = None;
+    let mut key = None;
+    for part in params.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", v)) => token = Some(v),
+            Some(("k", v)) => key = Some(v),
+            _ => {}
+        }
+    }
+    let (token, key) = token.zip(key).ok_or_else(|| anyhow!("missing t or k"))?;
+    if key != vapid.public_key {
+        return Err(anyhow!("unknown application server key"));
+    }
+    let (signing_input, sig) = token.rsplit_once('.').ok_or_else(|| anyhow!("malformed jwt"))?;
+    let sig = Signature::from_slice(&b64_decode(sig)?).map_err(|_| anyhow!("malformed signature"))?;
+    let verifying = VerifyingKey::from_sec1_bytes(&b64_decode(key)?).map_err(|_| anyhow!("bad key"))?;
+    verifying
+        .verify(signing_input.as_bytes(), &sig)
+        .map_err(|_| anyhow!("bad signature"))?;
+    let claims = signing_input.split('.').nth(1).ok_or_else(|| anyhow!("malformed jwt"))?;
+    let claims: serde_json::Value = serde_json::from_slice(&b64_decode(claims)?)?;
+    if claims.get("aud").and_then(|v| v.as_str()) != origin(endpoint).as_deref() {
+        return Err(anyhow!("audience mismatch"));
+    }
+    if claims.get("exp").and_then(|v| v.as_i64()).unwrap_or(0) <= Utc::now().timestamp() {
+        return Err(anyhow!("token expired"));
+    }
+    Ok(())
+}
+
+// RFC 8291 section 3.4: content encryption key and nonce.
+fn derive_keys(ecdh_secret: &[u8], auth_secret: &[u8], ua_public: &[u8], as_public: &[u8], salt: &[u8]) -> Result<([u8; 16], [u8; 12])> {
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(ua_public);
+    key_info.extend_from_slice(as_public);
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth_secret), ecdh_secret)
+        .expand(&key_info, &mut ikm)
+        .map_err(|_| anyhow!("hkdf"))?;
+    let prk = Hkdf::<Sha256>::new(Some(salt), &ikm);
+    let mut cek = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek).map_err(|_| anyhow!("hkdf"))?;
+    prk.expand(b"Content-Encoding: nonce\0", &mut nonce).map_err(|_| anyhow!("hkdf"))?;
+    Ok((cek, nonce))
+}
+
+/// Encrypt `plaintext` for a subscription's `p256dh` key and `auth` secret
+/// (raw bytes) as a single aes128gcm record.
+pub fn encrypt(ua_public: &[u8], auth_secret: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    if plaintext.len() > MAX_PAYLOAD {
+        return Err(anyhow!("payload too large"));
+    }
+    let ua_key = PublicKey::from_sec1_bytes(ua_public).map_err(|_| anyhow!("invalid p256dh key"))?;
+    let as_secret = EphemeralSecret::random(&mut OsRng);
+    let as_public = as_secret.public_key().to_encoded_point(false);
+    let shared = as_secret.diffie_hellman(&ua_key);
+
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let (cek, nonce) = derive_keys(shared.raw_secret_bytes(), auth_secret, ua_public, as_public.as_bytes(), &salt)?;
+
+    // 0x02 marks the last (only) record; no extra padding
+    let mut record = plaintext.to_vec();
+    record.push(2);
+    let ciphertext = Aes128Gcm::new_from_slice(&cek)
+        .map_err(|_| anyhow!("aes key"))?
+        .encrypt(Nonce::from_slice(&nonce), record.as_slice())
+        .map_err(|_| anyhow!("encryption failed"))?;
+
+    let mut out = Vec::with_capacity(21 + as_public.len() + ciphertext.len());
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    out.push(as_public.len() as u8);
+    out.extend_from_slice(as_public.as_bytes());
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt a single-record aes128gcm body as the user agent would. Used by the
+/// mock endpoint to check what was sent.
+pub fn decrypt(ua_secret: &SecretKey, auth_secret: &[u8], body: &[u8]) -> Result<Vec<u8>> {
+    if body.len() < 21 {
+        return Err(anyhow!("body too short"));
+    }
+    let salt = &body[..16];
+    let id_len = body[20] as usize;
+    let key_id = body.get(21..21 + id_len).ok_or_else(|| anyhow!("truncated header"))?;
+    let ciphertext = &body[21 + id_len..];
+
+    let as_public = PublicKey::from_sec1_bytes(key_id).map_err(|_| anyhow!("invalid key id"))?;
+    let shared = p256::ecdh::diffie_hellman(ua_secret.to_nonzero_scalar(), as_public.as_affine());
+    let ua_public = ua_secret.public_key().to_encoded_point(false);
+    let (cek, nonce) = derive_keys(shared.raw_secret_bytes(), auth_secret, ua_public.as_bytes(), key_id, salt)?;
+
+    let mut record = Aes128Gcm::new_from_slice(&cek)
+        .map_err(|_| anyhow!("aes key"))?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext)
+        .map_err(|_| anyhow!("decryption failed"))?;
+    while record.last() == Some(&0) {
+        record.pop();
+    }
+    match record.pop() {
+        Some(2) => Ok(record),
+        _ => Err(anyhow!("bad padding delimiter")),
+    }
+}
+
+pub struct Subscription {
+    pub id: i32,
+    pub user_id: i32,
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Delivered,
+    /// 404/410: the subscription expired or was revoked
+    Gone,
+    Failed(String),
+}
+
+/// RFC 8030 urgency for a notice priority.
+fn urgency(priority: &str) -> &'static str {
+    match priority {
+        "low" => "low",
+        "high" | "urgent" => "high",
+        _ => "normal",
+    }
+}
+
+/// Encrypt and POST one message to one subscription.
+pub async fn send(sub: &Subscription, payload: &[u8], urgency: &str) -> Outcome {
+    let vapid = match VAPID.as_ref() {
+        Some(v) => v,
+        None => return Outcome::Failed("web push disabled".to_string()),
+    };
+    // subscriptions registered before the host check, or a host dropped from WEB_PUSH_HOSTS
+    if validate_endpoint(&sub.endpoint).is_err() {
+        return Outcome::Gone;
+    }
+    let body = match b64_decode(&sub.p256dh)
+        .map_err(anyhow::Error::from)
+        .and_then(|key| Ok((key, b64_decode(&sub.auth)?)))
+        .and_then(|(key, auth)| encrypt(&key, &auth, payload))
+    {
+        Ok(b) => b,
+        // keys were validated on registration, so this subscription is unusable
+        Err(e) => return Outcome::Failed(format!("encrypt: {}", e)),
+    };
+    let authorization = match vapid_authorization(vapid, &sub.endpoint) {
+        Ok(a) => a,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    let res = CLIENT
+        .post(&sub.endpoint)
+        .header("Authorization", authorization)
+        .header("Content-Encoding", "aes128gcm")
+        .header("Content-Type", "application/octet-stream")
+        .header("TTL", TTL_SECS.to_string())
+        .header("Urgency", urgency)
+        .body(body)
+        .send()
+        .await;
+    match res {
+        Ok(r) if r.status().is_success() => Outcome::Delivered,
+        Ok(r) if r.status().as_u16() == 404 || r.status().as_u16() == 410 => Outcome::Gone,
+        Ok(r) => {
+            let status = r.status();
+            let text = r.text().await.unwrap_or_default();
+            Outcome::Failed(format!("{} {}", status, text.chars().take(200).collect::<String>()))
+        }
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// What the service worker gets: enough to show a notification and link back.
+fn event_payload(evt: &NotificationEvent) -> Vec<u8> {
+    let body: String = evt.body.chars().take(500).collect();
+    json!({
+        "id": evt.id,
+        "title": evt.title,
+        "body": body,
+        "category": evt.category,
+        "priority": evt.priority,
+        "url": "/",
+    })
+    .to_string()
+    .into_bytes()
+}
+
+async fn subscriptions_for(pool: &PgPool, user_ids: &[i32]) -> sqlx::Result<Vec<Subscription>> {
+    let rows = sqlx::query("SELECT id, user_id, endpoint, p256dh, auth FROM push_subscriptions WHERE user_id = ANY($1) ORDER BY id")
+        .bind(user_ids)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|r| Subscription {
+            id: r.get("id"),
+            user_id: r.get("user_id"),
+            endpoint: r.get("endpoint"),
+            p256dh: r.get("p256dh"),
+            auth: r.get("auth"),
+        })
+        .collect())
+}
+
+/// Apply delivery results: reset/bump failure counters and drop dead subscriptions.
+async fn record_outcome(pool: &PgPool, sub: &Subscription, outcome: &Outcome) -> sqlx::Result<()> {
+    match outcome {
+        Outcome::Delivered => {
+            sqlx::query("UPDATE push_subscriptions SET last_success_at = now(), failure_count = 0 WHERE id = $1")
+                .bind(sub.id)
+                .execute(pool)
+                .await?;
+        }
+        Outcome::Gone => {
+            sqlx::query("DELETE FROM push_subscriptions WHERE id = $1")
+                .bind(sub.id)
+                .execute(pool)
+                .await?;
+        }
+        Outcome::Failed(e) => {
+            eprintln!("Web push to subscription {} failed: {}", sub.id, e);
+            sqlx::query(
+                "WITH bumped AS (UPDATE push_subscriptions SET failure_count = failure_count + 1 WHERE id = $1 RETURNING id, failure_count)
+                 DELETE FROM push_subscriptions WHERE id IN (SELECT id FROM bumped WHERE failure_count >= $2)",
+            )
+            .bind(sub.id)
+            .bind(MAX_FAILURES)
+            .execute(pool)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Push each event to every subscription of its recipient.
+pub async fn send_events(pool: &PgPool, events: Vec<NotificationEvent>) -> Result<()> {
+    if !enabled() || events.is_empty() {
+        return Ok(());
+    }
+    let user_ids: Vec<i32> = events.iter().map(|e| e.user_id).collect();
+    let mut by_user: HashMap<i32, Vec<Subscription>> = HashMap::new();
+    for sub in subscriptions_for(pool, &user_ids).await? {
+        by_user.entry(sub.user_id).or_default().push(sub);
+    }
+    for evt in &events {
+        let subs = match by_user.get(&evt.user_id) {
+            Some(s) => s,
+            None => continue,
+        };
+        let payload = event_payload(evt);
+        for sub in subs {
+            let outcome = send(sub, &payload, urgency(&evt.priority)).await;
+            record_outcome(pool, sub, &outcome).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Send `payload` to all of one user's subscriptions and report each result.
+pub async fn send_to_user(pool: &PgPool, user_id: i32, payload: &[u8]) -> Result<Vec<(i32, Outcome)>> {
+    let mut results = Vec::new();
+    for sub in subscriptions_for(pool, &[user_id]).await? {
+        let outcome = send(&sub, payload, "normal").await;
+        record_outcome(pool, &sub, &outcome).await?;
+        results.push((sub.id, outcome));
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_round_trip() {
+        let ua_secret = SecretKey::random(&mut OsRng);
+        let ua_public = ua_secret.public_key().to_encoded_point(false);
+        let mut auth = [0u8; 16];
+        OsRng.fill_bytes(&mut auth);
+        let plaintext = br#"{"id":1,"title":"Booking confirmed"}"#;
+
+        let body = encrypt(ua_public.as_bytes(), &auth, plaintext).unwrap();
+        assert_eq!(&body[16..20], &RECORD_SIZE.to_be_bytes());
+        assert_eq!(body[20] as usize, 65);
+        assert_eq!(decrypt(&ua_secret, &auth, &body).unwrap(), plaintext);
+
+        let mut other_auth = auth;
+        other_auth[0] ^= 1;
+        assert!(decrypt(&ua_secret, &other_auth, &body).is_err());
+        assert!(encrypt(ua_public.as_bytes(), &auth, &[0u8; MAX_PAYLOAD + 1]).is_err());
+    }
+
+    #[test]
+    fn endpoints_limited_to_push_services() {
+        for ok in [
+            "https://fcm.googleapis.com/fcm/send/abc",
+            "https://updates.push.services.mozilla.com/wpush/v2/abc",
+            "https://web.push.apple.com/abc",
+            "https://wns2-par02p.notify.windows.com/w/?token=abc",
+        ] {
+            assert!(validate_endpoint(ok).is_ok(), "{}", ok);
+        }
+        for bad in [
+            "http://fcm.googleapis.com/fcm/send/abc",
+            "https://10.0.0.5/push",
+            "https://[::1]/push",
+            "https://metadata.internal/push",
+            "https://fcm.googleapis.com:8443/fcm/send/abc",
+            "https://user@fcm.googleapis.com/fcm/send/abc",
+            "https://fcm.googleapis.com.evil.example/abc",
+            "https://push.apple.com/abc",
+            "https://evilpush.apple.com.example/abc",
+        ] {
+            assert!(validate_endpoint(bad).is_err(), "{}", bad);
+        }
+    }
+}
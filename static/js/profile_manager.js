@@ -15,7 +15,8 @@ async function loadProfile(){
   document.getElementById('email_display').value = email;
   document.getElementById('email_verified_badge').textContent = email ? (p.email_verified ? 'verified' : 'unverified') : '';
   document.getElementById('full_name').value = p.full_name || '';
-  document.getElementById('tv_status').textContent = p.teacher_verification || 'Not Requested';
+  const tv = p.teacher_verification;
+  document.getElementById('tv_status').textContent = tv && tv.status ? tv.status.replace(/_/g, ' ') : 'Not Requested';
   document.getElementById('view_as_select').value = (p.view_as || p.role || 'student');
 }
 
@@ -46,7 +47,7 @@ async function uploadId(e){
   form.append('id_file', f);
   const res = await fetch('/api/upload_id', {method:'POST', body:form});
   if(!res.ok){ alert('Upload failed: '+await res.text()); return }
-  alert('Uploaded. Verification will be processed by admin.');
+  alert('Saved to your verification draft. Complete and submit it from the teacher dashboard.');
   await loadProfile();
 }
 
@@ -0,0 +1,39 @@
+// Service worker for Web Push notifications (registered from /settings/notifications).
+self.addEventListener('push', (event) => {
+  let data = {};
+  try { data = event.data ? event.data.json() : {}; } catch (_) { data = { body: event.data && event.data.text() }; }
+  const title = data.title || 'Skillvine';
+  event.waitUntil(self.registration.showNotification(title, {
+    body: data.body || '',
+    tag: data.id ? `notice-${data.id}` : undefined,
+    requireInteraction: data.priority === 'urgent',
+    data: { url: data.url || '/' }
+  }));
+});
+
+self.addEventListener('notificationclick', (event) => {
+  event.notification.close();
+  const url = (event.notification.data && event.notification.data.url) || '/';
+  event.waitUntil(self.clients.matchAll({ type: 'window', includeUncontrolled: true }).then((list) => {
+    for (const c of list) {
+      if ('focus' in c) { c.navigate(url); return c.focus(); }
+    }
+    return self.clients.openWindow(url);
+  }));
+});
+
+// The browser rotated the subscription: register the new one
+self.addEventListener('pushsubscriptionchange', (event) => {
+  event.waitUntil((async () => {
+    const res = await fetch('/api/push/public_key', { credentials: 'include' });
+    const info = await res.json();
+    if (!info.public_key) return;
+    const sub = await self.registration.pushManager.subscribe({ userVisibleOnly: true, applicationServerKey: info.public_key });
+    await fetch('/api/push/subscriptions', {
+      method: 'POST',
+      credentials: 'include',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(sub.toJSON())
+    });
+  })());
+});